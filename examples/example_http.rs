@@ -2,10 +2,9 @@ use http_rs::response::Response;
 use http_rs::response_status_code::ResponseStatusCode;
 use http_rs::server::Server;
 use http_rs::server_config::{KeepAliveConfig, ServerConfigBuilder};
+use http_rs::Result;
 use log::LevelFilter;
 use pretty_env_logger::env_logger::Target;
-use std::io::Result;
-use std::sync::Arc;
 
 fn main() -> Result<()> {
     pretty_env_logger::formatted_timed_builder()
@@ -53,5 +52,5 @@ fn main() -> Result<()> {
                     .get(),
             )
         })
-        .run(Arc::new(false))
+        .run()
 }
@@ -0,0 +1,12 @@
+// Prints the content hash manifest of a directory, e.g. cargo run --example manifest -- root
+
+use http_rs::manifest::build_manifest;
+use std::io::Result;
+
+fn main() -> Result<()> {
+    let root = std::env::args().nth(1).unwrap_or("root".to_string());
+
+    println!("{}", build_manifest(&root)?.to_json());
+
+    Ok(())
+}
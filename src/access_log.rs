@@ -0,0 +1,302 @@
+use crate::request::Request;
+use crate::utils::{civil_date, escape_json, iso8601};
+use log::error;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AccessLogFormat {
+    /// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET / HTTP/1.1" 200 2326`,
+    /// followed by the time taken in microseconds
+    #[default]
+    Common,
+    /// One JSON object per line
+    Json,
+}
+
+/// Where and how every request is logged, see `ServerConfig::access_log`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessLogConfig {
+    pub path: String,
+    pub format: AccessLogFormat,
+    /// The file is moved to `<path>.1` once it would grow past this, older ones to `<path>.2`
+    /// and so on. It grows without limit when None
+    pub max_size: Option<u64>,
+    /// How many rotated files are kept
+    pub max_files: u32,
+    /// 404s for these paths are left out, e.g. /favicon.ico, which browsers ask every site for
+    pub quiet_paths: Vec<String>,
+}
+
+impl AccessLogConfig {
+    pub fn new(path: &str) -> Self {
+        AccessLogConfig {
+            path: path.to_string(),
+            format: AccessLogFormat::default(),
+            max_size: None,
+            max_files: 5,
+            quiet_paths: vec![],
+        }
+    }
+
+    pub fn format(mut self, format: AccessLogFormat) -> Self {
+        self.format = format;
+
+        self
+    }
+
+    pub fn rotate(mut self, max_size: u64, max_files: u32) -> Self {
+        self.max_size = Some(max_size);
+        self.max_files = max_files;
+
+        self
+    }
+
+    pub fn quiet_path(mut self, path: &str) -> Self {
+        self.quiet_paths.push(path.to_string());
+
+        self
+    }
+}
+
+/// What gets logged about one request.
+pub(crate) struct AccessLogEntry<'a> {
+    pub(crate) time: SystemTime,
+    pub(crate) peer_addr: Option<IpAddr>,
+    /// None when the request could not be parsed
+    pub(crate) request: Option<&'a Request>,
+    pub(crate) status: u16,
+    /// Head and body, as written to the connection
+    pub(crate) bytes_sent: u64,
+    pub(crate) duration: Duration,
+}
+
+pub(crate) struct AccessLog {
+    config: AccessLogConfig,
+    // with the size of the file
+    file: Mutex<(File, u64)>,
+}
+
+impl AccessLog {
+    pub(crate) fn open(config: &AccessLogConfig) -> std::io::Result<Self> {
+        let file = open_append(&config.path)?;
+        let len = file.metadata()?.len();
+
+        Ok(AccessLog {
+            config: config.clone(),
+            file: Mutex::new((file, len)),
+        })
+    }
+
+    pub(crate) fn log(&self, entry: &AccessLogEntry) {
+        if self.is_quiet(entry) {
+            return;
+        }
+
+        let line = match self.config.format {
+            AccessLogFormat::Common => common_line(entry),
+            AccessLogFormat::Json => json_line(entry),
+        };
+        let mut file = self.file.lock().unwrap();
+
+        let over_max_size = self
+            .config
+            .max_size
+            .is_some_and(|max_size| file.1 > 0 && file.1 + line.len() as u64 > max_size);
+        if over_max_size {
+            match self.rotate() {
+                Ok(rotated) => *file = (rotated, 0),
+                Err(err) => error!("Rotating access log {} failed: {err}", self.config.path),
+            }
+        }
+
+        match file.0.write_all(line.as_bytes()) {
+            Ok(()) => file.1 += line.len() as u64,
+            Err(err) => error!("Writing access log {} failed: {err}", self.config.path),
+        }
+    }
+
+    fn is_quiet(&self, entry: &AccessLogEntry) -> bool {
+        let Some(request) = entry.request else {
+            return false;
+        };
+        let path = request.url.split(['?', '#']).next().unwrap_or_default();
+
+        entry.status == 404 && self.config.quiet_paths.iter().any(|quiet| quiet == path)
+    }
+
+    /// Shifts the rotated files by one, dropping the oldest, and starts a new file.
+    fn rotate(&self) -> std::io::Result<File> {
+        let rotated = |index: u32| PathBuf::from(format!("{}.{index}", self.config.path));
+
+        if self.config.max_files == 0 {
+            fs::remove_file(&self.config.path)?;
+        } else {
+            for index in (1..self.config.max_files).rev() {
+                if rotated(index).exists() {
+                    fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.config.path, rotated(1))?;
+        }
+
+        open_append(&self.config.path)
+    }
+}
+
+fn open_append(path: &str) -> std::io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn common_line(entry: &AccessLogEntry) -> String {
+    let request_line = match entry.request {
+        Some(request) => format!("{} {} {}", request.method, request.url, request.version),
+        None => "-".to_string(),
+    };
+
+    format!(
+        "{} - - [{}] \"{}\" {} {} {}\n",
+        peer_addr(entry),
+        common_time(entry.time),
+        request_line.replace('"', "\\\""),
+        entry.status,
+        entry.bytes_sent,
+        entry.duration.as_micros()
+    )
+}
+
+fn json_line(entry: &AccessLogEntry) -> String {
+    let (method, url, version) = match entry.request {
+        Some(request) => (
+            request.method.to_string(),
+            request.url.as_str(),
+            request.version.to_string(),
+        ),
+        None => (String::new(), "", String::new()),
+    };
+
+    format!(
+        concat!(
+            "{{\"time\": \"{}\", \"client\": \"{}\", \"method\": \"{}\", \"url\": \"{}\", ",
+            "\"version\": \"{}\", \"status\": {}, \"bytes\": {}, \"duration_ms\": {:.3}}}\n"
+        ),
+        iso8601(entry.time),
+        peer_addr(entry),
+        escape_json(&method),
+        escape_json(url),
+        escape_json(&version),
+        entry.status,
+        entry.bytes_sent,
+        entry.duration.as_secs_f64() * 1000.0
+    )
+}
+
+fn peer_addr(entry: &AccessLogEntry) -> String {
+    entry
+        .peer_addr
+        .map_or("-".to_string(), |peer_addr| peer_addr.to_string())
+}
+
+/// e.g. 10/Oct/2000:13:55:36 +0000
+fn common_time(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_date(seconds / 86400);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{day:02}/{}/{year:04}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use crate::access_log::{common_line, json_line, AccessLog, AccessLogConfig, AccessLogEntry};
+    use crate::request::Request;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn entry(request: &Request) -> AccessLogEntry<'_> {
+        AccessLogEntry {
+            time: UNIX_EPOCH + Duration::from_secs(971_186_136),
+            peer_addr: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            request: Some(request),
+            status: 200,
+            bytes_sent: 2326,
+            duration: Duration::from_micros(1500),
+        }
+    }
+
+    #[test]
+    fn formats_lines() {
+        assert_eq!(
+            common_line(&entry(&Request::builder().url("/index.html").get())),
+            "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /index.html HTTP/1.1\" 200 2326 1500\n"
+        );
+        assert_eq!(
+            json_line(&entry(&Request::builder().url("/\"quoted\"").get())),
+            concat!(
+                "{\"time\": \"2000-10-10T13:55:36.000Z\", \"client\": \"127.0.0.1\", ",
+                "\"method\": \"GET\", \"url\": \"/\\\"quoted\\\"\", \"version\": \"HTTP/1.1\", ",
+                "\"status\": 200, \"bytes\": 2326, \"duration_ms\": 1.500}\n"
+            )
+        );
+    }
+
+    #[test]
+    fn rotates_by_size() {
+        let path = std::env::temp_dir().join(format!("http_rs_access_{}.log", std::process::id()));
+        let path = path.to_str().unwrap();
+        let request = Request::builder().url("/").get();
+        let line_len = common_line(&entry(&request)).len() as u64;
+        let access_log =
+            AccessLog::open(&AccessLogConfig::new(path).rotate(line_len * 2, 1)).unwrap();
+
+        for _ in 0..5 {
+            access_log.log(&entry(&request));
+        }
+        let current = std::fs::read_to_string(path).unwrap();
+        let rotated = std::fs::read_to_string(format!("{path}.1")).unwrap();
+        let dropped = std::path::Path::new(&format!("{path}.2")).exists();
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(format!("{path}.1")).unwrap();
+
+        assert_eq!(current.lines().count(), 1);
+        assert_eq!(rotated.lines().count(), 2);
+        assert!(!dropped);
+    }
+
+    #[test]
+    fn leaves_out_not_found_quiet_paths() {
+        let path = std::env::temp_dir().join(format!("http_rs_quiet_{}.log", std::process::id()));
+        let path = path.to_str().unwrap();
+        let access_log =
+            AccessLog::open(&AccessLogConfig::new(path).quiet_path("/favicon.ico")).unwrap();
+        let favicon = Request::builder().url("/favicon.ico").get();
+
+        access_log.log(&AccessLogEntry {
+            status: 404,
+            ..entry(&favicon)
+        });
+        access_log.log(&entry(&favicon));
+        let log = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(log.lines().count(), 1);
+        assert!(log.contains("\" 200 "));
+    }
+}
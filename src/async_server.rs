@@ -0,0 +1,560 @@
+//! Serving on a tokio runtime, available with the `tokio` feature.
+//!
+//! Connections are read and written asynchronously, so idle keep-alive connections don't
+//! hold a thread. Once a request has arrived in full it goes through the same handling as
+//! with `Server::start`, on tokio's blocking pool, and its response is sent from memory.
+//! Connections are plain HTTP/1 only, unless the `http2` feature is on. The HTTPS listener is
+//! then served too, with HTTP/2 for clients that ask for it through ALPN and HTTP/1.1
+//! for the rest. Plain connections get HTTP/2 (h2c) when they start with its preface, for
+//! deployments that terminate TLS in front of the server, or ask for it with
+//! `Upgrade: h2c` while `ServerConfig::upgrade` is `UpgradePolicy::Honor`.
+
+#[cfg(feature = "http2")]
+use crate::http2::{self, Rewind, ALPN_H2, PREFACE, SWITCHING_TO_H2C};
+use crate::request::{parse_chunked_body_with_trailers, parse_request, RequestBodyType};
+#[cfg(feature = "http2")]
+use crate::server::init_https;
+use crate::server::{bind_listener, Server};
+#[cfg(feature = "http2")]
+use crate::server_config::UpgradePolicy;
+use crate::server_config::{KeepAliveConfig, ParserConfig};
+#[cfg(feature = "http2")]
+use crate::server_handle::ConnectionTracker;
+#[cfg(feature = "http2")]
+use log::error;
+use log::{debug, info};
+use std::net::IpAddr;
+#[cfg(feature = "http2")]
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+#[cfg(feature = "http2")]
+use tokio_rustls::TlsAcceptor;
+
+#[cfg(feature = "http2")]
+const ALPN_HTTP_1_1: &[u8] = b"http/1.1";
+
+pub struct AsyncServer {
+    server: Server,
+}
+
+impl AsyncServer {
+    pub fn new(server: Server) -> Self {
+        AsyncServer { server }
+    }
+
+    /// Binds the plain HTTP listener from the config and serves on it.
+    /// With the `http2` feature, the HTTPS listener is bound and served as well.
+    /// Fails before binding if `Server::self_check` does.
+    pub async fn run(&self) -> crate::Result<()> {
+        self.server.self_check()?;
+        let config = self.server.config();
+        let listener = bind_listener(&format!("{}:{}", config.bind_address, config.port), config)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        #[allow(unused_mut)]
+        let mut bound = vec![(listener.local_addr()?, false)];
+
+        #[cfg(feature = "http2")]
+        if config.https {
+            let bind_address = config
+                .https_bind_address
+                .as_ref()
+                .unwrap_or(&config.bind_address);
+            let https_listener =
+                bind_listener(&format!("{bind_address}:{}", config.https_port), config)?;
+            https_listener.set_nonblocking(true)?;
+            let https_listener = TcpListener::from_std(https_listener)?;
+            bound.push((https_listener.local_addr()?, true));
+            // runs for as long as the process, there is no handle to stop it with
+            self.server
+                .watch_certificates(Arc::new(ConnectionTracker::default()));
+            let server = AsyncServer::new(self.server.clone());
+
+            tokio::spawn(async move {
+                if let Err(err) = server.serve_tls(https_listener).await {
+                    error!("HTTPS listener stopped: {err}");
+                }
+            });
+        }
+
+        let workers = tokio::runtime::Handle::current().metrics().num_workers();
+        self.server
+            .log_startup(&bound, &format!("{workers} tokio worker threads"));
+
+        self.serve(listener).await
+    }
+
+    /// Accepts connections until accepting fails, each one is served on its own task.
+    pub async fn serve(&self, listener: TcpListener) -> crate::Result<()> {
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            debug!("New connection");
+            stream.set_nodelay(self.server.config().tcp_nodelay)?;
+            self.server.count_connection();
+            let server = self.server.clone();
+
+            tokio::spawn(async move {
+                match serve_connection(server, stream, peer_addr.ip(), false).await {
+                    Ok(()) => debug!("Connection closed"),
+                    Err(err) => info!("Connection error: {err:?}"),
+                }
+            });
+        }
+    }
+
+    /// Accepts TLS connections until accepting fails, with the certificates from the config.
+    /// h2 is offered ahead of http/1.1 through ALPN, and each connection is served
+    /// with the protocol it picks.
+    #[cfg(feature = "http2")]
+    pub async fn serve_tls(&self, listener: TcpListener) -> crate::Result<()> {
+        let Some(tls_config) = init_https(self.server.config(), &[ALPN_H2, ALPN_HTTP_1_1])? else {
+            return Err(crate::Error::Config(
+                "Serving TLS needs HTTPS in the config".to_string(),
+            ));
+        };
+        let acceptor = TlsAcceptor::from(tls_config);
+
+        loop {
+            let (stream, peer_addr) = listener.accept().await?;
+            debug!("New TLS connection");
+            stream.set_nodelay(self.server.config().tcp_nodelay)?;
+            self.server.count_connection();
+            let server = self.server.clone();
+            let acceptor = acceptor.clone();
+
+            tokio::spawn(async move {
+                let result = match acceptor.accept(stream).await {
+                    Ok(stream) if stream.get_ref().1.alpn_protocol() == Some(ALPN_H2) => {
+                        http2::serve_connection(server, stream, peer_addr.ip()).await
+                    }
+                    Ok(stream) => serve_connection(server, stream, peer_addr.ip(), true).await,
+                    Err(err) => Err(err),
+                };
+
+                match result {
+                    Ok(()) => debug!("Connection closed"),
+                    Err(err) => info!("Connection error: {err:?}"),
+                }
+            });
+        }
+    }
+}
+
+/// Serves HTTP/1 requests until the connection closes, `secure` if it speaks TLS.
+/// Plain connections switch to HTTP/2 on its preface or an h2c upgrade.
+async fn serve_connection<S>(
+    server: Server,
+    mut stream: S,
+    peer_addr: IpAddr,
+    secure: bool,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (_, max_requests) = server.persistence();
+    let idle_timeout = Duration::from_secs(match server.config().keep_alive {
+        KeepAliveConfig::On { timeout, .. } => timeout as u64,
+        _ => server.config().timeout as u64,
+    });
+    let mut buf = vec![];
+    let mut served_requests_count = 0u8;
+
+    loop {
+        let len = loop {
+            #[cfg(feature = "http2")]
+            let awaits_preface =
+                !secure && served_requests_count == 0 && http2::may_be_preface(&buf);
+            #[cfg(not(feature = "http2"))]
+            let awaits_preface = false;
+
+            #[cfg(feature = "http2")]
+            if awaits_preface && buf.len() >= PREFACE.len() {
+                debug!("Switching to HTTP/2 with prior knowledge");
+                return http2::serve_connection(server, Rewind::new(buf, stream), peer_addr).await;
+            }
+
+            if !awaits_preface {
+                if let Some(len) = request_len(&buf, &server.config().parser) {
+                    break len;
+                }
+            }
+
+            let mut read_buf = [0u8; 4096];
+            let read = match tokio::time::timeout(idle_timeout, stream.read(&mut read_buf)).await {
+                Ok(read) => read?,
+                Err(_) => {
+                    debug!("Connection timed out");
+                    return Ok(());
+                }
+            };
+            if read == 0 {
+                return Ok(());
+            }
+            buf.extend_from_slice(&read_buf[..read]);
+        };
+
+        #[cfg(feature = "http2")]
+        if !secure && server.config().upgrade == UpgradePolicy::Honor {
+            if let Some(frame) = http2::h2c_upgrade_frame(&buf[..len], &server.config().parser) {
+                debug!("Switching to HTTP/2 with an h2c upgrade");
+                buf.drain(..len);
+                stream.write_all(SWITCHING_TO_H2C).await?;
+                return http2::serve_upgraded(server, stream, buf, frame, peer_addr, idle_timeout)
+                    .await;
+            }
+        }
+
+        let request = buf.drain(..len).collect();
+        let remaining_requests = max_requests.saturating_sub(served_requests_count);
+        let server = server.clone();
+        let (response, close) = tokio::task::spawn_blocking(move || {
+            server.serve_buffered(request, Some(peer_addr), remaining_requests, secure)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+
+        stream.write_all(&response).await?;
+        served_requests_count += 1;
+
+        if close {
+            // TLS streams send close_notify here
+            return stream.shutdown().await;
+        }
+    }
+}
+
+/// Length of the first request in `buf`, None until all of it has arrived.
+/// Requests that don't parse are complete with their head, the state machine rejects them.
+fn request_len(buf: &[u8], parser_config: &ParserConfig) -> Option<usize> {
+    let head_len = buf.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+    let Ok((request, _)) = parse_request(&buf[..head_len], parser_config) else {
+        return Some(head_len);
+    };
+
+    match request.body_type() {
+        RequestBodyType::ContentLength => {
+            let len = head_len + request.content_length().unwrap_or_default();
+            (buf.len() >= len).then_some(len)
+        }
+        // like the state machine, the body is over once the buffer ends with an empty line
+        RequestBodyType::TransferEncodingChunked => {
+            let body = &buf[head_len..];
+            let is_complete = body.ends_with(b"\r\n\r\n")
+                && parse_chunked_body_with_trailers(body.to_vec())
+                    .is_ok_and(|(_, _, is_complete)| is_complete);
+
+            is_complete.then_some(buf.len())
+        }
+        RequestBodyType::None => Some(head_len),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::async_server::{request_len, AsyncServer};
+    use crate::response::Response;
+    use crate::server::Server;
+    use crate::server_config::{KeepAliveConfig, ParserConfig, ServerConfig};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[test]
+    fn finds_end_of_request() {
+        let config = ParserConfig::strict();
+        let get = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let post = b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc";
+        let chunked = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+
+        assert_eq!(request_len(get, &config), Some(get.len()));
+        assert_eq!(request_len(&get[..10], &config), None);
+        assert_eq!(request_len(post, &config), Some(post.len()));
+        assert_eq!(request_len(&post[..post.len() - 1], &config), None);
+        assert_eq!(request_len(chunked, &config), Some(chunked.len()));
+        assert_eq!(request_len(&chunked[..chunked.len() - 5], &config), None);
+
+        let mut pipelined = get.to_vec();
+        pipelined.extend_from_slice(post);
+        assert_eq!(request_len(&pipelined, &config), Some(get.len()));
+    }
+
+    #[test]
+    fn serves_keep_alive_requests() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        runtime.block_on(async {
+            let config = ServerConfig {
+                keep_alive: KeepAliveConfig::On {
+                    timeout: 1,
+                    max_requests: 2,
+                    include_header: false,
+                },
+                ..Default::default()
+            };
+            let server = Server::new(Some(config))
+                .listener(|request| Some(Response::builder().text_body(&request.url).get()));
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move { AsyncServer::new(server).serve(listener).await });
+
+            let mut stream = TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\nGET /b HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+
+            // the second request is the last one the connection may serve
+            assert_eq!(response.matches("HTTP/1.1 200 OK\r\n").count(), 2);
+            assert!(response.contains("\r\n\r\n/a"));
+            assert!(response.ends_with("\r\n\r\n/b"));
+        });
+    }
+
+    #[cfg(feature = "http2")]
+    mod h2c {
+        use crate::async_server::AsyncServer;
+        use crate::http2::{PREFACE, SWITCHING_TO_H2C};
+        use crate::response::Response;
+        use crate::server::Server;
+        use crate::server_config::{ServerConfig, UpgradePolicy};
+        use std::net::SocketAddr;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        static UPGRADE: &[u8] = b"GET /up HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n";
+
+        async fn start(upgrade: UpgradePolicy) -> SocketAddr {
+            let config = ServerConfig {
+                upgrade,
+                ..Default::default()
+            };
+            let server = Server::new(Some(config)).listener(|request| {
+                Some(
+                    Response::builder()
+                        .text_body(&format!("{} {}", request.version, request.url))
+                        .get(),
+                )
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move { AsyncServer::new(server).serve(listener).await });
+
+            address
+        }
+
+        fn runtime() -> tokio::runtime::Runtime {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        }
+
+        #[test]
+        fn serves_h2_with_prior_knowledge() {
+            runtime().block_on(async {
+                let address = start(UpgradePolicy::default()).await;
+                let stream = TcpStream::connect(address).await.unwrap();
+
+                let (mut client, connection) = h2::client::handshake(stream).await.unwrap();
+                tokio::spawn(connection);
+
+                let request = http::Request::get("http://localhost/prior")
+                    .body(())
+                    .unwrap();
+                let (response, _) = client.send_request(request, true).unwrap();
+                let response = response.await.unwrap();
+                assert_eq!(response.status(), 200);
+
+                let mut body = response.into_body();
+                let mut bytes = vec![];
+                while let Some(data) = body.data().await {
+                    bytes.extend_from_slice(&data.unwrap());
+                }
+                assert_eq!(bytes, b"HTTP/2 /prior");
+            });
+        }
+
+        #[test]
+        fn answers_upgrade_request_on_stream_1() {
+            runtime().block_on(async {
+                let address = start(UpgradePolicy::Honor).await;
+                let mut stream = TcpStream::connect(address).await.unwrap();
+                stream.write_all(UPGRADE).await.unwrap();
+
+                let mut switching = vec![0u8; SWITCHING_TO_H2C.len()];
+                stream.read_exact(&mut switching).await.unwrap();
+                assert_eq!(switching, SWITCHING_TO_H2C);
+
+                // preface and empty SETTINGS
+                stream.write_all(PREFACE).await.unwrap();
+                stream
+                    .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+
+                let mut body = vec![];
+                loop {
+                    let mut header = [0u8; 9];
+                    stream.read_exact(&mut header).await.unwrap();
+                    let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+                    let mut payload = vec![0u8; len];
+                    stream.read_exact(&mut payload).await.unwrap();
+
+                    let is_stream_1 = header[5..9] == [0, 0, 0, 1];
+                    // DATA
+                    if header[3] == 0 && is_stream_1 {
+                        body.extend_from_slice(&payload);
+                        if header[4] & 1 == 1 {
+                            break;
+                        }
+                    }
+                }
+                assert_eq!(body, b"HTTP/2 /up");
+            });
+        }
+
+        #[test]
+        fn stays_on_http_1_1_unless_upgrade_is_honored() {
+            runtime().block_on(async {
+                let address = start(UpgradePolicy::Strip).await;
+                let mut stream = TcpStream::connect(address).await.unwrap();
+                stream.write_all(UPGRADE).await.unwrap();
+                stream.shutdown().await.unwrap();
+
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+                assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+                assert!(response.ends_with("\r\n\r\nHTTP/1.1 /up"));
+            });
+        }
+    }
+
+    #[cfg(feature = "http2")]
+    mod serve_tls {
+        use crate::async_server::AsyncServer;
+        use crate::response::Response;
+        use crate::server::Server;
+        use crate::server_config::ServerConfig;
+        use std::net::SocketAddr;
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+        use tokio_rustls::client::TlsStream;
+        use tokio_rustls::TlsConnector;
+
+        // the test certificate is self-signed
+        struct AcceptAny;
+
+        impl rustls::client::ServerCertVerifier for AcceptAny {
+            fn verify_server_cert(
+                &self,
+                _end_entity: &rustls::Certificate,
+                _intermediates: &[rustls::Certificate],
+                _server_name: &rustls::ServerName,
+                _scts: &mut dyn Iterator<Item = &[u8]>,
+                _ocsp_response: &[u8],
+                _now: std::time::SystemTime,
+            ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+                Ok(rustls::client::ServerCertVerified::assertion())
+            }
+        }
+
+        async fn start() -> SocketAddr {
+            let config = ServerConfig {
+                https: true,
+                cert_path: Some("./test_files/keys/server.crt".to_string()),
+                key_path: Some("./test_files/keys/server.key".to_string()),
+                ..Default::default()
+            };
+            let server = Server::new(Some(config)).listener(|request| {
+                Some(
+                    Response::builder()
+                        .text_body(&format!("{} {}", request.version, request.url))
+                        .get(),
+                )
+            });
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move { AsyncServer::new(server).serve_tls(listener).await });
+
+            address
+        }
+
+        async fn connect(address: SocketAddr, protocols: &[&[u8]]) -> TlsStream<TcpStream> {
+            let mut tls_config = rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(Arc::new(AcceptAny))
+                .with_no_client_auth();
+            tls_config.alpn_protocols =
+                protocols.iter().map(|protocol| protocol.to_vec()).collect();
+            let stream = TcpStream::connect(address).await.unwrap();
+
+            TlsConnector::from(Arc::new(tls_config))
+                .connect("localhost".try_into().unwrap(), stream)
+                .await
+                .unwrap()
+        }
+
+        fn runtime() -> tokio::runtime::Runtime {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        }
+
+        #[test]
+        fn serves_h2_when_negotiated() {
+            runtime().block_on(async {
+                let address = start().await;
+                let stream = connect(address, &[b"h2", b"http/1.1"]).await;
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+                let (mut client, connection) = h2::client::handshake(stream).await.unwrap();
+                tokio::spawn(connection);
+
+                let request = http::Request::get("https://localhost/hello?a=1")
+                    .body(())
+                    .unwrap();
+                let (response, _) = client.send_request(request, true).unwrap();
+                let response = response.await.unwrap();
+
+                assert_eq!(response.status(), 200);
+                assert_eq!(response.version(), http::Version::HTTP_2);
+                assert!(!response.headers().contains_key("connection"));
+                assert!(response.headers().contains_key("date"));
+
+                let mut body = response.into_body();
+                let mut bytes = vec![];
+                while let Some(data) = body.data().await {
+                    bytes.extend_from_slice(&data.unwrap());
+                }
+                assert_eq!(bytes, b"HTTP/2 /hello?a=1");
+            });
+        }
+
+        #[test]
+        fn falls_back_to_http_1_1() {
+            runtime().block_on(async {
+                let address = start().await;
+                let mut stream = connect(address, &[b"http/1.1"]).await;
+                assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+                stream
+                    .write_all(b"GET /old HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                    .await
+                    .unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).await.unwrap();
+
+                assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+                assert!(response.ends_with("\r\n\r\nHTTP/1.1 /old"));
+            });
+        }
+    }
+}
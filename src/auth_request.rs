@@ -0,0 +1,113 @@
+use crate::header::names;
+use crate::request::{has_path_prefix, Request};
+use crate::request_method::RequestMethod;
+
+const X_ORIGINAL_URI: &str = "X-Original-URI";
+const X_ORIGINAL_METHOD: &str = "X-Original-Method";
+
+/// Requests under `path_prefix` are only served when a subrequest to `auth_path` gets a 2xx,
+/// like nginx's `auth_request`. 401 and 403 are passed on to the client, anything else is a 500.
+///
+/// The subrequest goes through listeners, routes and proxy routes like any other request,
+/// so a proxy route under `auth_path` delegates the decision to an upstream.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AuthRequest {
+    pub path_prefix: String,
+    pub auth_path: String,
+}
+
+impl AuthRequest {
+    pub fn new(path_prefix: &str, auth_path: &str) -> Self {
+        AuthRequest {
+            path_prefix: path_prefix.to_string(),
+            auth_path: auth_path.to_string(),
+        }
+    }
+
+    /// Requests for the auth path itself are not checked again. Expects a normalized path,
+    /// prefixes match whole segments.
+    pub(crate) fn applies_to(&self, request: &Request) -> bool {
+        has_path_prefix(request.path(), &self.path_prefix)
+            && !has_path_prefix(request.path(), &self.auth_path)
+    }
+
+    /// GET for the auth path with the headers of `request` and without its body.
+    pub(crate) fn subrequest(&self, request: &Request) -> Request {
+        let mut headers = request.headers.clone();
+        for header_name in [
+            names::CONTENT_LENGTH,
+            names::TRANSFER_ENCODING,
+            X_ORIGINAL_URI,
+            X_ORIGINAL_METHOD,
+        ] {
+            headers.remove(header_name);
+        }
+        headers.add(X_ORIGINAL_URI, &request.url);
+        headers.add(X_ORIGINAL_METHOD, &request.method.to_string());
+
+        Request {
+            method: RequestMethod::Get,
+            url: self.auth_path.clone(),
+            version: request.version,
+            headers,
+            body: vec![],
+            trailers: Default::default(),
+            peer_addr: request.peer_addr,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::auth_request::AuthRequest;
+    use crate::request::Request;
+    use crate::request_method::RequestMethod;
+
+    #[test]
+    fn subrequest_carries_original_request() {
+        let auth_request = AuthRequest::new("/private", "/internal/auth");
+        let request = Request::builder()
+            .method(RequestMethod::Post)
+            .url("/private/upload")
+            .header("Authorization", "Bearer token")
+            .header("Content-Length", "3")
+            .body(b"abc".to_vec())
+            .get();
+
+        let subrequest = auth_request.subrequest(&request);
+
+        assert_eq!(subrequest.method, RequestMethod::Get);
+        assert_eq!(subrequest.url, "/internal/auth");
+        assert!(subrequest.body.is_empty());
+        assert!(!subrequest.has_header("Content-Length", None));
+        assert_eq!(
+            subrequest.get_header("Authorization").as_deref(),
+            Some("Bearer token")
+        );
+        assert_eq!(
+            subrequest.get_header("X-Original-URI").as_deref(),
+            Some("/private/upload")
+        );
+        assert_eq!(
+            subrequest.get_header("X-Original-Method").as_deref(),
+            Some("POST")
+        );
+    }
+
+    #[test]
+    fn does_not_apply_to_auth_path() {
+        let auth_request = AuthRequest::new("/", "/internal/auth");
+
+        assert!(auth_request.applies_to(&Request::builder().url("/index.html").get()));
+        assert!(!auth_request.applies_to(&Request::builder().url("/internal/auth").get()));
+    }
+
+    #[test]
+    fn applies_below_prefix_only() {
+        let auth_request = AuthRequest::new("/private", "/internal/auth");
+
+        assert!(auth_request.applies_to(&Request::builder().url("/private?page=2").get()));
+        assert!(auth_request.applies_to(&Request::builder().url("/private/data").get()));
+        assert!(!auth_request.applies_to(&Request::builder().url("/privateer").get()));
+    }
+}
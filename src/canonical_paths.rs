@@ -0,0 +1,140 @@
+use crate::types::IoResult;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Device and inode of a file, None where the platform does not expose them.
+pub(crate) fn file_identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some((metadata.dev(), metadata.ino()))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+
+struct CanonicalPath {
+    path: PathBuf,
+    identity: (u64, u64),
+}
+
+/// Remembers canonical roots and request paths, so `fs::canonicalize` does not
+/// run on every request.
+#[derive(Default)]
+pub(crate) struct CanonicalPaths {
+    roots: Mutex<HashMap<String, PathBuf>>,
+    paths: Mutex<HashMap<PathBuf, CanonicalPath>>,
+}
+
+impl CanonicalPaths {
+    pub(crate) fn with_roots<'a>(roots: impl Iterator<Item = &'a str>) -> Self {
+        let canonical_paths = CanonicalPaths::default();
+
+        for root in roots {
+            // roots missing at startup are looked up again when requested
+            let _ = canonical_paths.root(root);
+        }
+
+        canonical_paths
+    }
+
+    pub(crate) fn root(&self, root: &str) -> IoResult<PathBuf> {
+        if let Some(path) = self.roots.lock().unwrap().get(root) {
+            return Ok(path.clone());
+        }
+
+        let path = fs::canonicalize(root)?;
+        self.roots
+            .lock()
+            .unwrap()
+            .insert(root.to_string(), path.clone());
+
+        Ok(path)
+    }
+
+    /// Cached canonical form of `path`, only valid if the file opened through it
+    /// still has the same identity (see `is_current`).
+    pub(crate) fn get(&self, path: &Path) -> Option<PathBuf> {
+        self.paths
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(|canonical| canonical.path.clone())
+    }
+
+    /// A directory swapped for a symlink makes the cached path point somewhere else,
+    /// which shows up as a different device or inode.
+    pub(crate) fn is_current(&self, path: &Path, metadata: &Metadata) -> bool {
+        let identity = file_identity(metadata);
+
+        self.paths
+            .lock()
+            .unwrap()
+            .get(path)
+            .is_some_and(|canonical| Some(canonical.identity) == identity)
+    }
+
+    pub(crate) fn insert(&self, path: PathBuf, canonical_path: PathBuf, metadata: &Metadata) {
+        let Some(identity) = file_identity(metadata) else {
+            return;
+        };
+
+        self.paths.lock().unwrap().insert(
+            path,
+            CanonicalPath {
+                path: canonical_path,
+                identity,
+            },
+        );
+    }
+
+    pub(crate) fn invalidate(&self, path: &Path) {
+        self.paths.lock().unwrap().remove(path);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::canonical_paths::CanonicalPaths;
+    use std::fs;
+    use std::path::Path;
+
+    // not going to mock fs
+    #[test]
+    fn caches_roots() {
+        let paths = CanonicalPaths::with_roots(["test_files"].into_iter());
+
+        assert_eq!(
+            paths.root("test_files").unwrap(),
+            fs::canonicalize("test_files").unwrap()
+        );
+        assert!(paths.root("0qhwe0t9h").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cached_path_is_current_only_for_same_file() {
+        let paths = CanonicalPaths::default();
+        let path = Path::new("test_files/file.txt");
+        let metadata = fs::metadata(path).unwrap();
+
+        paths.insert(
+            path.to_path_buf(),
+            fs::canonicalize(path).unwrap(),
+            &metadata,
+        );
+        assert!(paths.get(path).is_some());
+        assert!(paths.is_current(path, &metadata));
+        assert!(!paths.is_current(path, &fs::metadata("test_files").unwrap()));
+
+        paths.invalidate(path);
+        assert!(paths.get(path).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+//! Just enough DER to read when certificates expire, a full X.509 parser is not needed for that.
+
+use crate::server_config::load_certs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SEQUENCE: u8 = 0x30;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+// [0] EXPLICIT, only present for certificates other than v1
+const VERSION: u8 = 0xa0;
+
+/// When a certificate the server presents stops being valid.
+/// Returned from `Server::certificate_expiries` and passed to its expiry listener.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CertificateExpiry {
+    pub cert_path: String,
+    /// Earliest end of validity in the chain, None if it could not be read
+    pub not_after: Option<SystemTime>,
+}
+
+/// When the first certificate of the chain in `cert_path` expires. The chain stops being
+/// valid then, so that is the date that matters. None if none of them could be read.
+pub(crate) fn chain_expiry(cert_path: &str) -> Option<SystemTime> {
+    load_certs(cert_path)
+        .ok()?
+        .iter()
+        .filter_map(|cert| not_after(&cert.0))
+        .min()
+}
+
+/// The end of the validity period of a DER encoded certificate.
+pub(crate) fn not_after(der: &[u8]) -> Option<SystemTime> {
+    let (SEQUENCE, certificate, _) = read_element(der)? else {
+        return None;
+    };
+    let (SEQUENCE, mut fields, _) = read_element(certificate)? else {
+        return None;
+    };
+
+    if let (VERSION, _, rest) = read_element(fields)? {
+        fields = rest;
+    }
+    // serial number, signature algorithm and issuer come before the validity
+    for _ in 0..3 {
+        fields = read_element(fields)?.2;
+    }
+
+    let (SEQUENCE, validity, _) = read_element(fields)? else {
+        return None;
+    };
+    let (_, _, validity) = read_element(validity)?;
+    let (tag, time, _) = read_element(validity)?;
+
+    parse_time(tag, time)
+}
+
+/// Tag and contents of the element `der` starts with, and the bytes after it.
+fn read_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0usize, |len, &byte| (len << 8) | byte as usize);
+        (len, &rest[count..])
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+
+    Some((tag, &rest[..len], &rest[len..]))
+}
+
+/// UTCTime (YYMMDDHHMMSSZ) or GeneralizedTime (YYYYMMDDHHMMSSZ), both always in UTC
+/// and without fractions of a second in certificates.
+fn parse_time(tag: u8, time: &[u8]) -> Option<SystemTime> {
+    let time = std::str::from_utf8(time).ok()?.strip_suffix('Z')?;
+    if !time.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    let (year, rest) = match tag {
+        UTC_TIME => {
+            let year: u64 = time.get(..2)?.parse().ok()?;
+            // RFC 5280 puts two-digit years between 1950 and 2049
+            let year = if year >= 50 { 1900 + year } else { 2000 + year };
+            (year, &time[2..])
+        }
+        GENERALIZED_TIME => (time.get(..4)?.parse().ok()?, &time[4..]),
+        _ => return None,
+    };
+    if rest.len() != 10 {
+        return None;
+    }
+
+    let field = |index: usize| {
+        rest[index * 2..index * 2 + 2]
+            .parse::<u64>()
+            .unwrap_or_default()
+    };
+    let (month, day) = (field(0), field(1));
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day)?;
+    let seconds = days * 86400 + field(2) * 3600 + field(3) * 60 + field(4);
+
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// Days from 1970-01-01 to a date of the proleptic Gregorian calendar, None before it.
+/// From http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    (era * 146097 + day_of_era).checked_sub(719468)
+}
+
+#[cfg(test)]
+mod test {
+    mod not_after {
+        use crate::certificate::{chain_expiry, parse_time, GENERALIZED_TIME, UTC_TIME};
+        use std::time::UNIX_EPOCH;
+
+        #[test]
+        fn reads_end_of_validity() {
+            assert_eq!(
+                chain_expiry("test_files/keys/server.crt"),
+                httpdate::parse_http_date("Thu, 06 Jul 2023 18:36:10 GMT").ok()
+            );
+        }
+
+        #[test]
+        fn parses_both_time_formats() {
+            assert_eq!(
+                parse_time(UTC_TIME, b"000301000000Z"),
+                Some(UNIX_EPOCH + std::time::Duration::from_secs(11017 * 86400))
+            );
+            assert_eq!(
+                parse_time(GENERALIZED_TIME, b"20491231235959Z"),
+                httpdate::parse_http_date("Fri, 31 Dec 2049 23:59:59 GMT").ok()
+            );
+            assert_eq!(parse_time(UTC_TIME, b"700101000000Z"), Some(UNIX_EPOCH));
+            assert_eq!(parse_time(UTC_TIME, b"991301000000Z"), None);
+            assert_eq!(parse_time(UTC_TIME, b"990101000000+0100"), None);
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::error::Error;
+use crate::response_status_code::ResponseStatusCode;
+use crate::server_config::{ServerConfig, ServerConfigBuilder};
+use crate::static_response::{StaticResponse, StaticResponseBody};
+use log::LevelFilter;
+use std::str::FromStr;
+
+pub const USAGE: &str = "Usage: http_rs [options]
+
+Options:
+  --config <file>      Read settings from a config file, options given here take precedence
+  --port <port>        Port of the plain HTTP listener
+  --root <dir>         Directory files are served from
+  --rules <file>       Rules file
+  --cert <file>        Certificate chain in PEM, enables HTTPS together with --key
+  --key <file>         Private key in PEM
+  --log-level <level>  off, error, warn, info, debug or trace
+  --check-rules        Parse the rules file, print its warnings and exit
+  --dump-config        Print the effective settings in the config file format and exit
+  --help               Print this message
+
+The config file has one `key = value` setting per line, keys are: root, bind_address,
+port, https, https_port, https_bind_address, reuse_addr, reuse_port, backlog, tcp_nodelay,
+cert_path, key_path, rules_path, rules_cache, serve_manifest, metrics_path,
+serve_precompressed and static_writes. Lines starting with # are comments.
+
+Static responses are set with `static_response.\"<path>\".<field>` keys, fields are status,
+headers.<name>, body and file, e.g. `static_response.\"/healthz\".body = \"ok\"`.";
+
+/// Command line of the server binary.
+#[derive(Debug, Default, PartialEq)]
+pub struct Cli {
+    pub config: Option<String>,
+    pub port: Option<u32>,
+    pub root: Option<String>,
+    pub rules: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+    pub log_level: Option<LevelFilter>,
+    pub check_rules: bool,
+    pub dump_config: bool,
+    pub help: bool,
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> crate::Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid value \"{value}\" for {name}")))
+}
+
+impl Cli {
+    /// `args` without the program name.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> crate::Result<Self> {
+        let mut cli = Cli::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--check-rules" => cli.check_rules = true,
+                "--dump-config" => cli.dump_config = true,
+                "--help" | "-h" => cli.help = true,
+                "--config" | "--port" | "--root" | "--rules" | "--cert" | "--key"
+                | "--log-level" => {
+                    let Some(value) = args.next() else {
+                        return Err(Error::Config(format!("Missing value for {arg}")));
+                    };
+
+                    match arg.as_str() {
+                        "--config" => cli.config = Some(value),
+                        "--port" => cli.port = Some(parse_value(&arg, &value)?),
+                        "--root" => cli.root = Some(value),
+                        "--rules" => cli.rules = Some(value),
+                        "--cert" => cli.cert = Some(value),
+                        "--key" => cli.key = Some(value),
+                        "--log-level" => cli.log_level = Some(parse_value(&arg, &value)?),
+                        _ => unreachable!(),
+                    }
+                }
+                _ => return Err(Error::Config(format!("Unknown option {arg}"))),
+            }
+        }
+
+        Ok(cli)
+    }
+
+    /// Defaults, overridden by the config file, overridden by the options.
+    pub fn server_config(&self) -> crate::Result<ServerConfig> {
+        let mut builder = ServerConfigBuilder::new();
+
+        if let Some(path) = &self.config {
+            builder = apply_config_file(builder, &std::fs::read_to_string(path)?)?;
+        }
+        if let Some(port) = self.port {
+            builder = builder.port(port);
+        }
+        if let Some(root) = &self.root {
+            builder = builder.root(root);
+        }
+        if let Some(rules) = &self.rules {
+            builder = builder.rules_path(rules);
+        }
+        if let Some(cert) = &self.cert {
+            builder = builder.cert_path(cert);
+        }
+        if let Some(key) = &self.key {
+            builder = builder.key_path(key);
+        }
+        if self.cert.is_some() && self.key.is_some() {
+            builder = builder.https(true);
+        }
+
+        Ok(builder.get())
+    }
+}
+
+/// `static_response."<path>".<field>`, the path is quoted as it has slashes and dots.
+fn static_response_key(key: &str) -> Option<(&str, &str)> {
+    let (path, field) = key.strip_prefix("static_response.\"")?.split_once('"')?;
+
+    Some((path, field.strip_prefix('.')?))
+}
+
+fn apply_config_file(
+    mut builder: ServerConfigBuilder,
+    contents: &str,
+) -> crate::Result<ServerConfigBuilder> {
+    let mut static_responses: Vec<StaticResponse> = vec![];
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(Error::Config(format!(
+                "Expected key = value on line {}",
+                index + 1
+            )));
+        };
+        let key = key.trim();
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .unwrap_or(value);
+
+        if let Some((path, field)) = static_response_key(key) {
+            let position = static_responses
+                .iter()
+                .position(|static_response| static_response.path == path);
+            let static_response = match position {
+                Some(position) => &mut static_responses[position],
+                None => {
+                    static_responses.push(StaticResponse::new(path));
+                    static_responses.last_mut().unwrap()
+                }
+            };
+
+            match field.split_once('.') {
+                None if field == "status" => {
+                    static_response.status_code =
+                        ResponseStatusCode::try_from(parse_value::<u16>(key, value)?)
+                            .map_err(Error::Config)?;
+                }
+                None if field == "body" => {
+                    static_response.body = Some(StaticResponseBody::Inline(value.to_string()));
+                }
+                None if field == "file" => {
+                    static_response.body = Some(StaticResponseBody::File(value.to_string()));
+                }
+                Some(("headers", name)) => {
+                    static_response
+                        .headers
+                        .push((name.to_string(), value.to_string()));
+                }
+                _ => {
+                    return Err(Error::Config(format!(
+                        "Unknown key \"{key}\" on line {}",
+                        index + 1
+                    )))
+                }
+            }
+            continue;
+        }
+
+        builder = match key {
+            "root" => builder.root(value),
+            "bind_address" => builder.bind_address(value),
+            "port" => builder.port(parse_value(key, value)?),
+            "https" => builder.https(parse_value(key, value)?),
+            "https_port" => builder.https_port(parse_value(key, value)?),
+            "https_bind_address" => builder.https_bind_address(value),
+            "reuse_addr" => builder.reuse_addr(parse_value(key, value)?),
+            "reuse_port" => builder.reuse_port(parse_value(key, value)?),
+            "backlog" => builder.backlog(parse_value(key, value)?),
+            "tcp_nodelay" => builder.tcp_nodelay(parse_value(key, value)?),
+            "cert_path" => builder.cert_path(value),
+            "key_path" => builder.key_path(value),
+            "rules_path" => builder.rules_path(value),
+            "rules_cache" if parse_value(key, value)? => builder.rules_cache(),
+            "rules_cache" => builder,
+            "serve_manifest" => builder.serve_manifest(parse_value(key, value)?),
+            "metrics_path" => builder.metrics_path(value),
+            "serve_precompressed" => builder.serve_precompressed(parse_value(key, value)?),
+            "static_writes" => builder.static_writes(parse_value(key, value)?),
+            _ => {
+                return Err(Error::Config(format!(
+                    "Unknown key \"{key}\" on line {}",
+                    index + 1
+                )))
+            }
+        };
+    }
+
+    Ok(static_responses
+        .into_iter()
+        .fold(builder, |builder, static_response| {
+            builder.static_response(static_response)
+        }))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cli::{apply_config_file, Cli};
+    use crate::response_status_code::ResponseStatusCode;
+    use crate::server_config::ServerConfigBuilder;
+    use crate::static_response::StaticResponse;
+    use log::LevelFilter;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_options() {
+        let cli = Cli::parse(args(&[
+            "--port",
+            "8080",
+            "--root",
+            "public",
+            "--log-level",
+            "debug",
+            "--check-rules",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            cli,
+            Cli {
+                port: Some(8080),
+                root: Some("public".to_string()),
+                log_level: Some(LevelFilter::Debug),
+                check_rules: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_options_and_missing_values() {
+        assert!(Cli::parse(args(&["--verbose"])).is_err());
+        assert!(Cli::parse(args(&["--port"])).is_err());
+        assert!(Cli::parse(args(&["--port", "http"])).is_err());
+    }
+
+    #[test]
+    fn reads_config_file() {
+        let contents = "
+            # served over TLS
+            root = \"public\"
+            https = true
+            https_port = 8443
+            backlog = 1024
+        ";
+        let config = apply_config_file(ServerConfigBuilder::new(), contents)
+            .unwrap()
+            .get();
+
+        assert_eq!(config.root, "public");
+        assert!(config.https);
+        assert_eq!(config.https_port, 8443);
+        assert_eq!(config.backlog, 1024);
+        assert!(apply_config_file(ServerConfigBuilder::new(), "colour = red").is_err());
+    }
+
+    #[test]
+    fn reads_static_responses() {
+        let contents = "
+            static_response.\"/healthz\".body = \"ok\"
+            static_response.\"/api/v1.0/stub\".status = 201
+            static_response.\"/api/v1.0/stub\".headers.Content-Type = \"application/json\"
+            static_response.\"/api/v1.0/stub\".file = \"stubs/created.json\"
+        ";
+        let config = apply_config_file(ServerConfigBuilder::new(), contents)
+            .unwrap()
+            .get();
+
+        assert_eq!(
+            config.static_responses,
+            vec![
+                StaticResponse::new("/healthz").body("ok"),
+                StaticResponse::new("/api/v1.0/stub")
+                    .status_code(ResponseStatusCode::Created)
+                    .header("Content-Type", "application/json")
+                    .file("stubs/created.json"),
+            ]
+        );
+        for contents in [
+            "static_response.\"/healthz\".colour = \"red\"",
+            "static_response.\"/healthz\".status = 299",
+            "static_response./healthz.body = \"ok\"",
+        ] {
+            assert!(apply_config_file(ServerConfigBuilder::new(), contents).is_err());
+        }
+    }
+
+    #[test]
+    fn dumped_config_reads_back_the_same() {
+        let config = ServerConfigBuilder::new()
+            .root("public")
+            .port(8080)
+            .cert_path("cert.pem")
+            .metrics_path("/metrics")
+            .rules_cache()
+            .static_response(
+                StaticResponse::new("/healthz")
+                    .status_code(ResponseStatusCode::Accepted)
+                    .header("Cache-Control", "no-store")
+                    .body("ok"),
+            )
+            .get();
+        let dump = config.dump();
+
+        assert!(dump.contains("root = \"public\""));
+        assert!(dump.contains("# key_path =\n"));
+        assert_eq!(
+            apply_config_file(ServerConfigBuilder::new(), &dump)
+                .unwrap()
+                .get()
+                .schema(),
+            config.schema()
+        );
+    }
+
+    #[test]
+    fn options_take_precedence_over_config_file() {
+        let path = std::env::temp_dir().join(format!("http_rs_cli_{}.conf", std::process::id()));
+        std::fs::write(&path, "port = 8080\nroot = public").unwrap();
+
+        let cli = Cli::parse(args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--port",
+            "9090",
+        ]))
+        .unwrap();
+        let config = cli.server_config().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.port, 9090);
+        assert_eq!(config.root, "public");
+    }
+}
@@ -0,0 +1,689 @@
+use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+use crate::request::parse_chunked_body;
+#[cfg(feature = "https")]
+use crate::server_config::load_certs;
+use crate::types::IoResult;
+use log::debug;
+#[cfg(feature = "https")]
+use log::warn;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "https")]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "https")]
+type TlsConfig = Arc<rustls::ClientConfig>;
+// https upstreams are rejected up front without TLS support
+#[cfg(not(feature = "https"))]
+type TlsConfig = std::convert::Infallible;
+
+/// Response of an upstream server, with the body already de-chunked.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct UpstreamResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl UpstreamResponse {
+    pub(crate) fn header(&self, header_name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+enum UpstreamStream {
+    Plain(TcpStream),
+    #[cfg(feature = "https")]
+    Tls(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+}
+
+impl UpstreamStream {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            UpstreamStream::Plain(stream) => stream,
+            #[cfg(feature = "https")]
+            UpstreamStream::Tls(stream) => stream.get_ref(),
+        }
+    }
+
+    // an idle connection has nothing to read, unless the upstream has closed it
+    fn is_idle(&self) -> bool {
+        let tcp = self.tcp();
+        if tcp.set_nonblocking(true).is_err() {
+            return false;
+        }
+
+        let is_idle =
+            matches!(tcp.peek(&mut [0u8; 1]), Err(err) if err.kind() == ErrorKind::WouldBlock);
+
+        tcp.set_nonblocking(false).is_ok() && is_idle
+    }
+}
+
+impl Read for UpstreamStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        match self {
+            UpstreamStream::Plain(stream) => stream.read(buf),
+            #[cfg(feature = "https")]
+            UpstreamStream::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for UpstreamStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        match self {
+            UpstreamStream::Plain(stream) => stream.write(buf),
+            #[cfg(feature = "https")]
+            UpstreamStream::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        match self {
+            UpstreamStream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "https")]
+            UpstreamStream::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// How the certificate of an https upstream is checked.
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(not(feature = "https"), allow(dead_code))]
+pub(crate) struct TlsOptions {
+    /// Trusted certificates, there are no built-in roots
+    pub(crate) ca_certs_path: Option<String>,
+    /// Sent as SNI and checked against the certificate instead of the host
+    pub(crate) server_name: Option<String>,
+    /// Any certificate is accepted
+    pub(crate) skip_verify: bool,
+}
+
+/// Server that requests get sent to, over plain TCP or rustls.
+/// Connections are kept alive for later requests as far as the pool allows.
+pub(crate) struct Upstream {
+    // host and port, as given in the url
+    authority: String,
+    tls_config: Option<TlsConfig>,
+    server_name: Option<String>,
+    pool: ConnectionPool<UpstreamStream>,
+}
+
+impl Upstream {
+    /// Fails if an https url has no trusted certificates and does not skip verification,
+    /// like the server does for its own certificate.
+    pub(crate) fn new(
+        url: &str,
+        tls: &TlsOptions,
+        pool: ConnectionPoolConfig,
+    ) -> crate::Result<Self> {
+        let url = url.trim_end_matches('/');
+        let (https, authority) = match url.split_once("://") {
+            Some(("https", authority)) => (true, authority.to_string()),
+            Some((_, authority)) => (false, authority.to_string()),
+            None => (false, url.to_string()),
+        };
+
+        let tls_config = https.then(|| tls_config(tls)).transpose()?;
+
+        Ok(Upstream {
+            authority,
+            tls_config,
+            server_name: tls.server_name.clone(),
+            pool: ConnectionPool::new(pool),
+        })
+    }
+
+    pub(crate) fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    /// False if requests should ask the upstream to close the connection after the response.
+    pub(crate) fn keeps_alive(&self) -> bool {
+        self.pool.is_enabled()
+    }
+
+    /// Sends a complete request to `host`, which is the authority unless the
+    /// upstream is addressed by another name. Responses to HEAD have no body.
+    pub(crate) fn send(
+        &self,
+        host: &str,
+        request: &[u8],
+        is_head: bool,
+        timeout: Duration,
+    ) -> IoResult<UpstreamResponse> {
+        while let Some(stream) = self.pool.take(host) {
+            if !stream.is_idle() {
+                continue;
+            }
+
+            match self.exchange(host, stream, request, is_head, timeout) {
+                Ok(response) => return Ok(response),
+                // the upstream may have closed it just as it was taken, which it is
+                // allowed to do, but only requests that can be repeated are retried
+                Err(err) if !is_idempotent(request) || is_timeout(&err) => return Err(err),
+                Err(err) => debug!("Retrying on a new connection to {host}: {err}"),
+            }
+        }
+
+        let stream = self.connect(host, timeout)?;
+        self.exchange(host, stream, request, is_head, timeout)
+    }
+
+    /// Tries the addresses `host` resolves to in turn, giving each of them `timeout`.
+    fn connect(&self, host: &str, timeout: Duration) -> IoResult<UpstreamStream> {
+        let address = if host.contains(':') {
+            host.to_string()
+        } else if self.tls_config.is_some() {
+            format!("{host}:443")
+        } else {
+            format!("{host}:80")
+        };
+        let mut last_err = std::io::Error::from(ErrorKind::AddrNotAvailable);
+        let mut stream = None;
+        for address in address.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(connected) => {
+                    stream = Some(connected);
+                    break;
+                }
+                Err(err) => last_err = err,
+            }
+        }
+        let stream = stream.ok_or(last_err)?;
+
+        match &self.tls_config {
+            Some(tls_config) => {
+                let server_name = match &self.server_name {
+                    Some(server_name) => server_name,
+                    None => host.split(':').next().unwrap_or(host),
+                };
+
+                tls_stream(tls_config, server_name, stream)
+            }
+            None => Ok(UpstreamStream::Plain(stream)),
+        }
+    }
+
+    /// Writes the request and reads the response, keeping the connection if it is still
+    /// usable afterwards.
+    fn exchange(
+        &self,
+        host: &str,
+        mut stream: UpstreamStream,
+        request: &[u8],
+        is_head: bool,
+        timeout: Duration,
+    ) -> IoResult<UpstreamResponse> {
+        stream.tcp().set_read_timeout(Some(timeout))?;
+        stream.tcp().set_write_timeout(Some(timeout))?;
+        stream.write_all(request)?;
+        stream.flush()?;
+
+        let (bytes, keep_alive) = read_response(&mut stream, is_head)?;
+        if keep_alive {
+            self.pool.put(host, stream);
+        }
+
+        parse_response(bytes, is_head)
+    }
+}
+
+#[cfg(feature = "https")]
+fn tls_config(tls: &TlsOptions) -> crate::Result<TlsConfig> {
+    if tls.skip_verify {
+        warn!("Certificates of https upstreams are not verified");
+
+        return Ok(Arc::new(
+            rustls::ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(Arc::new(NoVerification))
+                .with_no_client_auth(),
+        ));
+    }
+
+    let mut roots = rustls::RootCertStore::empty();
+    let Some(ca_certs_path) = tls.ca_certs_path.as_deref() else {
+        return Err(crate::Error::Config(
+            "https upstream needs trusted certificates".to_string(),
+        ));
+    };
+
+    for cert in load_certs(ca_certs_path)? {
+        roots.add(&cert)?;
+    }
+
+    Ok(Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    ))
+}
+
+// for internal backends with self-signed certificates, the handshake signature is still checked
+#[cfg(feature = "https")]
+struct NoVerification;
+
+#[cfg(feature = "https")]
+impl rustls::client::ServerCertVerifier for NoVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(not(feature = "https"))]
+fn tls_config(_tls: &TlsOptions) -> crate::Result<TlsConfig> {
+    Err(crate::Error::Config(
+        "https upstream needs the https feature".to_string(),
+    ))
+}
+
+#[cfg(feature = "https")]
+fn tls_stream(
+    tls_config: &TlsConfig,
+    server_name: &str,
+    stream: TcpStream,
+) -> IoResult<UpstreamStream> {
+    let server_name = rustls::ServerName::try_from(server_name)
+        .map_err(|_| std::io::Error::from(ErrorKind::InvalidInput))?;
+    let connection = rustls::ClientConnection::new(tls_config.clone(), server_name)
+        .map_err(std::io::Error::other)?;
+
+    Ok(UpstreamStream::Tls(Box::new(rustls::StreamOwned::new(
+        connection, stream,
+    ))))
+}
+
+#[cfg(not(feature = "https"))]
+fn tls_stream(
+    tls_config: &TlsConfig,
+    _server_name: &str,
+    _stream: TcpStream,
+) -> IoResult<UpstreamStream> {
+    match *tls_config {}
+}
+
+// RFC 9110, section 9.2.2
+fn is_idempotent(request: &[u8]) -> bool {
+    let method = request
+        .split(|byte| *byte == b' ')
+        .next()
+        .unwrap_or_default();
+
+    [
+        &b"GET"[..],
+        b"HEAD",
+        b"PUT",
+        b"DELETE",
+        b"OPTIONS",
+        b"TRACE",
+    ]
+    .contains(&method)
+}
+
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock)
+}
+
+/// Reads one response, along with whether the connection can take another request after it.
+fn read_response(stream: &mut impl Read, is_head: bool) -> IoResult<(Vec<u8>, bool)> {
+    let mut bytes = vec![];
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        if is_complete(&bytes, is_head) {
+            let keep_alive = is_persistent(&bytes);
+            return Ok((bytes, keep_alive));
+        }
+
+        let len = match stream.read(&mut buffer) {
+            // some servers close without close_notify once the body is sent
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => 0,
+            result => result?,
+        };
+        if len == 0 {
+            return Ok((bytes, false));
+        }
+
+        bytes.extend_from_slice(&buffer[..len]);
+    }
+}
+
+/// True once `bytes` hold a whole response. Without Content-Length or chunked encoding,
+/// the body only ends when the upstream closes the connection.
+fn is_complete(bytes: &[u8], is_head: bool) -> bool {
+    let Ok((response, body_start)) = parse_head(bytes) else {
+        return false;
+    };
+    let body = &bytes[body_start..];
+
+    if !has_body(&response, is_head) {
+        true
+    } else if is_chunked(&response) {
+        body.ends_with(b"\r\n\r\n")
+            && parse_chunked_body(body.to_vec()).is_ok_and(|(_, is_complete)| is_complete)
+    } else {
+        content_length(&response).is_some_and(|len| body.len() >= len)
+    }
+}
+
+// HTTP/1.0 connections are closed by default
+fn is_persistent(bytes: &[u8]) -> bool {
+    let Ok((response, _)) = parse_head(bytes) else {
+        return false;
+    };
+
+    bytes.starts_with(b"HTTP/1.1 ")
+        && !response.header("Connection").is_some_and(|value| {
+            value
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+        })
+}
+
+/// The response without its body, and where the body starts.
+fn parse_head(bytes: &[u8]) -> IoResult<(UpstreamResponse, usize)> {
+    let invalid = || std::io::Error::from(ErrorKind::InvalidData);
+    let head_len = bytes
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(invalid)?;
+    let head = std::str::from_utf8(&bytes[..head_len]).map_err(|_| invalid())?;
+    let mut lines = head.split("\r\n");
+
+    let status = lines
+        .next()
+        .and_then(|status_line| status_line.split(' ').nth(1))
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(invalid)?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+        .collect();
+
+    let response = UpstreamResponse {
+        status,
+        headers,
+        body: vec![],
+    };
+
+    Ok((response, head_len + 4))
+}
+
+fn parse_response(bytes: Vec<u8>, is_head: bool) -> IoResult<UpstreamResponse> {
+    let (mut response, body_start) = parse_head(&bytes)?;
+
+    if !has_body(&response, is_head) {
+        return Ok(response);
+    }
+
+    let mut body = bytes[body_start..].to_vec();
+
+    if is_chunked(&response) {
+        body = parse_chunked_body(body)
+            .map_err(|_| std::io::Error::from(ErrorKind::InvalidData))?
+            .0;
+    } else if let Some(len) = content_length(&response) {
+        if body.len() < len {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        body.truncate(len);
+    }
+
+    response.body = body;
+
+    Ok(response)
+}
+
+fn has_body(response: &UpstreamResponse, is_head: bool) -> bool {
+    !is_head && response.status >= 200 && response.status != 204 && response.status != 304
+}
+
+fn is_chunked(response: &UpstreamResponse) -> bool {
+    response
+        .header("Transfer-Encoding")
+        .is_some_and(|value| value.eq_ignore_ascii_case("chunked"))
+}
+
+fn content_length(response: &UpstreamResponse) -> Option<usize> {
+    response
+        .header("Content-Length")
+        .and_then(|value| value.parse::<usize>().ok())
+}
+
+#[cfg(test)]
+mod test {
+    mod new {
+        use crate::client::{TlsOptions, Upstream};
+        use crate::connection_pool::ConnectionPoolConfig;
+        use crate::Error;
+
+        #[cfg(not(feature = "https"))]
+        #[test]
+        fn err_without_https_feature() {
+            let tls = TlsOptions {
+                skip_verify: true,
+                ..TlsOptions::default()
+            };
+            let result = Upstream::new("https://10.0.0.1", &tls, ConnectionPoolConfig::new());
+
+            assert!(matches!(result, Err(Error::Config(_))));
+        }
+
+        #[cfg(feature = "https")]
+        #[test]
+        fn err_without_trusted_certificates() {
+            let result = Upstream::new(
+                "https://10.0.0.1",
+                &TlsOptions::default(),
+                ConnectionPoolConfig::new(),
+            );
+
+            assert!(matches!(result, Err(Error::Config(_))));
+        }
+
+        #[cfg(feature = "https")]
+        #[test]
+        fn err_with_unreadable_trusted_certificates() {
+            let tls = TlsOptions {
+                ca_certs_path: Some("./test_files/keys/missing.crt".to_string()),
+                ..TlsOptions::default()
+            };
+            let result = Upstream::new("https://10.0.0.1", &tls, ConnectionPoolConfig::new());
+
+            assert!(matches!(result, Err(Error::Config(_))));
+        }
+
+        #[cfg(feature = "https")]
+        #[test]
+        fn skipping_verification_needs_no_certificates() {
+            let tls = TlsOptions {
+                server_name: Some("backend.internal".to_string()),
+                skip_verify: true,
+                ..TlsOptions::default()
+            };
+            let upstream =
+                Upstream::new("https://10.0.0.1:8443", &tls, ConnectionPoolConfig::new()).unwrap();
+
+            assert!(upstream.tls_config.is_some());
+            assert_eq!(upstream.server_name.as_deref(), Some("backend.internal"));
+        }
+    }
+
+    mod send {
+        use crate::client::{TlsOptions, Upstream};
+        use crate::connection_pool::ConnectionPoolConfig;
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::thread::JoinHandle;
+        use std::time::Duration;
+
+        const REQUEST: &[u8] = b"GET / HTTP/1.1\r\nHost: upstream\r\n\r\n";
+
+        // answers each request with Ok, closing after `per_connection` of them;
+        // true if no more connections than expected were opened
+        fn upstream(connections: usize, per_connection: usize) -> (Upstream, JoinHandle<bool>) {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let upstream = Upstream::new(
+                &format!("http://{}", listener.local_addr().unwrap()),
+                &TlsOptions::default(),
+                ConnectionPoolConfig::new(),
+            )
+            .unwrap();
+            let handle = std::thread::spawn(move || {
+                for _ in 0..connections {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    for _ in 0..per_connection {
+                        let _ = stream.read(&mut [0u8; 4096]).unwrap();
+                        stream
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOk")
+                            .unwrap();
+                    }
+                }
+
+                listener.set_nonblocking(true).unwrap();
+                std::thread::sleep(Duration::from_millis(100));
+                listener.accept().is_err()
+            });
+
+            (upstream, handle)
+        }
+
+        fn send(upstream: &Upstream) -> Vec<u8> {
+            upstream
+                .send(upstream.authority(), REQUEST, false, Duration::from_secs(1))
+                .unwrap()
+                .body
+        }
+
+        #[test]
+        fn reuses_kept_alive_connection() {
+            let (upstream, handle) = upstream(1, 3);
+
+            for _ in 0..3 {
+                assert_eq!(send(&upstream), b"Ok");
+            }
+            assert!(handle.join().unwrap());
+        }
+
+        #[test]
+        fn replaces_connection_closed_by_upstream() {
+            let (upstream, handle) = upstream(2, 1);
+
+            assert_eq!(send(&upstream), b"Ok");
+            // closing takes a moment to be noticed
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(send(&upstream), b"Ok");
+            assert!(handle.join().unwrap());
+        }
+
+        #[test]
+        fn closes_connection_if_asked_to() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = listener.local_addr().unwrap();
+            let upstream = Upstream::new(
+                &format!("http://{address}"),
+                &TlsOptions::default(),
+                ConnectionPoolConfig::new(),
+            )
+            .unwrap();
+            let handle = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0u8; 4096]).unwrap();
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 2\r\n\r\nOk",
+                    )
+                    .unwrap();
+                stream
+            });
+
+            assert_eq!(send(&upstream), b"Ok");
+            let _stream: TcpStream = handle.join().unwrap();
+            assert!(upstream.pool.take(upstream.authority()).is_none());
+        }
+
+        #[test]
+        fn gives_up_connecting_after_timeout() {
+            // not routable, so the handshake is never answered
+            let upstream = Upstream::new(
+                "http://10.255.255.1:81",
+                &TlsOptions::default(),
+                ConnectionPoolConfig::new(),
+            )
+            .unwrap();
+            let started = std::time::Instant::now();
+
+            let result = upstream.send(
+                upstream.authority(),
+                REQUEST,
+                false,
+                Duration::from_millis(200),
+            );
+
+            assert!(result.is_err());
+            assert!(started.elapsed() < Duration::from_secs(2));
+        }
+    }
+
+    mod parse_response {
+        use crate::client::parse_response;
+        use std::io::ErrorKind;
+
+        #[test]
+        fn body_by_content_length() {
+            let response = parse_response(
+                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOk and more".to_vec(),
+                false,
+            )
+            .unwrap();
+
+            assert_eq!(response.status, 200);
+            assert_eq!(response.header("content-length"), Some("2"));
+            assert_eq!(response.body, b"Ok");
+        }
+
+        #[test]
+        fn chunked_body() {
+            let response = parse_response(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nOk\r\n0\r\n\r\n"
+                    .to_vec(),
+                false,
+            )
+            .unwrap();
+
+            assert_eq!(response.body, b"Ok");
+        }
+
+        #[test]
+        fn no_body_for_head_and_not_modified() {
+            let head = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n".to_vec();
+            let not_modified = b"HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n".to_vec();
+
+            assert!(parse_response(head, true).unwrap().body.is_empty());
+            assert!(parse_response(not_modified, false).unwrap().body.is_empty());
+        }
+
+        #[test]
+        fn err_if_body_is_cut_short() {
+            assert!(matches!(
+                parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nOk".to_vec(), false),
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof
+            ));
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of the current time for deadlines, queue timeouts, cache expiry and Date headers,
+/// replaceable with `Server::clock`.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Wall clock time, only used for what gets sent to clients
+    fn system_time(&self) -> SystemTime;
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Clock that only moves when advanced, so timeouts can be tested without sleeping.
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    start_system_time: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::at(SystemTime::now())
+    }
+
+    /// Starts at a fixed wall clock time, for stable Date headers.
+    pub fn at(system_time: SystemTime) -> Self {
+        ManualClock {
+            start: Instant::now(),
+            start_system_time: system_time,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.start + *self.elapsed.lock().unwrap()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_system_time + *self.elapsed.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clock::{Clock, ManualClock};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn manual_clock_moves_only_when_advanced() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        assert_eq!(clock.now(), start);
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn manual_clock_system_time_follows_advance() {
+        let clock = ManualClock::at(UNIX_EPOCH);
+        clock.advance(Duration::from_secs(60));
+
+        assert_eq!(clock.system_time(), UNIX_EPOCH + Duration::from_secs(60));
+    }
+}
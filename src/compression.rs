@@ -0,0 +1,407 @@
+use crate::content_source::Content;
+use crate::header::names;
+use crate::response::Response;
+use crate::response_status_code::ResponseStatusCode;
+use crate::server::content_type_specificity;
+use crate::types::IoResult;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::xxh3_64;
+
+// names temporary files apart when several threads compress the same file
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// formats that are compressed already, like images and archives, are left out
+static DEFAULT_CONTENT_TYPES: [&str; 10] = [
+    "text/*",
+    "application/javascript",
+    "application/json",
+    "application/manifest+json",
+    "application/wasm",
+    "application/xml",
+    "application/atom+xml",
+    "application/rss+xml",
+    "image/svg+xml",
+    "font/ttf",
+];
+
+/// Gzip for clients that accept it, see `ServerConfig::compression`.
+/// Only 200 responses with a body the server holds, in memory or as a static file, are compressed,
+/// and never ones that already have a Content-Encoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressionConfig {
+    /// 0 (fastest) to 9 (smallest)
+    pub level: u32,
+    /// Smaller bodies are sent as they are, gzip would save little or even add bytes
+    pub min_size: u64,
+    /// Content types worth compressing, `text/*` covers all text.
+    /// Responses without a Content-Type are compressed too
+    pub content_types: Vec<String>,
+    /// Compressed variants of static files are kept here, keyed by path, modification time
+    /// and encoding, so repeat requests do not compress the same file again. Without it,
+    /// static files are compressed on every request, and those sent from disk not at all
+    pub cache_dir: Option<String>,
+}
+
+impl CompressionConfig {
+    pub fn new() -> Self {
+        CompressionConfig {
+            level: 6,
+            min_size: 1024,
+            content_types: DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|content_type| content_type.to_string())
+                .collect(),
+            cache_dir: None,
+        }
+    }
+
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+
+        self
+    }
+
+    pub fn min_size(mut self, min_size: u64) -> Self {
+        self.min_size = min_size;
+
+        self
+    }
+
+    /// Replaces the default content types.
+    pub fn content_types(mut self, content_types: &[&str]) -> Self {
+        self.content_types = content_types.iter().map(|v| v.to_string()).collect();
+
+        self
+    }
+
+    pub fn cache_dir(mut self, cache_dir: &str) -> Self {
+        self.cache_dir = Some(cache_dir.to_string());
+
+        self
+    }
+
+    /// True for responses worth compressing, once it is known that the client accepts gzip.
+    pub(crate) fn applies_to(&self, response: &Response) -> bool {
+        if *response.status_code() != ResponseStatusCode::Ok
+            || response.has_header(names::CONTENT_ENCODING)
+            || response.body_reader().is_some()
+        {
+            return false;
+        }
+
+        let len = response.response_body().len().unwrap_or_default();
+        if len == 0 || len < self.min_size {
+            return false;
+        }
+
+        response
+            .get_header(names::CONTENT_TYPE)
+            .is_none_or(|content_type| {
+                self.content_types
+                    .iter()
+                    .any(|pattern| content_type_specificity(pattern, content_type).is_some())
+            })
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        CompressionConfig::new()
+    }
+}
+
+/// True if an Accept-Encoding value allows gzip, by name or through "*".
+pub(crate) fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    accepts_coding(accept_encoding, &["gzip", "x-gzip"])
+}
+
+/// True if an Accept-Encoding value allows br, by name or through "*".
+pub(crate) fn accepts_brotli(accept_encoding: Option<&str>) -> bool {
+    accepts_coding(accept_encoding, &["br"])
+}
+
+fn accepts_coding(accept_encoding: Option<&str>, coding_names: &[&str]) -> bool {
+    let Some(accept_encoding) = accept_encoding else {
+        return false;
+    };
+    let mut coding_q = None;
+    let mut any_q = None;
+
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';').map(|part| part.trim());
+        let coding = parts.next().unwrap_or_default();
+        let q = parts
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+            .map_or(1.0, |(_, value)| value.trim().parse::<f32>().unwrap_or(0.0));
+
+        if coding_names
+            .iter()
+            .any(|name| coding.eq_ignore_ascii_case(name))
+        {
+            coding_q = Some(q);
+        } else if coding == "*" {
+            any_q = Some(q);
+        }
+    }
+
+    coding_q.or(any_q).is_some_and(|q| q > 0.0)
+}
+
+pub(crate) fn gzip(bytes: &[u8], level: u32) -> IoResult<Vec<u8>> {
+    let mut encoder = GzEncoder::new(vec![], Compression::new(level));
+    encoder.write_all(bytes)?;
+
+    encoder.finish()
+}
+
+/// Marks `response` as encoded with `coding`, its body is up to the caller. The ETag gets
+/// a suffix, so the encoded and the plain representation can be told apart.
+pub(crate) fn set_content_encoding(response: &mut Response, coding: &str) {
+    response.set_header(names::CONTENT_ENCODING, coding);
+
+    if let Some(etag) = response.get_header(names::ETAG) {
+        if let Some(opaque) = etag.strip_suffix('"') {
+            let etag = format!("{opaque}-{coding}\"");
+            response.remove_header(names::ETAG);
+            response.set_header(names::ETAG, &etag);
+        }
+    }
+}
+
+/// Gzipped variants of static files on disk, one per version of a file.
+/// Variants of older versions are not removed.
+pub(crate) struct CompressionCache {
+    dir: PathBuf,
+    level: u32,
+}
+
+impl CompressionCache {
+    pub(crate) fn new(dir: &str, level: u32) -> Self {
+        CompressionCache {
+            dir: PathBuf::from(dir),
+            level,
+        }
+    }
+
+    /// Gzipped `content` with its length, compressed only if the cache has no variant
+    /// of this version yet. None for content without a modification time, its versions
+    /// cannot be told apart.
+    pub(crate) fn get_or_compress(&self, content: &Content) -> IoResult<Option<(Arc<File>, u64)>> {
+        let Some(modified) = content.modified else {
+            return Ok(None);
+        };
+        let modified_nanos = modified
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        let key = xxh3_64(
+            format!(
+                "{}\0{modified_nanos}\0{}\0gzip",
+                content.path.display(),
+                content.len
+            )
+            .as_bytes(),
+        );
+        let path = self.dir.join(format!("{key:016x}.gz"));
+
+        match File::open(&path) {
+            Ok(file) => {
+                let len = file.metadata()?.len();
+                return Ok(Some((Arc::new(file), len)));
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let bytes = gzip(&content.read()?, self.level)?;
+
+        // written aside and renamed, so no request ever sees a partial variant
+        fs::create_dir_all(&self.dir)?;
+        let temp_path = self.dir.join(format!(
+            "{key:016x}.{}.{}.tmp",
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&temp_path, &bytes)?;
+        if let Err(err) = fs::rename(&temp_path, &path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+
+        Ok(Some((Arc::new(File::open(&path)?), bytes.len() as u64)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod accepts_gzip {
+        use crate::compression::{accepts_brotli, accepts_gzip};
+
+        #[test]
+        fn by_name_or_wildcard() {
+            assert!(accepts_gzip(Some("gzip")));
+            assert!(accepts_gzip(Some("deflate, GZIP;q=0.5")));
+            assert!(accepts_gzip(Some("br, *")));
+            assert!(!accepts_gzip(Some("br, deflate")));
+            assert!(!accepts_gzip(None));
+        }
+
+        #[test]
+        fn not_with_zero_quality() {
+            assert!(!accepts_gzip(Some("gzip;q=0")));
+            assert!(!accepts_gzip(Some("*, gzip;q=0")));
+            assert!(!accepts_gzip(Some("*;q=0")));
+        }
+
+        #[test]
+        fn brotli_by_its_own_name() {
+            assert!(accepts_brotli(Some("gzip, deflate, br")));
+            assert!(accepts_brotli(Some("*")));
+            assert!(!accepts_brotli(Some("gzip, x-gzip")));
+            assert!(!accepts_brotli(Some("br;q=0, gzip")));
+        }
+    }
+
+    mod applies_to {
+        use crate::compression::CompressionConfig;
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+
+        fn response(content_type: &str, len: usize) -> Response {
+            Response::builder()
+                .header("Content-Type", content_type)
+                .body(vec![b'a'; len])
+                .get()
+        }
+
+        #[test]
+        fn skips_small_bodies() {
+            let config = CompressionConfig::new().min_size(100);
+
+            assert!(config.applies_to(&response("text/html", 100)));
+            assert!(!config.applies_to(&response("text/html", 99)));
+            assert!(!CompressionConfig::new()
+                .min_size(0)
+                .applies_to(&response("text/html", 0)));
+        }
+
+        #[test]
+        fn only_allowed_content_types() {
+            let config = CompressionConfig::new();
+
+            assert!(config.applies_to(&response("text/css; charset=utf-8", 2000)));
+            assert!(config.applies_to(&response("application/json", 2000)));
+            assert!(!config.applies_to(&response("image/png", 2000)));
+            assert!(!config.applies_to(&response("application/zip", 2000)));
+            assert!(config.applies_to(&Response::builder().body(vec![b'a'; 2000]).get()));
+
+            let config = CompressionConfig::new().content_types(&["image/*"]);
+            assert!(config.applies_to(&response("image/bmp", 2000)));
+            assert!(!config.applies_to(&response("text/html", 2000)));
+        }
+
+        #[test]
+        fn never_encodes_twice() {
+            let mut encoded = response("text/html", 2000);
+            encoded.set_header("Content-Encoding", "br");
+            let mut not_found = response("text/html", 2000);
+            not_found.set_status_code(ResponseStatusCode::NotFound);
+
+            assert!(!CompressionConfig::new().applies_to(&encoded));
+            assert!(!CompressionConfig::new().applies_to(&not_found));
+        }
+    }
+
+    mod set_content_encoding {
+        use crate::compression::set_content_encoding;
+        use crate::response::Response;
+
+        #[test]
+        fn suffixes_etag() {
+            let mut strong = Response::builder().header("ETag", "\"abc\"").get();
+            let mut weak = Response::builder().header("ETag", "W/\"1-2\"").get();
+
+            set_content_encoding(&mut strong, "gzip");
+            set_content_encoding(&mut weak, "br");
+
+            assert_eq!(strong.get_header("Content-Encoding"), Some("gzip"));
+            assert_eq!(strong.get_header("ETag"), Some("\"abc-gzip\""));
+            assert_eq!(weak.get_header("Content-Encoding"), Some("br"));
+            assert_eq!(weak.get_header("ETag"), Some("W/\"1-2-br\""));
+        }
+    }
+
+    mod compression_cache {
+        use crate::compression::CompressionCache;
+        use crate::content_source::{Content, ContentBody};
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+        use std::path::PathBuf;
+        use std::sync::Arc;
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        fn content(bytes: &'static [u8], modified: Option<SystemTime>) -> Content {
+            Content {
+                path: PathBuf::from("/style.css"),
+                body: ContentBody::Static(bytes),
+                len: bytes.len() as u64,
+                modified,
+            }
+        }
+
+        fn entries(dir: &PathBuf) -> usize {
+            std::fs::read_dir(dir).unwrap().count()
+        }
+
+        #[test]
+        fn compresses_each_version_once() {
+            let dir = std::env::temp_dir()
+                .join(format!("http_rs_compression_cache_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            let cache = CompressionCache::new(dir.to_str().unwrap(), 6);
+            let modified = UNIX_EPOCH + Duration::from_secs(1_000_000);
+
+            let (file, len) = cache
+                .get_or_compress(&content(b"body { color: red }", Some(modified)))
+                .unwrap()
+                .unwrap();
+            let mut decoded = String::new();
+            GzDecoder::new(&*Arc::clone(&file))
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, "body { color: red }");
+            assert_eq!(len, file.metadata().unwrap().len());
+
+            cache
+                .get_or_compress(&content(b"body { color: red }", Some(modified)))
+                .unwrap();
+            assert_eq!(entries(&dir), 1);
+
+            let changed = modified + Duration::from_secs(1);
+            cache
+                .get_or_compress(&content(b"body { color: blue }", Some(changed)))
+                .unwrap();
+            assert_eq!(entries(&dir), 2);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn skips_content_without_modification_time() {
+            let cache = CompressionCache::new("/nonexistent", 6);
+
+            assert!(cache
+                .get_or_compress(&content(b"body", None))
+                .unwrap()
+                .is_none());
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use crate::clock::Clock;
+use crate::request::has_path_prefix;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConcurrencyLimit {
+    pub path_prefix: String,
+    pub max_concurrent: usize,
+    pub queue_size: usize,
+    pub queue_timeout: Duration,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(path_prefix: &str, max_concurrent: usize) -> Self {
+        ConcurrencyLimit {
+            path_prefix: path_prefix.to_string(),
+            max_concurrent,
+            queue_size: 0,
+            queue_timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn queue(mut self, queue_size: usize, queue_timeout: Duration) -> Self {
+        self.queue_size = queue_size;
+        self.queue_timeout = queue_timeout;
+
+        self
+    }
+}
+
+#[derive(Default)]
+struct LimiterState {
+    in_flight: usize,
+    waiting: usize,
+}
+
+pub(crate) struct RouteLimiter {
+    limit: ConcurrencyLimit,
+    state: Mutex<LimiterState>,
+    slot_freed: Condvar,
+}
+
+impl RouteLimiter {
+    pub(crate) fn new(limit: ConcurrencyLimit) -> Self {
+        RouteLimiter {
+            limit,
+            state: Mutex::new(LimiterState::default()),
+            slot_freed: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> &ConcurrencyLimit {
+        &self.limit
+    }
+
+    /// Expects a normalized path without the query, prefixes match whole segments.
+    pub(crate) fn matches(&self, path: &str) -> bool {
+        has_path_prefix(path, &self.limit.path_prefix)
+    }
+
+    /// Returns a permit that frees the slot when dropped, or None if the route
+    /// is saturated and the request could not be queued (or waited too long).
+    /// The queue timeout runs on `clock`, a stopped clock makes requests wait for a free slot.
+    pub(crate) fn acquire(self: &Arc<Self>, clock: &dyn Clock) -> Option<RoutePermit> {
+        let mut state = self.state.lock().unwrap();
+
+        if state.in_flight < self.limit.max_concurrent {
+            state.in_flight += 1;
+            return Some(RoutePermit {
+                limiter: self.clone(),
+            });
+        }
+
+        if state.waiting >= self.limit.queue_size {
+            return None;
+        }
+
+        state.waiting += 1;
+        let deadline = clock.now() + self.limit.queue_timeout;
+
+        while state.in_flight >= self.limit.max_concurrent {
+            let now = clock.now();
+            if now >= deadline {
+                state.waiting -= 1;
+                return None;
+            }
+
+            state = self
+                .slot_freed
+                .wait_timeout(state, deadline - now)
+                .unwrap()
+                .0;
+        }
+
+        state.waiting -= 1;
+        state.in_flight += 1;
+
+        Some(RoutePermit {
+            limiter: self.clone(),
+        })
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        self.slot_freed.notify_one();
+    }
+}
+
+pub(crate) struct RoutePermit {
+    limiter: Arc<RouteLimiter>,
+}
+
+impl Drop for RoutePermit {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clock::SystemClock;
+    use crate::concurrency_limit::{ConcurrencyLimit, RouteLimiter};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn matches_by_prefix() {
+        let limiter = RouteLimiter::new(ConcurrencyLimit::new("/reports", 1));
+
+        assert!(limiter.matches("/reports/daily"));
+        assert!(!limiter.matches("/reportsx"));
+        assert!(!limiter.matches("/index.html"));
+    }
+
+    #[test]
+    fn rejects_when_saturated_without_queue() {
+        let limiter = Arc::new(RouteLimiter::new(ConcurrencyLimit::new("/", 1)));
+
+        let permit = limiter.acquire(&SystemClock);
+        assert!(permit.is_some());
+        assert!(limiter.acquire(&SystemClock).is_none());
+
+        drop(permit);
+        assert!(limiter.acquire(&SystemClock).is_some());
+    }
+
+    #[test]
+    fn queued_request_times_out() {
+        let limiter = Arc::new(RouteLimiter::new(
+            ConcurrencyLimit::new("/", 1).queue(1, Duration::from_millis(20)),
+        ));
+
+        let _permit = limiter.acquire(&SystemClock);
+        assert!(limiter.acquire(&SystemClock).is_none());
+    }
+
+    #[test]
+    fn queued_request_gets_freed_slot() {
+        let limiter = Arc::new(RouteLimiter::new(
+            ConcurrencyLimit::new("/", 1).queue(1, Duration::from_secs(5)),
+        ));
+
+        let permit = limiter.acquire(&SystemClock);
+
+        let cloned_limiter = limiter.clone();
+        let handle = std::thread::spawn(move || cloned_limiter.acquire(&SystemClock).is_some());
+
+        std::thread::sleep(Duration::from_millis(20));
+        drop(permit);
+
+        assert!(handle.join().unwrap());
+    }
+}
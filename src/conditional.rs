@@ -0,0 +1,372 @@
+use crate::header::names;
+use crate::request::Request;
+use crate::response_status_code::ResponseStatusCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// State of the target resource that preconditions are evaluated against.
+pub(crate) struct Validators<'a> {
+    pub etag: Option<&'a str>,
+    pub modified: Option<SystemTime>,
+}
+
+fn opaque_tag(etag: &str) -> &str {
+    etag.trim().trim_start_matches("W/")
+}
+
+fn is_weak(etag: &str) -> bool {
+    etag.trim().starts_with("W/")
+}
+
+pub(crate) fn strong_compare(a: &str, b: &str) -> bool {
+    !is_weak(a) && !is_weak(b) && opaque_tag(a) == opaque_tag(b)
+}
+
+fn weak_compare(a: &str, b: &str) -> bool {
+    opaque_tag(a) == opaque_tag(b)
+}
+
+/// Entity tags of an If-Match or If-None-Match list, `W/` prefix included.
+/// Commas may appear inside the quotes, malformed members are skipped.
+fn etag_list(header_value: &str) -> Vec<&str> {
+    let mut etags = vec![];
+    let mut rest = header_value;
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return etags;
+        }
+
+        let opaque_start = if rest.starts_with("W/") { 2 } else { 0 };
+        let end = rest[opaque_start..]
+            .strip_prefix('"')
+            .and_then(|opaque| opaque.find('"'))
+            .map(|closing_quote| opaque_start + closing_quote + 2);
+
+        let member_end = match end {
+            Some(end)
+                if matches!(
+                    rest[end..].trim_start_matches([' ', '\t']).chars().next(),
+                    None | Some(',')
+                ) =>
+            {
+                etags.push(&rest[..end]);
+                end
+            }
+            // not an entity-tag, skip to the next member
+            _ => rest.find(',').unwrap_or(rest.len()),
+        };
+        rest = &rest[member_end..];
+    }
+}
+
+/// True when If-None-Match lists the current representation, or is `*` and it exists.
+fn if_none_match_matches(header_value: &str, validators: Option<&Validators>) -> bool {
+    let Some(validators) = validators else {
+        return false;
+    };
+
+    if header_value.trim() == "*" {
+        return true;
+    }
+
+    validators.etag.is_some_and(|current_etag| {
+        etag_list(header_value)
+            .into_iter()
+            .any(|etag| weak_compare(etag, current_etag))
+    })
+}
+
+fn truncate_to_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Evaluates the preconditions of a request that changes the target resource
+/// (RFC 9110, section 13.2.2), returns false when it should be answered with 412.
+/// `validators` is None when the target resource does not exist.
+pub(crate) fn write_preconditions_pass(request: &Request, validators: Option<&Validators>) -> bool {
+    if !unchanged_preconditions_pass(request, validators) {
+        return false;
+    }
+
+    // `*` makes a PUT create only, it fails if there is something already
+    request
+        .get_header(names::IF_NONE_MATCH)
+        .is_none_or(|if_none_match| !if_none_match_matches(&if_none_match, validators))
+}
+
+/// Evaluates If-Match, or If-Unmodified-Since without it.
+fn unchanged_preconditions_pass(request: &Request, validators: Option<&Validators>) -> bool {
+    if let Some(if_match) = request.get_header("If-Match") {
+        let Some(validators) = validators else {
+            return false;
+        };
+
+        if if_match.trim() == "*" {
+            return true;
+        }
+
+        let Some(current_etag) = validators.etag else {
+            return false;
+        };
+
+        return etag_list(&if_match)
+            .into_iter()
+            .any(|etag| strong_compare(etag, current_etag));
+    }
+
+    if let Some(if_unmodified_since) = request.get_header("If-Unmodified-Since") {
+        // invalid dates must be ignored
+        let Ok(date) = httpdate::parse_http_date(&if_unmodified_since) else {
+            return true;
+        };
+
+        let Some(modified) = validators.and_then(|validators| validators.modified) else {
+            return false;
+        };
+
+        return truncate_to_seconds(modified) <= truncate_to_seconds(date);
+    }
+
+    true
+}
+
+/// Evaluates the preconditions of a GET or HEAD against the response about to be sent,
+/// returns the status that replaces it: 412 for failed If-Match or If-Unmodified-Since,
+/// 304 when the client's copy is still current.
+pub(crate) fn read_preconditions(
+    request: &Request,
+    validators: &Validators,
+) -> Option<ResponseStatusCode> {
+    if !unchanged_preconditions_pass(request, Some(validators)) {
+        return Some(ResponseStatusCode::PreconditionFailed);
+    }
+
+    if let Some(if_none_match) = request.get_header(names::IF_NONE_MATCH) {
+        // If-Modified-Since is ignored when If-None-Match is present
+        return if_none_match_matches(&if_none_match, Some(validators))
+            .then_some(ResponseStatusCode::NotModified);
+    }
+
+    let date = request
+        .get_header(names::IF_MODIFIED_SINCE)
+        .and_then(|date| httpdate::parse_http_date(&date).ok())?;
+    let modified = validators.modified?;
+
+    (truncate_to_seconds(modified) <= truncate_to_seconds(date))
+        .then_some(ResponseStatusCode::NotModified)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::conditional::{
+        etag_list, read_preconditions, strong_compare, weak_compare, write_preconditions_pass,
+        Validators,
+    };
+    use crate::request::Request;
+    use crate::request_method::RequestMethod;
+    use crate::response_status_code::ResponseStatusCode;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn get_request(headers: &[(&str, &str)]) -> Request {
+        headers
+            .iter()
+            .fold(
+                Request::builder()
+                    .method(RequestMethod::Put)
+                    .url("/file.txt"),
+                |builder, (name, value)| builder.header(name, value),
+            )
+            .get()
+    }
+
+    static VALIDATORS: Validators = Validators {
+        etag: Some("\"abc\""),
+        modified: None,
+    };
+
+    #[test]
+    fn strong_comparison() {
+        assert!(strong_compare("\"1\"", "\"1\""));
+        assert!(!strong_compare("W/\"1\"", "\"1\""));
+        assert!(!strong_compare("W/\"1\"", "W/\"1\""));
+        assert!(!strong_compare("\"1\"", "\"2\""));
+    }
+
+    // the example table of RFC 9110, section 8.8.3.2
+    #[test]
+    fn weak_comparison() {
+        assert!(weak_compare("W/\"1\"", "W/\"1\""));
+        assert!(!weak_compare("W/\"1\"", "W/\"2\""));
+        assert!(weak_compare("W/\"1\"", "\"1\""));
+        assert!(weak_compare("\"1\"", "\"1\""));
+    }
+
+    #[test]
+    fn parses_etag_lists() {
+        assert_eq!(
+            etag_list("\"xyzzy\", \"r2d2xxxx\", \"c3piozzzz\""),
+            ["\"xyzzy\"", "\"r2d2xxxx\"", "\"c3piozzzz\""]
+        );
+        assert_eq!(
+            etag_list("W/\"xyzzy\",W/\"r2d2xxxx\""),
+            ["W/\"xyzzy\"", "W/\"r2d2xxxx\""]
+        );
+        assert_eq!(etag_list("\"a,b\", \"c\""), ["\"a,b\"", "\"c\""]);
+        assert_eq!(
+            etag_list("unquoted, \"ok\", \"trailing\"junk, w/\"lower\""),
+            ["\"ok\""]
+        );
+        assert!(etag_list(" , ").is_empty());
+    }
+
+    #[test]
+    fn passes_without_preconditions() {
+        assert!(write_preconditions_pass(&get_request(&[]), None));
+    }
+
+    #[test]
+    fn if_match_with_current_etag_passes() {
+        let request = get_request(&[("If-Match", "\"xyz\", \"abc\"")]);
+
+        assert!(write_preconditions_pass(&request, Some(&VALIDATORS)));
+    }
+
+    #[test]
+    fn if_match_with_stale_etag_fails() {
+        let request = get_request(&[("If-Match", "\"xyz\"")]);
+
+        assert!(!write_preconditions_pass(&request, Some(&VALIDATORS)));
+    }
+
+    #[test]
+    fn if_match_star_requires_existing_resource() {
+        let request = get_request(&[("If-Match", "*")]);
+
+        assert!(write_preconditions_pass(&request, Some(&VALIDATORS)));
+        assert!(!write_preconditions_pass(&request, None));
+    }
+
+    #[test]
+    fn if_unmodified_since() {
+        let validators = Validators {
+            etag: None,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(784111777)),
+        };
+
+        let request = get_request(&[("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(write_preconditions_pass(&request, Some(&validators)));
+
+        let request = get_request(&[("If-Unmodified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")]);
+        assert!(!write_preconditions_pass(&request, Some(&validators)));
+
+        let request = get_request(&[("If-Unmodified-Since", "yesterday")]);
+        assert!(write_preconditions_pass(&request, Some(&validators)));
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_if_unmodified_since() {
+        let request = get_request(&[
+            ("If-Match", "\"abc\""),
+            ("If-Unmodified-Since", "Thu, 01 Jan 1970 00:00:00 GMT"),
+        ]);
+        let validators = Validators {
+            etag: Some("\"abc\""),
+            modified: Some(UNIX_EPOCH + Duration::from_secs(1000)),
+        };
+
+        assert!(write_preconditions_pass(&request, Some(&validators)));
+    }
+
+    #[test]
+    fn if_none_match_with_current_etag_is_not_modified() {
+        let request = get_request(&[("If-None-Match", "\"xyz\", W/\"abc\"")]);
+
+        assert_eq!(
+            read_preconditions(&request, &VALIDATORS),
+            Some(ResponseStatusCode::NotModified)
+        );
+        assert_eq!(
+            read_preconditions(&get_request(&[("If-None-Match", "\"xyz\"")]), &VALIDATORS),
+            None
+        );
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let request = get_request(&[
+            ("If-None-Match", "\"xyz\""),
+            ("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT"),
+        ]);
+        let validators = Validators {
+            etag: Some("\"abc\""),
+            modified: Some(UNIX_EPOCH),
+        };
+
+        assert_eq!(read_preconditions(&request, &validators), None);
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let validators = Validators {
+            etag: None,
+            modified: Some(UNIX_EPOCH + Duration::from_secs(784111777)),
+        };
+
+        let request = get_request(&[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert_eq!(
+            read_preconditions(&request, &validators),
+            Some(ResponseStatusCode::NotModified)
+        );
+
+        let request = get_request(&[("If-Modified-Since", "Sun, 06 Nov 1994 08:49:36 GMT")]);
+        assert_eq!(read_preconditions(&request, &validators), None);
+    }
+
+    #[test]
+    fn if_none_match_is_weak_and_ignores_malformed_members() {
+        let request = get_request(&[("If-None-Match", "abc, \"a,b\", W/\"abc\"")]);
+        assert_eq!(
+            read_preconditions(&request, &VALIDATORS),
+            Some(ResponseStatusCode::NotModified)
+        );
+
+        let request = get_request(&[("If-None-Match", "abc")]);
+        assert_eq!(read_preconditions(&request, &VALIDATORS), None);
+    }
+
+    #[test]
+    fn if_none_match_star_makes_writes_create_only() {
+        let request = get_request(&[("If-None-Match", "*")]);
+
+        assert!(write_preconditions_pass(&request, None));
+        assert!(!write_preconditions_pass(&request, Some(&VALIDATORS)));
+        assert_eq!(
+            read_preconditions(&request, &VALIDATORS),
+            Some(ResponseStatusCode::NotModified)
+        );
+    }
+
+    #[test]
+    fn if_none_match_on_write_fails_for_current_etag() {
+        let request = get_request(&[("If-None-Match", "W/\"abc\"")]);
+
+        assert!(!write_preconditions_pass(&request, Some(&VALIDATORS)));
+        assert!(write_preconditions_pass(
+            &get_request(&[("If-None-Match", "\"xyz\"")]),
+            Some(&VALIDATORS)
+        ));
+    }
+
+    #[test]
+    fn failed_if_match_is_precondition_failed() {
+        let request = get_request(&[("If-Match", "\"xyz\"")]);
+
+        assert_eq!(
+            read_preconditions(&request, &VALIDATORS),
+            Some(ResponseStatusCode::PreconditionFailed)
+        );
+    }
+}
@@ -1,13 +1,27 @@
+use crate::clock::{Clock, SystemClock};
+use crate::rate_limit::Throttle;
+use crate::stats::ConnectionStats;
 use crate::types::IoResult;
+use crate::utils::read_exact_at;
+#[cfg(feature = "https")]
 use log::{debug, error};
+#[cfg(feature = "https")]
 use rustls::IoState;
+use std::fs::File;
 use std::io::{ErrorKind, Read, Write};
-use std::net::{SocketAddr, TcpStream};
+use std::net::{IpAddr, TcpStream};
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, RawFd};
 use std::sync::Arc;
+use std::time::Instant;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum ReadStrategy {
-    UntilDoubleCrlf,
+    /// Until the end of the head, `bare_lf` also ends it on an empty LF-terminated line
+    UntilDoubleCrlf {
+        bare_lf: bool,
+    },
     UntilDoubleCrlfAtEnd,
     UntilNoBytesRead(usize),
 }
@@ -16,6 +30,12 @@ pub trait ReadWrite: Read + Write {
     fn as_read_mut(&mut self) -> &mut dyn Read;
 
     fn as_write_mut(&mut self) -> &mut dyn Write;
+
+    /// Socket descriptor for zero-copy writes, None if the stream is not a socket
+    #[cfg(target_os = "linux")]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
 }
 
 impl ReadWrite for TcpStream {
@@ -26,37 +46,163 @@ impl ReadWrite for TcpStream {
     fn as_write_mut(&mut self) -> &mut dyn Write {
         self
     }
+
+    #[cfg(target_os = "linux")]
+    fn raw_fd(&self) -> Option<RawFd> {
+        Some(self.as_raw_fd())
+    }
+}
+
+/// Request that has already arrived in full, the response is kept in memory.
+#[cfg(feature = "tokio")]
+pub(crate) struct BufferedStream {
+    request: std::io::Cursor<Vec<u8>>,
+    pub(crate) response: Vec<u8>,
 }
 
+#[cfg(feature = "tokio")]
+impl BufferedStream {
+    pub(crate) fn new(request: Vec<u8>) -> Self {
+        BufferedStream {
+            request: std::io::Cursor::new(request),
+            response: vec![],
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Read for BufferedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.request.read(buf)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl Write for BufferedStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.response.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl ReadWrite for BufferedStream {
+    fn as_read_mut(&mut self) -> &mut dyn Read {
+        self
+    }
+
+    fn as_write_mut(&mut self) -> &mut dyn Write {
+        self
+    }
+}
+
+#[cfg(feature = "https")]
+pub(crate) type TlsConfig = Arc<rustls::ServerConfig>;
+#[cfg(feature = "https")]
+type TlsConnection = rustls::ServerConnection;
+
+// without TLS support there is nothing to configure, connections are always plain
+#[cfg(not(feature = "https"))]
+pub(crate) type TlsConfig = Arc<std::convert::Infallible>;
+#[cfg(not(feature = "https"))]
+type TlsConnection = std::convert::Infallible;
+
 pub struct Connection<'stream> {
     stream: &'stream mut dyn ReadWrite,
-    tls_connection: Option<rustls::ServerConnection>,
+    tls_connection: Option<TlsConnection>,
+    // TLS is handled before the bytes get here, by AsyncServer
+    tls_terminated: bool,
+    // only decides whether TLS sessions get closed after a response
+    #[cfg_attr(not(feature = "https"), allow(dead_code))]
     persistent: bool,
+    pub(crate) stats: ConnectionStats,
+    // reads and writes past this point fail with TimedOut
+    deadline: Option<Instant>,
+    clock: Arc<dyn Clock>,
+    peer_addr: Option<IpAddr>,
+    // writes wait for both, the first one lasts as long as the connection
+    throttle: Option<Throttle>,
+    response_throttle: Option<Throttle>,
 }
 
 impl<'stream> Connection<'stream> {
+    /// The connection speaks TLS when `https_config` is given.
     pub fn new(
         stream: &'stream mut TcpStream,
-        https_config: Option<Arc<rustls::ServerConfig>>,
+        https_config: Option<TlsConfig>,
         persistent: bool,
     ) -> Self {
-        let port = match stream.local_addr().unwrap() {
-            SocketAddr::V4(addr) => addr.port(),
-            SocketAddr::V6(_) => unimplemented!(),
-        };
-        let tls_connection = match port {
-            443 => https_config
-                .map(|https_config| rustls::ServerConnection::new(https_config).unwrap()),
-            _ => None,
-        };
+        let tls_connection = https_config.map(tls_connection);
+
+        let peer_addr = stream.peer_addr().ok().map(|addr| addr.ip());
 
         Connection {
             stream,
             tls_connection,
+            tls_terminated: false,
+            persistent,
+            stats: ConnectionStats {
+                connections: 1,
+                ..Default::default()
+            },
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr,
+            throttle: None,
+            response_throttle: None,
+        }
+    }
+
+    /// Plain connection over any stream, used to replay captured traffic
+    /// and to serve requests read by `AsyncServer`.
+    #[cfg(any(test, feature = "testing", feature = "tokio"))]
+    pub(crate) fn plain(stream: &'stream mut dyn ReadWrite, persistent: bool) -> Self {
+        Connection {
+            stream,
+            tls_connection: None,
+            tls_terminated: false,
             persistent,
+            stats: ConnectionStats {
+                connections: 1,
+                ..Default::default()
+            },
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
         }
     }
 
+    pub(crate) fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(crate) fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    /// Applies to the response being sent, on top of the connection's throttle.
+    pub(crate) fn set_response_throttle(&mut self, throttle: Option<Throttle>) {
+        self.response_throttle = throttle;
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn set_peer_addr(&mut self, peer_addr: Option<IpAddr>) {
+        self.peer_addr = peer_addr;
+    }
+
+    pub(crate) fn peer_addr(&self) -> Option<IpAddr> {
+        self.peer_addr
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
     pub fn read(&mut self, read_strategy: ReadStrategy) -> std::io::Result<Vec<u8>> {
         let mut read_state_machine = ReadStateMachine::new(self, read_strategy);
 
@@ -71,31 +217,191 @@ impl<'stream> Connection<'stream> {
         }
     }
 
+    pub fn is_tls(&self) -> bool {
+        self.tls_connection.is_some() || self.tls_terminated
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn set_tls_terminated(&mut self, tls_terminated: bool) {
+        self.tls_terminated = tls_terminated;
+    }
+
     pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.write_part(bytes, true)
+    }
+
+    /// Writes the last `len` bytes of a response straight from a file.
+    /// Uses sendfile on Linux for plain connections, copies through a buffer otherwise.
+    /// The file offset is left untouched, so the handle can be shared between connections.
+    pub fn send_file(&mut self, file: &File, len: u64) -> std::io::Result<()> {
+        #[cfg(target_os = "linux")]
+        if self.tls_connection.is_none() && !self.is_throttled() {
+            if let Some(fd) = self.stream.raw_fd() {
+                send_file_zero_copy(fd, file, len)?;
+                self.stats.bytes_out += len;
+                return Ok(());
+            }
+        }
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut offset = 0;
+
+        while offset < len {
+            let chunk_len = buf.len().min((len - offset) as usize);
+            read_exact_at(file, &mut buf[..chunk_len], offset)?;
+            self.write_part(&buf[..chunk_len], false)?;
+            offset += chunk_len as u64;
+        }
+
+        self.write_part(&[], true)
+    }
+
+    /// Sends everything `reader` gives, as chunks (RFC 9112, section 7.1) if `chunked`.
+    /// The last chunk is up to the caller, it may carry trailers.
+    /// Stops once the reader gives more than `max_len` bytes, without sending the excess.
+    /// Returns how many bytes were read, which is over `max_len` if it stopped early.
+    pub fn send_reader(
+        &mut self,
+        reader: &mut dyn Read,
+        chunked: bool,
+        max_len: Option<u64>,
+    ) -> std::io::Result<u64> {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut total = 0;
+
+        loop {
+            let read = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            total += read as u64;
+            if max_len.is_some_and(|max_len| total > max_len) {
+                return Ok(total);
+            }
+
+            if chunked {
+                let mut chunk = format!("{read:X}\r\n").into_bytes();
+                chunk.extend_from_slice(&buf[..read]);
+                chunk.extend_from_slice(b"\r\n");
+                self.write_part(&chunk, false)?;
+            } else {
+                self.write_part(&buf[..read], false)?;
+            }
+        }
+
+        Ok(total)
+    }
+
+    /// Parts other than the last one do not close a non-persistent TLS session.
+    pub fn write_part(&mut self, bytes: &[u8], is_last: bool) -> std::io::Result<()> {
+        if !self.is_throttled() {
+            return self.write_unthrottled(bytes, is_last);
+        }
+
+        let slice_len = [&self.throttle, &self.response_throttle]
+            .into_iter()
+            .flatten()
+            .map(|throttle| throttle.slice_len())
+            .min()
+            .unwrap_or(bytes.len());
+
+        for slice in bytes.chunks(slice_len) {
+            let now = self.clock.now();
+            let wait = [&mut self.throttle, &mut self.response_throttle]
+                .into_iter()
+                .flatten()
+                .map(|throttle| throttle.take(slice.len(), now))
+                .max()
+                .unwrap_or_default();
+            if !wait.is_zero() {
+                std::thread::sleep(wait);
+            }
+
+            self.write_unthrottled(slice, false)?;
+        }
+
+        self.write_unthrottled(&[], is_last)
+    }
+
+    fn is_throttled(&self) -> bool {
+        self.throttle.is_some() || self.response_throttle.is_some()
+    }
+
+    #[cfg_attr(not(feature = "https"), allow(unused_variables))]
+    fn write_unthrottled(&mut self, bytes: &[u8], is_last: bool) -> std::io::Result<()> {
+        if self
+            .deadline
+            .is_some_and(|deadline| self.clock.now() >= deadline)
+        {
+            return Err(ErrorKind::TimedOut.into());
+        }
+
+        #[cfg(feature = "https")]
         if let Some(conn) = self.tls_connection.as_mut() {
             // todo: try not to set unlimited buffer size
             conn.set_buffer_limit(None);
             conn.writer().write_all(bytes)?;
-            if !self.persistent {
+            if is_last && !self.persistent {
                 conn.send_close_notify();
             }
             while conn.wants_write() {
-                conn.write_tls(self.stream.as_write_mut())?;
+                self.stats.bytes_out += conn.write_tls(self.stream.as_write_mut())? as u64;
             }
-        } else {
-            self.stream.as_write_mut().write_all(bytes)?;
+
+            return Ok(());
         }
 
+        self.stream.as_write_mut().write_all(bytes)?;
+        self.stats.bytes_out += bytes.len() as u64;
+
         Ok(())
     }
 }
 
+#[cfg(feature = "https")]
+fn tls_connection(https_config: TlsConfig) -> TlsConnection {
+    rustls::ServerConnection::new(https_config).unwrap()
+}
+
+#[cfg(not(feature = "https"))]
+fn tls_connection(https_config: TlsConfig) -> TlsConnection {
+    match *https_config {}
+}
+
+#[cfg(target_os = "linux")]
+fn send_file_zero_copy(socket_fd: RawFd, file: &File, len: u64) -> std::io::Result<()> {
+    let mut offset: libc::off_t = 0;
+
+    while (offset as u64) < len {
+        let count = (len - offset as u64) as usize;
+        // SAFETY: both descriptors stay open for the whole call, offset points to a live local
+        let sent = unsafe { libc::sendfile(socket_fd, file.as_raw_fd(), &mut offset, count) };
+
+        match sent {
+            -1 => {
+                let err = std::io::Error::last_os_error();
+                if err.kind() != ErrorKind::Interrupted {
+                    return Err(err);
+                }
+            }
+            // file got shorter since its size was read
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "https")]
 fn read_tls_plaintext_bytes(
     tls_connection: &mut rustls::ServerConnection,
     state: &IoState,
 ) -> std::io::Result<Vec<u8>> {
-    let mut buf = vec![];
-    buf.resize(state.plaintext_bytes_to_read(), 0u8);
+    let mut buf = vec![0u8; state.plaintext_bytes_to_read()];
     tls_connection.reader().read_exact(&mut buf)?;
 
     Ok(buf)
@@ -105,7 +411,9 @@ fn read_tls_plaintext_bytes(
 enum ReadState {
     Before,
     Read,
+    #[cfg(feature = "https")]
     TlsHandshake,
+    #[cfg(feature = "https")]
     TlsRead,
     After(usize),
     Done,
@@ -137,10 +445,15 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
 
     fn next(mut self) -> Self {
         let mut next_state = match self.state {
-            ReadState::Before if self.connection.tls_connection.is_none() => ReadState::Read,
-            ReadState::Before => ReadState::TlsHandshake,
+            #[cfg(feature = "https")]
+            ReadState::Before if self.connection.tls_connection.is_some() => {
+                ReadState::TlsHandshake
+            }
+            ReadState::Before => ReadState::Read,
             ReadState::Read => Self::map_error(self.read()),
+            #[cfg(feature = "https")]
             ReadState::TlsHandshake => Self::map_error(self.tls_handshake()),
+            #[cfg(feature = "https")]
             ReadState::TlsRead => Self::map_error(self.tls_read()),
             ReadState::After(read_bytes) => self.check_if_finished(read_bytes),
             ReadState::Done | ReadState::Error(_) => self.state,
@@ -164,6 +477,10 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
     fn map_error(result: IoResult<ReadState>) -> ReadState {
         match result {
             Ok(state) => state,
+            // read timeouts are reported as WouldBlock on unix platforms
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                ReadState::Error(ErrorKind::TimedOut)
+            }
             Err(err) => ReadState::Error(err.kind()),
         }
     }
@@ -175,12 +492,21 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
         let mut read_bytes: usize = 0;
 
         loop {
+            if self
+                .connection
+                .deadline
+                .is_some_and(|deadline| self.connection.clock.now() >= deadline)
+            {
+                return Err(ErrorKind::TimedOut.into());
+            }
+
             let read_length = stream.as_read_mut().read(stream_buf.as_mut_slice())?;
             self.read_bytes.reserve(read_length);
             self.read_bytes
                 .extend_from_slice(&stream_buf[0..read_length]);
 
             read_bytes += read_length;
+            self.connection.stats.bytes_in += read_length as u64;
 
             if read_length < stream_buf.len() {
                 break;
@@ -190,15 +516,17 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
         Ok(ReadState::After(read_bytes))
     }
 
+    #[cfg(feature = "https")]
     fn tls_handshake(&mut self) -> IoResult<ReadState> {
         let tls_connection = self.connection.tls_connection.as_mut().unwrap();
         let stream = &mut self.connection.stream;
 
         while tls_connection.is_handshaking() {
-            tls_connection.read_tls(stream.as_read_mut())?;
+            self.connection.stats.bytes_in += tls_connection.read_tls(stream.as_read_mut())? as u64;
             match &mut tls_connection.process_new_packets() {
                 Err(err) => {
                     error!("Handshake error: {err:?}");
+                    self.connection.stats.tls_handshake_failures += 1;
                     tls_connection.write_tls(stream.as_write_mut())?;
                     return Err(ErrorKind::Other.into());
                 }
@@ -215,17 +543,19 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
                     }
                 }
             }
-            tls_connection.write_tls(stream.as_write_mut())?;
+            self.connection.stats.bytes_out +=
+                tls_connection.write_tls(stream.as_write_mut())? as u64;
         }
 
         Ok(ReadState::TlsRead)
     }
 
+    #[cfg(feature = "https")]
     fn tls_read(&mut self) -> IoResult<ReadState> {
         let tls_connection = self.connection.tls_connection.as_mut().unwrap();
         let stream = &mut self.connection.stream;
 
-        tls_connection.read_tls(stream.as_read_mut())?;
+        self.connection.stats.bytes_in += tls_connection.read_tls(stream.as_read_mut())? as u64;
         match &mut tls_connection.process_new_packets() {
             Err(err) => {
                 error!("Plaintext read error: {err:?}");
@@ -247,10 +577,12 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
         }
 
         match self.read_strategy {
-            ReadStrategy::UntilDoubleCrlf => {
+            ReadStrategy::UntilDoubleCrlf { bare_lf } => {
                 for bytes in self.read_bytes.windows(4) {
-                    if let [.., b'\r', b'\n', b'\r', b'\n'] = bytes {
-                        return ReadState::Done;
+                    match bytes {
+                        [b'\r', b'\n', b'\r', b'\n'] => return ReadState::Done,
+                        [_, _, b'\n', b'\n'] if bare_lf => return ReadState::Done,
+                        _ => {}
                     }
                 }
             }
@@ -272,13 +604,16 @@ impl<'connection, 'stream> ReadStateMachine<'connection, 'stream> {
 
 #[cfg(test)]
 mod test {
-    use crate::connection::{Connection, ReadStrategy};
+    use crate::clock::SystemClock;
+    use crate::connection::{Connection, ReadState, ReadStateMachine, ReadStrategy};
+    use crate::stats::ConnectionStats;
     use crate::test::mocks::MockReadWrite;
     use rand::RngCore;
+    use std::fs::File;
+    use std::sync::Arc;
 
     fn get_rand_vec(len: usize) -> Vec<u8> {
-        let mut read_buf: Vec<u8> = Vec::new();
-        read_buf.resize(len, 0);
+        let mut read_buf: Vec<u8> = vec![0; len];
         rand::thread_rng().fill_bytes(&mut read_buf);
 
         read_buf
@@ -302,13 +637,96 @@ mod test {
         let mut connection = Connection {
             stream: &mut mock,
             tls_connection: None,
+            tls_terminated: false,
             persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
         };
 
-        let read_bytes = connection.read(ReadStrategy::UntilDoubleCrlf).unwrap();
+        let read_bytes = connection
+            .read(ReadStrategy::UntilDoubleCrlf { bare_lf: false })
+            .unwrap();
         assert_eq!(read_bytes.len(), 734);
     }
 
+    #[test]
+    fn counts_bytes_in_and_out() {
+        let mut mock = prepare_mock(734);
+        let mut connection = Connection {
+            stream: &mut mock,
+            tls_connection: None,
+            tls_terminated: false,
+            persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
+        };
+
+        connection
+            .read(ReadStrategy::UntilDoubleCrlf { bare_lf: false })
+            .unwrap();
+        connection.write(b"HTTP/1.1 200 OK\r\n\r\n").unwrap();
+
+        assert_eq!(connection.stats.bytes_in, 734);
+        assert_eq!(connection.stats.bytes_out, 19);
+    }
+
+    #[test]
+    fn sends_file_through_buffer_without_socket() {
+        let mut mock = prepare_mock(4);
+        let mut connection = Connection {
+            stream: &mut mock,
+            tls_connection: None,
+            tls_terminated: false,
+            persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
+        };
+
+        let path = "test_files/file.txt";
+        let file = File::open(path).unwrap();
+        let len = file.metadata().unwrap().len();
+        connection.send_file(&file, len).unwrap();
+
+        assert_eq!(connection.stats.bytes_out, len);
+        assert_eq!(mock.write_buf, std::fs::read(path).unwrap());
+    }
+
+    #[test]
+    fn stops_sending_reader_over_max_len() {
+        let mut mock = prepare_mock(4);
+        let mut connection = Connection {
+            stream: &mut mock,
+            tls_connection: None,
+            tls_terminated: false,
+            persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
+        };
+
+        let read = connection
+            .send_reader(&mut &b"0123456789"[..], false, Some(4))
+            .unwrap();
+
+        assert_eq!(read, 10);
+        assert!(mock.write_buf.is_empty());
+    }
+
     #[test]
     fn reads_all_bytes_until_double_crlf_mid_way() {
         let mut mock = {
@@ -325,13 +743,51 @@ mod test {
         let mut connection = Connection {
             stream: &mut mock,
             tls_connection: None,
+            tls_terminated: false,
             persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
         };
 
-        let read_bytes = connection.read(ReadStrategy::UntilDoubleCrlf).unwrap();
+        let read_bytes = connection
+            .read(ReadStrategy::UntilDoubleCrlf { bare_lf: false })
+            .unwrap();
         assert_eq!(read_bytes.len(), 395);
     }
 
+    #[test]
+    fn ends_head_at_bare_lf_only_when_allowed() {
+        let mut mock = MockReadWrite {
+            read_buf: vec![],
+            write_buf: vec![],
+        };
+        let mut connection = Connection {
+            stream: &mut mock,
+            tls_connection: None,
+            tls_terminated: false,
+            persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
+        };
+
+        for (bare_lf, is_done) in [(false, false), (true, true)] {
+            let mut read_state_machine =
+                ReadStateMachine::new(&mut connection, ReadStrategy::UntilDoubleCrlf { bare_lf });
+            read_state_machine.read_bytes = b"GET / HTTP/1.1\n\n".to_vec();
+
+            let state = read_state_machine.check_if_finished(16);
+            assert_eq!(matches!(state, ReadState::Done), is_done);
+        }
+    }
+
     #[test]
     fn reads_all_bytes_until_no_bytes() {
         let mut mock = MockReadWrite {
@@ -341,7 +797,14 @@ mod test {
         let mut connection = Connection {
             stream: &mut mock,
             tls_connection: None,
+            tls_terminated: false,
             persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
         };
 
         let read_bytes = connection
@@ -359,10 +822,19 @@ mod test {
         let mut connection = Connection {
             stream: &mut mock,
             tls_connection: None,
+            tls_terminated: false,
             persistent: false,
+            stats: ConnectionStats::default(),
+            deadline: None,
+            clock: Arc::new(SystemClock),
+            peer_addr: None,
+            throttle: None,
+            response_throttle: None,
         };
 
-        let read_bytes = connection.read(ReadStrategy::UntilDoubleCrlf).unwrap();
+        let read_bytes = connection
+            .read(ReadStrategy::UntilDoubleCrlf { bare_lf: false })
+            .unwrap();
         assert_eq!(read_bytes.len(), 0);
     }
 }
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How many keep-alive connections to an upstream are kept for later requests, and for how
+/// long, see `ProxyRoute::pool`. Connections are kept per host and only while idle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ConnectionPoolConfig {
+    /// Idle connections kept per host, 0 closes every connection after its response
+    pub max_idle_per_host: usize,
+    /// Idle connections are closed after this, keep it below the upstream's own keep-alive
+    /// timeout so that requests rarely go out on a connection it is about to close
+    pub idle_timeout: Duration,
+}
+
+impl ConnectionPoolConfig {
+    pub fn new() -> Self {
+        ConnectionPoolConfig {
+            max_idle_per_host: 8,
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Every request gets a fresh connection.
+    pub fn disabled() -> Self {
+        ConnectionPoolConfig {
+            max_idle_per_host: 0,
+            ..Self::new()
+        }
+    }
+
+    pub fn max_idle_per_host(mut self, max_idle_per_host: usize) -> Self {
+        self.max_idle_per_host = max_idle_per_host;
+
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+
+        self
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.max_idle_per_host > 0
+    }
+}
+
+impl Default for ConnectionPoolConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Idle connections by host, the most recently used one is handed out first.
+pub(crate) struct ConnectionPool<S> {
+    config: ConnectionPoolConfig,
+    // with the time each connection became idle
+    idle: Mutex<HashMap<String, Vec<(S, Instant)>>>,
+}
+
+impl<S> ConnectionPool<S> {
+    pub(crate) fn new(config: ConnectionPoolConfig) -> Self {
+        ConnectionPool {
+            config,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.config.is_enabled()
+    }
+
+    pub(crate) fn take(&self, host: &str) -> Option<S> {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.get_mut(host)?;
+        let connection = connections
+            .pop()
+            .filter(|(_, since)| since.elapsed() < self.config.idle_timeout)
+            .map(|(connection, _)| connection);
+
+        // anything older than the newest one has expired too if it did
+        if connection.is_none() {
+            idle.remove(host);
+        }
+
+        connection
+    }
+
+    /// Keeps `connection` for the next request to `host`, closing the oldest one if
+    /// there are too many.
+    pub(crate) fn put(&self, host: &str, connection: S) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut idle = self.idle.lock().unwrap();
+        let now = Instant::now();
+
+        // hosts that are not asked for anymore would keep their connections open otherwise
+        for connections in idle.values_mut() {
+            connections.retain(|(_, since)| now.duration_since(*since) < self.config.idle_timeout);
+        }
+        idle.retain(|_, connections| !connections.is_empty());
+
+        let connections = idle.entry(host.to_string()).or_default();
+        if connections.len() >= self.config.max_idle_per_host {
+            connections.remove(0);
+        }
+        connections.push((connection, now));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::connection_pool::{ConnectionPool, ConnectionPoolConfig};
+    use std::time::Duration;
+
+    #[test]
+    fn hands_out_most_recent_connection_of_host() {
+        let pool = ConnectionPool::new(ConnectionPoolConfig::new().max_idle_per_host(2));
+        pool.put("a:80", 1);
+        pool.put("a:80", 2);
+        pool.put("a:80", 3);
+        pool.put("b:80", 4);
+
+        assert_eq!(pool.take("a:80"), Some(3));
+        assert_eq!(pool.take("a:80"), Some(2));
+        assert_eq!(pool.take("a:80"), None);
+        assert_eq!(pool.take("b:80"), Some(4));
+        assert_eq!(pool.take("c:80"), None);
+    }
+
+    #[test]
+    fn drops_expired_connections() {
+        let pool = ConnectionPool::new(ConnectionPoolConfig::new().idle_timeout(Duration::ZERO));
+        pool.put("a:80", 1);
+
+        assert_eq!(pool.take("a:80"), None);
+    }
+
+    #[test]
+    fn keeps_nothing_when_disabled() {
+        let pool = ConnectionPool::new(ConnectionPoolConfig::disabled());
+        pool.put("a:80", 1);
+
+        assert_eq!(pool.take("a:80"), None);
+    }
+}
@@ -0,0 +1,249 @@
+use crate::canonical_paths::CanonicalPaths;
+use crate::types::IoResult;
+use crate::utils::read_exact_at;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+pub enum ContentBody {
+    /// Can be sent with sendfile, see `ServerConfig::sendfile_threshold`
+    File(Arc<File>),
+    Memory(Arc<[u8]>),
+    Static(&'static [u8]),
+}
+
+/// A single static resource, as found by a `ContentSource`.
+pub struct Content {
+    /// Identifies the resource in caches, does not have to exist on disk
+    pub path: PathBuf,
+    pub body: ContentBody,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl Content {
+    pub fn read(&self) -> IoResult<Vec<u8>> {
+        match &self.body {
+            ContentBody::File(file) => {
+                let mut bytes = vec![0u8; self.len as usize];
+                read_exact_at(file, &mut bytes, 0)?;
+
+                Ok(bytes)
+            }
+            ContentBody::Memory(bytes) => Ok(bytes.to_vec()),
+            ContentBody::Static(bytes) => Ok(bytes.to_vec()),
+        }
+    }
+}
+
+/// Where static content gets served from, set with `Server::content_source`.
+/// Static writes (PUT and DELETE) always go to the filesystem.
+pub trait ContentSource: Send + Sync {
+    /// Resource for a request url under `root`, NotFound if there is none
+    /// and PermissionDenied if the url points outside of `root`.
+    fn get(&self, root: &str, url: &str) -> IoResult<Content>;
+}
+
+/// Serves files under the root directory, the default source.
+#[derive(Default)]
+pub struct FsContentSource {
+    paths: Arc<CanonicalPaths>,
+}
+
+impl FsContentSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_paths(paths: Arc<CanonicalPaths>) -> Self {
+        FsContentSource { paths }
+    }
+}
+
+impl ContentSource for FsContentSource {
+    fn get(&self, root: &str, url: &str) -> IoResult<Content> {
+        get_content(&self.paths, root, url)
+    }
+}
+
+/// Resources kept in memory and keyed by url, the same for every root.
+#[derive(Default)]
+pub struct MemoryContentSource {
+    files: HashMap<String, (Arc<[u8]>, Option<SystemTime>)>,
+}
+
+impl MemoryContentSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn file(mut self, url: &str, bytes: &[u8]) -> Self {
+        self.files
+            .insert(normalize_url(url), (Arc::from(bytes), None));
+
+        self
+    }
+
+    pub fn file_modified(mut self, url: &str, bytes: &[u8], modified: SystemTime) -> Self {
+        self.files
+            .insert(normalize_url(url), (Arc::from(bytes), Some(modified)));
+
+        self
+    }
+}
+
+fn normalize_url(url: &str) -> String {
+    format!("/{}", url.trim_start_matches('/'))
+}
+
+impl ContentSource for MemoryContentSource {
+    fn get(&self, _root: &str, url: &str) -> IoResult<Content> {
+        let url = normalize_url(url);
+        let (bytes, modified) = self
+            .files
+            .get(&url)
+            .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound))?;
+
+        Ok(Content {
+            path: PathBuf::from(&url),
+            len: bytes.len() as u64,
+            body: ContentBody::Memory(bytes.clone()),
+            modified: *modified,
+        })
+    }
+}
+
+pub(crate) fn get_content(
+    paths: &CanonicalPaths,
+    root: &str,
+    content_path: &str,
+) -> IoResult<Content> {
+    let path = Path::new(root).join(content_path.trim_start_matches('/'));
+
+    if let Some(canonical_path) = paths.get(&path) {
+        if let Ok(file) = File::open(&canonical_path) {
+            let metadata = file.metadata()?;
+
+            if paths.is_current(&path, &metadata) {
+                return Ok(Content {
+                    path: canonical_path,
+                    body: ContentBody::File(Arc::new(file)),
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                });
+            }
+        }
+
+        paths.invalidate(&path);
+    }
+
+    let canonical_root_path = paths.root(root)?;
+    let canonical_path = fs::canonicalize(&path)?;
+
+    // Do this check so no smarty-pants tries to access files
+    // outside web root directory, e.g. with GET /../example_http.rs
+    if !canonical_path.starts_with(canonical_root_path) {
+        return Err(std::io::Error::from(ErrorKind::PermissionDenied));
+    }
+
+    let file = File::open(&canonical_path)?;
+    let metadata = file.metadata()?;
+
+    if !metadata.is_file() {
+        return Err(std::io::Error::from(ErrorKind::NotFound));
+    }
+
+    paths.insert(path, canonical_path.clone(), &metadata);
+
+    Ok(Content {
+        path: canonical_path,
+        body: ContentBody::File(Arc::new(file)),
+        len: metadata.len(),
+        modified: metadata.modified().ok(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    mod get_content {
+        // These tests are dumb but I'm not going to mock fs
+        use crate::canonical_paths::CanonicalPaths;
+        use crate::content_source::get_content;
+        use std::io::ErrorKind;
+
+        #[test]
+        fn ok_if_file_exists() {
+            assert!(get_content(&CanonicalPaths::default(), "test_files", "file.txt").is_ok());
+        }
+
+        #[test]
+        fn ok_if_served_from_cached_path() {
+            let paths = CanonicalPaths::default();
+            let first = get_content(&paths, "test_files", "file.txt").unwrap();
+            let second = get_content(&paths, "test_files", "file.txt").unwrap();
+
+            assert_eq!(first.path, second.path);
+        }
+
+        #[test]
+        fn ok_if_file_does_not_exist() {
+            assert!(
+                get_content(&CanonicalPaths::default(), "test_files", "0qhwe0t9h.txt").is_err()
+            );
+        }
+
+        #[test]
+        fn err_if_file_is_outside_root() {
+            assert!(
+                matches!(get_content(&CanonicalPaths::default(), "test_files/dir", "/../file.txt"), Err(e) if e.kind() == ErrorKind::PermissionDenied)
+            );
+        }
+    }
+
+    mod memory_content_source {
+        use crate::content_source::{ContentSource, MemoryContentSource};
+        use crate::server::Server;
+        use crate::testing::{run_script, ConnectionState, ScriptStep};
+        use std::io::ErrorKind;
+        use std::sync::Arc;
+
+        #[test]
+        fn serves_files_by_url() {
+            let source = MemoryContentSource::new().file("index.html", b"<p>hi</p>");
+            let content = source.get("any_root", "/index.html").unwrap();
+
+            assert_eq!(content.len, 9);
+            assert_eq!(content.read().unwrap(), b"<p>hi</p>");
+        }
+
+        #[test]
+        fn server_serves_from_memory() {
+            let server = Server::new(None).content_source(Arc::new(
+                MemoryContentSource::new().file("/hello.txt", b"hello"),
+            ));
+            let run = run_script(
+                &server,
+                None,
+                vec![ScriptStep::Send(
+                    b"GET /hello.txt HTTP/1.1\r\n\r\n".to_vec(),
+                )],
+            );
+
+            assert!(run.states.contains(&ConnectionState::SendResponse(200)));
+            assert!(run.written.ends_with(b"\r\n\r\nhello"));
+        }
+
+        #[test]
+        fn not_found_for_unknown_url() {
+            let source = MemoryContentSource::new();
+
+            assert!(
+                matches!(source.get("any_root", "/index.html"), Err(e) if e.kind() == ErrorKind::NotFound)
+            );
+        }
+    }
+}
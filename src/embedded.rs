@@ -0,0 +1,160 @@
+use crate::content_source::{Content, ContentBody, ContentSource};
+use crate::types::IoResult;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// A file compiled into the binary, usually listed by `write_embedded_assets`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EmbeddedFile {
+    pub url: &'static str,
+    pub bytes: &'static [u8],
+}
+
+/// Serves files embedded in the binary, the same for every root.
+///
+/// In `build.rs`:
+/// ```ignore
+/// http_rs::embedded::write_embedded_assets("static", out_dir.join("assets.rs")).unwrap();
+/// ```
+/// And in the crate:
+/// ```ignore
+/// static ASSETS: &[EmbeddedFile] = include!(concat!(env!("OUT_DIR"), "/assets.rs"));
+///
+/// let server = Server::new(None).content_source(Arc::new(EmbeddedContentSource::new(ASSETS)));
+/// ```
+pub struct EmbeddedContentSource {
+    files: HashMap<&'static str, &'static [u8]>,
+}
+
+impl EmbeddedContentSource {
+    pub fn new(files: &'static [EmbeddedFile]) -> Self {
+        EmbeddedContentSource {
+            files: files.iter().map(|file| (file.url, file.bytes)).collect(),
+        }
+    }
+}
+
+impl ContentSource for EmbeddedContentSource {
+    fn get(&self, _root: &str, url: &str) -> IoResult<Content> {
+        let url = format!("/{}", url.trim_start_matches('/'));
+        let bytes = self
+            .files
+            .get(url.as_str())
+            .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound))?;
+
+        Ok(Content {
+            path: PathBuf::from(&url),
+            body: ContentBody::Static(bytes),
+            len: bytes.len() as u64,
+            modified: None,
+        })
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> IoResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a Rust expression listing every file under `dir` as an `EmbeddedFile`,
+/// meant to be called from a build script and pulled in with `include!`.
+/// Urls are paths relative to `dir`, with forward slashes.
+pub fn write_embedded_assets(
+    dir: impl AsRef<Path>,
+    out_file: impl AsRef<Path>,
+) -> crate::Result<()> {
+    let dir = fs::canonicalize(dir)?;
+    let mut files = vec![];
+    collect_files(&dir, &mut files)?;
+    files.sort();
+
+    let mut source = String::from("&[\n");
+
+    for file in &files {
+        let relative_path = file
+            .strip_prefix(&dir)
+            .map_err(|_| std::io::Error::from(ErrorKind::InvalidInput))?;
+        let url = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        source += &format!(
+            "    http_rs::embedded::EmbeddedFile {{ url: {:?}, bytes: include_bytes!({:?}) }},\n",
+            format!("/{url}"),
+            file.to_string_lossy()
+        );
+    }
+
+    source += "]\n";
+
+    // picks up added and removed files, not just changed ones
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    fs::write(out_file, source)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    mod embedded_content_source {
+        use crate::content_source::ContentSource;
+        use crate::embedded::{EmbeddedContentSource, EmbeddedFile};
+        use std::io::ErrorKind;
+
+        static FILES: &[EmbeddedFile] = &[EmbeddedFile {
+            url: "/index.html",
+            bytes: b"<p>hi</p>",
+        }];
+
+        #[test]
+        fn serves_embedded_files() {
+            let source = EmbeddedContentSource::new(FILES);
+            let content = source.get("any_root", "index.html").unwrap();
+
+            assert_eq!(content.len, 9);
+            assert_eq!(content.read().unwrap(), b"<p>hi</p>");
+        }
+
+        #[test]
+        fn not_found_for_unknown_url() {
+            let source = EmbeddedContentSource::new(FILES);
+
+            assert!(
+                matches!(source.get("any_root", "/missing.html"), Err(e) if e.kind() == ErrorKind::NotFound)
+            );
+        }
+    }
+
+    mod write_embedded_assets {
+        use crate::embedded::write_embedded_assets;
+        use std::fs;
+
+        // not going to mock fs
+        #[test]
+        fn lists_files_with_urls() {
+            let out_file = std::env::temp_dir().join("http_rs_embedded_assets.rs");
+            write_embedded_assets("test_files/keys", &out_file).unwrap();
+
+            let source = fs::read_to_string(&out_file).unwrap();
+            fs::remove_file(&out_file).unwrap();
+
+            assert!(source.starts_with("&[\n"));
+            assert!(source.contains("url: \"/server.crt\", bytes: include_bytes!("));
+            assert!(source.contains("url: \"/server.key\""));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt::{Display, Formatter};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Everything that can go wrong in the public API.
+#[derive(Debug)]
+pub enum Error {
+    /// Malformed HTTP message
+    Parse(String),
+    Io(std::io::Error),
+    /// Handshake or certificate failure, boxed so the variant is there with or without `https`
+    Tls(Box<dyn std::error::Error + Send + Sync>),
+    /// Server configuration that cannot be used
+    Config(String),
+    /// Rules file that could not be parsed, formatted with the offending line
+    Rules(String),
+}
+
+impl Error {
+    pub(crate) fn parse(message: &str) -> Self {
+        Error::Parse(message.to_string())
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(message) => write!(f, "Parse error: {message}"),
+            Error::Io(err) => write!(f, "IO error: {err}"),
+            Error::Tls(err) => write!(f, "TLS error: {err}"),
+            Error::Config(message) => write!(f, "Configuration error: {message}"),
+            Error::Rules(message) => write!(f, "Rules error: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Tls(err) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+#[cfg(feature = "https")]
+impl From<rustls::Error> for Error {
+    fn from(err: rustls::Error) -> Self {
+        Error::Tls(Box::new(err))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::error::Error;
+    use std::error::Error as _;
+    use std::io::ErrorKind;
+
+    #[test]
+    fn io_errors_keep_their_source() {
+        let err = Error::from(std::io::Error::from(ErrorKind::NotFound));
+
+        assert!(matches!(&err, Error::Io(io_err) if io_err.kind() == ErrorKind::NotFound));
+        assert!(err.source().is_some());
+    }
+
+    #[cfg(feature = "https")]
+    #[test]
+    fn tls_errors_keep_their_source() {
+        let err = Error::from(rustls::Error::DecryptError);
+
+        assert!(matches!(&err, Error::Tls(_)));
+        assert!(err.source().is_some());
+    }
+
+    #[test]
+    fn displays_cause() {
+        assert_eq!(
+            Error::parse("Invalid header").to_string(),
+            "Parse error: Invalid header"
+        );
+    }
+}
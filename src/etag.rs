@@ -0,0 +1,136 @@
+use crate::utils::hex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Cheap validator built from file size and modification time.
+pub(crate) fn weak_etag(len: u64, modified: Option<SystemTime>) -> String {
+    let modified_nanos = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_nanos())
+        .unwrap_or(0);
+
+    format!("W/\"{len:x}-{modified_nanos:x}\"")
+}
+
+pub(crate) fn content_hash(bytes: &[u8]) -> String {
+    format!("{:016x}", xxh3_64(bytes))
+}
+
+pub(crate) fn strong_etag(bytes: &[u8]) -> String {
+    format!("\"{}\"", content_hash(bytes))
+}
+
+pub(crate) fn sha256_etag(bytes: &[u8]) -> String {
+    format!("\"{}\"", hex(&Sha256::digest(bytes)))
+}
+
+struct CachedHash {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: String,
+}
+
+/// Content hashes keyed by path, recomputed only when size or mtime changes.
+#[derive(Default)]
+pub(crate) struct HashCache {
+    entries: Mutex<HashMap<PathBuf, CachedHash>>,
+}
+
+impl HashCache {
+    /// Errors are not cached, the next call computes the hash again.
+    /// The hash is computed without holding the lock, other paths don't wait for it.
+    pub(crate) fn try_get_or_compute<E>(
+        &self,
+        path: &Path,
+        len: u64,
+        modified: Option<SystemTime>,
+        compute: impl FnOnce() -> Result<String, E>,
+    ) -> Result<String, E> {
+        if let Some(cached) = self.entries.lock().unwrap().get(path) {
+            if cached.len == len && cached.modified == modified {
+                return Ok(cached.hash.clone());
+            }
+        }
+
+        let hash = compute()?;
+        self.entries.lock().unwrap().insert(
+            path.to_path_buf(),
+            CachedHash {
+                len,
+                modified,
+                hash: hash.clone(),
+            },
+        );
+
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::etag::{sha256_etag, strong_etag, weak_etag, HashCache};
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn weak_etag_has_weak_prefix() {
+        let etag = weak_etag(10, Some(UNIX_EPOCH + Duration::from_secs(1)));
+
+        assert!(etag.starts_with("W/\""));
+        assert_ne!(
+            etag,
+            weak_etag(11, Some(UNIX_EPOCH + Duration::from_secs(1)))
+        );
+    }
+
+    #[test]
+    fn strong_etag_depends_on_content() {
+        assert_eq!(strong_etag(b"123"), strong_etag(b"123"));
+        assert_ne!(strong_etag(b"123"), strong_etag(b"124"));
+    }
+
+    #[test]
+    fn sha256_etag_is_hex_digest() {
+        // echo -n "123" | sha256sum
+        assert_eq!(
+            sha256_etag(b"123"),
+            "\"a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3\""
+        );
+    }
+
+    #[test]
+    fn cache_returns_stored_hash_until_file_changes() {
+        let cache = HashCache::default();
+        let path = Path::new("/a.txt");
+        let modified = Some(UNIX_EPOCH);
+
+        let get = |modified, bytes: &[u8]| {
+            cache
+                .try_get_or_compute(path, 3, modified, || Ok::<_, ()>(strong_etag(bytes)))
+                .unwrap()
+        };
+
+        let first = get(modified, b"123");
+        // same size and mtime, so the cached value wins even though bytes differ
+        assert_eq!(get(modified, b"456"), first);
+
+        let changed = get(Some(UNIX_EPOCH + Duration::from_secs(1)), b"456");
+        assert_eq!(changed, strong_etag(b"456"));
+    }
+
+    #[test]
+    fn cache_does_not_store_errors() {
+        let cache = HashCache::default();
+        let path = Path::new("/a.txt");
+
+        assert_eq!(cache.try_get_or_compute(path, 3, None, || Err(())), Err(()));
+        assert_eq!(
+            cache.try_get_or_compute(path, 3, None, || Ok::<_, ()>(strong_etag(b"123"))),
+            Ok(strong_etag(b"123"))
+        );
+    }
+}
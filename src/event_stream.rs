@@ -0,0 +1,207 @@
+use std::io::Read;
+use std::time::Duration;
+
+/// Message of a `text/event-stream` response, see `Response::event_stream`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    /// Type of the event, clients get "message" when None
+    pub event: Option<String>,
+    /// Sent back by reconnecting clients in Last-Event-ID
+    pub id: Option<String>,
+    /// How long clients wait before reconnecting
+    pub retry: Option<Duration>,
+    /// Split into a data field per line
+    pub data: String,
+    /// Sent as a comment, ignored by clients, e.g. to keep idle connections open
+    pub comment: Option<String>,
+}
+
+impl Event {
+    pub fn new(data: &str) -> Self {
+        Event {
+            data: data.to_string(),
+            ..Event::default()
+        }
+    }
+
+    /// Event with no data, which clients do not dispatch.
+    pub fn comment(comment: &str) -> Self {
+        Event {
+            comment: Some(comment.to_string()),
+            ..Event::default()
+        }
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+
+        self
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+
+        self
+    }
+
+    /// Event as sent on the wire, ended by a blank line. Line breaks in single-line
+    /// fields are dropped, they would end the field early.
+    pub(crate) fn as_bytes(&self) -> Vec<u8> {
+        let mut text = String::new();
+
+        if let Some(comment) = &self.comment {
+            for line in lines(comment) {
+                text += &format!(":{line}\n");
+            }
+        }
+        if let Some(event) = &self.event {
+            text += &format!("event: {}\n", single_line(event));
+        }
+        if let Some(id) = &self.id {
+            text += &format!("id: {}\n", single_line(id));
+        }
+        if let Some(retry) = self.retry {
+            text += &format!("retry: {}\n", retry.as_millis());
+        }
+        if !self.data.is_empty() || self.comment.is_none() {
+            for line in lines(&self.data) {
+                text += &format!("data: {line}\n");
+            }
+        }
+        text.push('\n');
+
+        text.into_bytes()
+    }
+}
+
+// CRLF, CR and LF all end a line in an event stream
+fn lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split("\r\n").flat_map(|line| line.split(['\r', '\n']))
+}
+
+fn single_line(text: &str) -> String {
+    text.replace(['\r', '\n'], "")
+}
+
+/// Body of an event stream, gives the events one at a time as the server reads it,
+/// so each one gets sent as soon as the iterator yields it.
+pub(crate) struct EventStreamReader<I> {
+    events: I,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl<I: Iterator<Item = Event>> EventStreamReader<I> {
+    pub(crate) fn new(events: I) -> Self {
+        EventStreamReader {
+            events,
+            pending: vec![],
+            position: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Event>> Read for EventStreamReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.pending.len() {
+            match self.events.next() {
+                Some(event) => {
+                    self.pending = event.as_bytes();
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len() - self.position);
+        buf[..len].copy_from_slice(&self.pending[self.position..self.position + len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod event {
+        use crate::event_stream::Event;
+        use std::time::Duration;
+
+        #[test]
+        fn writes_fields_in_order() {
+            let event = Event::new("hello")
+                .event("greeting")
+                .id("1")
+                .retry(Duration::from_secs(3));
+
+            assert_eq!(
+                event.as_bytes(),
+                b"event: greeting\nid: 1\nretry: 3000\ndata: hello\n\n"
+            );
+        }
+
+        #[test]
+        fn splits_data_into_lines() {
+            assert_eq!(
+                Event::new("a\r\nb\rc\nd").as_bytes(),
+                b"data: a\ndata: b\ndata: c\ndata: d\n\n"
+            );
+        }
+
+        #[test]
+        fn drops_line_breaks_in_single_line_fields() {
+            assert_eq!(
+                Event::new("x").event("a\nid: 2").as_bytes(),
+                b"event: aid: 2\ndata: x\n\n"
+            );
+        }
+
+        #[test]
+        fn comment_has_no_data() {
+            assert_eq!(Event::comment("keep-alive").as_bytes(), b":keep-alive\n\n");
+        }
+    }
+
+    mod event_stream_reader {
+        use crate::event_stream::{Event, EventStreamReader};
+        use std::io::Read;
+
+        #[test]
+        fn reads_one_event_at_a_time() {
+            let events = vec![Event::new("first"), Event::new("second")];
+            let mut reader = EventStreamReader::new(events.into_iter());
+            let mut buf = [0u8; 64];
+
+            let read = reader.read(&mut buf).unwrap();
+            assert_eq!(&buf[..read], b"data: first\n\n");
+
+            let read = reader.read(&mut buf).unwrap();
+            assert_eq!(&buf[..read], b"data: second\n\n");
+
+            assert_eq!(reader.read(&mut buf).unwrap(), 0);
+        }
+
+        #[test]
+        fn splits_events_over_small_buffers() {
+            let mut reader = EventStreamReader::new(std::iter::once(Event::new("hello")));
+            let mut body = vec![];
+            let mut buf = [0u8; 4];
+
+            loop {
+                let read = reader.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                body.extend_from_slice(&buf[..read]);
+            }
+
+            assert_eq!(body, b"data: hello\n\n");
+        }
+    }
+}
@@ -0,0 +1,391 @@
+use crate::clock::Clock;
+use crate::content_source::{Content, ContentBody};
+use crate::server_config::{ContentCacheConfig, OpenFileCacheConfig};
+use crate::types::IoResult;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime};
+
+struct CachedContent {
+    content: Arc<Content>,
+    validated_at: Instant,
+}
+
+/// Open handles and metadata of recently served files, keyed by root and url.
+/// Entries older than `valid` are looked up again, so changes on disk show up within that time.
+pub(crate) struct OpenFileCache {
+    config: OpenFileCacheConfig,
+    entries: Mutex<HashMap<(String, String), CachedContent>>,
+}
+
+impl OpenFileCache {
+    pub(crate) fn new(config: OpenFileCacheConfig) -> Self {
+        OpenFileCache {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn get_or_open(
+        &self,
+        root: &str,
+        url: &str,
+        clock: &dyn Clock,
+        open: impl FnOnce() -> IoResult<Content>,
+    ) -> IoResult<Arc<Content>> {
+        let key = (root.to_string(), url.to_string());
+
+        if let Some(cached) = self.entries.lock().unwrap().get(&key) {
+            if clock.now().duration_since(cached.validated_at) < self.config.valid {
+                return Ok(cached.content.clone());
+            }
+        }
+
+        // opened without holding the lock, a miss should not stall other paths
+        let content = match open() {
+            Ok(content) => Arc::new(content),
+            Err(err) => {
+                self.entries.lock().unwrap().remove(&key);
+                return Err(err);
+            }
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if !entries.contains_key(&key) && entries.len() >= self.config.max_entries {
+            let oldest_key = entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.validated_at)
+                .map(|(key, _)| key.clone());
+
+            if let Some(oldest_key) = oldest_key {
+                entries.remove(&oldest_key);
+            }
+        }
+
+        if self.config.max_entries > 0 {
+            entries.insert(
+                key,
+                CachedContent {
+                    content: content.clone(),
+                    validated_at: clock.now(),
+                },
+            );
+        }
+
+        Ok(content)
+    }
+
+    pub(crate) fn invalidate(&self, root: &str, url: &str) {
+        self.entries
+            .lock()
+            .unwrap()
+            .remove(&(root.to_string(), url.to_string()));
+    }
+}
+
+struct CachedBytes {
+    bytes: Arc<[u8]>,
+    modified: SystemTime,
+    // ContentCacheState::uses when it was last served, the smallest one is evicted first
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct ContentCacheState {
+    entries: HashMap<PathBuf, CachedBytes>,
+    size: u64,
+    uses: u64,
+}
+
+/// Contents of small static files, keyed by canonical path, so hot files are not read
+/// from disk on every request. An entry is read again once the file's modification time
+/// or size changes, the least recently used ones make room for new ones.
+pub(crate) struct ContentCache {
+    config: ContentCacheConfig,
+    state: Mutex<ContentCacheState>,
+}
+
+impl ContentCache {
+    pub(crate) fn new(config: ContentCacheConfig) -> Self {
+        ContentCache {
+            config,
+            state: Mutex::new(ContentCacheState::default()),
+        }
+    }
+
+    /// Bytes of `content`, from the cache when they are still current. Files over
+    /// `max_entry_size` and ones without a modification time are read every time.
+    pub(crate) fn get_or_read(&self, content: &Content) -> IoResult<Vec<u8>> {
+        let (ContentBody::File(_), Some(modified)) = (&content.body, content.modified) else {
+            return content.read();
+        };
+        if content.len > self.config.max_entry_size || content.len > self.config.capacity {
+            return content.read();
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            state.uses += 1;
+            let uses = state.uses;
+
+            if let Some(cached) = state.entries.get_mut(&content.path) {
+                if cached.modified == modified && cached.bytes.len() as u64 == content.len {
+                    cached.last_used = uses;
+                    return Ok(cached.bytes.to_vec());
+                }
+            }
+        }
+
+        // read without holding the lock, a miss should not stall other files
+        let bytes = content.read()?;
+        let mut state = self.state.lock().unwrap();
+        state.remove(&content.path);
+
+        while state.size + content.len > self.config.capacity {
+            let Some(least_used) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, cached)| cached.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            state.remove(&least_used);
+        }
+
+        state.size += content.len;
+        let last_used = state.uses;
+        state.entries.insert(
+            content.path.clone(),
+            CachedBytes {
+                bytes: Arc::from(bytes.as_slice()),
+                modified,
+                last_used,
+            },
+        );
+
+        Ok(bytes)
+    }
+
+    /// For files changed by the server itself, whose modification time may not have moved.
+    pub(crate) fn invalidate(&self, path: &Path) {
+        self.state.lock().unwrap().remove(path);
+    }
+}
+
+impl ContentCacheState {
+    fn remove(&mut self, path: &Path) {
+        if let Some(cached) = self.entries.remove(path) {
+            self.size -= cached.bytes.len() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clock::ManualClock;
+    use crate::content_source::{Content, ContentBody};
+    use crate::file_cache::OpenFileCache;
+    use crate::server_config::OpenFileCacheConfig;
+    use std::cell::Cell;
+    use std::fs::File;
+    use std::io::ErrorKind;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    // not going to mock fs
+    fn open_content() -> std::io::Result<Content> {
+        Ok(Content {
+            path: "test_files/file.txt".into(),
+            body: ContentBody::File(Arc::new(File::open("test_files/file.txt")?)),
+            len: 0,
+            modified: None,
+        })
+    }
+
+    fn get_cache(max_entries: usize, valid: Duration) -> OpenFileCache {
+        OpenFileCache::new(OpenFileCacheConfig { max_entries, valid })
+    }
+
+    #[test]
+    fn reuses_entry_until_it_expires() {
+        let opened = Cell::new(0);
+        let open = || {
+            opened.set(opened.get() + 1);
+            open_content()
+        };
+
+        let clock = ManualClock::new();
+        let cache = get_cache(10, Duration::from_secs(60));
+        cache
+            .get_or_open("root", "/file.txt", &clock, open)
+            .unwrap();
+        clock.advance(Duration::from_secs(59));
+        cache
+            .get_or_open("root", "/file.txt", &clock, open)
+            .unwrap();
+        assert_eq!(opened.get(), 1);
+
+        clock.advance(Duration::from_secs(1));
+        cache
+            .get_or_open("root", "/file.txt", &clock, open)
+            .unwrap();
+        assert_eq!(opened.get(), 2);
+    }
+
+    #[test]
+    fn evicts_oldest_entry_when_full() {
+        let opened = Cell::new(0);
+        let open = || {
+            opened.set(opened.get() + 1);
+            open_content()
+        };
+
+        let clock = ManualClock::new();
+        let cache = get_cache(1, Duration::from_secs(60));
+        cache.get_or_open("root", "/a", &clock, open).unwrap();
+        cache.get_or_open("root", "/b", &clock, open).unwrap();
+        cache.get_or_open("root", "/a", &clock, open).unwrap();
+
+        assert_eq!(opened.get(), 3);
+    }
+
+    #[test]
+    fn invalidated_entry_is_opened_again() {
+        let opened = Cell::new(0);
+        let open = || {
+            opened.set(opened.get() + 1);
+            open_content()
+        };
+
+        let clock = ManualClock::new();
+        let cache = get_cache(10, Duration::from_secs(60));
+        cache
+            .get_or_open("root", "/file.txt", &clock, open)
+            .unwrap();
+        cache.invalidate("root", "/file.txt");
+        cache
+            .get_or_open("root", "/file.txt", &clock, open)
+            .unwrap();
+
+        assert_eq!(opened.get(), 2);
+    }
+
+    #[test]
+    fn does_not_cache_errors() {
+        let clock = ManualClock::new();
+        let cache = get_cache(10, Duration::from_secs(60));
+
+        assert!(cache
+            .get_or_open("root", "/missing", &clock, || Err(
+                ErrorKind::NotFound.into()
+            ))
+            .is_err());
+        assert!(cache
+            .get_or_open("root", "/missing", &clock, open_content)
+            .is_ok());
+    }
+
+    mod content_cache {
+        use crate::content_source::{Content, ContentBody};
+        use crate::file_cache::ContentCache;
+        use crate::server_config::ContentCacheConfig;
+        use std::fs::File;
+        use std::path::PathBuf;
+        use std::sync::Arc;
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        struct TempDir(PathBuf);
+
+        impl TempDir {
+            fn new(name: &str) -> Self {
+                let path = std::env::temp_dir().join(format!(
+                    "http_rs_content_cache_{name}_{}",
+                    std::process::id()
+                ));
+                std::fs::create_dir_all(&path).unwrap();
+
+                TempDir(path)
+            }
+
+            // `modified` is what the cache gets told, the file on disk keeps its own
+            fn content(&self, name: &str, bytes: &str, modified: u64) -> Content {
+                let path = self.0.join(name);
+                std::fs::write(&path, bytes).unwrap();
+
+                Content {
+                    body: ContentBody::File(Arc::new(File::open(&path).unwrap())),
+                    path,
+                    len: bytes.len() as u64,
+                    modified: Some(UNIX_EPOCH + Duration::from_secs(modified)),
+                }
+            }
+        }
+
+        impl Drop for TempDir {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_dir_all(&self.0);
+            }
+        }
+
+        fn get_cache(max_entry_size: u64, capacity: u64) -> ContentCache {
+            ContentCache::new(ContentCacheConfig {
+                max_entry_size,
+                capacity,
+            })
+        }
+
+        #[test]
+        fn reads_again_once_modified() {
+            let dir = TempDir::new("modified");
+            let cache = get_cache(100, 100);
+
+            let bytes = cache.get_or_read(&dir.content("a.txt", "old", 1)).unwrap();
+            assert_eq!(bytes, b"old");
+            let bytes = cache.get_or_read(&dir.content("a.txt", "new", 1)).unwrap();
+            assert_eq!(bytes, b"old");
+            let bytes = cache.get_or_read(&dir.content("a.txt", "new", 2)).unwrap();
+            assert_eq!(bytes, b"new");
+
+            let content = dir.content("a.txt", "newer", 2);
+            cache.invalidate(&content.path);
+            assert_eq!(cache.get_or_read(&content).unwrap(), b"newer");
+        }
+
+        #[test]
+        fn evicts_least_recently_used_to_stay_within_capacity() {
+            let dir = TempDir::new("capacity");
+            let cache = get_cache(100, 6);
+
+            cache.get_or_read(&dir.content("a", "aaa", 1)).unwrap();
+            cache.get_or_read(&dir.content("b", "bbb", 1)).unwrap();
+            cache.get_or_read(&dir.content("a", "aaa", 1)).unwrap();
+            cache.get_or_read(&dir.content("c", "ccc", 1)).unwrap();
+
+            let state = cache.state.lock().unwrap();
+            assert!(state.entries.contains_key(&dir.0.join("a")));
+            assert!(!state.entries.contains_key(&dir.0.join("b")));
+            assert!(state.entries.contains_key(&dir.0.join("c")));
+            assert_eq!(state.size, 6);
+        }
+
+        #[test]
+        fn skips_large_files_and_ones_without_modification_time() {
+            let dir = TempDir::new("skipped");
+            let cache = get_cache(3, 100);
+
+            cache.get_or_read(&dir.content("large", "aaaa", 1)).unwrap();
+            let mut content = dir.content("unknown", "aaa", 1);
+            content.modified = None;
+            assert_eq!(cache.get_or_read(&content).unwrap(), b"aaa");
+            content.modified = Some(SystemTime::now());
+            cache.get_or_read(&content).unwrap();
+
+            let state = cache.state.lock().unwrap();
+            assert_eq!(state.entries.len(), 1);
+            assert_eq!(state.size, 3);
+        }
+    }
+}
@@ -2,7 +2,46 @@ use crate::token::is_valid_token;
 use std::collections::HashMap;
 use std::slice::Iter;
 
-pub static HEADERS_WITH_NUMBER_VALUES: [&str; 1] = ["Content-Length"];
+pub static HEADERS_WITH_NUMBER_VALUES: [&str; 1] = [names::CONTENT_LENGTH];
+
+/// Canonical spelling of header names used by the server.
+pub mod names {
+    pub const ACCEPT: &str = "Accept";
+    pub const ACCEPT_ENCODING: &str = "Accept-Encoding";
+    pub const ACCEPT_RANGES: &str = "Accept-Ranges";
+    pub const AGE: &str = "Age";
+    pub const ALLOW: &str = "Allow";
+    pub const ALT_SVC: &str = "Alt-Svc";
+    pub const CACHE_CONTROL: &str = "Cache-Control";
+    pub const CONNECTION: &str = "Connection";
+    pub const CONTENT_ENCODING: &str = "Content-Encoding";
+    pub const CONTENT_LENGTH: &str = "Content-Length";
+    pub const CONTENT_RANGE: &str = "Content-Range";
+    pub const CONTENT_TYPE: &str = "Content-Type";
+    pub const DATE: &str = "Date";
+    pub const ETAG: &str = "ETag";
+    pub const EXPIRES: &str = "Expires";
+    pub const HOST: &str = "Host";
+    pub const IF_MATCH: &str = "If-Match";
+    pub const IF_MODIFIED_SINCE: &str = "If-Modified-Since";
+    pub const IF_NONE_MATCH: &str = "If-None-Match";
+    pub const IF_RANGE: &str = "If-Range";
+    pub const IF_UNMODIFIED_SINCE: &str = "If-Unmodified-Since";
+    pub const KEEP_ALIVE: &str = "Keep-Alive";
+    pub const LAST_MODIFIED: &str = "Last-Modified";
+    pub const LOCATION: &str = "Location";
+    pub const PRAGMA: &str = "Pragma";
+    pub const RANGE: &str = "Range";
+    pub const REFERER: &str = "Referer";
+    pub const SERVER: &str = "Server";
+    pub const STRICT_TRANSPORT_SECURITY: &str = "Strict-Transport-Security";
+    pub const TRAILER: &str = "Trailer";
+    pub const TRANSFER_ENCODING: &str = "Transfer-Encoding";
+    pub const UPGRADE: &str = "Upgrade";
+    pub const VARY: &str = "Vary";
+    pub const VIA: &str = "Via";
+    pub const WWW_AUTHENTICATE: &str = "WWW-Authenticate";
+}
 
 pub fn is_header_valid(header_name: &str, header_value: &str) -> bool {
     if !is_valid_token(header_name) {
@@ -16,7 +55,7 @@ pub fn is_header_valid(header_name: &str, header_value: &str) -> bool {
     true
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Headers {
     inner: Vec<(String, String)>,
 }
@@ -59,10 +98,29 @@ impl Headers {
             .map(|index| self.inner[index].1.clone())
     }
 
+    pub fn contains_key(&self, header_name: &str) -> bool {
+        self.has(header_name, None)
+    }
+
+    /// Removes every value of the header.
+    pub fn remove(&mut self, header_name: &str) {
+        self.inner
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(header_name));
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+
     pub(crate) fn iter(&self) -> Iter<'_, (String, String)> {
         self.inner.iter()
     }
 
+    #[allow(dead_code)]
     pub(crate) fn as_map(&self) -> HashMap<String, String> {
         let mut out = HashMap::new();
 
@@ -81,3 +139,35 @@ impl<const N: usize> From<[(String, String); N]> for Headers {
         }
     }
 }
+
+impl FromIterator<(String, String)> for Headers {
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(iter: T) -> Self {
+        Headers {
+            inner: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod headers {
+        use crate::header::Headers;
+
+        #[test]
+        fn remove_drops_every_value_ignoring_case() {
+            let mut headers = Headers::from_iter([
+                ("Accept".to_string(), "text/html".to_string()),
+                ("Via".to_string(), "1.1 a".to_string()),
+                ("accept".to_string(), "*/*".to_string()),
+            ]);
+
+            headers.remove("ACCEPT");
+
+            assert_eq!(headers.len(), 1);
+            assert_eq!(headers.get("accept"), None);
+            assert_eq!(headers.get("via"), Some("1.1 a".to_string()));
+            assert!(!headers.contains_key("Accept"));
+            assert!(headers.contains_key("VIA"));
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::header::names;
+use crate::redirect::is_redirect_allowed;
+use crate::request::Request;
+use crate::server::content_type_specificity;
+
+/// Keeps other sites from embedding media, by the Referer of requests for it.
+/// A Referer from the requested host itself is always allowed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotlinkProtection {
+    /// Content types of the files it applies to, `image/*` covers all images
+    pub content_types: Vec<String>,
+    /// Hosts allowed to link, `*.example.com` covers the subdomains of example.com
+    pub allowed_hosts: Vec<String>,
+    /// Requests without a Referer are allowed too, browsers leave it out for privacy
+    pub allow_missing_referer: bool,
+    /// Blocked requests are redirected here, they get 403 when None
+    pub redirect: Option<String>,
+}
+
+impl HotlinkProtection {
+    pub fn new(content_types: &[&str]) -> Self {
+        HotlinkProtection {
+            content_types: content_types.iter().map(|v| v.to_string()).collect(),
+            allowed_hosts: vec![],
+            allow_missing_referer: true,
+            redirect: None,
+        }
+    }
+
+    pub fn allow_host(mut self, host: &str) -> Self {
+        self.allowed_hosts.push(host.to_string());
+
+        self
+    }
+
+    pub fn deny_missing_referer(mut self) -> Self {
+        self.allow_missing_referer = false;
+
+        self
+    }
+
+    pub fn redirect(mut self, location: &str) -> Self {
+        self.redirect = Some(location.to_string());
+
+        self
+    }
+
+    /// True if `request` is for covered media and its Referer is not allowed.
+    pub(crate) fn blocks(&self, request: &Request) -> bool {
+        let path = request.url.split(['?', '#']).next().unwrap_or_default();
+        let Some(mime_type) = mime_guess::from_path(path).first() else {
+            return false;
+        };
+        let is_covered = self
+            .content_types
+            .iter()
+            .any(|pattern| content_type_specificity(pattern, mime_type.essence_str()).is_some());
+        if !is_covered {
+            return false;
+        }
+
+        match request.get_header(names::REFERER) {
+            // only an absolute url names the linking site
+            Some(referer) if referer.contains("://") => !is_redirect_allowed(
+                &referer,
+                request.get_header(names::HOST).as_deref(),
+                &self.allowed_hosts,
+            ),
+            Some(_) => true,
+            None => !self.allow_missing_referer,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hotlink::HotlinkProtection;
+    use crate::request::Request;
+
+    fn image_request(referer: Option<&str>) -> Request {
+        let builder = Request::builder()
+            .url("/images/cat.png?size=large")
+            .header("Host", "example.com:8080");
+
+        match referer {
+            Some(referer) => builder.header("Referer", referer).get(),
+            None => builder.get(),
+        }
+    }
+
+    #[test]
+    fn allows_own_and_listed_hosts() {
+        let protection = HotlinkProtection::new(&["image/*"])
+            .allow_host("partner.org")
+            .allow_host("*.cdn.net");
+
+        assert!(!protection.blocks(&image_request(Some("https://example.com/page"))));
+        assert!(!protection.blocks(&image_request(Some("http://partner.org:81/"))));
+        assert!(!protection.blocks(&image_request(Some("https://eu.cdn.net/x"))));
+        assert!(protection.blocks(&image_request(Some("https://cdn.net/x"))));
+        assert!(protection.blocks(&image_request(Some("https://notpartner.org/"))));
+        assert!(protection.blocks(&image_request(Some("garbage"))));
+    }
+
+    #[test]
+    fn missing_referer_is_allowed_unless_denied() {
+        let protection = HotlinkProtection::new(&["image/png"]);
+
+        assert!(!protection.blocks(&image_request(None)));
+        assert!(protection
+            .deny_missing_referer()
+            .blocks(&image_request(None)));
+    }
+
+    #[test]
+    fn ignores_other_content_types() {
+        let protection = HotlinkProtection::new(&["video/*"]);
+
+        assert!(!protection.blocks(&image_request(Some("https://other.org/"))));
+    }
+}
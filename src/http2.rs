@@ -0,0 +1,493 @@
+//! HTTP/2 connections for `AsyncServer`, through the h2 crate.
+//!
+//! Every stream carries one request, which goes through the same handlers and rules as
+//! HTTP/1 requests, on tokio's blocking pool. Bodies read while sending, from files or
+//! readers, are sent in parts as the flow control window allows. Throttles, the maximum
+//! response size, trailers and the access log only apply to HTTP/1 so far.
+//!
+//! Besides TLS with ALPN, plain connections get HTTP/2 when they start with the client
+//! preface (prior knowledge) or upgrade with `Upgrade: h2c` (RFC 7540, section 3.2).
+
+use crate::header::names;
+use crate::request::{parse_request, Request, RequestBodyType};
+use crate::request_method::RequestMethod;
+use crate::response::{BodyReader, Response, ResponseBody};
+use crate::response_status_code::ResponseStatusCode;
+use crate::server::Server;
+use crate::server_config::ParserConfig;
+use crate::utils::read_exact_at;
+use bytes::Bytes;
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use log::debug;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+pub(crate) const ALPN_H2: &[u8] = b"h2";
+
+/// What HTTP/2 clients start every connection with.
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub(crate) const SWITCHING_TO_H2C: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+const FRAME_HEADER_LEN: usize = 9;
+const FRAME_TYPE_HEADERS: u8 = 0x1;
+const FRAME_TYPE_SETTINGS: u8 = 0x4;
+const FLAG_END_STREAM: u8 = 0x1;
+const FLAG_END_HEADERS: u8 = 0x4;
+// SETTINGS_MAX_FRAME_SIZE until the client learns the server's settings
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024;
+
+// meaningful for the HTTP/1.1 connection only, left out of the request taken over as stream 1
+static UPGRADE_ONLY_HEADERS: [&str; 6] = [
+    "Connection",
+    "HTTP2-Settings",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+// bodies are read and sent in parts of at most this size
+const BODY_CHUNK: usize = 64 * 1024;
+
+// HTTP/2 frames messages itself and forbids connection-specific fields (RFC 9113, section 8.2.2),
+// trailers are not sent yet
+static DROPPED_HEADERS: [&str; 6] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Transfer-Encoding",
+    "Upgrade",
+    "Trailer",
+];
+
+/// Serves the streams of an HTTP/2 connection, each on its own task, until the client goes away.
+pub(crate) async fn serve_connection<S>(
+    server: Server,
+    stream: S,
+    peer_addr: IpAddr,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut connection = h2::server::handshake(stream).await.map_err(io_error)?;
+
+    while let Some(result) = connection.accept().await {
+        let (request, respond) = result.map_err(io_error)?;
+        let server = server.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = serve_stream(server, request, respond, peer_addr).await {
+                debug!("Stream error: {err:?}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+/// True while `buf` may still turn out to be the client preface.
+pub(crate) fn may_be_preface(buf: &[u8]) -> bool {
+    let len = buf.len().min(PREFACE.len());
+
+    buf[..len] == PREFACE[..len]
+}
+
+/// HEADERS frame that carries `request_bytes` over as stream 1, if it is a request asking
+/// to upgrade to h2c that can be taken over. Requests with a body are answered over HTTP/1.1,
+/// the body would have to be sent on as DATA frames within the flow control window.
+pub(crate) fn h2c_upgrade_frame(
+    request_bytes: &[u8],
+    parser_config: &ParserConfig,
+) -> Option<Vec<u8>> {
+    let (request, _) = parse_request(request_bytes, parser_config).ok()?;
+    let has_token = |header_name: &str, token: &str| {
+        request.get_header(header_name).is_some_and(|value| {
+            value
+                .split(',')
+                .any(|member| member.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    let is_upgrade = has_token(names::UPGRADE, "h2c")
+        && has_token(names::CONNECTION, "Upgrade")
+        && has_token(names::CONNECTION, "HTTP2-Settings")
+        && request.has_header("HTTP2-Settings", None);
+    if !is_upgrade || !matches!(request.body_type(), RequestBodyType::None) {
+        return None;
+    }
+
+    let mut block = vec![];
+    encode_header(&mut block, ":method", &request.method.to_string());
+    encode_header(&mut block, ":scheme", "http");
+    encode_header(&mut block, ":path", &request.url);
+    if let Some(host) = request.get_header(names::HOST) {
+        encode_header(&mut block, ":authority", &host);
+    }
+    for (name, value) in request.headers.iter() {
+        let is_dropped = name.eq_ignore_ascii_case(names::HOST)
+            || UPGRADE_ONLY_HEADERS
+                .iter()
+                .any(|dropped| dropped.eq_ignore_ascii_case(name));
+        if !is_dropped {
+            encode_header(&mut block, &name.to_ascii_lowercase(), value);
+        }
+    }
+    if block.len() > DEFAULT_MAX_FRAME_SIZE {
+        return None;
+    }
+
+    let mut frame = (block.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.push(FRAME_TYPE_HEADERS);
+    frame.push(FLAG_END_STREAM | FLAG_END_HEADERS);
+    frame.extend_from_slice(&1u32.to_be_bytes());
+    frame.extend_from_slice(&block);
+
+    Some(frame)
+}
+
+// literal field without indexing (RFC 7541, section 6.2.2), so the client's dynamic table,
+// which its own HEADERS frames refer to, stays as it is
+fn encode_header(block: &mut Vec<u8>, name: &str, value: &str) {
+    block.push(0);
+    for string in [name, value] {
+        encode_length(block, string.len());
+        block.extend_from_slice(string.as_bytes());
+    }
+}
+
+// integer with a 7-bit prefix, the Huffman flag left unset (RFC 7541, section 5.1)
+fn encode_length(block: &mut Vec<u8>, len: usize) {
+    if len < 0x7f {
+        block.push(len as u8);
+        return;
+    }
+
+    block.push(0x7f);
+    let mut rest = len - 0x7f;
+    while rest >= 0x80 {
+        block.push((rest % 0x80) as u8 | 0x80);
+        rest /= 0x80;
+    }
+    block.push(rest as u8);
+}
+
+/// Serves a connection switched to h2c, with the request that asked for it as stream 1.
+/// h2 cannot be handed a request that came in over HTTP/1.1, so `request_frame` is put in
+/// what the client sends, right after its preface and SETTINGS. `buf` holds what the client
+/// sent after the request.
+pub(crate) async fn serve_upgraded<S>(
+    server: Server,
+    mut stream: S,
+    mut buf: Vec<u8>,
+    request_frame: Vec<u8>,
+    peer_addr: IpAddr,
+    timeout: Duration,
+) -> std::io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let settings_start = PREFACE.len();
+    let settings_end = loop {
+        if buf.len() >= settings_start + FRAME_HEADER_LEN {
+            if !buf.starts_with(PREFACE) || buf[settings_start + 3] != FRAME_TYPE_SETTINGS {
+                return Err(ErrorKind::InvalidData.into());
+            }
+            let len = u32::from_be_bytes([
+                0,
+                buf[settings_start],
+                buf[settings_start + 1],
+                buf[settings_start + 2],
+            ]) as usize;
+            let settings_end = settings_start + FRAME_HEADER_LEN + len;
+            if buf.len() >= settings_end {
+                break settings_end;
+            }
+        }
+
+        let mut read_buf = [0u8; 4096];
+        let read = match tokio::time::timeout(timeout, stream.read(&mut read_buf)).await {
+            Ok(read) => read?,
+            Err(_) => return Err(ErrorKind::TimedOut.into()),
+        };
+        if read == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        buf.extend_from_slice(&read_buf[..read]);
+    };
+    buf.splice(settings_end..settings_end, request_frame);
+
+    serve_connection(server, Rewind::new(buf, stream), peer_addr).await
+}
+
+/// Stream that gives back bytes already read from it before reading on.
+pub(crate) struct Rewind<S> {
+    buf: Vec<u8>,
+    position: usize,
+    inner: S,
+}
+
+impl<S> Rewind<S> {
+    pub(crate) fn new(buf: Vec<u8>, inner: S) -> Self {
+        Rewind {
+            buf,
+            position: 0,
+            inner,
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Rewind<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        read_buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.position < self.buf.len() {
+            let len = read_buf.remaining().min(self.buf.len() - self.position);
+            read_buf.put_slice(&self.buf[self.position..self.position + len]);
+            self.position += len;
+
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut self.inner).poll_read(cx, read_buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Rewind<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+async fn serve_stream(
+    server: Server,
+    request: http::Request<RecvStream>,
+    respond: SendResponse<Bytes>,
+    peer_addr: IpAddr,
+) -> std::io::Result<()> {
+    let (parts, mut recv_stream) = request.into_parts();
+    let mut body = vec![];
+
+    while let Some(data) = recv_stream.data().await {
+        let data = data.map_err(io_error)?;
+        // lets the client send more
+        let _ = recv_stream.flow_control().release_capacity(data.len());
+        body.extend_from_slice(&data);
+    }
+
+    let mut request = match Request::try_from(http::Request::from_parts(parts, body)) {
+        Ok(request) => request,
+        Err(err) => {
+            debug!("Unusable HTTP/2 request: {err}");
+            let response = Response::builder()
+                .status_code(ResponseStatusCode::BadRequest)
+                .get();
+            return send_response(respond, response, false).await;
+        }
+    };
+    request.peer_addr = Some(peer_addr);
+    let is_head = request.method == RequestMethod::Head;
+
+    let response = tokio::task::spawn_blocking(move || server.respond(request))
+        .await
+        .map_err(std::io::Error::other)?;
+
+    send_response(respond, response, is_head).await
+}
+
+async fn send_response(
+    mut respond: SendResponse<Bytes>,
+    response: Response,
+    is_head: bool,
+) -> std::io::Result<()> {
+    let mut head = http::Response::builder().status(*response.status_code() as u16);
+    for (name, value) in response.header_lines() {
+        if !DROPPED_HEADERS
+            .iter()
+            .any(|dropped| dropped.eq_ignore_ascii_case(name))
+        {
+            head = head.header(name, value);
+        }
+    }
+    let head = head.body(()).map_err(std::io::Error::other)?;
+
+    let mut body = match response.response_body() {
+        _ if is_head => Body::Memory(Bytes::new()),
+        ResponseBody::File(file, len) => Body::File {
+            file: file.try_clone()?,
+            offset: 0,
+            len: *len,
+        },
+        ResponseBody::Reader(reader) => Body::Reader(reader.clone()),
+        ResponseBody::Bytes(bytes) => Body::Memory(Bytes::from(bytes.clone())),
+    };
+
+    if let Body::Memory(bytes) = body {
+        let is_empty = bytes.is_empty();
+        let mut stream = respond.send_response(head, is_empty).map_err(io_error)?;
+        if !is_empty {
+            send_data(&mut stream, bytes).await?;
+            stream.send_data(Bytes::new(), true).map_err(io_error)?;
+        }
+
+        return Ok(());
+    }
+
+    let mut stream = respond.send_response(head, false).map_err(io_error)?;
+
+    loop {
+        let (chunk, rest) = tokio::task::spawn_blocking(move || {
+            let chunk = body.next_chunk();
+            (chunk, body)
+        })
+        .await
+        .map_err(std::io::Error::other)?;
+        body = rest;
+
+        let chunk = chunk?;
+        if chunk.is_empty() {
+            break;
+        }
+        send_data(&mut stream, Bytes::from(chunk)).await?;
+    }
+
+    stream.send_data(Bytes::new(), true).map_err(io_error)
+}
+
+/// Sends `data` in parts the flow control window has room for.
+async fn send_data(stream: &mut SendStream<Bytes>, mut data: Bytes) -> std::io::Result<()> {
+    while !data.is_empty() {
+        stream.reserve_capacity(data.len());
+
+        let capacity = match std::future::poll_fn(|cx| stream.poll_capacity(cx)).await {
+            Some(capacity) => capacity.map_err(io_error)?,
+            None => return Err(ErrorKind::ConnectionReset.into()),
+        };
+        if capacity == 0 {
+            continue;
+        }
+
+        let part = data.split_to(capacity.min(data.len()));
+        stream.send_data(part, false).map_err(io_error)?;
+    }
+
+    Ok(())
+}
+
+enum Body {
+    Memory(Bytes),
+    File { file: File, offset: u64, len: u64 },
+    Reader(BodyReader),
+}
+
+impl Body {
+    /// Next part of a body that is read while sending, empty once it is over.
+    fn next_chunk(&mut self) -> std::io::Result<Vec<u8>> {
+        match self {
+            Body::Memory(bytes) => Ok(std::mem::take(bytes).to_vec()),
+            Body::File { file, offset, len } => {
+                let chunk_len = BODY_CHUNK.min((*len - *offset) as usize);
+                let mut chunk = vec![0u8; chunk_len];
+                read_exact_at(file, &mut chunk, *offset)?;
+                *offset += chunk_len as u64;
+
+                Ok(chunk)
+            }
+            Body::Reader(reader) => {
+                let mut chunk = vec![0u8; BODY_CHUNK];
+                let mut reader = reader.lock().unwrap();
+
+                loop {
+                    match reader.read(&mut chunk) {
+                        Ok(read) => {
+                            chunk.truncate(read);
+                            return Ok(chunk);
+                        }
+                        Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn io_error(err: h2::Error) -> std::io::Error {
+    if err.is_io() {
+        return err.into_io().unwrap();
+    }
+
+    std::io::Error::other(err)
+}
+
+#[cfg(test)]
+mod test {
+    mod h2c_upgrade_frame {
+        use crate::http2::{encode_length, h2c_upgrade_frame};
+        use crate::server_config::ParserConfig;
+
+        fn frame(request: &str) -> Option<Vec<u8>> {
+            h2c_upgrade_frame(request.as_bytes(), &ParserConfig::strict())
+        }
+
+        #[test]
+        fn carries_request_as_stream_1() {
+            let frame = frame(
+                "GET /up HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\nAccept: */*\r\n\r\n",
+            )
+            .unwrap();
+            let block = &frame[9..];
+
+            assert_eq!(&frame[..3], &(block.len() as u32).to_be_bytes()[1..]);
+            // HEADERS, END_STREAM and END_HEADERS, stream 1
+            assert_eq!(&frame[3..9], &[1, 5, 0, 0, 0, 1]);
+            assert!(
+                block.starts_with(b"\0\x07:method\x03GET\0\x07:scheme\x04http\0\x05:path\x03/up")
+            );
+            assert!(block.ends_with(b"\0\x06accept\x03*/*"));
+            assert!(!block.windows(7).any(|window| window == b"upgrade"));
+        }
+
+        #[test]
+        fn none_without_upgrade_or_with_body() {
+            assert_eq!(
+                frame("GET / HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n"),
+                None
+            );
+            assert_eq!(
+                frame("POST / HTTP/1.1\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\nContent-Length: 1\r\n\r\na"),
+                None
+            );
+        }
+
+        #[test]
+        fn encodes_long_lengths_in_continuation_bytes() {
+            let mut block = vec![];
+            encode_length(&mut block, 126);
+            encode_length(&mut block, 127);
+            encode_length(&mut block, 300);
+
+            assert_eq!(block, [0x7e, 0x7f, 0x00, 0x7f, 0xad, 0x01]);
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use crate::error::{Error, Result};
+use crate::header::Headers;
+use crate::http_version::HttpVersion;
+use crate::request::Request;
+use crate::request_method::RequestMethod;
+use crate::response::Response;
+use crate::response_status_code::ResponseStatusCode;
+use crate::utils::read_exact_at;
+
+fn version_from_http(version: http::Version) -> Result<HttpVersion> {
+    match version {
+        http::Version::HTTP_09 => Ok(HttpVersion::Http0_9),
+        http::Version::HTTP_10 => Ok(HttpVersion::Http1_0),
+        http::Version::HTTP_11 => Ok(HttpVersion::Http1_1),
+        http::Version::HTTP_2 => Ok(HttpVersion::Http2),
+        version => Err(Error::Parse(format!("Unsupported version {version:?}"))),
+    }
+}
+
+fn version_to_http(version: &HttpVersion) -> http::Version {
+    match version {
+        HttpVersion::Http0_9 => http::Version::HTTP_09,
+        HttpVersion::Http1_0 => http::Version::HTTP_10,
+        HttpVersion::Http1_1 => http::Version::HTTP_11,
+        HttpVersion::Http2 => http::Version::HTTP_2,
+    }
+}
+
+fn header_value(value: &http::HeaderValue) -> Result<&str> {
+    value
+        .to_str()
+        .map_err(|_| Error::parse("Header value is not visible ASCII"))
+}
+
+fn http_error(err: http::Error) -> Error {
+    Error::Parse(err.to_string())
+}
+
+impl TryFrom<http::Request<Vec<u8>>> for Request {
+    type Error = Error;
+
+    /// The url keeps only the path and query, an absolute uri's authority becomes
+    /// the Host header unless one is already there.
+    fn try_from(request: http::Request<Vec<u8>>) -> Result<Self> {
+        let (parts, body) = request.into_parts();
+        let method = parts
+            .method
+            .as_str()
+            .parse::<RequestMethod>()
+            .map_err(|_| Error::Parse(format!("Unsupported method {}", parts.method)))?;
+        let url = parts
+            .uri
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str())
+            .to_string();
+
+        let mut headers = Headers::new();
+        for (name, value) in parts.headers.iter() {
+            headers.add(name.as_str(), header_value(value)?);
+        }
+
+        if let Some(authority) = parts.uri.authority() {
+            if !headers.has("Host", None) {
+                headers.add("Host", authority.as_str());
+            }
+        }
+
+        Ok(Request {
+            method,
+            url,
+            version: version_from_http(parts.version)?,
+            headers,
+            body,
+            trailers: Headers::new(),
+            peer_addr: None,
+        })
+    }
+}
+
+impl TryFrom<Request> for http::Request<Vec<u8>> {
+    type Error = Error;
+
+    /// Trailers are dropped, http::Request has no place for them.
+    fn try_from(request: Request) -> Result<Self> {
+        let mut builder = http::Request::builder()
+            .method(request.method.to_string().as_str())
+            .uri(request.url.as_str())
+            .version(version_to_http(&request.version));
+
+        for (name, value) in request.headers.iter() {
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        builder.body(request.body).map_err(http_error)
+    }
+}
+
+impl TryFrom<http::Response<Vec<u8>>> for Response {
+    type Error = Error;
+
+    /// Repeated headers are folded into one comma separated value, but Set-Cookie.
+    fn try_from(response: http::Response<Vec<u8>>) -> Result<Self> {
+        let (parts, body) = response.into_parts();
+        let status_code =
+            ResponseStatusCode::try_from(parts.status.as_u16()).map_err(Error::Parse)?;
+
+        let mut response = Response::builder().status_code(status_code).get();
+        for (name, value) in parts.headers.iter() {
+            response.append_header(name.as_str(), header_value(value)?);
+        }
+        if !body.is_empty() && !response.has_header("Content-Length") {
+            response.set_header("Content-Length", &body.len().to_string());
+        }
+        response.set_body(body);
+
+        Ok(response)
+    }
+}
+
+impl TryFrom<Response> for http::Response<Vec<u8>> {
+    type Error = Error;
+
+    /// A body served from a file or a reader is read into memory.
+    fn try_from(response: Response) -> Result<Self> {
+        let mut builder = http::Response::builder()
+            .status(*response.status_code() as u16)
+            .version(version_to_http(response.version()));
+
+        for (name, value) in response.header_lines() {
+            builder = builder.header(name, value);
+        }
+
+        let body = match (response.body_file(), response.body_reader()) {
+            (Some((file, len)), _) => {
+                let mut body = vec![0u8; len as usize];
+                read_exact_at(file, &mut body, 0)?;
+                body
+            }
+            (None, Some(reader)) => {
+                let mut body = vec![];
+                reader.lock().unwrap().read_to_end(&mut body)?;
+                body
+            }
+            (None, None) => response.body().clone(),
+        };
+
+        builder.body(body).map_err(http_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod request {
+        use crate::error::Error;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+
+        #[test]
+        fn round_trips_through_http() {
+            let request = http::Request::builder()
+                .method("POST")
+                .uri("http://localhost:8080/api?id=1")
+                .header("Content-Type", "text/plain")
+                .body(b"Hello".to_vec())
+                .unwrap();
+
+            let request = Request::try_from(request).unwrap();
+
+            assert_eq!(request.method, RequestMethod::Post);
+            assert_eq!(request.url, "/api?id=1");
+            assert_eq!(
+                request.get_header("host"),
+                Some("localhost:8080".to_string())
+            );
+            assert_eq!(
+                request.get_header("content-type"),
+                Some("text/plain".to_string())
+            );
+
+            let request = http::Request::try_from(request).unwrap();
+
+            assert_eq!(request.method(), http::Method::POST);
+            assert_eq!(request.uri(), "/api?id=1");
+            assert_eq!(request.headers()["host"], "localhost:8080");
+            assert_eq!(request.body(), b"Hello");
+        }
+
+        #[test]
+        fn err_if_method_is_not_supported() {
+            let request = http::Request::builder()
+                .method("TRACE")
+                .uri("/")
+                .body(vec![])
+                .unwrap();
+
+            assert!(matches!(Request::try_from(request), Err(Error::Parse(_))));
+        }
+    }
+
+    mod response {
+        use crate::error::Error;
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+
+        #[test]
+        fn round_trips_through_http() {
+            let response = http::Response::builder()
+                .status(404)
+                .header("Cache-Control", "no-cache")
+                .header("Cache-Control", "no-store")
+                .body(b"Not here".to_vec())
+                .unwrap();
+
+            let response = Response::try_from(response).unwrap();
+
+            assert_eq!(response.status_code(), &ResponseStatusCode::NotFound);
+            assert_eq!(
+                response.headers().get("cache-control"),
+                Some(&"no-cache, no-store".to_string())
+            );
+            assert_eq!(
+                response.headers().get("Content-Length"),
+                Some(&"8".to_string())
+            );
+
+            let response = http::Response::try_from(response).unwrap();
+
+            assert_eq!(response.status(), http::StatusCode::NOT_FOUND);
+            assert_eq!(response.headers()["content-length"], "8");
+            assert_eq!(response.body(), b"Not here");
+        }
+
+        #[test]
+        fn keeps_set_cookie_lines() {
+            let response = http::Response::builder()
+                .header("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT")
+                .header("Set-Cookie", "b=2")
+                .body(vec![])
+                .unwrap();
+
+            let response = http::Response::try_from(Response::try_from(response).unwrap()).unwrap();
+
+            let cookies: Vec<&str> = response
+                .headers()
+                .get_all("set-cookie")
+                .iter()
+                .map(|value| value.to_str().unwrap())
+                .collect();
+            assert_eq!(
+                cookies,
+                ["a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT", "b=2"]
+            );
+        }
+
+        #[test]
+        fn err_if_status_is_not_supported() {
+            let response = http::Response::builder().status(451).body(vec![]).unwrap();
+
+            assert!(matches!(Response::try_from(response), Err(Error::Parse(_))));
+        }
+    }
+}
@@ -2,7 +2,7 @@ use crate::utils::StringUtils;
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 #[allow(dead_code)]
 pub enum HttpVersion {
     Http0_9,
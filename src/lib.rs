@@ -1,15 +1,65 @@
+mod canonical_paths;
+mod client;
+mod conditional;
 mod connection;
-mod header;
+mod error;
+mod etag;
+mod file_cache;
+#[cfg(feature = "http2")]
+mod http2;
+#[cfg(feature = "http")]
+mod http_interop;
+mod load_balancer;
+mod path_lock;
+mod proxy_cache;
+mod redirect;
+#[cfg(test)]
 mod test;
+mod timing;
 mod token;
 mod types;
 mod utils;
 
+pub mod access_log;
+#[cfg(feature = "tokio")]
+pub mod async_server;
+pub mod auth_request;
+#[cfg(feature = "https")]
+pub mod certificate;
+pub mod cli;
+pub mod clock;
+pub mod compression;
+pub mod concurrency_limit;
+pub mod connection_pool;
+pub mod content_source;
+pub mod embedded;
+pub mod event_stream;
+pub mod header;
+pub mod hotlink;
 pub mod http_version;
+pub mod manifest;
+pub mod negotiation;
+pub mod proxy;
+pub mod rate_limit;
+pub mod recorder;
 pub mod request;
+pub mod request_headers;
 pub mod request_method;
 pub mod response;
 pub mod response_status_code;
+pub mod router;
 pub mod rules;
+#[cfg(feature = "s3")]
+pub mod s3;
 pub mod server;
 pub mod server_config;
+pub mod server_handle;
+pub mod signed_url;
+pub mod static_response;
+pub mod stats;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod vhost;
+pub mod well_known;
+
+pub use error::{Error, Result};
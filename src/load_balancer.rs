@@ -0,0 +1,298 @@
+use crate::client::Upstream;
+use crate::proxy::{HealthCheck, LoadBalancing, ProxyRoute};
+use log::{info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct Backend {
+    upstream: Upstream,
+    // requests in flight
+    active: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    // failures in a row
+    fails: u32,
+    // passively marked down, after max_fails failures
+    down_until: Option<Instant>,
+    // by the last active check
+    check_failed: bool,
+}
+
+impl Backend {
+    fn is_available(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap();
+
+        !health.check_failed && health.down_until.is_none_or(|down_until| now >= down_until)
+    }
+}
+
+/// Counts a request as in flight on its backend until dropped.
+pub(crate) struct BackendGuard<'a> {
+    backend: &'a Backend,
+}
+
+impl BackendGuard<'_> {
+    pub(crate) fn upstream(&self) -> &Upstream {
+        &self.backend.upstream
+    }
+}
+
+impl Drop for BackendGuard<'_> {
+    fn drop(&mut self) {
+        self.backend.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Spreads the requests of a proxy route over its backends, skipping those that failed.
+pub(crate) struct LoadBalancer {
+    backends: Vec<Backend>,
+    strategy: LoadBalancing,
+    max_fails: u32,
+    fail_timeout: Duration,
+    // round-robin position
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    /// Starts the active health checks if the route has them, they stop once
+    /// the balancer is dropped. Fails if any backend's upstream does.
+    pub(crate) fn new(route: &ProxyRoute) -> crate::Result<Arc<Self>> {
+        let backends = std::iter::once(&route.upstream)
+            .chain(&route.backends)
+            .map(|url| {
+                Ok(Backend {
+                    upstream: Upstream::new(url, &route.tls_options(), route.pool)?,
+                    active: AtomicUsize::new(0),
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect::<crate::Result<_>>()?;
+
+        let balancer = Arc::new(LoadBalancer {
+            backends,
+            strategy: route.load_balancing,
+            max_fails: route.max_fails,
+            fail_timeout: route.fail_timeout,
+            next: AtomicUsize::new(0),
+        });
+
+        if let Some(health_check) = &route.health_check {
+            spawn_health_checks(Arc::downgrade(&balancer), health_check.clone());
+        }
+
+        Ok(balancer)
+    }
+
+    /// The first backend's, which stands for all of them in the cache.
+    pub(crate) fn authority(&self) -> &str {
+        self.backends[0].upstream.authority()
+    }
+
+    /// Picks a backend that has not been `tried` yet. Backends that are down are only
+    /// picked when all of them are, a request that may fail beats one that surely does.
+    pub(crate) fn pick(
+        &self,
+        client: Option<IpAddr>,
+        tried: &[usize],
+    ) -> Option<(usize, BackendGuard<'_>)> {
+        let now = Instant::now();
+        let untried: Vec<usize> = (0..self.backends.len())
+            .filter(|index| !tried.contains(index))
+            .collect();
+        let available: Vec<usize> = untried
+            .iter()
+            .copied()
+            .filter(|index| self.backends[*index].is_available(now))
+            .collect();
+        let candidates = match available.is_empty() {
+            true if tried.is_empty() => untried,
+            true => return None,
+            false => available,
+        };
+
+        let index = match (self.strategy, client) {
+            (LoadBalancing::LeastConnections, _) => *candidates
+                .iter()
+                .min_by_key(|index| self.backends[**index].active.load(Ordering::Relaxed))?,
+            // the same client keeps getting the same backend while it is up
+            (LoadBalancing::IpHash, Some(client)) => {
+                let mut hasher = DefaultHasher::new();
+                client.hash(&mut hasher);
+                let start = hasher.finish() as usize % self.backends.len();
+
+                (start..self.backends.len())
+                    .chain(0..start)
+                    .find(|index| candidates.contains(index))?
+            }
+            _ => candidates[self.next.fetch_add(1, Ordering::Relaxed) % candidates.len()],
+        };
+
+        let backend = &self.backends[index];
+        backend.active.fetch_add(1, Ordering::Relaxed);
+
+        Some((index, BackendGuard { backend }))
+    }
+
+    /// Marks the backend down for `fail_timeout` once it failed `max_fails` times in a row.
+    pub(crate) fn report(&self, index: usize, succeeded: bool) {
+        let backend = &self.backends[index];
+        let mut health = backend.health.lock().unwrap();
+
+        if succeeded {
+            health.fails = 0;
+            return;
+        }
+
+        health.fails += 1;
+        if self.max_fails > 0 && health.fails >= self.max_fails {
+            warn!(
+                "Upstream {} is down for {:?}",
+                backend.upstream.authority(),
+                self.fail_timeout
+            );
+            health.fails = 0;
+            health.down_until = Some(Instant::now() + self.fail_timeout);
+        }
+    }
+
+    fn check(&self, health_check: &HealthCheck) {
+        for backend in &self.backends {
+            let authority = backend.upstream.authority();
+            let mut request = format!(
+                "GET {} HTTP/1.1\r\nHost: {authority}\r\nUser-Agent: http_rs health check\r\n",
+                health_check.path
+            );
+            if !backend.upstream.keeps_alive() {
+                request += "Connection: close\r\n";
+            }
+            request += "\r\n";
+
+            let passed = backend
+                .upstream
+                .send(authority, request.as_bytes(), false, health_check.timeout)
+                .is_ok_and(|response| (200..400).contains(&response.status));
+
+            let mut health = backend.health.lock().unwrap();
+            if health.check_failed == passed {
+                match passed {
+                    true => info!("Upstream {authority} passed its health check"),
+                    false => warn!("Upstream {authority} failed its health check"),
+                }
+            }
+            health.check_failed = !passed;
+        }
+    }
+}
+
+fn spawn_health_checks(balancer: Weak<LoadBalancer>, health_check: HealthCheck) {
+    thread::spawn(move || loop {
+        match balancer.upgrade() {
+            Some(balancer) => balancer.check(&health_check),
+            None => break,
+        }
+
+        thread::sleep(health_check.interval);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use crate::load_balancer::LoadBalancer;
+    use crate::proxy::{HealthCheck, LoadBalancing, ProxyRoute};
+    use crate::test::mocks::fake_upstream;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    fn route(strategy: LoadBalancing) -> ProxyRoute {
+        ProxyRoute::new("/api", "http://a:80")
+            .backend("http://b:80")
+            .backend("http://c:80")
+            .load_balancing(strategy)
+    }
+
+    fn picks(balancer: &LoadBalancer, client: Option<IpAddr>, count: usize) -> Vec<usize> {
+        (0..count)
+            .map(|_| balancer.pick(client, &[]).unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_skips_backends_marked_down() {
+        let balancer = LoadBalancer::new(
+            &route(LoadBalancing::RoundRobin).passive_health(2, Duration::from_secs(60)),
+        )
+        .unwrap();
+
+        assert_eq!(picks(&balancer, None, 4), vec![0, 1, 2, 0]);
+
+        balancer.report(1, false);
+        assert_eq!(picks(&balancer, None, 3), vec![1, 2, 0]);
+
+        balancer.report(1, false);
+        assert_eq!(picks(&balancer, None, 3), vec![2, 0, 2]);
+    }
+
+    #[test]
+    fn least_connections_picks_least_busy() {
+        let balancer = LoadBalancer::new(&route(LoadBalancing::LeastConnections)).unwrap();
+        let (first, _first_guard) = balancer.pick(None, &[]).unwrap();
+        let (second, second_guard) = balancer.pick(None, &[]).unwrap();
+        drop(second_guard);
+
+        assert_eq!(first, 0);
+        assert_eq!(second, 1);
+        assert_eq!(balancer.pick(None, &[]).unwrap().0, 1);
+    }
+
+    #[test]
+    fn ip_hash_sticks_to_backend() {
+        let balancer = LoadBalancer::new(&route(LoadBalancing::IpHash)).unwrap();
+        let client = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)));
+        let backend = balancer.pick(client, &[]).unwrap().0;
+
+        assert_eq!(picks(&balancer, client, 3), vec![backend; 3]);
+
+        balancer.report(backend, false);
+        let failover = balancer.pick(client, &[]).unwrap().0;
+        assert_ne!(failover, backend);
+        assert_eq!(picks(&balancer, client, 3), vec![failover; 3]);
+    }
+
+    #[test]
+    fn tries_every_backend_once() {
+        let balancer = LoadBalancer::new(&route(LoadBalancing::RoundRobin)).unwrap();
+        balancer.report(0, false);
+        balancer.report(1, false);
+        balancer.report(2, false);
+
+        // all down, so any of them
+        assert!(balancer.pick(None, &[]).is_some());
+        assert!(balancer.pick(None, &[0]).is_none());
+    }
+
+    #[test]
+    fn active_health_check_marks_backends() {
+        let (healthy, handle) = fake_upstream(vec!["HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"]);
+        let balancer = LoadBalancer::new(
+            &ProxyRoute::new("/api", &healthy)
+                // nothing listens on the discard port
+                .backend("http://127.0.0.1:9")
+                .health_check(HealthCheck::new("/health").interval(Duration::from_secs(60))),
+        )
+        .unwrap();
+        let requests = handle.join().unwrap();
+        // the failing backend is checked right after the healthy one
+        std::thread::sleep(Duration::from_millis(100));
+
+        assert!(requests[0].starts_with("GET /health HTTP/1.1\r\n"));
+        assert_eq!(picks(&balancer, None, 3), vec![0, 0, 0]);
+    }
+}
@@ -0,0 +1,147 @@
+use http_rs::cli::{Cli, USAGE};
+use http_rs::rules::{format_warning_in_file, parse_file};
+use http_rs::server::Server;
+use http_rs::server_config::ServerConfig;
+use log::{error, LevelFilter};
+#[cfg(target_os = "linux")]
+use log::{info, warn};
+use std::process::ExitCode;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+/// How long in-flight requests get to finish after SIGINT or SIGTERM.
+#[cfg(target_os = "linux")]
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// SIGINT and SIGTERM are blocked in every thread and picked up with sigwait,
+/// so they never interrupt a thread in the middle of a response.
+#[cfg(target_os = "linux")]
+mod signals {
+    use std::mem::MaybeUninit;
+
+    pub struct ShutdownSignals(libc::sigset_t);
+
+    impl ShutdownSignals {
+        /// Has to be called before any other thread is spawned, they inherit the signal mask.
+        pub fn block() -> Self {
+            // SAFETY: the set is initialized by sigemptyset before it is read
+            unsafe {
+                let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+                libc::sigemptyset(set.as_mut_ptr());
+                let mut set = set.assume_init();
+                libc::sigaddset(&mut set, libc::SIGINT);
+                libc::sigaddset(&mut set, libc::SIGTERM);
+                libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut());
+
+                ShutdownSignals(set)
+            }
+        }
+
+        /// Blocks until one of the signals arrives and returns its number.
+        pub fn wait(&self) -> i32 {
+            let mut signal = 0;
+            // SAFETY: the set was initialized in block(), signal is a live local
+            unsafe { libc::sigwait(&self.0, &mut signal) };
+
+            signal
+        }
+    }
+}
+
+fn check_rules(config: &ServerConfig) -> ExitCode {
+    let Some(rules_path) = &config.rules_path else {
+        eprintln!("No rules file to check, pass one with --rules");
+        return ExitCode::FAILURE;
+    };
+
+    match parse_file(rules_path) {
+        Ok(rules) => {
+            for warning in rules.warnings() {
+                println!("{}\n", format_warning_in_file(warning, &rules.file));
+            }
+            println!(
+                "{} rules, {} warnings",
+                rules.rules.len(),
+                rules.warnings().len()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("{err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cli.help {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    pretty_env_logger::formatted_timed_builder()
+        .filter_level(cli.log_level.unwrap_or(LevelFilter::Info))
+        .init();
+
+    let config = match cli.server_config() {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if cli.dump_config {
+        print!("{}", config.dump());
+        return ExitCode::SUCCESS;
+    }
+
+    if cli.check_rules {
+        return check_rules(&config);
+    }
+
+    match run(config) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn run(config: ServerConfig) -> http_rs::Result<()> {
+    let signals = signals::ShutdownSignals::block();
+    let mut server = Server::new(Some(config));
+    server.self_check()?;
+    let handle = server.start()?;
+
+    let signal = signals.wait();
+    info!("Received signal {signal}, waiting for open connections to finish");
+
+    // a second signal skips the wait
+    std::thread::spawn(move || {
+        signals.wait();
+        warn!("Received second signal, exiting");
+        std::process::exit(130);
+    });
+
+    if !handle.shutdown_timeout(SHUTDOWN_TIMEOUT) {
+        warn!("Some connections were still open after {SHUTDOWN_TIMEOUT:?}");
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn run(config: ServerConfig) -> http_rs::Result<()> {
+    Server::new(Some(config)).run()
+}
@@ -0,0 +1,226 @@
+use crate::etag::{content_hash, HashCache};
+use crate::types::IoResult;
+use crate::utils::escape_json;
+use base64::Engine;
+use log::warn;
+use sha2::{Digest, Sha384};
+use std::fs;
+use std::path::Path;
+
+pub static MANIFEST_URL: &str = "/__manifest.json";
+
+#[derive(Debug, PartialEq)]
+pub struct ManifestEntry {
+    pub url: String,
+    pub size: u64,
+    /// Short content hash, meant for cache busting (e.g. app.js?v=<hash>)
+    pub hash: String,
+    /// Value for the integrity attribute of script/link tags
+    pub integrity: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    pub fn get(&self, url: &str) -> Option<&ManifestEntry> {
+        self.entries.iter().find(|entry| entry.url == url)
+    }
+
+    pub fn to_json(&self) -> String {
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "  \"{}\": {{\"size\": {}, \"hash\": \"{}\", \"integrity\": \"{}\"}}",
+                    escape_json(&entry.url),
+                    entry.size,
+                    entry.hash,
+                    entry.integrity
+                )
+            })
+            .collect::<Vec<String>>();
+
+        if entries.is_empty() {
+            return "{}".to_string();
+        }
+
+        format!("{{\n{}\n}}", entries.join(",\n"))
+    }
+}
+
+pub fn integrity(bytes: &[u8]) -> String {
+    let digest = Sha384::digest(bytes);
+
+    format!(
+        "sha384-{}",
+        base64::engine::general_purpose::STANDARD.encode(digest)
+    )
+}
+
+/// Walks the web root and hashes every file in it. Symlinks are skipped, they may loop or lead
+/// out of the root, and so are files that cannot be read.
+pub fn build_manifest(root: &str) -> IoResult<Manifest> {
+    build_manifest_cached(root, &ManifestCache::default())
+}
+
+#[derive(Default)]
+pub(crate) struct ManifestCache {
+    hashes: HashCache,
+    integrity: HashCache,
+}
+
+pub(crate) fn build_manifest_cached(root: &str, cache: &ManifestCache) -> IoResult<Manifest> {
+    let canonical_root_path = fs::canonicalize(root)?;
+    let mut entries = vec![];
+
+    collect_entries(
+        &canonical_root_path,
+        &canonical_root_path,
+        cache,
+        &mut entries,
+    )?;
+    entries.sort_by(|a, b| a.url.cmp(&b.url));
+
+    Ok(Manifest { entries })
+}
+
+fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    cache: &ManifestCache,
+    entries: &mut Vec<ManifestEntry>,
+) -> IoResult<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        let metadata = fs::symlink_metadata(&path)?;
+
+        if metadata.is_dir() {
+            collect_entries(root, &path, cache, entries)?;
+            continue;
+        }
+        // symlinks may loop or lead out of the root, pipes would block the walk
+        if !metadata.is_file() {
+            continue;
+        }
+
+        let Ok(relative_path) = path.strip_prefix(root) else {
+            continue;
+        };
+
+        let url = relative_path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .fold(String::new(), |url, component| url + "/" + &component);
+
+        let len = metadata.len();
+        let modified = metadata.modified().ok();
+        // read lazily, so the file is not touched at all when both hashes are cached
+        let mut bytes: Option<Vec<u8>> = None;
+
+        let hash = cache.hashes.try_get_or_compute(&path, len, modified, || {
+            read_once(&mut bytes, &path).map(content_hash)
+        });
+        let integrity = cache
+            .integrity
+            .try_get_or_compute(&path, len, modified, || {
+                read_once(&mut bytes, &path).map(integrity)
+            });
+        // hashing the bytes that could be read would cache a wrong hash until the file changes
+        let (hash, integrity) = match (hash, integrity) {
+            (Ok(hash), Ok(integrity)) => (hash, integrity),
+            (Err(err), _) | (_, Err(err)) => {
+                warn!("Leaving {} out of the manifest: {err}", path.display());
+                continue;
+            }
+        };
+
+        entries.push(ManifestEntry {
+            url,
+            size: len,
+            hash,
+            integrity,
+        });
+    }
+
+    Ok(())
+}
+
+fn read_once<'bytes>(bytes: &'bytes mut Option<Vec<u8>>, path: &Path) -> IoResult<&'bytes [u8]> {
+    if bytes.is_none() {
+        *bytes = Some(fs::read(path)?);
+    }
+
+    Ok(bytes.as_deref().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::manifest::{build_manifest, integrity, Manifest, ManifestEntry};
+    use std::fs;
+
+    #[test]
+    fn integrity_is_base64_sha384() {
+        // echo -n "" | openssl dgst -sha384 -binary | openssl base64 -A
+        assert_eq!(
+            integrity(b""),
+            "sha384-OLBgp1GsljhM2TJ+sbHjaiH9txEUvgdDTAzHv2P24donTt6/529l+9Ua0vFImLlb"
+        );
+    }
+
+    #[test]
+    fn contains_files_from_nested_directories() {
+        let manifest = build_manifest("test_files").unwrap();
+
+        let entry = manifest.get("/file.txt").unwrap();
+        assert_eq!(
+            entry.size,
+            fs::metadata("test_files/file.txt").unwrap().len()
+        );
+        assert!(manifest.get("/keys/server.crt").is_some());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn skips_symlinks() {
+        let root = std::env::temp_dir().join(format!("http_rs_manifest_{}", std::process::id()));
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::write(root.join("dir/app.js"), "app").unwrap();
+        std::os::unix::fs::symlink(&root, root.join("dir/loop")).unwrap();
+        std::os::unix::fs::symlink(
+            fs::canonicalize("test_files/file.txt").unwrap(),
+            root.join("outside.txt"),
+        )
+        .unwrap();
+
+        let manifest = build_manifest(root.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        let urls: Vec<&str> = manifest
+            .entries
+            .iter()
+            .map(|entry| entry.url.as_str())
+            .collect();
+        assert_eq!(urls, ["/dir/app.js"]);
+    }
+
+    #[test]
+    fn json_contains_entries() {
+        let manifest = Manifest {
+            entries: vec![ManifestEntry {
+                url: "/a.js".to_string(),
+                size: 3,
+                hash: "abc".to_string(),
+                integrity: "sha384-x".to_string(),
+            }],
+        };
+
+        assert_eq!(
+            manifest.to_json(),
+            "{\n  \"/a.js\": {\"size\": 3, \"hash\": \"abc\", \"integrity\": \"sha384-x\"}\n}"
+        );
+    }
+}
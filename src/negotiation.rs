@@ -0,0 +1,162 @@
+#[derive(Debug, PartialEq)]
+pub struct MediaRange {
+    pub media_type: String,
+    pub subtype: String,
+    pub q: f32,
+}
+
+impl MediaRange {
+    /// Higher is more specific, None if the range does not match at all
+    fn specificity(&self, media_type: &str, subtype: &str) -> Option<u8> {
+        match (self.media_type.as_str(), self.subtype.as_str()) {
+            ("*", "*") => Some(0),
+            (t, "*") if t.eq_ignore_ascii_case(media_type) => Some(1),
+            (t, s) if t.eq_ignore_ascii_case(media_type) && s.eq_ignore_ascii_case(subtype) => {
+                Some(2)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses an Accept header value, skipping malformed entries.
+pub fn parse_accept(value: &str) -> Vec<MediaRange> {
+    let mut ranges = vec![];
+
+    for entry in value.split(',') {
+        let mut parts = entry.split(';').map(|part| part.trim());
+        let Some((media_type, subtype)) = parts.next().and_then(|range| range.split_once('/'))
+        else {
+            continue;
+        };
+
+        if media_type.is_empty() || subtype.is_empty() {
+            continue;
+        }
+
+        let mut q = 1.0;
+
+        for param in parts {
+            if let Some((name, value)) = param.split_once('=') {
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = value.trim().parse::<f32>().unwrap_or(0.0).clamp(0.0, 1.0);
+                }
+            }
+        }
+
+        ranges.push(MediaRange {
+            media_type: media_type.to_string(),
+            subtype: subtype.to_string(),
+            q,
+        });
+    }
+
+    ranges
+}
+
+fn quality(ranges: &[MediaRange], offered: &str) -> f32 {
+    let (media_type, subtype) = offered
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .split_once('/')
+        .unwrap_or((offered, ""));
+
+    ranges
+        .iter()
+        .filter_map(|range| {
+            range
+                .specificity(media_type, subtype)
+                .map(|specificity| (specificity, range.q))
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .map(|(_, q)| q)
+        .unwrap_or(0.0)
+}
+
+/// Picks the offered type the client prefers the most, earlier offers win ties.
+/// Without an Accept header any type is acceptable, so the first one is returned.
+pub fn negotiate<'a>(accept: Option<&str>, offered: &[&'a str]) -> Option<&'a str> {
+    let Some(accept) = accept else {
+        return offered.first().copied();
+    };
+
+    let ranges = parse_accept(accept);
+    let mut best: Option<(&str, f32)> = None;
+
+    for offer in offered {
+        let q = quality(&ranges, offer);
+
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((offer, q));
+        }
+    }
+
+    best.map(|(offer, _)| offer)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::negotiation::{negotiate, parse_accept, MediaRange};
+
+    #[test]
+    fn parses_quality_values() {
+        assert_eq!(
+            parse_accept("text/html;q=0.5, */*"),
+            vec![
+                MediaRange {
+                    media_type: "text".to_string(),
+                    subtype: "html".to_string(),
+                    q: 0.5
+                },
+                MediaRange {
+                    media_type: "*".to_string(),
+                    subtype: "*".to_string(),
+                    q: 1.0
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_malformed_entries() {
+        assert_eq!(parse_accept("html, /, text/plain").len(), 1);
+    }
+
+    #[test]
+    fn picks_highest_quality() {
+        let accept = "text/html;q=0.8, application/json";
+
+        assert_eq!(
+            negotiate(Some(accept), &["text/html", "application/json"]),
+            Some("application/json")
+        );
+    }
+
+    #[test]
+    fn q_zero_is_not_acceptable() {
+        assert_eq!(
+            negotiate(Some("text/html;q=0, */*;q=0.1"), &["text/html"]),
+            None
+        );
+    }
+
+    #[test]
+    fn most_specific_range_wins() {
+        let accept = "text/*;q=0.1, text/html;q=0.9, */*;q=0.5";
+
+        assert_eq!(
+            negotiate(Some(accept), &["text/plain", "text/html", "image/png"]),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn first_offer_without_accept() {
+        assert_eq!(
+            negotiate(None, &["text/html", "application/json"]),
+            Some("text/html")
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+
+/// Serializes writes to the same path, so preconditions checked before a write still hold
+/// when it happens. Writes to other paths go on in parallel.
+#[derive(Default)]
+pub(crate) struct PathLocks {
+    locked: Mutex<HashSet<PathBuf>>,
+    unlocked: Condvar,
+}
+
+impl PathLocks {
+    /// Waits until no other guard holds `path`.
+    pub(crate) fn lock(&self, path: &Path) -> PathGuard<'_> {
+        let mut locked = self.locked.lock().unwrap();
+        while locked.contains(path) {
+            locked = self.unlocked.wait(locked).unwrap();
+        }
+        locked.insert(path.to_path_buf());
+
+        PathGuard {
+            locks: self,
+            path: path.to_path_buf(),
+        }
+    }
+}
+
+pub(crate) struct PathGuard<'locks> {
+    locks: &'locks PathLocks,
+    path: PathBuf,
+}
+
+impl Drop for PathGuard<'_> {
+    fn drop(&mut self) {
+        self.locks.locked.lock().unwrap().remove(&self.path);
+        self.locks.unlocked.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::path_lock::PathLocks;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn second_lock_on_path_waits_for_first() {
+        let locks = Arc::new(PathLocks::default());
+        let guard = locks.lock(Path::new("/root/a.txt"));
+        let _other = locks.lock(Path::new("/root/b.txt"));
+
+        let (sender, receiver) = mpsc::channel();
+        let cloned_locks = locks.clone();
+        let handle = std::thread::spawn(move || {
+            let _guard = cloned_locks.lock(Path::new("/root/a.txt"));
+            sender.send(()).unwrap();
+        });
+
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        drop(guard);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        handle.join().unwrap();
+    }
+}
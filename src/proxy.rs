@@ -0,0 +1,918 @@
+use crate::client::{TlsOptions, UpstreamResponse};
+use crate::clock::Clock;
+use crate::connection_pool::ConnectionPoolConfig;
+use crate::load_balancer::LoadBalancer;
+use crate::proxy_cache::{is_storable, CacheControl, CachedResponse, ProxyCache};
+use crate::request::Request;
+use crate::request_method::RequestMethod;
+use crate::response::Response;
+use crate::response_status_code::ResponseStatusCode;
+use log::{error, warn};
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+// meaningful for a single connection only (RFC 9110, section 7.6.1)
+const HOP_BY_HOP_HEADERS: [&str; 8] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Upgrade",
+];
+
+/// Forwards requests under a path prefix to another server, urls unchanged.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyRoute {
+    pub path_prefix: String,
+    /// Scheme and authority, e.g. http://localhost:8080
+    pub upstream: String,
+    /// Trusted certificates for https upstreams, there are no built-in roots
+    pub ca_certs_path: Option<String>,
+    /// Sent as SNI and checked against the certificates of https upstreams instead of
+    /// their host, for upstreams addressed by IP or an internal name
+    pub tls_server_name: Option<String>,
+    /// Accepts any certificate from https upstreams, for internal backends with self-signed
+    /// ones. Anyone on the network path can then read and change the traffic
+    pub tls_skip_verify: bool,
+    pub timeout: Duration,
+    pub cache: Option<ProxyCacheConfig>,
+    /// Keep-alive connections to the upstream, kept for later requests
+    pub pool: ConnectionPoolConfig,
+    /// Upstreams that share the requests with `upstream`, with the same certificates
+    pub backends: Vec<String>,
+    pub load_balancing: LoadBalancing,
+    /// Failed requests in a row, connecting or timing out, after which a backend is left
+    /// out for `fail_timeout`. Never when 0
+    pub max_fails: u32,
+    pub fail_timeout: Duration,
+    pub health_check: Option<HealthCheck>,
+}
+
+impl ProxyRoute {
+    pub fn new(path_prefix: &str, upstream: &str) -> Self {
+        ProxyRoute {
+            path_prefix: path_prefix.to_string(),
+            upstream: upstream.to_string(),
+            ca_certs_path: None,
+            tls_server_name: None,
+            tls_skip_verify: false,
+            timeout: Duration::from_secs(30),
+            cache: None,
+            pool: ConnectionPoolConfig::new(),
+            backends: vec![],
+            load_balancing: LoadBalancing::default(),
+            max_fails: 1,
+            fail_timeout: Duration::from_secs(10),
+            health_check: None,
+        }
+    }
+
+    pub fn ca_certs(mut self, ca_certs_path: &str) -> Self {
+        self.ca_certs_path = Some(ca_certs_path.to_string());
+
+        self
+    }
+
+    pub fn tls_server_name(mut self, server_name: &str) -> Self {
+        self.tls_server_name = Some(server_name.to_string());
+
+        self
+    }
+
+    pub fn danger_skip_tls_verify(mut self) -> Self {
+        self.tls_skip_verify = true;
+
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+
+    pub fn cache(mut self, cache: ProxyCacheConfig) -> Self {
+        self.cache = Some(cache);
+
+        self
+    }
+
+    pub fn pool(mut self, pool: ConnectionPoolConfig) -> Self {
+        self.pool = pool;
+
+        self
+    }
+
+    pub fn backend(mut self, upstream: &str) -> Self {
+        self.backends.push(upstream.to_string());
+
+        self
+    }
+
+    pub fn load_balancing(mut self, load_balancing: LoadBalancing) -> Self {
+        self.load_balancing = load_balancing;
+
+        self
+    }
+
+    pub fn passive_health(mut self, max_fails: u32, fail_timeout: Duration) -> Self {
+        self.max_fails = max_fails;
+        self.fail_timeout = fail_timeout;
+
+        self
+    }
+
+    pub fn health_check(mut self, health_check: HealthCheck) -> Self {
+        self.health_check = Some(health_check);
+
+        self
+    }
+
+    pub(crate) fn tls_options(&self) -> TlsOptions {
+        TlsOptions {
+            ca_certs_path: self.ca_certs_path.clone(),
+            server_name: self.tls_server_name.clone(),
+            skip_verify: self.tls_skip_verify,
+        }
+    }
+}
+
+/// How the requests of a route are spread over its backends.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LoadBalancing {
+    #[default]
+    RoundRobin,
+    /// The backend with the fewest requests in flight
+    LeastConnections,
+    /// The same backend for each client address, while it is up. Requests without
+    /// a known address go round-robin
+    IpHash,
+}
+
+/// Requests `path` from every backend each `interval`, leaving out those that do not
+/// answer it with 2xx or 3xx until they do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl HealthCheck {
+    pub fn new(path: &str) -> Self {
+        HealthCheck {
+            path: path.to_string(),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+}
+
+/// Shared HTTP cache (RFC 9111) for the responses of an upstream, kept on disk.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProxyCacheConfig {
+    pub dir: PathBuf,
+    /// Larger responses are passed through without being stored
+    pub max_entry_size: u64,
+}
+
+impl ProxyCacheConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ProxyCacheConfig {
+            dir: dir.into(),
+            max_entry_size: 10 * 1024 * 1024,
+        }
+    }
+
+    pub fn max_entry_size(mut self, max_entry_size: u64) -> Self {
+        self.max_entry_size = max_entry_size;
+
+        self
+    }
+}
+
+pub(crate) struct Proxy {
+    route: ProxyRoute,
+    balancer: Arc<LoadBalancer>,
+    cache: Option<ProxyCache>,
+    // urls with a background revalidation in flight
+    refreshing: Mutex<HashSet<String>>,
+    // GET misses being fetched from the upstream, by url
+    in_flight: Mutex<HashMap<String, Arc<Flight>>>,
+}
+
+#[derive(Default)]
+struct Flight {
+    done: Mutex<bool>,
+    finished: Condvar,
+}
+
+impl Flight {
+    fn wait(&self, timeout: Duration) {
+        let done = self.done.lock().unwrap();
+        let _ = self
+            .finished
+            .wait_timeout_while(done, timeout, |done| !*done)
+            .unwrap();
+    }
+}
+
+enum FlightRole<'a> {
+    Leader(FlightGuard<'a>),
+    Follower(Arc<Flight>),
+}
+
+// lands the flight once the leader is done, including when the upstream fails
+struct FlightGuard<'a> {
+    in_flight: &'a Mutex<HashMap<String, Arc<Flight>>>,
+    url: String,
+    flight: Arc<Flight>,
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.url);
+        *self.flight.done.lock().unwrap() = true;
+        self.flight.finished.notify_all();
+    }
+}
+
+impl Proxy {
+    pub(crate) fn new(route: ProxyRoute) -> crate::Result<Self> {
+        Ok(Proxy {
+            balancer: LoadBalancer::new(&route)?,
+            cache: route.cache.clone().map(ProxyCache::new),
+            route,
+            refreshing: Mutex::new(HashSet::new()),
+            in_flight: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn path_prefix(&self) -> &str {
+        &self.route.path_prefix
+    }
+
+    /// Errors are the status codes to answer with when the upstream could not be reached.
+    pub(crate) fn handle(
+        self: &Arc<Self>,
+        request: &Request,
+        clock: &Arc<dyn Clock>,
+    ) -> Result<Response, ResponseStatusCode> {
+        let is_head = request.method == RequestMethod::Head;
+
+        let Some(cache) = &self.cache else {
+            return self
+                .forward(request, &[])
+                .map(|response| client_response(&response, is_head));
+        };
+
+        if !request.method.is_safe() {
+            let response = self.forward(request, &[])?;
+
+            if (200..400).contains(&response.status) {
+                cache.invalidate(self.balancer.authority(), &request.url);
+            }
+
+            return Ok(client_response(&response, is_head));
+        }
+
+        let request_cache_control = CacheControl::of_request(request);
+        let stored = match request.method {
+            RequestMethod::Get | RequestMethod::Head => {
+                cache.lookup(self.balancer.authority(), request)
+            }
+            _ => None,
+        };
+
+        if let Some(stored) = stored {
+            let now = clock.system_time();
+            let age = stored.current_age(now);
+            let stored_cache_control = stored.cache_control();
+
+            if is_reusable(&stored, &request_cache_control, now) {
+                return Ok(cached_response(&stored, age, is_head));
+            }
+
+            // only GET responses get stored, revalidating a HEAD would not refresh the body
+            if request.method == RequestMethod::Get {
+                if !request_cache_control.no_cache
+                    && stored.is_stale_usable(now, stored_cache_control.stale_while_revalidate)
+                {
+                    self.revalidate_in_background(request, stored.clone(), clock.clone());
+                    return Ok(cached_response(&stored, age, false));
+                }
+
+                let stale_if_error = stored_cache_control
+                    .stale_if_error
+                    .or(request_cache_control.stale_if_error);
+
+                return match self.revalidate(cache, request, stored.clone(), &**clock) {
+                    Ok(response) if (*response.status_code() as u16) < 500 => Ok(response),
+                    _ if stored.is_stale_usable(now, stale_if_error) => {
+                        warn!("Upstream failed, serving stale {}", request.url);
+                        Ok(cached_response(&stored, age, false))
+                    }
+                    result => result,
+                };
+            }
+        }
+
+        // concurrent misses wait for the first one, then take its response from the cache
+        let _flight = match request.method {
+            RequestMethod::Get => match self.join_flight(&request.url) {
+                FlightRole::Leader(guard) => Some(guard),
+                FlightRole::Follower(flight) => {
+                    flight.wait(self.route.timeout);
+
+                    if let Some(stored) = cache.lookup(self.balancer.authority(), request) {
+                        let now = clock.system_time();
+
+                        if is_reusable(&stored, &request_cache_control, now) {
+                            return Ok(cached_response(&stored, stored.current_age(now), false));
+                        }
+                    }
+
+                    // not storable, or varies on something else
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        let request_time = clock.system_time();
+        let response = self.forward(request, &[])?;
+
+        if request.method == RequestMethod::Get {
+            return Ok(self.store_and_respond(cache, request, response, request_time, &**clock));
+        }
+
+        Ok(client_response(&response, is_head))
+    }
+
+    fn revalidate(
+        &self,
+        cache: &ProxyCache,
+        request: &Request,
+        mut stored: CachedResponse,
+        clock: &dyn Clock,
+    ) -> Result<Response, ResponseStatusCode> {
+        let request_time = clock.system_time();
+        let response = self.forward(request, &validators(&stored))?;
+        let response_time = clock.system_time();
+
+        if response.status == 304 {
+            stored.freshen(&response, request_time, response_time);
+            self.store(cache, request, &stored);
+
+            return Ok(cached_response(
+                &stored,
+                stored.current_age(response_time),
+                false,
+            ));
+        }
+
+        Ok(self.store_and_respond(cache, request, response, request_time, clock))
+    }
+
+    fn join_flight(&self, url: &str) -> FlightRole<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+
+        if let Some(flight) = in_flight.get(url) {
+            return FlightRole::Follower(flight.clone());
+        }
+
+        let flight = Arc::new(Flight::default());
+        in_flight.insert(url.to_string(), flight.clone());
+
+        FlightRole::Leader(FlightGuard {
+            in_flight: &self.in_flight,
+            url: url.to_string(),
+            flight,
+        })
+    }
+
+    /// At most one refresh per url runs at a time, later requests keep getting the stale response.
+    fn revalidate_in_background(
+        self: &Arc<Self>,
+        request: &Request,
+        stored: CachedResponse,
+        clock: Arc<dyn Clock>,
+    ) {
+        if !self.refreshing.lock().unwrap().insert(request.url.clone()) {
+            return;
+        }
+
+        let proxy = self.clone();
+        let request = detached_request(request);
+
+        thread::spawn(move || {
+            if let Some(cache) = &proxy.cache {
+                let _ = proxy.revalidate(cache, &request, stored, &*clock);
+            }
+
+            proxy.refreshing.lock().unwrap().remove(&request.url);
+        });
+    }
+
+    fn store_and_respond(
+        &self,
+        cache: &ProxyCache,
+        request: &Request,
+        response: UpstreamResponse,
+        request_time: SystemTime,
+        clock: &dyn Clock,
+    ) -> Response {
+        let client_response = client_response(&response, false);
+
+        if is_storable(request, &response, cache.max_entry_size()) {
+            let stored = CachedResponse {
+                request_time,
+                response_time: clock.system_time(),
+                response,
+            };
+            self.store(cache, request, &stored);
+        }
+
+        client_response
+    }
+
+    fn store(&self, cache: &ProxyCache, request: &Request, stored: &CachedResponse) {
+        if let Err(err) = cache.store(self.balancer.authority(), request, stored) {
+            warn!("Could not cache {}: {err}", request.url);
+        }
+    }
+
+    /// Sends the request upstream with `extra_headers`, which replace the client's conditionals.
+    /// Requests that are safe to repeat go to the next backend when one fails.
+    fn forward(
+        &self,
+        request: &Request,
+        extra_headers: &[(&str, String)],
+    ) -> Result<UpstreamResponse, ResponseStatusCode> {
+        let mut tried = vec![];
+        let mut status_code = ResponseStatusCode::BadGateway;
+
+        while let Some((index, backend)) = self.balancer.pick(request.peer_addr, &tried) {
+            let upstream = backend.upstream();
+            let authority = upstream.authority();
+            let bytes = upstream_request(request, authority, extra_headers, upstream.keeps_alive());
+            let result = upstream.send(
+                authority,
+                &bytes,
+                request.method == RequestMethod::Head,
+                self.route.timeout,
+            );
+            self.balancer.report(index, result.is_ok());
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    error!("Proxying {} to {authority} failed: {err}", request.url);
+
+                    status_code = match err.kind() {
+                        ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+                            ResponseStatusCode::GatewayTimeout
+                        }
+                        _ => ResponseStatusCode::BadGateway,
+                    };
+                }
+            }
+
+            if !request.method.is_safe() {
+                break;
+            }
+            tried.push(index);
+        }
+
+        Err(status_code)
+    }
+}
+
+fn is_hop_by_hop(header_name: &str) -> bool {
+    HOP_BY_HOP_HEADERS
+        .iter()
+        .any(|hop_by_hop| hop_by_hop.eq_ignore_ascii_case(header_name))
+}
+
+fn is_reusable(
+    stored: &CachedResponse,
+    request_cache_control: &CacheControl,
+    now: SystemTime,
+) -> bool {
+    stored.is_fresh(now)
+        && !stored.cache_control().no_cache
+        && !request_cache_control.no_cache
+        && request_cache_control
+            .max_age
+            .is_none_or(|max_age| stored.current_age(now).as_secs() <= max_age)
+}
+
+// copy of a GET request to be revalidated after the response has been sent
+fn detached_request(request: &Request) -> Request {
+    request
+        .headers
+        .iter()
+        .fold(
+            Request::builder().url(&request.url),
+            |builder, (name, value)| builder.header(name, value),
+        )
+        .get()
+}
+
+fn validators(stored: &CachedResponse) -> Vec<(&'static str, String)> {
+    let mut headers = vec![];
+
+    if let Some(etag) = stored.response.header("ETag") {
+        headers.push(("If-None-Match", etag.to_string()));
+    }
+
+    if let Some(last_modified) = stored.response.header("Last-Modified") {
+        headers.push(("If-Modified-Since", last_modified.to_string()));
+    }
+
+    headers
+}
+
+fn upstream_request(
+    request: &Request,
+    authority: &str,
+    extra_headers: &[(&str, String)],
+    keep_alive: bool,
+) -> Vec<u8> {
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {authority}\r\n",
+        request.method, request.url
+    );
+    let replaces_conditionals = !extra_headers.is_empty();
+
+    for (name, value) in request.headers.iter() {
+        if is_hop_by_hop(name)
+            || ["Host", "Content-Length", "Transfer-Encoding"]
+                .iter()
+                .any(|skipped| skipped.eq_ignore_ascii_case(name))
+            || (replaces_conditionals && name.to_ascii_lowercase().starts_with("if-"))
+        {
+            continue;
+        }
+
+        head += &format!("{name}: {value}\r\n");
+    }
+
+    for (name, value) in extra_headers {
+        head += &format!("{name}: {value}\r\n");
+    }
+
+    // the body has been read in full, chunked or not
+    if !request.body.is_empty() {
+        head += &format!("Content-Length: {}\r\n", request.body.len());
+    }
+
+    head += "Via: 1.1 http_rs\r\n";
+    // persistent by default in HTTP/1.1
+    if !keep_alive {
+        head += "Connection: close\r\n";
+    }
+    head += "\r\n";
+
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(&request.body);
+
+    bytes
+}
+
+fn client_response(upstream_response: &UpstreamResponse, is_head: bool) -> Response {
+    let Ok(status_code) = ResponseStatusCode::try_from(upstream_response.status) else {
+        error!(
+            "Upstream responded with unsupported status {}",
+            upstream_response.status
+        );
+        return Response::builder()
+            .status_code(ResponseStatusCode::BadGateway)
+            .get();
+    };
+
+    let mut response = Response::builder().status_code(status_code).get();
+    // the upstream's connection options are for the connection to it only
+    let connection_listed: Vec<&str> = upstream_response
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("Connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim)
+        .collect();
+
+    for (name, value) in &upstream_response.headers {
+        if is_hop_by_hop(name)
+            || connection_listed
+                .iter()
+                .any(|listed| listed.eq_ignore_ascii_case(name))
+            || name.eq_ignore_ascii_case("Transfer-Encoding")
+            || (name.eq_ignore_ascii_case("Content-Length") && !is_head)
+        {
+            continue;
+        }
+
+        // repeated fields get folded into one line, Set-Cookie lines are kept apart
+        response.append_header(name, value);
+    }
+
+    if !is_head && ![204, 304].contains(&upstream_response.status) {
+        response.set_header("Content-Length", &upstream_response.body.len().to_string());
+        response.set_body(upstream_response.body.clone());
+    }
+
+    response
+}
+
+fn cached_response(stored: &CachedResponse, age: Duration, is_head: bool) -> Response {
+    let mut response = client_response(&stored.response, false);
+    response.set_header("Age", &age.as_secs().to_string());
+
+    if is_head {
+        response.set_body(vec![]);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    mod upstream_request {
+        use crate::header::Headers;
+        use crate::http_version::HttpVersion;
+        use crate::proxy::upstream_request;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+
+        #[test]
+        fn drops_hop_by_hop_headers() {
+            let request = Request {
+                method: RequestMethod::Post,
+                url: "/api".to_string(),
+                version: HttpVersion::Http1_1,
+                headers: Headers::from([
+                    ("Host".to_string(), "example.com".to_string()),
+                    ("Connection".to_string(), "keep-alive".to_string()),
+                    ("Transfer-Encoding".to_string(), "chunked".to_string()),
+                    ("Accept".to_string(), "text/html".to_string()),
+                ]),
+                body: b"Ok".to_vec(),
+                trailers: Headers::new(),
+                peer_addr: None,
+            };
+
+            let bytes =
+                String::from_utf8(upstream_request(&request, "upstream:8080", &[], false)).unwrap();
+            let keep_alive_bytes =
+                String::from_utf8(upstream_request(&request, "upstream:8080", &[], true)).unwrap();
+
+            assert_eq!(
+                bytes,
+                "POST /api HTTP/1.1\r\nHost: upstream:8080\r\nAccept: text/html\r\n\
+                 Content-Length: 2\r\nVia: 1.1 http_rs\r\nConnection: close\r\n\r\nOk"
+            );
+            assert!(keep_alive_bytes.ends_with("Via: 1.1 http_rs\r\n\r\nOk"));
+        }
+    }
+
+    mod client_response {
+        use crate::client::UpstreamResponse;
+        use crate::proxy::client_response;
+
+        #[test]
+        fn keeps_set_cookie_lines_and_drops_connection_options() {
+            let headers = [
+                ("Connection", "close, X-Upstream-Hop"),
+                ("X-Upstream-Hop", "1"),
+                ("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT"),
+                ("Set-Cookie", "b=2"),
+            ];
+            let upstream_response = UpstreamResponse {
+                status: 204,
+                headers: headers
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.to_string()))
+                    .collect(),
+                body: vec![],
+            };
+
+            let response = client_response(&upstream_response, false);
+            let lines: Vec<(&str, &str)> = response.header_lines().collect();
+
+            assert_eq!(
+                lines,
+                [
+                    ("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT"),
+                    ("Set-Cookie", "b=2")
+                ]
+            );
+        }
+    }
+
+    mod handle {
+        use crate::clock::ManualClock;
+        use crate::proxy::{ProxyCacheConfig, ProxyRoute};
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::test::mocks::fake_upstream;
+        use crate::testing::{run_script, ScriptStep};
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        fn get(url: &str) -> ScriptStep {
+            ScriptStep::Send(format!("GET {url} HTTP/1.1\r\n\r\n").into_bytes())
+        }
+
+        fn cached_server(upstream: &str, dir: &str) -> (Server, Arc<ManualClock>) {
+            let dir = std::env::temp_dir().join(dir);
+            let _ = std::fs::remove_dir_all(&dir);
+            let clock = Arc::new(ManualClock::new());
+            let config = ServerConfigBuilder::new()
+                .proxy_route(ProxyRoute::new("/api", upstream).cache(ProxyCacheConfig::new(dir)))
+                .get();
+
+            (Server::new(Some(config)).clock(clock.clone()), clock)
+        }
+
+        #[test]
+        fn passes_upstream_response_through() {
+            let (upstream, handle) = fake_upstream(vec![
+                "HTTP/1.1 201 Created\r\nX-Upstream: yes\r\nContent-Length: 2\r\n\r\nOk",
+            ]);
+            let config = ServerConfigBuilder::new()
+                .proxy_route(ProxyRoute::new("/api", &upstream))
+                .get();
+            let run = run_script(&Server::new(Some(config)), None, vec![get("/api/items")]);
+            let written = String::from_utf8_lossy(&run.written);
+
+            assert!(written.starts_with("HTTP/1.1 201 Created\r\n"));
+            assert!(written.contains("X-Upstream: yes\r\n"));
+            assert!(written.ends_with("\r\n\r\nOk"));
+            assert!(handle.join().unwrap()[0].starts_with("GET /api/items HTTP/1.1\r\n"));
+        }
+
+        #[test]
+        fn bad_gateway_if_upstream_is_down() {
+            let config = ServerConfigBuilder::new()
+                // nothing listens on the discard port
+                .proxy_route(ProxyRoute::new("/api", "http://127.0.0.1:9"))
+                .get();
+            let run = run_script(&Server::new(Some(config)), None, vec![get("/api")]);
+
+            assert!(String::from_utf8_lossy(&run.written).starts_with("HTTP/1.1 502"));
+        }
+
+        #[test]
+        fn fails_over_to_next_backend() {
+            let (upstream, handle) =
+                fake_upstream(vec!["HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOk"]);
+            let config = ServerConfigBuilder::new()
+                .proxy_route(
+                    // nothing listens on the discard port
+                    ProxyRoute::new("/api", "http://127.0.0.1:9").backend(&upstream),
+                )
+                .get();
+            let run = run_script(&Server::new(Some(config)), None, vec![get("/api/items")]);
+
+            assert!(String::from_utf8_lossy(&run.written).ends_with("\r\n\r\nOk"));
+            assert_eq!(handle.join().unwrap().len(), 1);
+        }
+
+        // not going to mock fs
+        #[test]
+        fn serves_fresh_responses_from_cache_with_age() {
+            let (upstream, handle) = fake_upstream(vec![
+                "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: 2\r\n\r\nOk",
+            ]);
+            let (server, clock) = cached_server(&upstream, "http_rs_proxy_fresh");
+            run_script(&server, None, vec![get("/api/items")]);
+            clock.advance(Duration::from_secs(5));
+            let run = run_script(&server, None, vec![get("/api/items")]);
+            let written = String::from_utf8_lossy(&run.written);
+
+            assert!(written.starts_with("HTTP/1.1 200 OK"));
+            assert!(written.contains("Age: 5\r\n"));
+            assert!(written.ends_with("Ok"));
+            assert_eq!(handle.join().unwrap().len(), 1);
+        }
+
+        #[test]
+        fn revalidates_stale_responses() {
+            let (upstream, handle) = fake_upstream(vec![
+                "HTTP/1.1 200 OK\r\nCache-Control: max-age=1\r\nETag: \"v1\"\r\nContent-Length: 2\r\n\r\nOk",
+                "HTTP/1.1 304 Not Modified\r\nCache-Control: max-age=60\r\nETag: \"v1\"\r\n\r\n",
+            ]);
+            let (server, clock) = cached_server(&upstream, "http_rs_proxy_stale");
+            run_script(&server, None, vec![get("/api/items")]);
+            clock.advance(Duration::from_secs(10));
+            let run = run_script(&server, None, vec![get("/api/items")]);
+            let written = String::from_utf8_lossy(&run.written);
+            let requests = handle.join().unwrap();
+
+            assert!(written.starts_with("HTTP/1.1 200 OK"));
+            assert!(written.contains("Cache-Control: max-age=60\r\n"));
+            assert!(written.ends_with("Ok"));
+            assert!(requests[1].contains("If-None-Match: \"v1\"\r\n"));
+        }
+
+        #[test]
+        fn serves_stale_while_revalidating_in_background() {
+            let (upstream, handle) = fake_upstream(vec![
+                "HTTP/1.1 200 OK\r\nCache-Control: max-age=1, stale-while-revalidate=60\r\nContent-Length: 3\r\n\r\nOld",
+                "HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: 3\r\n\r\nNew",
+            ]);
+            let (server, clock) = cached_server(&upstream, "http_rs_proxy_swr");
+            run_script(&server, None, vec![get("/api/items")]);
+            clock.advance(Duration::from_secs(10));
+            let run = run_script(&server, None, vec![get("/api/items")]);
+
+            assert!(String::from_utf8_lossy(&run.written).ends_with("Old"));
+            assert_eq!(handle.join().unwrap().len(), 2);
+
+            // the refreshed response gets stored right after the upstream answers
+            let refreshed = (0..50).any(|_| {
+                let run = run_script(&server, None, vec![get("/api/items")]);
+                std::thread::sleep(Duration::from_millis(10));
+                String::from_utf8_lossy(&run.written).ends_with("New")
+            });
+            assert!(refreshed);
+        }
+
+        #[test]
+        fn coalesces_concurrent_misses() {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let upstream = format!("http://{}", listener.local_addr().unwrap());
+            let upstream_thread = std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let _ = stream.read(&mut [0u8; 4096]).unwrap();
+                // slow enough for every other request to miss the cache too
+                std::thread::sleep(Duration::from_millis(300));
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: 2\r\n\r\nOk")
+                    .unwrap();
+                drop(stream);
+
+                listener.set_nonblocking(true).unwrap();
+                std::thread::sleep(Duration::from_millis(300));
+                listener.accept().is_ok()
+            });
+            let (server, _) = cached_server(&upstream, "http_rs_proxy_coalesce");
+
+            let clients: Vec<_> = (0..5)
+                .map(|_| {
+                    let server = server.clone();
+                    std::thread::spawn(move || {
+                        let run = run_script(&server, None, vec![get("/api/items")]);
+                        String::from_utf8_lossy(&run.written).ends_with("Ok")
+                    })
+                })
+                .collect();
+
+            for client in clients {
+                assert!(client.join().unwrap());
+            }
+            assert!(
+                !upstream_thread.join().unwrap(),
+                "more than one upstream request"
+            );
+        }
+
+        #[test]
+        fn serves_stale_if_upstream_fails() {
+            let (upstream, handle) = fake_upstream(vec![
+                "HTTP/1.1 200 OK\r\nCache-Control: max-age=1, stale-if-error=60\r\nContent-Length: 2\r\n\r\nOk",
+                "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+            ]);
+            let (server, clock) = cached_server(&upstream, "http_rs_proxy_sie");
+            run_script(&server, None, vec![get("/api/items")]);
+            clock.advance(Duration::from_secs(10));
+            let run = run_script(&server, None, vec![get("/api/items")]);
+            let written = String::from_utf8_lossy(&run.written);
+
+            assert!(written.starts_with("HTTP/1.1 200 OK"));
+            assert!(written.ends_with("Ok"));
+            handle.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,637 @@
+use crate::client::UpstreamResponse;
+use crate::proxy::ProxyCacheConfig;
+use crate::request::Request;
+use crate::types::IoResult;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use xxhash_rust::xxh3::xxh3_64;
+
+// statuses that can be cached without explicit freshness (RFC 9110, section 15.1)
+const HEURISTICALLY_CACHEABLE: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+// file listing the request headers a url varies on, next to its entries
+const VARY_FILE: &str = "vary";
+
+/// Cache-Control directives the cache acts on, from either a request or a response.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub must_revalidate: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    /// How long past its freshness a response may be served while it is revalidated (RFC 5861)
+    pub stale_while_revalidate: Option<u64>,
+    /// How long past its freshness a response may be served when the upstream fails (RFC 5861)
+    pub stale_if_error: Option<u64>,
+}
+
+impl CacheControl {
+    pub(crate) fn parse(header_value: Option<&str>) -> Self {
+        let mut cache_control = CacheControl::default();
+
+        for directive in header_value.unwrap_or_default().split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = value.and_then(|value| value.parse::<u64>().ok());
+
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => cache_control.no_store = true,
+                // no-cache with field names only restricts those fields, treated as a whole
+                "no-cache" => cache_control.no_cache = true,
+                "private" => cache_control.private = true,
+                "public" => cache_control.public = true,
+                "must-revalidate" | "proxy-revalidate" => cache_control.must_revalidate = true,
+                "max-age" => cache_control.max_age = seconds.or(Some(0)),
+                "s-maxage" => cache_control.s_maxage = seconds.or(Some(0)),
+                "stale-while-revalidate" => cache_control.stale_while_revalidate = seconds,
+                "stale-if-error" => cache_control.stale_if_error = seconds,
+                _ => {}
+            }
+        }
+
+        cache_control
+    }
+
+    /// Directives of the request, with `Pragma: no-cache` counting when there is no Cache-Control.
+    pub(crate) fn of_request(request: &Request) -> Self {
+        match request.get_header("Cache-Control") {
+            Some(cache_control) => CacheControl::parse(Some(&cache_control)),
+            None => CacheControl {
+                no_cache: request.has_header("Pragma", Some("no-cache")),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+fn http_date(header_value: Option<&str>) -> Option<SystemTime> {
+    header_value.and_then(|value| httpdate::parse_http_date(value).ok())
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Stored response, with the times needed to tell its age (RFC 9111, section 4.2.3).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CachedResponse {
+    pub request_time: SystemTime,
+    pub response_time: SystemTime,
+    pub response: UpstreamResponse,
+}
+
+impl CachedResponse {
+    pub(crate) fn cache_control(&self) -> CacheControl {
+        CacheControl::parse(self.response.header("Cache-Control"))
+    }
+
+    fn date(&self) -> SystemTime {
+        http_date(self.response.header("Date")).unwrap_or(self.response_time)
+    }
+
+    /// RFC 9111, section 4.2.1, as a shared cache.
+    pub(crate) fn freshness_lifetime(&self) -> Duration {
+        let cache_control = self.cache_control();
+
+        if let Some(seconds) = cache_control.s_maxage.or(cache_control.max_age) {
+            return Duration::from_secs(seconds);
+        }
+
+        if let Some(expires) = self.response.header("Expires") {
+            // invalid dates, like "0", mean already expired
+            return http_date(Some(expires))
+                .and_then(|expires| expires.duration_since(self.date()).ok())
+                .unwrap_or_default();
+        }
+
+        match http_date(self.response.header("Last-Modified")) {
+            Some(last_modified) if HEURISTICALLY_CACHEABLE.contains(&self.response.status) => self
+                .date()
+                .duration_since(last_modified)
+                .map(|since_modified| since_modified / 10)
+                .unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// RFC 9111, section 4.2.3, in whole seconds.
+    pub(crate) fn current_age(&self, now: SystemTime) -> Duration {
+        let age_value = self
+            .response
+            .header("Age")
+            .and_then(|age| age.parse::<u64>().ok())
+            .unwrap_or(0);
+        let response_time = unix_seconds(self.response_time);
+        let apparent_age = response_time.saturating_sub(unix_seconds(self.date()));
+        let response_delay = response_time.saturating_sub(unix_seconds(self.request_time));
+        // Age comes from the upstream, it may be anything up to u64::MAX
+        let corrected_initial_age = apparent_age.max(age_value.saturating_add(response_delay));
+        let resident_time = unix_seconds(now).saturating_sub(response_time);
+
+        Duration::from_secs(corrected_initial_age.saturating_add(resident_time))
+    }
+
+    pub(crate) fn is_fresh(&self, now: SystemTime) -> bool {
+        self.freshness_lifetime() > self.current_age(now)
+    }
+
+    /// Whether the response is stale by no more than `extension` seconds.
+    /// Directives that require revalidation win over the extension.
+    pub(crate) fn is_stale_usable(&self, now: SystemTime, extension: Option<u64>) -> bool {
+        let cache_control = self.cache_control();
+
+        match extension {
+            Some(extension) if !cache_control.must_revalidate && !cache_control.no_cache => {
+                let usable_for = self
+                    .freshness_lifetime()
+                    .saturating_add(Duration::from_secs(extension));
+                self.current_age(now) < usable_for
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes in the headers of a 304 that validated this response (RFC 9111, section 4.3.4).
+    pub(crate) fn freshen(
+        &mut self,
+        not_modified: &UpstreamResponse,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) {
+        for (name, value) in &not_modified.headers {
+            // these describe the 304 itself, not the stored content
+            if name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+
+            self.response
+                .headers
+                .retain(|(stored_name, _)| !stored_name.eq_ignore_ascii_case(name));
+            self.response.headers.push((name.clone(), value.clone()));
+        }
+
+        self.request_time = request_time;
+        self.response_time = response_time;
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!(
+            "{} {} {}\n",
+            unix_seconds(self.request_time),
+            unix_seconds(self.response_time),
+            self.response.status
+        );
+
+        for (name, value) in &self.response.headers {
+            head += &format!("{name}: {value}\n");
+        }
+
+        head += "\n";
+
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.response.body);
+
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let head_len = bytes.windows(2).position(|window| window == b"\n\n")?;
+        let head = std::str::from_utf8(&bytes[..head_len]).ok()?;
+        let mut lines = head.split('\n');
+
+        let mut times = lines.next()?.split(' ');
+        let mut next_number = || times.next()?.parse::<u64>().ok();
+        let request_time = UNIX_EPOCH + Duration::from_secs(next_number()?);
+        let response_time = UNIX_EPOCH + Duration::from_secs(next_number()?);
+        let status = u16::try_from(next_number()?).ok()?;
+
+        let headers = lines
+            .filter_map(|line| line.split_once(": "))
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Some(CachedResponse {
+            request_time,
+            response_time,
+            response: UpstreamResponse {
+                status,
+                headers,
+                body: bytes[head_len + 2..].to_vec(),
+            },
+        })
+    }
+}
+
+/// Whether a response to a GET request may be stored by a shared cache (RFC 9111, section 3).
+pub(crate) fn is_storable(request: &Request, response: &UpstreamResponse, max_size: u64) -> bool {
+    let request_cache_control = CacheControl::of_request(request);
+    let cache_control = CacheControl::parse(response.header("Cache-Control"));
+    let explicitly_cacheable = cache_control.public
+        || cache_control.max_age.is_some()
+        || cache_control.s_maxage.is_some()
+        || response.header("Expires").is_some();
+
+    if request_cache_control.no_store
+        || cache_control.no_store
+        || cache_control.private
+        || response.body.len() as u64 > max_size
+        || response
+            .header("Vary")
+            .is_some_and(|vary| vary.split(',').any(|member| member.trim() == "*"))
+    {
+        return false;
+    }
+
+    // cookies are set for one client, other clients must not get them from the cache
+    if response.header("Set-Cookie").is_some()
+        && !(cache_control.public || cache_control.s_maxage.is_some())
+    {
+        return false;
+    }
+
+    // responses to authenticated requests are only shared when the origin says so
+    if request.has_header("Authorization", None)
+        && !(cache_control.public
+            || cache_control.must_revalidate
+            || cache_control.s_maxage.is_some())
+    {
+        return false;
+    }
+
+    match response.status {
+        // there is no way to combine partial content, and a 304 has nothing to store
+        206 | 304 => false,
+        status => explicitly_cacheable || HEURISTICALLY_CACHEABLE.contains(&status),
+    }
+}
+
+/// Responses stored on disk, one directory per url:
+/// `vary` lists the request headers picked from the latest response,
+/// the entries are named after the values of those headers.
+pub(crate) struct ProxyCache {
+    config: ProxyCacheConfig,
+    // makes temporary file names unique between threads
+    writes: AtomicU64,
+}
+
+impl ProxyCache {
+    pub(crate) fn new(config: ProxyCacheConfig) -> Self {
+        ProxyCache {
+            config,
+            writes: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn max_entry_size(&self) -> u64 {
+        self.config.max_entry_size
+    }
+
+    fn url_dir(&self, authority: &str, url: &str) -> PathBuf {
+        let key = xxh3_64(format!("GET {authority}{url}").as_bytes());
+
+        self.config.dir.join(format!("{key:016x}"))
+    }
+
+    fn entry_name(vary: &str, request: &Request) -> String {
+        let mut secondary_key = String::new();
+
+        for header_name in vary
+            .split(',')
+            .map(|member| member.trim().to_ascii_lowercase())
+        {
+            if header_name.is_empty() {
+                continue;
+            }
+
+            let header_value = request.get_header(&header_name);
+            secondary_key += &format!(
+                "{header_name}={:?}\n",
+                header_value.as_deref().map(str::trim)
+            );
+        }
+
+        format!("{:016x}", xxh3_64(secondary_key.as_bytes()))
+    }
+
+    pub(crate) fn lookup(&self, authority: &str, request: &Request) -> Option<CachedResponse> {
+        let url_dir = self.url_dir(authority, &request.url);
+        let vary = fs::read_to_string(url_dir.join(VARY_FILE)).ok()?;
+        let bytes = fs::read(url_dir.join(Self::entry_name(&vary, request))).ok()?;
+
+        CachedResponse::from_bytes(&bytes)
+    }
+
+    pub(crate) fn store(
+        &self,
+        authority: &str,
+        request: &Request,
+        cached: &CachedResponse,
+    ) -> IoResult<()> {
+        let url_dir = self.url_dir(authority, &request.url);
+        fs::create_dir_all(&url_dir)?;
+
+        let vary = cached.response.header("Vary").unwrap_or_default();
+        self.write_atomically(url_dir.join(VARY_FILE), vary.as_bytes())?;
+        self.write_atomically(
+            url_dir.join(Self::entry_name(vary, request)),
+            &cached.to_bytes(),
+        )
+    }
+
+    /// Drops every stored response for the url, after an unsafe request went through
+    /// (RFC 9111, section 4.4).
+    pub(crate) fn invalidate(&self, authority: &str, url: &str) {
+        match fs::remove_dir_all(self.url_dir(authority, url)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                log::warn!("Could not invalidate cached {url}: {err}");
+            }
+            _ => {}
+        }
+    }
+
+    // readers never see half-written files
+    fn write_atomically(&self, path: PathBuf, bytes: &[u8]) -> IoResult<()> {
+        let write_id = self.writes.fetch_add(1, Ordering::Relaxed);
+        let tmp_path = path.with_extension(format!("tmp{}-{write_id}", std::process::id()));
+        fs::write(&tmp_path, bytes)?;
+
+        fs::rename(&tmp_path, &path).inspect_err(|_| {
+            let _ = fs::remove_file(&tmp_path);
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::client::UpstreamResponse;
+    use crate::request::Request;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn get_request(headers: &[(&str, &str)]) -> Request {
+        headers
+            .iter()
+            .fold(Request::builder().url("/page"), |builder, (name, value)| {
+                builder.header(name, value)
+            })
+            .get()
+    }
+
+    fn response(status: u16, headers: &[(&str, &str)]) -> UpstreamResponse {
+        UpstreamResponse {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: b"body".to_vec(),
+        }
+    }
+
+    fn at(seconds: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    mod cache_control {
+        use crate::proxy_cache::CacheControl;
+
+        #[test]
+        fn parses_directives() {
+            let cache_control =
+                CacheControl::parse(Some("public, Max-Age=60, s-maxage=\"30\", no-cache"));
+
+            assert!(cache_control.public);
+            assert!(cache_control.no_cache);
+            assert_eq!(cache_control.max_age, Some(60));
+            assert_eq!(cache_control.s_maxage, Some(30));
+        }
+
+        #[test]
+        fn parses_stale_extensions() {
+            let cache_control =
+                CacheControl::parse(Some("stale-while-revalidate=30, stale-if-error=600"));
+
+            assert_eq!(cache_control.stale_while_revalidate, Some(30));
+            assert_eq!(cache_control.stale_if_error, Some(600));
+        }
+
+        #[test]
+        fn invalid_max_age_is_zero() {
+            assert_eq!(CacheControl::parse(Some("max-age=soon")).max_age, Some(0));
+        }
+    }
+
+    mod cached_response {
+        use super::{at, response};
+        use crate::proxy_cache::CachedResponse;
+        use std::time::Duration;
+
+        fn cached(headers: &[(&str, &str)]) -> CachedResponse {
+            CachedResponse {
+                request_time: at(1_000),
+                response_time: at(1_002),
+                response: response(200, headers),
+            }
+        }
+
+        #[test]
+        fn s_maxage_wins_over_max_age() {
+            let cached = cached(&[("Cache-Control", "max-age=60, s-maxage=10")]);
+
+            assert_eq!(cached.freshness_lifetime(), Duration::from_secs(10));
+        }
+
+        #[test]
+        fn freshness_from_expires() {
+            let date = httpdate::fmt_http_date(at(1_000));
+            let expires = httpdate::fmt_http_date(at(1_100));
+            let cached = cached(&[("Date", &date), ("Expires", &expires)]);
+
+            assert_eq!(cached.freshness_lifetime(), Duration::from_secs(100));
+        }
+
+        #[test]
+        fn invalid_expires_means_stale() {
+            assert_eq!(
+                cached(&[("Expires", "0")]).freshness_lifetime(),
+                Duration::ZERO
+            );
+        }
+
+        #[test]
+        fn heuristic_freshness_from_last_modified() {
+            let date = httpdate::fmt_http_date(at(1_000));
+            let last_modified = httpdate::fmt_http_date(at(0));
+            let cached = cached(&[("Date", &date), ("Last-Modified", &last_modified)]);
+
+            assert_eq!(cached.freshness_lifetime(), Duration::from_secs(100));
+        }
+
+        #[test]
+        fn age_includes_delay_and_resident_time() {
+            let date = httpdate::fmt_http_date(at(1_001));
+            let cached = cached(&[("Date", &date), ("Age", "5")]);
+
+            // 5 from upstream, 2 on the way, 10 in the cache
+            assert_eq!(cached.current_age(at(1_012)), Duration::from_secs(17));
+        }
+
+        #[test]
+        fn age_saturates_instead_of_overflowing() {
+            let cached = cached(&[("Age", &u64::MAX.to_string())]);
+
+            assert_eq!(cached.current_age(at(1_012)), Duration::from_secs(u64::MAX));
+            assert!(!cached.is_stale_usable(at(1_012), Some(u64::MAX)));
+        }
+
+        #[test]
+        fn stale_usable_within_extension() {
+            let cached = cached(&[("Cache-Control", "max-age=10")]);
+
+            assert!(cached.is_stale_usable(at(1_020), Some(15)));
+            assert!(!cached.is_stale_usable(at(1_030), Some(15)));
+            assert!(!cached.is_stale_usable(at(1_020), None));
+        }
+
+        #[test]
+        fn must_revalidate_wins_over_stale_extension() {
+            let cached = cached(&[("Cache-Control", "max-age=10, must-revalidate")]);
+
+            assert!(!cached.is_stale_usable(at(1_020), Some(15)));
+        }
+
+        #[test]
+        fn freshen_replaces_stored_headers() {
+            let mut cached = cached(&[("ETag", "\"a\""), ("Content-Length", "4")]);
+            cached.freshen(
+                &super::response(304, &[("etag", "\"b\""), ("Content-Length", "0")]),
+                at(2_000),
+                at(2_001),
+            );
+
+            assert_eq!(cached.response.header("ETag"), Some("\"b\""));
+            assert_eq!(cached.response.header("Content-Length"), Some("4"));
+            assert_eq!(cached.response_time, at(2_001));
+        }
+
+        #[test]
+        fn round_trips_through_bytes() {
+            let cached = cached(&[("Content-Type", "text/plain")]);
+
+            assert_eq!(CachedResponse::from_bytes(&cached.to_bytes()), Some(cached));
+        }
+    }
+
+    mod is_storable {
+        use super::{get_request, response};
+        use crate::proxy_cache::is_storable;
+
+        #[test]
+        fn stores_explicitly_fresh_responses() {
+            assert!(is_storable(
+                &get_request(&[]),
+                &response(200, &[("Cache-Control", "max-age=60")]),
+                1024
+            ));
+        }
+
+        #[test]
+        fn does_not_store_private_or_no_store() {
+            for cache_control in ["private", "no-store"] {
+                assert!(!is_storable(
+                    &get_request(&[]),
+                    &response(200, &[("Cache-Control", cache_control)]),
+                    1024
+                ));
+            }
+        }
+
+        #[test]
+        fn does_not_store_set_cookie_unless_public() {
+            let set_cookie = ("Set-Cookie", "session=1");
+
+            assert!(!is_storable(
+                &get_request(&[]),
+                &response(200, &[set_cookie, ("Cache-Control", "max-age=60")]),
+                1024
+            ));
+            assert!(is_storable(
+                &get_request(&[]),
+                &response(200, &[set_cookie, ("Cache-Control", "public, max-age=60")]),
+                1024
+            ));
+        }
+
+        #[test]
+        fn does_not_store_authorized_unless_public() {
+            let request = get_request(&[("Authorization", "Basic YTpi")]);
+
+            assert!(!is_storable(&request, &response(200, &[]), 1024));
+            assert!(is_storable(
+                &request,
+                &response(200, &[("Cache-Control", "public")]),
+                1024
+            ));
+        }
+
+        #[test]
+        fn does_not_store_vary_star_or_oversized() {
+            assert!(!is_storable(
+                &get_request(&[]),
+                &response(200, &[("Vary", "*")]),
+                1024
+            ));
+            assert!(!is_storable(&get_request(&[]), &response(200, &[]), 2));
+        }
+
+        #[test]
+        fn does_not_store_unknown_statuses_without_freshness() {
+            assert!(!is_storable(&get_request(&[]), &response(500, &[]), 1024));
+        }
+    }
+
+    mod proxy_cache {
+        use super::{at, get_request, response};
+        use crate::proxy::ProxyCacheConfig;
+        use crate::proxy_cache::{CachedResponse, ProxyCache};
+
+        // not going to mock fs
+        #[test]
+        fn looks_up_by_vary_headers() {
+            let dir = std::env::temp_dir().join("http_rs_proxy_cache_vary");
+            let _ = std::fs::remove_dir_all(&dir);
+            let cache = ProxyCache::new(ProxyCacheConfig::new(&dir));
+            let english = get_request(&[("Accept-Language", "en")]);
+            let cached = CachedResponse {
+                request_time: at(0),
+                response_time: at(0),
+                response: response(200, &[("Vary", "Accept-Language")]),
+            };
+
+            cache.store("upstream", &english, &cached).unwrap();
+
+            assert_eq!(cache.lookup("upstream", &english), Some(cached));
+            assert_eq!(
+                cache.lookup("upstream", &get_request(&[("Accept-Language", "pl")])),
+                None
+            );
+            assert_eq!(cache.lookup("other_upstream", &english), None);
+
+            cache.invalidate("upstream", "/page");
+            assert_eq!(cache.lookup("upstream", &english), None);
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
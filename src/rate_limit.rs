@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+/// Caps how fast responses to paths under `path_prefix` are sent, like nginx's `limit_rate`.
+/// Every response gets its own allowance, see `ServerConfig::connection_rate_limit`
+/// for one shared by all responses on a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    pub path_prefix: String,
+    pub bytes_per_second: u64,
+    /// Bytes sent at full speed before the limit kicks in
+    pub burst: u64,
+}
+
+impl RateLimit {
+    pub fn new(path_prefix: &str, bytes_per_second: u64) -> Self {
+        RateLimit {
+            path_prefix: path_prefix.to_string(),
+            bytes_per_second,
+            burst: bytes_per_second,
+        }
+    }
+
+    pub fn burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+
+        self
+    }
+}
+
+/// Token bucket refilled at `bytes_per_second`, holding at most `burst` bytes.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    bytes_per_second: f64,
+    burst: f64,
+    // negative when more was sent than the bucket held, it is paid back by waiting
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    pub(crate) fn new(bytes_per_second: u64, burst: u64, now: Instant) -> Self {
+        Throttle {
+            bytes_per_second: bytes_per_second.max(1) as f64,
+            burst: burst as f64,
+            tokens: burst as f64,
+            refilled: now,
+        }
+    }
+
+    /// How long to wait before sending `len` more bytes.
+    pub(crate) fn take(&mut self, len: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed * self.bytes_per_second).min(self.burst);
+        self.tokens -= len as f64;
+
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_second)
+        }
+    }
+
+    /// Writes are split into slices of about a tenth of a second, so the waits stay short.
+    pub(crate) fn slice_len(&self) -> usize {
+        ((self.bytes_per_second / 10.0) as usize).clamp(512, 16 * 1024)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rate_limit::Throttle;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn sends_burst_without_waiting() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(1000, 500, now);
+
+        assert_eq!(throttle.take(500, now), Duration::ZERO);
+        assert_eq!(throttle.take(250, now), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn refills_over_time_up_to_burst() {
+        let now = Instant::now();
+        let mut throttle = Throttle::new(1000, 500, now);
+
+        assert_eq!(throttle.take(1000, now), Duration::from_millis(500));
+        // the debt is paid off after half a second, the bucket does not go over the burst
+        assert_eq!(
+            throttle.take(500, now + Duration::from_millis(500)),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            throttle.take(500, now + Duration::from_secs(10)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,389 @@
+use crate::header::Headers;
+use crate::http_version::HttpVersion;
+use crate::request::Request;
+use crate::request_method::RequestMethod;
+use crate::response::Response;
+use crate::utils::{escape_json, iso8601};
+use base64::Engine;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedBody {
+    /// At most `Recorder::max_body_len` bytes
+    pub bytes: Vec<u8>,
+    /// Length before truncation
+    pub size: usize,
+}
+
+impl RecordedBody {
+    fn new(bytes: &[u8], max_len: usize) -> Self {
+        RecordedBody {
+            bytes: bytes[..bytes.len().min(max_len)].to_vec(),
+            size: bytes.len(),
+        }
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.bytes.len() < self.size
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordedExchange {
+    pub started: SystemTime,
+    pub duration: Duration,
+    pub method: String,
+    pub url: String,
+    pub version: String,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: RecordedBody,
+    pub status: u16,
+    pub status_text: String,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: RecordedBody,
+    /// What went over the wire for the response, status line and headers included
+    pub bytes_sent: u64,
+}
+
+/// Replayed exchange whose response differs from the recorded one.
+#[derive(Debug, PartialEq)]
+pub struct ReplayMismatch {
+    pub url: String,
+    pub recorded_status: u16,
+    pub replayed_status: u16,
+    pub body_differs: bool,
+}
+
+/// Keeps request/response pairs seen by a server, enabled with `Server::recorder`.
+pub struct Recorder {
+    max_body_len: usize,
+    max_entries: usize,
+    exchanges: Mutex<Vec<RecordedExchange>>,
+}
+
+impl Recorder {
+    pub fn new(max_body_len: usize, max_entries: usize) -> Self {
+        Recorder {
+            max_body_len,
+            max_entries,
+            exchanges: Mutex::new(vec![]),
+        }
+    }
+
+    pub(crate) fn record(
+        &self,
+        request: &Request,
+        response: &Response,
+        duration: Duration,
+        bytes_sent: u64,
+    ) {
+        let mut response_headers = response
+            .header_lines()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<(String, String)>>();
+        response_headers.sort();
+
+        let exchange = RecordedExchange {
+            started: SystemTime::now() - duration,
+            duration,
+            method: request.method.to_string(),
+            url: request.url.clone(),
+            version: request.version.to_string(),
+            request_headers: request.headers.iter().cloned().collect(),
+            request_body: RecordedBody::new(&request.body, self.max_body_len),
+            status: *response.status_code() as u16,
+            status_text: response.status_code().to_string(),
+            response_headers,
+            response_body: RecordedBody::new(response.body(), self.max_body_len),
+            bytes_sent,
+        };
+
+        let mut exchanges = self.exchanges.lock().unwrap();
+
+        if exchanges.len() >= self.max_entries {
+            exchanges.remove(0);
+        }
+
+        if self.max_entries > 0 {
+            exchanges.push(exchange);
+        }
+    }
+
+    pub fn exchanges(&self) -> Vec<RecordedExchange> {
+        self.exchanges.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.exchanges.lock().unwrap().clear();
+    }
+
+    pub fn to_har(&self) -> String {
+        to_har(&self.exchanges())
+    }
+
+    /// Sends every recorded request to `handler` again and reports responses that differ.
+    /// Truncated bodies are only compared up to the recorded length.
+    pub fn replay(&self, handler: impl Fn(&Request) -> Response) -> Vec<ReplayMismatch> {
+        replay(&self.exchanges(), handler)
+    }
+}
+
+pub fn replay(
+    exchanges: &[RecordedExchange],
+    handler: impl Fn(&Request) -> Response,
+) -> Vec<ReplayMismatch> {
+    let mut mismatches = vec![];
+
+    for exchange in exchanges {
+        let Some(request) = exchange.to_request() else {
+            continue;
+        };
+
+        let response = handler(&request);
+        let replayed_status = *response.status_code() as u16;
+        let replayed_body = RecordedBody::new(response.body(), exchange.response_body.bytes.len());
+        let body_differs = replayed_body.bytes != exchange.response_body.bytes
+            || (!exchange.response_body.is_truncated()
+                && replayed_body.size != exchange.response_body.size);
+
+        if replayed_status != exchange.status || body_differs {
+            mismatches.push(ReplayMismatch {
+                url: exchange.url.clone(),
+                recorded_status: exchange.status,
+                replayed_status,
+                body_differs,
+            });
+        }
+    }
+
+    mismatches
+}
+
+impl RecordedExchange {
+    /// None if the recorded method or version is not supported anymore.
+    pub fn to_request(&self) -> Option<Request> {
+        let mut headers = Headers::new();
+
+        for (name, value) in &self.request_headers {
+            headers.add(name, value);
+        }
+
+        Some(Request {
+            method: RequestMethod::from_str(&self.method).ok()?,
+            url: self.url.clone(),
+            version: HttpVersion::from_str(&self.version).ok()?,
+            headers,
+            body: self.request_body.bytes.clone(),
+            trailers: Headers::new(),
+            peer_addr: None,
+        })
+    }
+
+    fn header(headers: &[(String, String)], header_name: &str) -> Option<String> {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+            .map(|(_, value)| value.clone())
+    }
+
+    fn full_url(&self) -> String {
+        match Self::header(&self.request_headers, "Host") {
+            Some(host) => format!("http://{host}{}", self.url),
+            None => self.url.clone(),
+        }
+    }
+}
+
+fn har_headers(headers: &[(String, String)]) -> String {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{{\"name\": \"{}\", \"value\": \"{}\"}}",
+                escape_json(name),
+                escape_json(value)
+            )
+        })
+        .collect::<Vec<String>>();
+
+    format!("[{}]", headers.join(", "))
+}
+
+/// HAR content fields, binary bodies are base64 encoded.
+fn har_content(body: &RecordedBody, mime_type: &str) -> String {
+    let text = match std::str::from_utf8(&body.bytes) {
+        Ok(text) => format!("\"text\": \"{}\"", escape_json(text)),
+        Err(_) => format!(
+            "\"text\": \"{}\", \"encoding\": \"base64\"",
+            base64::engine::general_purpose::STANDARD.encode(&body.bytes)
+        ),
+    };
+    let comment = if body.is_truncated() {
+        ", \"comment\": \"truncated\""
+    } else {
+        ""
+    };
+
+    format!(
+        "\"mimeType\": \"{}\", {text}{comment}",
+        escape_json(mime_type)
+    )
+}
+
+fn har_entry(exchange: &RecordedExchange) -> String {
+    let millis = exchange.duration.as_secs_f64() * 1000.0;
+    let request_mime_type =
+        RecordedExchange::header(&exchange.request_headers, "Content-Type").unwrap_or_default();
+    let response_mime_type =
+        RecordedExchange::header(&exchange.response_headers, "Content-Type").unwrap_or_default();
+    let post_data = if exchange.request_body.size > 0 {
+        format!(
+            ", \"postData\": {{{}}}",
+            har_content(&exchange.request_body, &request_mime_type)
+        )
+    } else {
+        String::new()
+    };
+    let redirect_url =
+        RecordedExchange::header(&exchange.response_headers, "Location").unwrap_or_default();
+
+    format!(
+        concat!(
+            "{{\"startedDateTime\": \"{started}\", \"time\": {millis:.3}, ",
+            "\"request\": {{\"method\": \"{method}\", \"url\": \"{url}\", \"httpVersion\": \"{version}\", ",
+            "\"headers\": {request_headers}, \"queryString\": [], \"cookies\": [], ",
+            "\"headersSize\": -1, \"bodySize\": {request_size}{post_data}}}, ",
+            "\"response\": {{\"status\": {status}, \"statusText\": \"{status_text}\", \"httpVersion\": \"{version}\", ",
+            "\"headers\": {response_headers}, \"cookies\": [], ",
+            "\"content\": {{\"size\": {response_size}, {content}}}, ",
+            "\"redirectURL\": \"{redirect_url}\", \"headersSize\": -1, \"bodySize\": {response_size}, \"_transferSize\": {bytes_sent}}}, ",
+            "\"cache\": {{}}, \"timings\": {{\"send\": 0, \"wait\": {millis:.3}, \"receive\": 0}}}}"
+        ),
+        started = iso8601(exchange.started),
+        millis = millis,
+        method = escape_json(&exchange.method),
+        url = escape_json(&exchange.full_url()),
+        version = escape_json(&exchange.version),
+        request_headers = har_headers(&exchange.request_headers),
+        request_size = exchange.request_body.size,
+        post_data = post_data,
+        status = exchange.status,
+        status_text = escape_json(&exchange.status_text),
+        response_headers = har_headers(&exchange.response_headers),
+        response_size = exchange.response_body.size,
+        content = har_content(&exchange.response_body, &response_mime_type),
+        redirect_url = escape_json(&redirect_url),
+        bytes_sent = exchange.bytes_sent,
+    )
+}
+
+pub fn to_har(exchanges: &[RecordedExchange]) -> String {
+    let entries = exchanges.iter().map(har_entry).collect::<Vec<String>>();
+
+    format!(
+        "{{\"log\": {{\"version\": \"1.2\", \"creator\": {{\"name\": \"{}\", \"version\": \"{}\"}}, \"entries\": [{}]}}}}",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        entries.join(", ")
+    )
+}
+
+/// UTC timestamp with milliseconds, e.g. 2009-07-24T19:20:30.450Z
+#[cfg(test)]
+mod test {
+    use crate::header::Headers;
+    use crate::http_version::HttpVersion;
+    use crate::recorder::Recorder;
+    use crate::request::Request;
+    use crate::request_method::RequestMethod;
+    use crate::response::Response;
+    use crate::response_status_code::ResponseStatusCode;
+    use crate::utils::iso8601;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn get_request(url: &str) -> Request {
+        Request {
+            method: RequestMethod::Post,
+            url: url.to_string(),
+            version: HttpVersion::Http1_1,
+            headers: Headers::from([("Host".to_string(), "localhost".to_string())]),
+            body: b"123456".to_vec(),
+            trailers: Headers::new(),
+            peer_addr: None,
+        }
+    }
+
+    fn ok_response(body: &str) -> Response {
+        Response::builder()
+            .header("Content-Type", "text/plain")
+            .text_body(body)
+            .get()
+    }
+
+    #[test]
+    fn formats_iso8601() {
+        assert_eq!(
+            iso8601(UNIX_EPOCH + Duration::from_millis(784_111_777_450)),
+            "1994-11-06T08:49:37.450Z"
+        );
+        assert_eq!(iso8601(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn truncates_bodies_and_drops_oldest_entries() {
+        let recorder = Recorder::new(4, 1);
+        recorder.record(&get_request("/a"), &ok_response("ok"), Duration::ZERO, 0);
+        recorder.record(&get_request("/b"), &ok_response("ok"), Duration::ZERO, 0);
+
+        let exchanges = recorder.exchanges();
+        assert_eq!(exchanges.len(), 1);
+        assert_eq!(exchanges[0].url, "/b");
+        assert_eq!(exchanges[0].request_body.bytes, b"1234");
+        assert!(exchanges[0].request_body.is_truncated());
+    }
+
+    #[test]
+    fn exports_har() {
+        let recorder = Recorder::new(1024, 10);
+        recorder.record(
+            &get_request("/a"),
+            &ok_response("ok"),
+            Duration::from_millis(5),
+            64,
+        );
+
+        let har = recorder.to_har();
+        assert!(har.starts_with("{\"log\": {\"version\": \"1.2\""));
+        assert!(har.contains("\"url\": \"http://localhost/a\""));
+        assert!(har.contains("\"postData\": {\"mimeType\": \"\", \"text\": \"123456\"}"));
+        assert!(har.contains(
+            "\"content\": {\"size\": 2, \"mimeType\": \"text/plain\", \"text\": \"ok\"}"
+        ));
+        assert!(har.contains("\"time\": 5.000"));
+        assert!(har.contains("\"_transferSize\": 64"));
+    }
+
+    #[test]
+    fn replay_reports_differences() {
+        let recorder = Recorder::new(1024, 10);
+        recorder.record(&get_request("/a"), &ok_response("ok"), Duration::ZERO, 0);
+        recorder.record(&get_request("/b"), &ok_response("ok"), Duration::ZERO, 0);
+
+        let mismatches = recorder.replay(|request| {
+            if request.url == "/a" {
+                ok_response("ok")
+            } else {
+                Response::builder()
+                    .status_code(ResponseStatusCode::NotFound)
+                    .get()
+            }
+        });
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].url, "/b");
+        assert_eq!(mismatches[0].replayed_status, 404);
+        assert!(mismatches[0].body_differs);
+    }
+}
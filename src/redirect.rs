@@ -0,0 +1,137 @@
+use crate::vhost::normalize_host;
+
+/// Percent-encodes what may not appear in a URI reference (RFC 3986), CR and LF included,
+/// so a Location value can neither split the response nor be read differently by clients.
+/// Existing escapes are kept as they are.
+pub(crate) fn encode_location(location: &str) -> String {
+    let mut out = String::with_capacity(location.len());
+
+    for byte in location.trim().bytes() {
+        match byte {
+            b'!'..=b'~' if !b"\"<>\\^`{|}".contains(&byte) => out.push(byte as char),
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+
+    out
+}
+
+fn scheme(location: &str) -> Option<&str> {
+    let (scheme, _) = location.split_once(':')?;
+    let mut chars = scheme.chars();
+
+    (chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)))
+    .then_some(scheme)
+}
+
+/// Relative redirects are always allowed, absolute ones only to `own_host` (the Host of the
+/// request) or to one of `allowed_hosts`, where "*.example.com" matches any subdomain.
+/// Expects an encoded location.
+pub(crate) fn is_redirect_allowed(
+    location: &str,
+    own_host: Option<&str>,
+    allowed_hosts: &[String],
+) -> bool {
+    let rest = match scheme(location) {
+        Some(scheme) => &location[scheme.len() + 1..],
+        None => location,
+    };
+
+    let Some(rest) = rest.strip_prefix("//") else {
+        // a scheme without an authority (javascript:, data:) is not a redirect
+        return scheme(location).is_none();
+    };
+
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = normalize_host(authority.rsplit('@').next().unwrap_or_default());
+
+    own_host.is_some_and(|own_host| normalize_host(own_host) == host)
+        || allowed_hosts.iter().any(|allowed| {
+            let allowed = normalize_host(allowed);
+            match allowed.strip_prefix('*') {
+                Some(suffix) => host.ends_with(suffix),
+                None => allowed == host,
+            }
+        })
+}
+
+#[cfg(test)]
+mod test {
+    mod encode_location {
+        use crate::redirect::encode_location;
+
+        #[test]
+        fn encodes_line_breaks_and_unsafe_characters() {
+            assert_eq!(
+                encode_location("/a b\r\nSet-Cookie: x=1"),
+                "/a%20b%0D%0ASet-Cookie:%20x=1"
+            );
+            assert_eq!(encode_location("/\\evil.com"), "/%5Cevil.com");
+            assert_eq!(encode_location("/zażółć"), "/za%C5%BC%C3%B3%C5%82%C4%87");
+        }
+
+        #[test]
+        fn keeps_valid_locations() {
+            let location = "https://example.com/a%20b?c=d&e=f#g";
+
+            assert_eq!(encode_location(location), location);
+        }
+    }
+
+    mod is_redirect_allowed {
+        use crate::redirect::is_redirect_allowed;
+
+        fn allowed_hosts() -> Vec<String> {
+            vec!["example.com".to_string(), "*.cdn.example.com".to_string()]
+        }
+
+        #[test]
+        fn relative_locations_are_allowed() {
+            assert!(is_redirect_allowed("/index.html", None, &[]));
+            assert!(is_redirect_allowed("index.html?a=b:c", None, &[]));
+        }
+
+        #[test]
+        fn own_host_is_allowed() {
+            assert!(is_redirect_allowed(
+                "https://localhost/a",
+                Some("localhost:8080"),
+                &[]
+            ));
+        }
+
+        #[test]
+        fn matches_allowed_hosts() {
+            assert!(is_redirect_allowed(
+                "https://Example.com:443/a",
+                None,
+                &allowed_hosts()
+            ));
+            assert!(is_redirect_allowed(
+                "//img.cdn.example.com",
+                None,
+                &allowed_hosts()
+            ));
+            assert!(!is_redirect_allowed(
+                "https://evil.com",
+                None,
+                &allowed_hosts()
+            ));
+            assert!(!is_redirect_allowed(
+                "https://example.com@evil.com/",
+                None,
+                &allowed_hosts()
+            ));
+        }
+
+        #[test]
+        fn schemes_without_authority_are_refused() {
+            assert!(!is_redirect_allowed(
+                "javascript:alert(1)",
+                None,
+                &allowed_hosts()
+            ));
+        }
+    }
+}
@@ -1,14 +1,14 @@
-use crate::header::{is_header_valid, Headers};
+use crate::error::{Error, Result};
+use crate::header::{is_header_valid, names, Headers};
 use crate::http_version::HttpVersion;
 use crate::request_method::RequestMethod;
-use crate::utils::{skip_whitespace, IteratorUtils, StringUtils};
+use crate::server_config::ParserConfig;
+use crate::utils::{body_summary, skip_spaces, skip_whitespace, IteratorUtils, StringUtils};
 use log::debug;
-use std::error::Error;
 use std::fmt;
+use std::net::IpAddr;
 use std::str::FromStr;
 
-type Result<T> = std::result::Result<T, Box<dyn Error>>;
-
 #[derive(Debug)]
 pub enum RequestBodyType {
     None,
@@ -16,12 +16,17 @@ pub enum RequestBodyType {
     TransferEncodingChunked,
 }
 
+#[derive(Clone, PartialEq)]
 pub struct Request {
     pub method: RequestMethod,
     pub url: String,
     pub version: HttpVersion,
     pub headers: Headers,
     pub body: Vec<u8>,
+    /// Trailer fields of a chunked body
+    pub trailers: Headers,
+    /// Address of the client, None when the request did not come in over a socket
+    pub peer_addr: Option<IpAddr>,
 }
 
 impl Request {
@@ -50,37 +55,156 @@ impl Request {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::from(self.head());
+
+        if !self.body.is_empty() {
+            bytes.append(&mut self.body.clone());
+        }
+
+        bytes
+    }
+
+    /// Request as sent on the wire, with the body summarized for logging.
+    pub fn to_wire_string(&self) -> String {
+        self.head() + &body_summary(&self.body)
+    }
+
+    fn head(&self) -> String {
         let mut str = format!("{} {} {}\r\n", self.method, self.url, self.version);
 
         for (name, value) in self.headers.iter() {
             str += format!("{}: {}\r\n", name, value).as_str();
         }
 
-        str += "\r\n";
+        str + "\r\n"
+    }
 
-        let mut bytes = Vec::from(str);
+    pub fn builder() -> RequestBuilder {
+        RequestBuilder::new()
+    }
 
-        if !self.body.is_empty() {
-            bytes.append(&mut self.body.clone());
+    /// The url without its query, what files and path prefixes are matched against.
+    pub fn path(&self) -> &str {
+        self.url.split(['?', '#']).next().unwrap_or_default()
+    }
+}
+
+/// `path` the way the file system sees it: repeated slashes collapsed, `.` and `..` resolved.
+/// A trailing slash is kept, None if `..` climbs above the root.
+pub(crate) fn normalize_path(path: &str) -> Option<String> {
+    let mut segments = vec![];
+    let mut is_directory = false;
+
+    for segment in path.split('/') {
+        is_directory = true;
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => {
+                segments.push(segment);
+                is_directory = false;
+            }
         }
+    }
 
-        bytes
+    let mut normalized = format!("/{}", segments.join("/"));
+    if is_directory && !segments.is_empty() {
+        normalized.push('/');
     }
+
+    Some(normalized)
+}
+
+/// Whether `path` is `prefix` or below it, /static matches /static/app.js but not /statics.
+pub(crate) fn has_path_prefix(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/') || prefix.ends_with('/'))
 }
 
 impl fmt::Debug for Request {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Request")
-            .field("method", &self.method)
-            .field("url", &self.url)
-            .field("version", &self.version)
-            .field("headers", &self.headers)
-            .field("body", &format!("{} bytes", self.body.len()))
-            .finish()
+        f.write_str(&self.to_wire_string())
     }
 }
 
-fn take_until_crlf<'a>(iterator: &mut impl Iterator<Item = &'a u8>) -> Result<Vec<u8>>
+#[derive(Debug)]
+pub struct RequestBuilder {
+    request: Request,
+}
+
+#[allow(clippy::new_without_default)]
+impl RequestBuilder {
+    pub fn new() -> Self {
+        RequestBuilder {
+            request: Request {
+                method: RequestMethod::Get,
+                url: String::from("/"),
+                version: HttpVersion::Http1_1,
+                headers: Headers::new(),
+                body: vec![],
+                trailers: Headers::new(),
+                peer_addr: None,
+            },
+        }
+    }
+
+    pub fn method(mut self, method: RequestMethod) -> Self {
+        self.request.method = method;
+
+        self
+    }
+
+    pub fn url(mut self, url: &str) -> Self {
+        self.request.url = String::from(url);
+
+        self
+    }
+
+    pub fn version(mut self, version: HttpVersion) -> Self {
+        self.request.version = version;
+
+        self
+    }
+
+    pub fn header(mut self, header_name: &str, header_value: &str) -> Self {
+        self.request.headers.add(header_name, header_value);
+
+        self
+    }
+
+    pub fn body(mut self, body: Vec<u8>) -> Self {
+        self.request.body = body;
+
+        self
+    }
+
+    pub fn text_body(mut self, body: &str) -> Self {
+        self.request.body = body.as_bytes().to_vec();
+
+        self
+    }
+
+    pub fn get(self) -> Request {
+        if !self.request.body.is_empty()
+            && !self.request.has_header("Content-Length", None)
+            && !self
+                .request
+                .has_header("Transfer-Encoding", Some("chunked"))
+        {
+            let len = self.request.body.len();
+            return self.header("Content-Length", &len.to_string()).request;
+        }
+
+        self.request
+    }
+}
+
+fn take_until_crlf<'a>(
+    iterator: &mut impl Iterator<Item = &'a u8>,
+    config: &ParserConfig,
+) -> Result<Vec<u8>>
 where
     u8: Copy,
 {
@@ -95,44 +219,82 @@ where
                 return if *values.last().unwrap_or(&0u8) == b'\r' {
                     values.pop();
 
+                    Ok(values)
+                } else if config.allow_lf_line_endings {
                     Ok(values)
                 } else {
-                    Err("Could not find CRLF".into())
+                    Err(Error::parse("Could not find CRLF"))
                 };
             }
 
             values.push(*value);
         } else {
-            return Err("Could not find CRLF".into());
+            return Err(Error::parse("Could not find CRLF"));
         }
     }
 }
 
 fn parse_request_line<'a>(
-    iterator: &mut (impl Iterator<Item = &'a u8> + IteratorUtils<'a, u8>),
+    iterator: &mut impl IteratorUtils<'a, u8>,
+    config: &ParserConfig,
 ) -> Result<(RequestMethod, String, HttpVersion)> {
+    let mut iterator = iterator.peekable();
+
+    if config.allow_extra_whitespace {
+        skip_whitespace(&mut iterator);
+    }
+
     let method_bytes = iterator.take_while_copy(|byte| **byte != b' ');
-    let method = RequestMethod::from_str(std::str::from_utf8(&method_bytes).unwrap());
+    let method_str = String::from_vec(method_bytes);
+    let method = if config.allow_lowercase_methods {
+        RequestMethod::from_str(&method_str.to_uppercase())
+    } else {
+        RequestMethod::from_str(&method_str)
+    };
+
+    if config.allow_extra_whitespace {
+        skip_spaces(&mut iterator);
+    }
 
     let url_bytes = iterator.take_while_copy(|byte| **byte != b' ');
     let url = String::from_vec(url_bytes);
 
-    let version_bytes = take_until_crlf(iterator)?;
-    let version = HttpVersion::from_str(std::str::from_utf8(&version_bytes).unwrap());
+    if config.allow_extra_whitespace {
+        skip_spaces(&mut iterator);
+    }
+
+    let version_bytes = take_until_crlf(&mut iterator, config)?;
+    let version_str = String::from_vec(version_bytes);
+    let version = if config.allow_extra_whitespace {
+        HttpVersion::from_str(version_str.trim_end())
+    } else {
+        HttpVersion::from_str(&version_str)
+    };
 
     match (method, version) {
-        (Ok(method), Ok(version)) if !url.is_empty() && version == HttpVersion::Http1_1 => {
+        (Ok(method), Ok(version @ (HttpVersion::Http1_0 | HttpVersion::Http1_1)))
+            if !url.is_empty() =>
+        {
             Ok((method, url, version))
         }
-        _ => Err("Request line parsing error".into()),
+        _ => Err(Error::parse("Request line parsing error")),
     }
 }
 
-fn parse_headers<'a>(iterator: &mut impl Iterator<Item = &'a u8>) -> Result<Headers> {
+fn parse_headers<'a>(
+    iterator: &mut impl Iterator<Item = &'a u8>,
+    config: &ParserConfig,
+) -> Result<Headers> {
     let mut headers = Headers::new();
 
     loop {
         let mut peekable_iterator = iterator.peekable();
+
+        if config.allow_lf_line_endings && **peekable_iterator.peek().unwrap_or(&&0u8) == b'\n' {
+            peekable_iterator.next();
+            return Ok(headers);
+        }
+
         // check if the first value of current line is CRLF
         if **peekable_iterator.peek().unwrap_or(&&0u8) == b'\r' {
             peekable_iterator.next();
@@ -142,18 +304,31 @@ fn parse_headers<'a>(iterator: &mut impl Iterator<Item = &'a u8>) -> Result<Head
                 return Ok(headers);
             }
 
-            return Err("Found CR without LF in header line".into());
+            return Err(Error::parse("Found CR without LF in header line"));
         }
 
         let header = peekable_iterator.take_while_copy(|byte| **byte != b':');
         skip_whitespace(&mut peekable_iterator);
-        let header_value = take_until_crlf(&mut peekable_iterator)?;
+        let header_value = take_until_crlf(&mut peekable_iterator, config)?;
 
         let header_name = String::from_vec(header);
-        let header_value = String::from_vec(header_value);
+        // trailing whitespace is not part of the value
+        let header_value = String::from_vec(header_value).trim_end().to_string();
 
         if !is_header_valid(&header_name, &header_value) {
-            return Err("Invalid header".into());
+            return Err(Error::parse("Invalid header"));
+        }
+
+        // a proxy in front could pick the other one, and route or frame the request differently
+        if header_name.eq_ignore_ascii_case(names::HOST) && headers.contains_key(names::HOST) {
+            return Err(Error::parse("Duplicate Host header"));
+        }
+        if header_name.eq_ignore_ascii_case(names::CONTENT_LENGTH)
+            && headers
+                .get(names::CONTENT_LENGTH)
+                .is_some_and(|content_length| content_length != header_value)
+        {
+            return Err(Error::parse("Conflicting Content-Length headers"));
         }
 
         headers.add(&header_name, &header_value);
@@ -161,45 +336,63 @@ fn parse_headers<'a>(iterator: &mut impl Iterator<Item = &'a u8>) -> Result<Head
 }
 
 pub fn parse_chunked_body(body: Vec<u8>) -> Result<(Vec<u8>, bool)> {
+    parse_chunked_body_with_trailers(body).map(|(body, _, is_complete)| (body, is_complete))
+}
+
+/// Decodes a chunked body, returning its trailer fields alongside.
+/// Chunks are taken by their declared size, so they may contain CRLF.
+pub fn parse_chunked_body_with_trailers(body: Vec<u8>) -> Result<(Vec<u8>, Headers, bool)> {
+    let config = ParserConfig::strict();
     let mut parsed: Vec<u8> = vec![];
-    let mut iterator = body.iter();
+    let mut rest = body.as_slice();
 
     loop {
-        let mut peekable_iterator = iterator.by_ref().peekable();
-
-        if peekable_iterator.peek().is_none() {
+        if rest.is_empty() {
             debug!("Returning incomplete chunked body");
-            return Ok((parsed, false));
+            return Ok((parsed, Headers::new(), false));
         }
 
-        let chunk_len_bytes = take_until_crlf(&mut peekable_iterator)?;
-        let chunk_len_str = std::str::from_utf8(&chunk_len_bytes)?;
-        let chunk_len = chunk_len_str.parse::<usize>()?;
-
-        if peekable_iterator.peek().is_none() {
-            return Err("Incorrect chunked body structure".into());
-        }
+        let mut iterator = rest.iter();
+        let chunk_len_bytes = take_until_crlf(&mut iterator, &config)?;
+        rest = iterator.as_slice();
 
-        // todo: this must not take all bytes until crlf, rather chunk_len bytes and then make sure
-        // that the next 2 bytes are crlf to allow crlf inside chunk contents
-        let mut chunk_bytes = take_until_crlf(&mut peekable_iterator)?;
+        // chunk extensions are allowed, but nothing uses them
+        let chunk_len_str = std::str::from_utf8(&chunk_len_bytes)
+            .map_err(|_| Error::parse("Chunk size is not valid UTF-8"))?;
+        let chunk_len_str = chunk_len_str.split(';').next().unwrap_or("").trim();
+        let chunk_len = usize::from_str_radix(chunk_len_str, 16)
+            .map_err(|_| Error::parse("Invalid chunk size"))?;
 
-        if chunk_bytes.len() != chunk_len {
-            return Err("Incorrect chunk length".into());
+        if chunk_len == 0 {
+            let trailers = parse_headers(&mut rest.iter(), &config)?;
+            return Ok((parsed, trailers, true));
         }
 
-        if chunk_len == 0 {
-            return Ok((parsed, true));
-        } else {
-            parsed.append(&mut chunk_bytes);
+        // the size comes from the client, so it may be anything up to usize::MAX
+        let chunk_end = chunk_len
+            .checked_add(2)
+            .filter(|chunk_end| *chunk_end <= rest.len())
+            .ok_or_else(|| Error::parse("Incorrect chunk length"))?;
+        if &rest[chunk_len..chunk_end] != b"\r\n" {
+            return Err(Error::parse("Incorrect chunk length"));
         }
+
+        parsed.extend_from_slice(&rest[..chunk_len]);
+        rest = &rest[chunk_end..];
     }
 }
 
-pub fn parse_request(bytes: &[u8]) -> Result<(Request, bool)> {
+pub fn parse_request(bytes: &[u8], config: &ParserConfig) -> Result<(Request, bool)> {
     let mut bytes_iter = bytes.iter();
-    let (method, url, version) = parse_request_line(bytes_iter.by_ref())?;
-    let headers = parse_headers(bytes_iter.by_ref())?;
+    let (method, url, version) = parse_request_line(bytes_iter.by_ref(), config)?;
+    let headers = parse_headers(bytes_iter.by_ref(), config)?;
+
+    if headers.contains_key(names::CONTENT_LENGTH) && headers.contains_key(names::TRANSFER_ENCODING)
+    {
+        return Err(Error::parse(
+            "Both Content-Length and Transfer-Encoding headers",
+        ));
+    }
 
     let mut request = Request {
         method,
@@ -207,20 +400,23 @@ pub fn parse_request(bytes: &[u8]) -> Result<(Request, bool)> {
         version,
         headers,
         body: vec![],
+        trailers: Headers::new(),
+        peer_addr: None,
     };
 
-    let mut is_complete = false;
-
-    match request.body_type() {
+    let is_complete = match request.body_type() {
         RequestBodyType::ContentLength => {
             request.body = bytes_iter.copied().collect();
-            is_complete = request.body.len() == request.content_length().unwrap();
+            request.body.len() == request.content_length().unwrap()
         }
         RequestBodyType::TransferEncodingChunked => {
-            (request.body, is_complete) = parse_chunked_body(bytes_iter.copied().collect())?;
+            let is_complete;
+            (request.body, request.trailers, is_complete) =
+                parse_chunked_body_with_trailers(bytes_iter.copied().collect())?;
+            is_complete
         }
-        RequestBodyType::None => is_complete = true,
-    }
+        RequestBodyType::None => true,
+    };
 
     Ok((request, is_complete))
 }
@@ -228,13 +424,87 @@ pub fn parse_request(bytes: &[u8]) -> Result<(Request, bool)> {
 #[cfg(test)]
 mod tests {
     mod parse_request_line {
+        use crate::error::Error;
         use crate::http_version::HttpVersion;
         use crate::request::parse_request_line;
         use crate::request_method::RequestMethod;
-        use std::error::Error;
+        use crate::server_config::ParserConfig;
 
-        fn msg_result(msg: &str) -> Result<(RequestMethod, String, HttpVersion), Box<dyn Error>> {
-            parse_request_line(&mut format!("{}\r\n\r\n", msg).as_bytes().iter())
+        fn msg_result(msg: &str) -> Result<(RequestMethod, String, HttpVersion), Error> {
+            parse_request_line(
+                &mut format!("{}\r\n\r\n", msg).as_bytes().iter(),
+                &ParserConfig::strict(),
+            )
+        }
+
+        fn lenient_msg_result(msg: &str) -> Result<(RequestMethod, String, HttpVersion), Error> {
+            parse_request_line(&mut msg.as_bytes().iter(), &ParserConfig::lenient())
+        }
+
+        #[test]
+        fn err_with_lf_line_ending() {
+            let result = parse_request_line(
+                &mut "GET /index.html HTTP/1.1\n".as_bytes().iter(),
+                &ParserConfig::strict(),
+            );
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn err_with_extra_whitespace() {
+            let result = msg_result("GET  /index.html HTTP/1.1");
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn ok_with_http_1_0() {
+            let (_, _, version) = msg_result("GET /index.html HTTP/1.0").unwrap();
+            assert_eq!(version, HttpVersion::Http1_0);
+        }
+
+        #[test]
+        fn err_with_other_versions() {
+            assert!(msg_result("GET /index.html HTTP/0.9").is_err());
+            assert!(msg_result("GET /index.html HTTP/2").is_err());
+        }
+
+        #[test]
+        fn lenient_accepts_lf_line_ending() {
+            let result = lenient_msg_result("GET /index.html HTTP/1.1\n");
+            assert!(result.is_ok());
+        }
+
+        #[test]
+        fn lenient_accepts_extra_whitespace() {
+            let (method, url, version) =
+                lenient_msg_result("  get   /index.html  HTTP/1.1 \r\n").unwrap();
+            assert_eq!(method, RequestMethod::Get);
+            assert_eq!(url, "/index.html");
+            assert_eq!(version, HttpVersion::Http1_1);
+        }
+
+        #[test]
+        fn lowercase_method_needs_its_own_flag() {
+            let config = ParserConfig {
+                allow_lowercase_methods: false,
+                ..ParserConfig::lenient()
+            };
+            let result = parse_request_line(
+                &mut "get /index.html HTTP/1.1\r\n".as_bytes().iter(),
+                &config,
+            );
+            assert!(result.is_err());
+
+            let config = ParserConfig {
+                allow_lowercase_methods: true,
+                ..ParserConfig::strict()
+            };
+            let (method, _, _) = parse_request_line(
+                &mut "get /index.html HTTP/1.1\r\n".as_bytes().iter(),
+                &config,
+            )
+            .unwrap();
+            assert_eq!(method, RequestMethod::Get);
         }
 
         #[test]
@@ -269,12 +539,41 @@ mod tests {
     }
 
     mod parse_headers {
+        use crate::error::Error;
         use crate::header::Headers;
         use crate::request::parse_headers;
-        use std::error::Error;
+        use crate::server_config::ParserConfig;
 
-        fn msg_result(msg: &str) -> Result<Headers, Box<dyn Error>> {
-            parse_headers(&mut format!("{}\r\n\r\n", msg).as_bytes().iter())
+        fn msg_result(msg: &str) -> Result<Headers, Error> {
+            parse_headers(
+                &mut format!("{}\r\n\r\n", msg).as_bytes().iter(),
+                &ParserConfig::strict(),
+            )
+        }
+
+        #[test]
+        fn trailing_whitespace_is_not_part_of_value() {
+            let result = msg_result("Content-Type: text/html  ").unwrap();
+            assert_eq!(result.get("Content-Type"), Some("text/html".to_string()));
+        }
+
+        #[test]
+        fn lenient_accepts_lf_line_endings() {
+            let result = parse_headers(
+                &mut "Content-Type: text/html\nAccept: */*\n\n".as_bytes().iter(),
+                &ParserConfig::lenient(),
+            )
+            .unwrap();
+            assert_eq!(result.get("Accept"), Some("*/*".to_string()));
+        }
+
+        #[test]
+        fn lenient_still_rejects_whitespace_before_colon() {
+            let result = parse_headers(
+                &mut "Content-Type : text/html\n\n".as_bytes().iter(),
+                &ParserConfig::lenient(),
+            );
+            assert!(result.is_err());
         }
 
         #[test]
@@ -294,20 +593,35 @@ mod tests {
             let result = msg_result("Content-Length: text/html");
             assert!(result.is_err());
         }
+
+        #[test]
+        fn err_with_duplicate_host() {
+            assert!(msg_result("Host: a.com\r\nhost: b.com").is_err());
+            assert!(msg_result("Host: a.com\r\nHost: a.com").is_err());
+        }
+
+        #[test]
+        fn err_with_conflicting_content_length() {
+            assert!(msg_result("Content-Length: 3\r\nContent-Length: 4").is_err());
+
+            let result = msg_result("Content-Length: 3\r\nContent-Length: 3").unwrap();
+            assert_eq!(result.len(), 1);
+        }
     }
 
     mod parse_request {
+        use crate::error::Error;
         use crate::http_version::HttpVersion;
         use crate::request::{parse_request, Request};
         use crate::request_method::RequestMethod;
+        use crate::server_config::ParserConfig;
         use std::collections::HashMap;
-        use std::error::Error;
 
         static TEST_MESSAGE: &str =
             "POST /index.html HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\n123";
 
-        fn msg_result(msg: &str) -> Result<Request, Box<dyn Error>> {
-            parse_request(msg.as_bytes()).map(|v| v.0)
+        fn msg_result(msg: &str) -> Result<Request, Error> {
+            parse_request(msg.as_bytes(), &ParserConfig::strict()).map(|v| v.0)
         }
 
         #[test]
@@ -333,17 +647,114 @@ mod tests {
             let result = msg_result(TEST_MESSAGE);
             assert_eq!(result.unwrap().body, vec![b'1', b'2', b'3']);
         }
+
+        #[test]
+        fn err_with_content_length_and_transfer_encoding() {
+            let result = msg_result(
+                "POST / HTTP/1.1\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            );
+            assert!(result.is_err());
+        }
+    }
+
+    mod parse_chunked_body_with_trailers {
+        use crate::request::parse_chunked_body_with_trailers;
+
+        fn parse(body: &[u8]) -> (Vec<u8>, Option<String>, bool) {
+            let (body, trailers, is_complete) =
+                parse_chunked_body_with_trailers(body.to_vec()).unwrap();
+
+            (body, trailers.get("Grpc-Status"), is_complete)
+        }
+
+        #[test]
+        fn chunk_sizes_are_hexadecimal() {
+            let body = [b"a\r\n".as_slice(), &[b'x'; 10], b"\r\n0\r\n\r\n"].concat();
+
+            assert_eq!(parse(&body), (vec![b'x'; 10], None, true));
+        }
+
+        #[test]
+        fn chunks_may_contain_crlf() {
+            assert_eq!(
+                parse(b"4\r\n\r\n\r\n\r\n0\r\n\r\n"),
+                (b"\r\n\r\n".to_vec(), None, true)
+            );
+        }
+
+        #[test]
+        fn returns_trailers() {
+            assert_eq!(
+                parse(b"1\r\na\r\n0\r\nGrpc-Status: 0\r\n\r\n"),
+                (b"a".to_vec(), Some("0".to_string()), true)
+            );
+        }
+
+        #[test]
+        fn incomplete_without_last_chunk() {
+            assert_eq!(parse(b"1\r\na\r\n"), (b"a".to_vec(), None, false));
+        }
+
+        #[test]
+        fn err_with_wrong_chunk_length() {
+            assert!(parse_chunked_body_with_trailers(b"2\r\nabc\r\n0\r\n\r\n".to_vec()).is_err());
+        }
+
+        #[test]
+        fn err_with_chunk_size_past_body() {
+            for size in ["FFFFFFFFFFFFFFFF", "FFFFFFFFFFFFFFFE", "10"] {
+                let body = format!("{size}\r\nabc\r\n");
+                assert!(parse_chunked_body_with_trailers(body.into_bytes()).is_err());
+            }
+        }
+    }
+
+    mod normalize_path {
+        use crate::request::{has_path_prefix, normalize_path};
+
+        #[test]
+        fn resolves_like_the_file_system() {
+            assert_eq!(normalize_path("/file.txt").as_deref(), Some("/file.txt"));
+            assert_eq!(normalize_path("//file.txt").as_deref(), Some("/file.txt"));
+            assert_eq!(normalize_path("/./file.txt").as_deref(), Some("/file.txt"));
+            assert_eq!(
+                normalize_path("/x/../file.txt").as_deref(),
+                Some("/file.txt")
+            );
+            assert_eq!(normalize_path("/docs/./").as_deref(), Some("/docs/"));
+            assert_eq!(normalize_path("/docs/x/..").as_deref(), Some("/docs/"));
+            assert_eq!(normalize_path("/").as_deref(), Some("/"));
+            assert_eq!(normalize_path("/x/..").as_deref(), Some("/"));
+        }
+
+        #[test]
+        fn none_above_root() {
+            assert_eq!(normalize_path("/../file.txt"), None);
+            assert_eq!(normalize_path("/x/../../file.txt"), None);
+        }
+
+        #[test]
+        fn prefixes_match_whole_segments() {
+            assert!(has_path_prefix("/static", "/static"));
+            assert!(has_path_prefix("/static/app.js", "/static"));
+            assert!(has_path_prefix("/static/app.js", "/static/"));
+            assert!(has_path_prefix("/index.html", "/"));
+            assert!(!has_path_prefix("/statics", "/static"));
+            assert!(!has_path_prefix("/static", "/static/"));
+        }
     }
 
     mod misc {
+        use crate::error::Error;
         use crate::request::{parse_request, Request};
-        use std::error::Error;
+        use crate::request_method::RequestMethod;
+        use crate::server_config::ParserConfig;
 
         static TEST_MESSAGE: &str =
             "POST /index.html HTTP/1.1\r\nContent-Type: text/plain\r\nContent-Length: 3\r\n\r\n123";
 
-        fn msg_result(msg: &str) -> Result<Request, Box<dyn Error>> {
-            parse_request(msg.as_bytes()).map(|v| v.0)
+        fn msg_result(msg: &str) -> Result<Request, Error> {
+            parse_request(msg.as_bytes(), &ParserConfig::strict()).map(|v| v.0)
         }
 
         #[test]
@@ -353,5 +764,26 @@ mod tests {
             assert!(result.has_header("content-type", None));
             assert!(result.has_header("CONTENT-LENGTH", None));
         }
+
+        #[test]
+        fn builder_adds_content_length() {
+            let request = Request::builder()
+                .method(RequestMethod::Post)
+                .url("/index.html")
+                .header("Content-Type", "text/plain")
+                .text_body("123")
+                .get();
+
+            assert_eq!(request.to_wire_string(), TEST_MESSAGE);
+            assert_eq!(Request::builder().get().content_length(), None);
+        }
+
+        #[test]
+        fn wire_string_matches_message() {
+            let result = msg_result(TEST_MESSAGE).unwrap();
+
+            assert_eq!(result.to_wire_string(), TEST_MESSAGE);
+            assert_eq!(format!("{result:?}"), TEST_MESSAGE);
+        }
     }
 }
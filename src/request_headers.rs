@@ -0,0 +1,200 @@
+use crate::header::{names, Headers};
+use crate::request::Request;
+use std::net::IpAddr;
+
+// meaningful for the connection to the client only, dropped before handlers see the request
+const HOP_BY_HOP_HEADERS: [&str; 2] = [names::KEEP_ALIVE, "TE"];
+
+// the server acts on these itself, a client listing them in Connection must not get them
+// dropped. Connection stays too, it decides whether the connection is kept open
+const CONNECTION_LEVEL_HEADERS: [&str; 5] = [
+    names::CONNECTION,
+    names::CONTENT_LENGTH,
+    names::HOST,
+    names::TRANSFER_ENCODING,
+    names::UPGRADE,
+];
+
+/// How request headers are cleaned up before handlers and rules see them, see
+/// `ServerConfig::request_headers`. Whitespace in values is normalized and hop-by-hop
+/// headers, the ones listed in Connection, Keep-Alive, TE and Proxy-*, are dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestHeadersConfig {
+    /// Headers the server is authoritative for, e.g. X-Request-Id, dropped from requests
+    /// unless they come from a trusted peer
+    pub server_owned: Vec<String>,
+    /// Peers allowed to set server-owned headers, e.g. a load balancer in front of the server
+    pub trusted_peers: Vec<IpAddr>,
+}
+
+impl RequestHeadersConfig {
+    pub fn new() -> Self {
+        RequestHeadersConfig {
+            server_owned: vec!["X-Request-Id".to_string()],
+            trusted_peers: vec![],
+        }
+    }
+
+    /// Replaces the default server-owned headers.
+    pub fn server_owned(mut self, header_names: &[&str]) -> Self {
+        self.server_owned = header_names.iter().map(|v| v.to_string()).collect();
+
+        self
+    }
+
+    pub fn trust_peer(mut self, peer_addr: IpAddr) -> Self {
+        self.trusted_peers.push(peer_addr);
+
+        self
+    }
+
+    pub(crate) fn normalize(&self, request: &mut Request) {
+        let connection_listed: Vec<String> = request
+            .get_header(names::CONNECTION)
+            .map(|connection| {
+                connection
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .filter(|name| {
+                        !name.is_empty()
+                            && !CONNECTION_LEVEL_HEADERS
+                                .iter()
+                                .any(|kept| kept.eq_ignore_ascii_case(name))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let is_trusted = request
+            .peer_addr
+            .is_some_and(|peer_addr| self.trusted_peers.contains(&peer_addr));
+
+        let is_dropped = |name: &str| {
+            HOP_BY_HOP_HEADERS
+                .into_iter()
+                .chain(connection_listed.iter().map(String::as_str))
+                .any(|dropped| dropped.eq_ignore_ascii_case(name))
+                || name
+                    .get(..6)
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case("Proxy-"))
+                || (!is_trusted
+                    && self
+                        .server_owned
+                        .iter()
+                        .any(|owned| owned.eq_ignore_ascii_case(name)))
+        };
+
+        request.headers = request
+            .headers
+            .iter()
+            .filter(|(name, _)| !is_dropped(name))
+            .map(|(name, value)| (name.clone(), normalize_whitespace(value)))
+            .collect::<Headers>();
+    }
+}
+
+impl Default for RequestHeadersConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Trims the value and turns every run of spaces and tabs outside quoted strings into one space.
+fn normalize_whitespace(value: &str) -> String {
+    let mut normalized = String::with_capacity(value.len());
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut pending_space = false;
+
+    for char in value.trim_matches([' ', '\t']).chars() {
+        if !in_quotes && (char == ' ' || char == '\t') {
+            pending_space = true;
+            continue;
+        }
+        if pending_space {
+            normalized.push(' ');
+            pending_space = false;
+        }
+
+        if in_quotes && escaped {
+            escaped = false;
+        } else if in_quotes && char == '\\' {
+            escaped = true;
+        } else if char == '"' {
+            in_quotes = !in_quotes;
+        }
+        normalized.push(char);
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod test {
+    mod normalize {
+        use crate::request::Request;
+        use crate::request_headers::RequestHeadersConfig;
+        use std::net::{IpAddr, Ipv4Addr};
+
+        const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+
+        fn normalized(config: &RequestHeadersConfig, headers: &[(&str, &str)]) -> Request {
+            let mut request = headers
+                .iter()
+                .fold(Request::builder(), |builder, (name, value)| {
+                    builder.header(name, value)
+                })
+                .get();
+            request.peer_addr = Some(PEER);
+            config.normalize(&mut request);
+
+            request
+        }
+
+        #[test]
+        fn collapses_whitespace_outside_quotes() {
+            let request = normalized(
+                &RequestHeadersConfig::new(),
+                &[
+                    ("Accept", " text/html, \t */*  "),
+                    ("If-None-Match", "\"a  b\""),
+                ],
+            );
+
+            assert_eq!(request.get_header("Accept").unwrap(), "text/html, */*");
+            assert_eq!(request.get_header("If-None-Match").unwrap(), "\"a  b\"");
+        }
+
+        #[test]
+        fn drops_hop_by_hop_headers() {
+            let request = normalized(
+                &RequestHeadersConfig::new(),
+                &[
+                    ("Connection", "keep-alive, X-Secret, Content-Length"),
+                    ("Keep-Alive", "timeout=5"),
+                    ("X-Secret", "1"),
+                    ("Proxy-Authorization", "Basic YTpi"),
+                    ("Content-Length", "0"),
+                    ("Accept", "*/*"),
+                ],
+            );
+
+            assert!(!request.has_header("X-Secret", None));
+            assert!(!request.has_header("Keep-Alive", None));
+            assert!(!request.has_header("Proxy-Authorization", None));
+            assert!(request.has_header("Connection", None));
+            assert!(request.has_header("Content-Length", None));
+            assert!(request.has_header("Accept", None));
+        }
+
+        #[test]
+        fn keeps_server_owned_headers_from_trusted_peers_only() {
+            let headers = [("X-Request-Id", "abc")];
+
+            let request = normalized(&RequestHeadersConfig::new(), &headers);
+            assert!(!request.has_header("X-Request-Id", None));
+
+            let request = normalized(&RequestHeadersConfig::new().trust_peer(PEER), &headers);
+            assert_eq!(request.get_header("X-Request-Id").unwrap(), "abc");
+        }
+    }
+}
@@ -1,7 +1,7 @@
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RequestMethod {
     Get,
     Head,
@@ -21,7 +21,7 @@ impl RequestMethod {
     }
 
     pub fn safe_methods_str() -> String {
-        vec![
+        [
             RequestMethod::Get,
             RequestMethod::Head,
             RequestMethod::Options,
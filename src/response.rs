@@ -1,17 +1,127 @@
+use crate::event_stream::{Event, EventStreamReader};
 use crate::http_version::HttpVersion;
 use crate::response_status_code::ResponseStatusCode;
-use crate::utils::StringUtils;
+use crate::utils::{body_summary, StringUtils};
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
 
 const SPACE: u8 = b' ';
 static CRLF: [u8; 2] = [b'\r', b'\n'];
 
-#[derive(Debug)]
+// Headers that describe the response itself go first, the rest follows alphabetically
+static DEFAULT_HEADER_ORDER: [&str; 14] = [
+    "Date",
+    "Server",
+    "Location",
+    "Content-Type",
+    "Content-Length",
+    "Content-Encoding",
+    "Transfer-Encoding",
+    "ETag",
+    "Last-Modified",
+    "Cache-Control",
+    "Vary",
+    "Allow",
+    "Connection",
+    "Keep-Alive",
+];
+
+// Names that title-casing would get wrong
+static IRREGULAR_HEADER_NAMES: [&str; 6] = [
+    "ETag",
+    "WWW-Authenticate",
+    "Content-MD5",
+    "X-XSS-Protection",
+    "DNT",
+    "TE",
+];
+
+/// How headers are written on the wire.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeaderFormat {
+    /// Rewrite names to their canonical casing, e.g. content-type -> Content-Type
+    pub canonical_case: bool,
+    /// Headers listed here are written first, in this order
+    pub order: Vec<String>,
+}
+
+impl Default for HeaderFormat {
+    fn default() -> Self {
+        HeaderFormat {
+            canonical_case: true,
+            order: DEFAULT_HEADER_ORDER
+                .iter()
+                .map(|name| name.to_string())
+                .collect(),
+        }
+    }
+}
+
+pub fn canonical_header_name(header_name: &str) -> String {
+    if let Some(name) = IRREGULAR_HEADER_NAMES
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(header_name))
+    {
+        return name.to_string();
+    }
+
+    header_name
+        .split('-')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => {
+                    first.to_ascii_uppercase().to_string() + &chars.as_str().to_ascii_lowercase()
+                }
+                None => String::new(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+pub(crate) type BodyReader = Arc<Mutex<dyn Read + Send>>;
+type TrailerResolver = Arc<dyn Fn() -> Vec<(String, String)> + Send + Sync>;
+
+// what `Response::body` gives for bodies that are not in memory
+static NO_BYTES: Vec<u8> = Vec::new();
+
+/// What is sent after the head of a response.
+#[derive(Clone)]
+pub(crate) enum ResponseBody {
+    Bytes(Vec<u8>),
+    /// Sent straight from disk, with sendfile on Linux, in fixed-size parts elsewhere
+    File(Arc<File>, u64),
+    /// Read and sent in parts, chunked when the length is not known
+    Reader(BodyReader),
+}
+
+impl ResponseBody {
+    /// Length in bytes, None for readers.
+    pub(crate) fn len(&self) -> Option<u64> {
+        match self {
+            ResponseBody::Bytes(bytes) => Some(bytes.len() as u64),
+            ResponseBody::File(_, len) => Some(*len),
+            ResponseBody::Reader(_) => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Response {
     version: HttpVersion,
     status_code: ResponseStatusCode,
     headers: HashMap<String, String>,
-    body: Vec<u8>,
+    // Set-Cookie lines after the first, which is in `headers`
+    more_set_cookies: Vec<String>,
+    body: ResponseBody,
+    // called once the body is sent, for the fields announced in the Trailer header
+    trailers: Option<TrailerResolver>,
+    // dropped with the response once it is sent, e.g. the slot of a concurrency limit
+    held: Option<Arc<dyn Send + Sync>>,
 }
 
 #[allow(dead_code)]
@@ -24,27 +134,228 @@ impl Response {
         &self.status_code
     }
 
+    /// One value per header, repeated Set-Cookie values are only in `header_lines`.
     pub fn headers(&self) -> &HashMap<String, String> {
         &self.headers
     }
 
+    /// Every header line as written, with a line per Set-Cookie value.
+    pub fn header_lines(&self) -> impl Iterator<Item = (&str, &str)> {
+        let set_cookie = self
+            .headers
+            .keys()
+            .find(|name| name.eq_ignore_ascii_case("Set-Cookie"));
+
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .chain(
+                self.more_set_cookies.iter().filter_map(move |value| {
+                    set_cookie.map(|name| (name.as_str(), value.as_str()))
+                }),
+            )
+    }
+
+    /// Body held in memory, empty for ones sent from a file or a reader.
     pub fn body(&self) -> &Vec<u8> {
+        match &self.body {
+            ResponseBody::Bytes(bytes) => bytes,
+            _ => &NO_BYTES,
+        }
+    }
+
+    pub(crate) fn response_body(&self) -> &ResponseBody {
         &self.body
     }
 
+    /// Replaces the body, leaving Content-Length to the caller.
+    pub(crate) fn set_response_body(&mut self, body: ResponseBody) {
+        self.body = body;
+    }
+
     pub fn set_status_code(&mut self, status_code: ResponseStatusCode) {
         self.status_code = status_code;
     }
 
+    /// Replaces the header whatever the casing of its name, so it is never written twice.
     pub fn set_header(&mut self, header_name: &str, header_value: &str) {
+        self.remove_header(header_name);
         self.headers.insert(header_name.into(), header_value.into());
     }
 
+    /// Adds a value to the header, joined to an existing one with ", ". Set-Cookie values
+    /// may contain commas themselves, so each one gets its own line instead.
+    pub fn append_header(&mut self, header_name: &str, header_value: &str) {
+        match self.get_header(header_name) {
+            None => self.set_header(header_name, header_value),
+            Some(_) if header_name.eq_ignore_ascii_case("Set-Cookie") => {
+                self.more_set_cookies.push(header_value.to_string())
+            }
+            Some(existing) => {
+                let folded = format!("{existing}, {header_value}");
+                self.set_header(header_name, &folded);
+            }
+        }
+    }
+
+    pub fn get_header(&self, header_name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn has_header(&self, header_name: &str) -> bool {
+        self.headers
+            .keys()
+            .any(|name| name.eq_ignore_ascii_case(header_name))
+    }
+
+    pub fn remove_header(&mut self, header_name: &str) {
+        self.headers
+            .retain(|name, _| !name.eq_ignore_ascii_case(header_name));
+        if header_name.eq_ignore_ascii_case("Set-Cookie") {
+            self.more_set_cookies.clear();
+        }
+    }
+
     pub fn set_body(&mut self, body: Vec<u8>) {
-        self.body = body;
+        self.body = ResponseBody::Bytes(body);
+    }
+
+    /// Body produced while the response is sent, e.g. output whose length is not known
+    /// up front. Without a Content-Length it is sent chunked to HTTP/1.1 clients and
+    /// until the connection closes to HTTP/1.0 ones.
+    pub fn set_body_reader(&mut self, reader: impl Read + Send + 'static) {
+        self.body = ResponseBody::Reader(Arc::new(Mutex::new(reader)));
+    }
+
+    /// `text/event-stream` response that sends each event as the iterator yields it,
+    /// keeping the connection open until the iterator ends. The request timeout and the
+    /// maximum response size, when set, still bound the whole stream.
+    pub fn event_stream(events: impl Iterator<Item = Event> + Send + 'static) -> Response {
+        Response::builder()
+            .header("Content-Type", "text/event-stream")
+            .header("Cache-Control", "no-cache")
+            .body_reader(EventStreamReader::new(events))
+            .get()
+    }
+
+    pub(crate) fn body_reader(&self) -> Option<&BodyReader> {
+        match &self.body {
+            ResponseBody::Reader(reader) => Some(reader),
+            _ => None,
+        }
+    }
+
+    /// Announces `names` in the Trailer header, `resolve` gives their values once the body
+    /// is sent, e.g. a checksum of a streamed body. Trailers are only sent after the last
+    /// chunk of a chunked body, fields not announced are dropped.
+    pub fn set_trailers(
+        &mut self,
+        names: &[&str],
+        resolve: impl Fn() -> Vec<(String, String)> + Send + Sync + 'static,
+    ) {
+        self.set_header("Trailer", &names.join(", "));
+        self.trailers = Some(Arc::new(resolve));
+    }
+
+    /// Keeps `value` alive until the response is sent, not just until it is built.
+    pub(crate) fn hold_until_sent(&mut self, value: Arc<dyn Send + Sync>) {
+        self.held = Some(value);
+    }
+
+    /// Chunk that ends a chunked body, with the trailers.
+    pub(crate) fn last_chunk(&self) -> Vec<u8> {
+        let mut bytes = b"0\r\n".to_vec();
+        let announced: Vec<String> = self
+            .get_header("Trailer")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+
+        let trailers = self
+            .trailers
+            .as_ref()
+            .map(|resolve| resolve())
+            .unwrap_or_default();
+        for (name, value) in trailers {
+            if announced.contains(&name.to_ascii_lowercase()) {
+                bytes.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+            }
+        }
+
+        bytes.extend_from_slice(&CRLF);
+
+        bytes
+    }
+
+    pub(crate) fn body_file(&self) -> Option<(&File, u64)> {
+        match &self.body {
+            ResponseBody::File(file, len) => Some((file.as_ref(), *len)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_body_file(&mut self, file: Arc<File>, len: u64) {
+        self.set_header("Content-Length", &len.to_string());
+        self.body = ResponseBody::File(file, len);
+    }
+
+    /// Adds a member to the Vary header, keeping members unique (case-insensitively).
+    /// "*" swallows every other member.
+    pub fn add_vary(&mut self, header_name: &str) {
+        let mut members: Vec<String> = self
+            .get_header("Vary")
+            .map(|vary| {
+                vary.split(',')
+                    .map(|member| member.trim().to_string())
+                    .filter(|member| !member.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if members.iter().any(|member| member == "*") {
+            return;
+        }
+
+        if header_name == "*" {
+            members = vec!["*".to_string()];
+        } else if !members
+            .iter()
+            .any(|member| member.eq_ignore_ascii_case(header_name))
+        {
+            members.push(header_name.to_string());
+        }
+
+        self.set_header("Vary", &members.join(", "));
     }
 
     pub(crate) fn as_bytes(&self) -> Vec<u8> {
+        self.as_bytes_with_format(&HeaderFormat::default())
+    }
+
+    pub(crate) fn as_bytes_with_format(&self, format: &HeaderFormat) -> Vec<u8> {
+        let mut bytes = self.head(format);
+        bytes.extend_from_slice(self.body());
+
+        bytes
+    }
+
+    /// Response as sent on the wire, with the body summarized for logging.
+    pub fn to_wire_string(&self) -> String {
+        let head = self.head(&HeaderFormat::default());
+        let body = match &self.body {
+            ResponseBody::Bytes(bytes) => body_summary(bytes),
+            ResponseBody::File(_, len) => format!("[{len} bytes from file]"),
+            ResponseBody::Reader(_) => "[streamed body]".to_string(),
+        };
+
+        String::from_utf8_lossy(&head).to_string() + &body
+    }
+
+    fn head(&self, format: &HeaderFormat) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![];
 
         bytes.append(&mut self.version.as_bytes());
@@ -52,25 +363,78 @@ impl Response {
         bytes.append(&mut self.status_code.as_bytes());
         bytes.extend_from_slice(&CRLF);
 
-        for (header_name, header_value) in self.headers.iter() {
+        for (header_name, header_value) in self.ordered_headers(format) {
+            let header_name = if format.canonical_case {
+                canonical_header_name(header_name)
+            } else {
+                header_name.to_string()
+            };
+
             bytes.append(&mut header_name.as_bytes_vec());
             bytes.push(b':');
             bytes.push(SPACE);
-            bytes.append(&mut header_value.as_bytes_vec());
+            bytes.extend_from_slice(header_value.as_bytes());
             bytes.extend_from_slice(&CRLF);
         }
 
         bytes.extend_from_slice(&CRLF);
-        bytes.extend_from_slice(&self.body);
 
         bytes
     }
 
+    fn ordered_headers(&self, format: &HeaderFormat) -> Vec<(&str, &str)> {
+        let position = |header_name: &str| {
+            format
+                .order
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(header_name))
+                .unwrap_or(format.order.len())
+        };
+
+        // the sort is stable, Set-Cookie lines keep their order
+        let mut headers = self.header_lines().collect::<Vec<(&str, &str)>>();
+        headers.sort_by(|(a, _), (b, _)| {
+            position(a)
+                .cmp(&position(b))
+                .then_with(|| a.to_ascii_lowercase().cmp(&b.to_ascii_lowercase()))
+        });
+
+        headers
+    }
+
     pub fn builder() -> ResponseBuilder {
         ResponseBuilder::new()
     }
 }
 
+impl PartialEq for Response {
+    // file and reader bodies are equal when they share the handle
+    fn eq(&self, other: &Self) -> bool {
+        let same_body = match (&self.body, &other.body) {
+            (ResponseBody::Bytes(bytes), ResponseBody::Bytes(other_bytes)) => bytes == other_bytes,
+            (ResponseBody::File(file, len), ResponseBody::File(other_file, other_len)) => {
+                Arc::ptr_eq(file, other_file) && len == other_len
+            }
+            (ResponseBody::Reader(reader), ResponseBody::Reader(other_reader)) => {
+                Arc::ptr_eq(reader, other_reader)
+            }
+            _ => false,
+        };
+
+        self.version == other.version
+            && self.status_code == other.status_code
+            && self.headers == other.headers
+            && self.more_set_cookies == other.more_set_cookies
+            && same_body
+    }
+}
+
+impl fmt::Debug for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_wire_string())
+    }
+}
+
 #[derive(Debug)]
 pub struct ResponseBuilder {
     response: Response,
@@ -84,7 +448,10 @@ impl ResponseBuilder {
                 version: HttpVersion::Http1_1,
                 status_code: ResponseStatusCode::Ok,
                 headers: HashMap::new(),
-                body: vec![],
+                more_set_cookies: vec![],
+                body: ResponseBody::Bytes(vec![]),
+                trailers: None,
+                held: None,
             },
         }
     }
@@ -96,28 +463,44 @@ impl ResponseBuilder {
     }
 
     pub fn header(mut self, header_name: &str, header_value: &str) -> Self {
-        self.response
-            .headers
-            .insert(String::from(header_name), String::from(header_value));
+        self.response.set_header(header_name, header_value);
 
         self
     }
 
     pub fn body(mut self, body: Vec<u8>) -> Self {
-        self.response.body = body;
+        self.response.set_body(body);
 
         self
     }
 
     pub fn text_body(mut self, body: &str) -> Self {
-        self.response.body = body.as_bytes().to_vec();
+        self.response.set_body(body.as_bytes().to_vec());
+
+        self
+    }
+
+    /// See `Response::set_body_reader`.
+    pub fn body_reader(mut self, reader: impl Read + Send + 'static) -> Self {
+        self.response.set_body_reader(reader);
+
+        self
+    }
+
+    /// See `Response::set_trailers`.
+    pub fn trailers(
+        mut self,
+        names: &[&str],
+        resolve: impl Fn() -> Vec<(String, String)> + Send + Sync + 'static,
+    ) -> Self {
+        self.response.set_trailers(names, resolve);
 
         self
     }
 
     pub fn get(self) -> Response {
-        if !self.response.body.is_empty() && !self.response.headers.contains_key("Content-Length") {
-            let len = self.response.body.len();
+        if !self.response.body().is_empty() && !self.response.has_header("Content-Length") {
+            let len = self.response.body().len();
             return self.header("Content-Length", &len.to_string()).response;
         }
 
@@ -128,9 +511,142 @@ impl ResponseBuilder {
 #[cfg(test)]
 mod test {
     mod response {
-        use crate::response::Response;
+        use crate::response::{HeaderFormat, Response};
         use crate::response_status_code::ResponseStatusCode;
 
+        #[test]
+        fn headers_are_written_in_configured_order() {
+            let response = Response::builder()
+                .header("x-custom", "1")
+                .header("Connection", "close")
+                .header("content-type", "text/plain")
+                .header("Accept-Ranges", "bytes")
+                .header("etag", "\"1\"")
+                .get();
+            let bytes = response.as_bytes();
+            let response_str = std::str::from_utf8(&bytes).unwrap();
+
+            assert_eq!(
+                response_str,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nETag: \"1\"\r\nConnection: close\r\nAccept-Ranges: bytes\r\nX-Custom: 1\r\n\r\n"
+            );
+        }
+
+        #[test]
+        fn keeps_casing_if_not_canonical() {
+            let response = Response::builder().header("x-custom", "1").get();
+            let format = HeaderFormat {
+                canonical_case: false,
+                order: vec![],
+            };
+            let bytes = response.as_bytes_with_format(&format);
+
+            assert!(std::str::from_utf8(&bytes)
+                .unwrap()
+                .contains("\r\nx-custom: 1\r\n"));
+        }
+
+        #[test]
+        fn remove_header_ignores_case() {
+            let mut response = Response::builder().header("keep-alive", "timeout=5").get();
+            assert!(response.has_header("Keep-Alive"));
+
+            response.remove_header("Keep-Alive");
+            assert!(!response.has_header("keep-alive"));
+        }
+
+        #[test]
+        fn set_header_replaces_any_casing() {
+            let mut response = Response::builder()
+                .header("content-length", "3")
+                .text_body("abc")
+                .get();
+            response.set_header("Content-Length", "3");
+            let bytes = response.as_bytes();
+
+            assert_eq!(
+                std::str::from_utf8(&bytes).unwrap(),
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc"
+            );
+        }
+
+        #[test]
+        fn append_header_folds_all_but_set_cookie() {
+            let mut response = Response::builder().get();
+            response.append_header("Cache-Control", "no-cache");
+            response.append_header("cache-control", "no-store");
+            response.append_header("Set-Cookie", "a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT");
+            response.append_header("Set-Cookie", "b=2");
+            let bytes = response.as_bytes();
+
+            assert_eq!(
+                std::str::from_utf8(&bytes).unwrap(),
+                "HTTP/1.1 200 OK\r\nCache-Control: no-cache, no-store\r\n\
+                 Set-Cookie: a=1; Expires=Wed, 21 Oct 2026 07:28:00 GMT\r\nSet-Cookie: b=2\r\n\r\n"
+            );
+
+            response.set_header("Set-Cookie", "c=3");
+            assert_eq!(response.header_lines().count(), 2);
+        }
+
+        #[test]
+        fn add_vary_accumulates_unique_members() {
+            let mut response = Response::builder().get();
+
+            response.add_vary("Accept-Encoding");
+            response.add_vary("Accept");
+            response.add_vary("accept-encoding");
+
+            assert_eq!(
+                response.headers().get("Vary"),
+                Some(&"Accept-Encoding, Accept".to_string())
+            );
+        }
+
+        #[test]
+        fn add_vary_star_swallows_other_members() {
+            let mut response = Response::builder().header("Vary", "Origin").get();
+
+            response.add_vary("*");
+            response.add_vary("Accept");
+
+            assert_eq!(response.headers().get("Vary"), Some(&"*".to_string()));
+        }
+
+        #[test]
+        fn add_vary_merges_into_header_in_any_casing() {
+            let mut response = Response::builder().header("vary", "Origin").get();
+
+            response.add_vary("Accept-Encoding");
+
+            assert_eq!(response.headers().len(), 1);
+            assert_eq!(response.get_header("Vary"), Some("Origin, Accept-Encoding"));
+        }
+
+        #[test]
+        fn wire_string_summarizes_body() {
+            let text = Response::builder().text_body(&"a".repeat(600)).get();
+            let binary = Response::builder().body(vec![0xff, 0xfe, 0x00]).get();
+
+            assert!(text
+                .to_wire_string()
+                .ends_with(&format!("\r\n\r\n{}[... 88 more bytes]", "a".repeat(512))));
+            assert!(format!("{binary:?}").starts_with(
+                "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n[3 bytes of binary data]"
+            ));
+        }
+
+        #[test]
+        fn clones_compare_equal() {
+            let response = Response::builder().text_body("Ok").get();
+            let mut copy = response.clone();
+
+            assert_eq!(copy, response);
+
+            copy.set_header("Vary", "Accept");
+            assert_ne!(copy, response);
+        }
+
         #[test]
         fn correct_as_bytes_representation() {
             let response = Response::builder()
@@ -159,5 +675,21 @@ mod test {
                 }
             }
         }
+
+        #[test]
+        fn last_chunk_carries_announced_trailers() {
+            let response = Response::builder()
+                .trailers(&["X-Checksum"], || {
+                    vec![
+                        ("x-checksum".to_string(), "abc".to_string()),
+                        ("X-Other".to_string(), "1".to_string()),
+                    ]
+                })
+                .get();
+
+            assert_eq!(response.get_header("Trailer"), Some("X-Checksum"));
+            assert_eq!(response.last_chunk(), b"0\r\nx-checksum: abc\r\n\r\n");
+            assert_eq!(Response::builder().get().last_chunk(), b"0\r\n\r\n");
+        }
     }
 }
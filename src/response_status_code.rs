@@ -1,5 +1,4 @@
 use crate::utils::StringUtils;
-use std::error::Error;
 use std::fmt::{Debug, Display, Formatter};
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -31,7 +30,9 @@ pub enum ResponseStatusCode {
     NotFound = 404,
     MethodNotAllowed = 405,
     RequestTimeout = 408,
+    PreconditionFailed = 412,
     ImATeapot = 418,
+    UpgradeRequired = 426,
     TooManyRequests = 429,
 
     // Server error responses (500 - 599)
@@ -44,6 +45,10 @@ pub enum ResponseStatusCode {
 }
 
 impl ResponseStatusCode {
+    pub fn is_informational(&self) -> bool {
+        (*self as u16) < 200
+    }
+
     pub fn is_redirect(&self) -> bool {
         let self_int = *self as u16;
         (300..400).contains(&self_int)
@@ -88,7 +93,9 @@ impl Display for ResponseStatusCode {
             ResponseStatusCode::NotFound => "Not Found",
             ResponseStatusCode::MethodNotAllowed => "Method Not Allowed",
             ResponseStatusCode::RequestTimeout => "Request Timeout",
+            ResponseStatusCode::PreconditionFailed => "Precondition Failed",
             ResponseStatusCode::ImATeapot => "I'm a teapot",
+            ResponseStatusCode::UpgradeRequired => "Upgrade Required",
             ResponseStatusCode::TooManyRequests => "Too Many Requests",
             ResponseStatusCode::InternalServerError => "Internal Server Error",
             ResponseStatusCode::NotImplemented => "Not Implemented",
@@ -128,7 +135,9 @@ impl TryFrom<u16> for ResponseStatusCode {
             404 => ResponseStatusCode::NotFound,
             405 => ResponseStatusCode::MethodNotAllowed,
             408 => ResponseStatusCode::RequestTimeout,
+            412 => ResponseStatusCode::PreconditionFailed,
             418 => ResponseStatusCode::ImATeapot,
+            426 => ResponseStatusCode::UpgradeRequired,
             429 => ResponseStatusCode::TooManyRequests,
 
             500 => ResponseStatusCode::InternalServerError,
@@ -0,0 +1,312 @@
+use crate::header::names;
+use crate::request::Request;
+use crate::request_method::RequestMethod;
+use crate::response::Response;
+use crate::response_status_code::ResponseStatusCode;
+use std::str::FromStr;
+use std::sync::Arc;
+
+type Handler = dyn Fn(&Request, &Params) -> Response + Send + Sync;
+
+/// Dispatches requests to handlers by method and path, see `Server::router`.
+///
+/// Patterns are matched segment by segment: `:name` captures one segment,
+/// `*name` (or a bare `*`) as the last segment captures the rest of the path.
+/// The first route registered wins. HEAD requests are served by GET routes.
+#[derive(Clone, Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+#[derive(Clone)]
+struct Route {
+    method: RequestMethod,
+    pattern: Vec<Segment>,
+    handler: Arc<Handler>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Rest(String),
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let segments: Vec<&str> = pattern.trim_matches('/').split('/').collect();
+    let last = segments.len() - 1;
+
+    segments
+        .into_iter()
+        .enumerate()
+        .filter(|(_, segment)| !segment.is_empty())
+        .map(|(index, segment)| match segment.split_at(1) {
+            (":", name) => Segment::Param(name.to_string()),
+            ("*", name) if index == last => Segment::Rest(name.to_string()),
+            _ => Segment::Literal(segment.to_string()),
+        })
+        .collect()
+}
+
+/// Decodes %XX escapes, invalid ones are kept as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| value.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn match_path(pattern: &[Segment], path: &str) -> Option<Params> {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let mut params = Params::default();
+
+    for segment in pattern {
+        match segment {
+            Segment::Rest(name) => {
+                let rest: Vec<&str> = segments.by_ref().collect();
+                params
+                    .values
+                    .push((name.clone(), percent_decode(&rest.join("/"))));
+            }
+            Segment::Literal(literal) => {
+                if segments.next()? != literal {
+                    return None;
+                }
+            }
+            Segment::Param(name) => {
+                let value = segments.next().filter(|value| !value.is_empty())?;
+                params.values.push((name.clone(), percent_decode(value)));
+            }
+        }
+    }
+
+    // a trailing slash is fine, anything else is not
+    match (segments.next(), segments.next()) {
+        (None, _) | (Some(""), None) => Some(params),
+        _ => None,
+    }
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Router::default()
+    }
+
+    pub fn route(
+        mut self,
+        method: RequestMethod,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.routes.push(Route {
+            method,
+            pattern: parse_pattern(pattern),
+            handler: Arc::new(handler),
+        });
+
+        self
+    }
+
+    pub fn get(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(RequestMethod::Get, pattern, handler)
+    }
+
+    pub fn post(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(RequestMethod::Post, pattern, handler)
+    }
+
+    pub fn put(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(RequestMethod::Put, pattern, handler)
+    }
+
+    pub fn patch(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(RequestMethod::Patch, pattern, handler)
+    }
+
+    pub fn delete(
+        self,
+        pattern: &str,
+        handler: impl Fn(&Request, &Params) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.route(RequestMethod::Delete, pattern, handler)
+    }
+
+    /// None when no route matches the path, 405 when routes match it but not the method.
+    pub fn handle(&self, request: &Request) -> Option<Response> {
+        let path = request.url.split(['?', '#']).next().unwrap_or_default();
+        let mut allowed: Vec<RequestMethod> = vec![];
+
+        for route in &self.routes {
+            let Some(params) = match_path(&route.pattern, path) else {
+                continue;
+            };
+
+            let method_matches = route.method == request.method
+                || (route.method == RequestMethod::Get && request.method == RequestMethod::Head);
+            if method_matches {
+                return Some((route.handler)(request, &params));
+            }
+
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if allowed.is_empty() {
+            return None;
+        }
+
+        if allowed.contains(&RequestMethod::Get) {
+            allowed.push(RequestMethod::Head);
+        }
+        let allow = allowed
+            .iter()
+            .map(|method| method.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        Some(
+            Response::builder()
+                .status_code(ResponseStatusCode::MethodNotAllowed)
+                .header(names::ALLOW, &allow)
+                .get(),
+        )
+    }
+}
+
+/// Values captured by `:name` and `*name` segments, percent-decoded.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params {
+    values: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn raw(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(param_name, _)| param_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// None when the parameter is missing or does not parse as `T`.
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.raw(name)?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::request::Request;
+    use crate::request_method::RequestMethod;
+    use crate::response::Response;
+    use crate::response_status_code::ResponseStatusCode;
+    use crate::router::Router;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/users/:id", |_, params| {
+                let id = params.get::<u32>("id").unwrap_or_default();
+                Response::builder().text_body(&format!("user {id}")).get()
+            })
+            .delete("/users/:id", |_, _| Response::builder().get())
+            .get("/files/*path", |_, params| {
+                Response::builder()
+                    .text_body(params.raw("path").unwrap())
+                    .get()
+            })
+    }
+
+    fn request(method: RequestMethod, url: &str) -> Request {
+        Request::builder().method(method).url(url).get()
+    }
+
+    #[test]
+    fn captures_typed_params() {
+        let response = router()
+            .handle(&request(RequestMethod::Get, "/users/42?full=1"))
+            .unwrap();
+
+        assert_eq!(response.body(), b"user 42");
+    }
+
+    #[test]
+    fn captures_rest_of_path() {
+        let response = router()
+            .handle(&request(RequestMethod::Get, "/files/a/b%20c.txt"))
+            .unwrap();
+
+        assert_eq!(response.body(), b"a/b c.txt");
+    }
+
+    #[test]
+    fn none_if_path_does_not_match() {
+        let router = router();
+
+        assert!(router
+            .handle(&request(RequestMethod::Get, "/users"))
+            .is_none());
+        assert!(router
+            .handle(&request(RequestMethod::Get, "/users/42/posts"))
+            .is_none());
+        assert!(router
+            .handle(&request(RequestMethod::Get, "/other"))
+            .is_none());
+    }
+
+    #[test]
+    fn method_not_allowed_lists_allowed_methods() {
+        let response = router()
+            .handle(&request(RequestMethod::Post, "/users/42"))
+            .unwrap();
+
+        assert_eq!(
+            response.status_code(),
+            &ResponseStatusCode::MethodNotAllowed
+        );
+        assert_eq!(response.get_header("Allow"), Some("GET, DELETE, HEAD"));
+    }
+
+    #[test]
+    fn head_uses_get_routes() {
+        let response = router()
+            .handle(&request(RequestMethod::Head, "/users/7/"))
+            .unwrap();
+
+        assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+    }
+}
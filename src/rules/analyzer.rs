@@ -0,0 +1,157 @@
+use crate::rules::error::{RuleWarning, RuleWarningKind};
+use crate::rules::expr::{ExprOrValue, Operator};
+use crate::rules::grammar::{Statement, StatementKind};
+use crate::rules::lexer::Position;
+use crate::rules::rule::{concurrency_limit_args, concurrency_limit_call};
+use crate::rules::scope::RuleScope;
+use crate::rules::value::Type;
+use crate::rules::Rule;
+use std::collections::HashSet;
+
+/// Looks for things that are valid, but most likely not what the author meant.
+pub(crate) fn analyze(rules: &[Rule]) -> Vec<RuleWarning> {
+    let mut warnings = vec![];
+    let mut patterns: HashSet<&str> = HashSet::new();
+
+    for rule in rules {
+        if !patterns.insert(&rule.pattern) {
+            warnings.push(RuleWarning::new(
+                RuleWarningKind::DuplicatePattern(rule.pattern.clone()),
+                rule.position,
+            ));
+        }
+
+        analyze_statements(&rule.statements, false, &mut warnings);
+    }
+
+    warnings
+}
+
+fn analyze_statements(statements: &[Statement], nested: bool, warnings: &mut Vec<RuleWarning>) {
+    for statement in statements {
+        let ignored_limit = concurrency_limit_call(&statement.kind).is_some()
+            && (nested || concurrency_limit_args(&statement.kind).is_none());
+        if ignored_limit {
+            warnings.push(RuleWarning::new(
+                RuleWarningKind::IgnoredConcurrencyLimit,
+                statement.position,
+            ));
+        }
+
+        match &statement.kind {
+            StatementKind::If(condition, statements) => {
+                if is_always_false(condition) {
+                    warnings.push(RuleWarning::new(
+                        RuleWarningKind::AlwaysFalse,
+                        condition_position(condition).unwrap_or(statement.position),
+                    ));
+                }
+
+                analyze_statements(statements, true, warnings);
+            }
+            StatementKind::Matches(_, statements) => analyze_statements(statements, true, warnings),
+            _ => {}
+        }
+    }
+}
+
+/// Only literals and operators on them can be decided without a request.
+fn is_constant(expr: &ExprOrValue) -> bool {
+    match expr {
+        ExprOrValue::Value(token) => token.kind.is_lit(),
+        ExprOrValue::Expr(expr) => {
+            !matches!(expr.operator, Operator::Dot | Operator::Call)
+                && is_constant(&expr.lhs)
+                && is_constant(&expr.rhs)
+        }
+        ExprOrValue::List(_) => false,
+    }
+}
+
+fn is_always_false(condition: &ExprOrValue) -> bool {
+    if !is_constant(condition) {
+        return false;
+    }
+
+    // errors, like modulo by zero, are reported when the rule runs
+    condition
+        .eval(&RuleScope::new())
+        .is_ok_and(|value| matches!(value.t(), Type::Bool(false)))
+}
+
+fn condition_position(condition: &ExprOrValue) -> Option<Position> {
+    match condition {
+        ExprOrValue::Value(token) => Some(token.position),
+        ExprOrValue::Expr(expr) => {
+            Some(&condition_position(&expr.lhs)? + &condition_position(&expr.rhs)?)
+        }
+        ExprOrValue::List(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rules::error::RuleWarningKind;
+    use crate::rules::parser::parse_str;
+
+    fn warnings(source: &str) -> Vec<RuleWarningKind> {
+        let (_, warnings) = parse_str(source).unwrap();
+
+        warnings
+            .into_iter()
+            .map(|warning| warning.kind().clone())
+            .collect()
+    }
+
+    #[test]
+    fn warns_about_shadowed_constants() {
+        assert_eq!(
+            warnings("const A = 1; const B = 2; const A = 3; matches / { }"),
+            vec![RuleWarningKind::ShadowedConstant("A".to_string())]
+        );
+    }
+
+    #[test]
+    fn warns_about_duplicate_patterns() {
+        assert_eq!(
+            warnings("matches /a { } matches /b { } matches /a { }"),
+            vec![RuleWarningKind::DuplicatePattern("/a".to_string())]
+        );
+    }
+
+    #[test]
+    fn warns_about_always_false_conditions() {
+        let source = r#"
+            const LIMIT = 10;
+            matches / {
+                if 1 == 2 { return 404; }
+                if LIMIT > 20 && 1 < 2 { return 404; }
+                matches /a { if "a" != "a" { return 404; } }
+                if 1 == 1 { return 404; }
+            }
+        "#;
+
+        assert_eq!(warnings(source), vec![RuleWarningKind::AlwaysFalse; 3]);
+    }
+
+    #[test]
+    fn warns_about_ignored_concurrency_limits() {
+        let source = r#"
+            matches /a { concurrency_limit(2, 5, 10); }
+            matches /b { concurrency_limit(2); }
+            matches /c { if 1 == 1 { concurrency_limit(2, 5, 10); } }
+        "#;
+
+        assert_eq!(
+            warnings(source),
+            vec![RuleWarningKind::IgnoredConcurrencyLimit; 2]
+        );
+    }
+
+    #[test]
+    fn skips_conditions_depending_on_request() {
+        let source = r#"matches / { if request.method == "NOPE" || 1 % 0 == 1 { return 404; } }"#;
+
+        assert!(warnings(source).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::rules::callable::wrap_callable;
+use crate::rules::error::{RuleError, RuntimeErrorKind};
+use crate::rules::lexer::Position;
+use crate::rules::rule::EvaluationOptions;
+use crate::rules::scope::RuleScope;
+use crate::rules::value::Type;
+use log::info;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::rc::Rc;
+use xxhash_rust::xxh3::xxh3_64;
+
+/// Adds the functions every rule can call to `scope`, unless it defines them already.
+pub fn register(scope: &mut RuleScope, options: &EvaluationOptions) {
+    scope.define_var(
+        "log",
+        Type::Function(wrap_callable(|text: String| {
+            info!("{}", text);
+            Ok(Type::Bool(true))
+        })),
+    );
+    scope.define_var(
+        "contains",
+        Type::Function(wrap_callable(|text: String, pattern: String| {
+            Ok(Type::Bool(text.contains(&pattern)))
+        })),
+    );
+    scope.define_var(
+        "env",
+        Type::Function(wrap_callable(|name: String| {
+            Ok(Type::String(std::env::var(name).unwrap_or_default()))
+        })),
+    );
+    let secrets = options.secrets.clone();
+    scope.define_var(
+        "secret",
+        Type::Function(wrap_callable(move |name: String| {
+            match secrets.get(&name) {
+                Some(value) => Ok(Type::String(value.clone())),
+                None => Err(RuleError::runtime(
+                    RuntimeErrorKind::UnresolvedReference(format!("secret({name:?})")),
+                    Position::zero(),
+                )),
+            }
+        })),
+    );
+    scope.define_var(
+        "rand_percent",
+        Type::Function(wrap_callable(|| {
+            // every RandomState is seeded differently, which is random enough for a rollout
+            let random = RandomState::new().build_hasher().finish();
+            Ok(Type::Int((random % 100) as u32))
+        })),
+    );
+    scope.define_var(
+        "hash",
+        Type::Function(wrap_callable(|text: String| {
+            // stable across restarts and builds, so rollouts stay sticky
+            Ok(Type::Int(xxh3_64(text.as_bytes()) as u32))
+        })),
+    );
+    // Rule::concurrency_limit reads the call when the rules load
+    scope.define_var(
+        "concurrency_limit",
+        Type::Function(Rc::new(|_| Ok(Type::Bool(true)))),
+    );
+    scope.define_var(
+        "cidr_match",
+        Type::Function(wrap_callable(|ip: String, cidr: String| {
+            let matches = cidr_contains(&cidr).ok_or_else(|| {
+                RuleError::runtime(
+                    RuntimeErrorKind::InvalidArgument(format!("CIDR block {cidr:?}")),
+                    Position::zero(),
+                )
+            })?;
+
+            // requests without a known address match no block
+            Ok(Type::Bool(ip.parse::<IpAddr>().is_ok_and(matches)))
+        })),
+    );
+    #[cfg(feature = "geoip")]
+    {
+        let geoip = options.geoip.clone();
+        scope.define_var(
+            "geoip_country",
+            Type::Function(wrap_callable(move |ip: String| {
+                let country = match (&geoip, ip.parse::<IpAddr>()) {
+                    (Some(geoip), Ok(ip)) => geoip.country(ip),
+                    _ => None,
+                };
+
+                Ok(Type::String(country.unwrap_or_default()))
+            })),
+        );
+    }
+}
+
+/// Parses a block like "10.0.0.0/8" or "2001:db8::/32", a bare address is a block of one.
+/// IPv4-mapped IPv6 addresses match IPv4 blocks.
+fn cidr_contains(cidr: &str) -> Option<impl Fn(IpAddr) -> bool> {
+    let (network, prefix) = match cidr.trim().split_once('/') {
+        Some((network, prefix)) => (network.parse::<IpAddr>().ok()?, Some(prefix)),
+        None => (cidr.trim().parse::<IpAddr>().ok()?, None),
+    };
+    let bits = match network {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    let prefix = match prefix {
+        Some(prefix) => prefix
+            .parse::<u32>()
+            .ok()
+            .filter(|prefix| *prefix <= bits)?,
+        None => bits,
+    };
+
+    Some(move |ip: IpAddr| match (network, ip.to_canonical()) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    mod cidr_contains {
+        use crate::rules::builtins::cidr_contains;
+
+        #[test]
+        fn matches_ipv4_blocks() {
+            let matches = cidr_contains("10.0.0.0/8").unwrap();
+
+            assert!(matches("10.1.2.3".parse().unwrap()));
+            assert!(matches("::ffff:10.1.2.3".parse().unwrap()));
+            assert!(!matches("11.0.0.1".parse().unwrap()));
+            assert!(cidr_contains("0.0.0.0/0").unwrap()(
+                "1.2.3.4".parse().unwrap()
+            ));
+            assert!(!cidr_contains("1.2.3.4").unwrap()(
+                "1.2.3.5".parse().unwrap()
+            ));
+        }
+
+        #[test]
+        fn matches_ipv6_blocks() {
+            let matches = cidr_contains("2001:db8::/32").unwrap();
+
+            assert!(matches("2001:db8::1".parse().unwrap()));
+            assert!(!matches("2001:db9::1".parse().unwrap()));
+            assert!(!matches("10.0.0.1".parse().unwrap()));
+        }
+
+        #[test]
+        fn none_if_block_is_invalid() {
+            assert!(cidr_contains("10.0.0.0/33").is_none());
+            assert!(cidr_contains("10.0.0/8").is_none());
+            assert!(cidr_contains("localhost").is_none());
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use crate::response_status_code::ResponseStatusCode;
+use crate::rules::error::{RuleWarning, RuleWarningKind};
+use crate::rules::expr::{Expr, ExprOrValue, Operator};
+use crate::rules::grammar::{Statement, StatementKind};
+use crate::rules::lexer::{Position, RuleToken, RuleTokenKind};
+use crate::rules::Rule;
+use std::path::PathBuf;
+use xxhash_rust::xxh3::xxh3_64;
+
+// bumped whenever the encoding of anything below changes
+const MAGIC: &[u8] = b"HTTPRS-RULES\x01";
+
+/// Where the compiled form of the rules file at `path` is kept.
+pub(crate) fn cache_path(path: &str) -> PathBuf {
+    PathBuf::from(format!("{path}.cache"))
+}
+
+/// Compiled rules, tied to the exact source they were parsed from.
+pub(crate) fn to_bytes(source: &str, rules: &[Rule], warnings: &[RuleWarning]) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    xxh3_64(source.as_bytes()).encode(&mut out);
+    rules.encode(&mut out);
+    warnings.encode(&mut out);
+
+    out
+}
+
+/// None when the cache was written by another version or for another source,
+/// or is not valid at all.
+pub(crate) fn from_bytes(source: &str, bytes: &[u8]) -> Option<(Vec<Rule>, Vec<RuleWarning>)> {
+    let mut input = bytes.strip_prefix(MAGIC)?;
+
+    if u64::decode(&mut input)? != xxh3_64(source.as_bytes()) {
+        return None;
+    }
+
+    let rules = Vec::<Rule>::decode(&mut input)?;
+    let warnings = Vec::<RuleWarning>::decode(&mut input)?;
+
+    input.is_empty().then_some((rules, warnings))
+}
+
+trait Encode {
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+trait Decode: Sized {
+    fn decode(input: &mut &[u8]) -> Option<Self>;
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if input.len() < len {
+        return None;
+    }
+
+    let (taken, rest) = input.split_at(len);
+    *input = rest;
+
+    Some(taken)
+}
+
+macro_rules! int_codec {
+    ($($int:ty),*) => {
+        $(
+            impl Encode for $int {
+                fn encode(&self, out: &mut Vec<u8>) {
+                    out.extend_from_slice(&self.to_le_bytes());
+                }
+            }
+
+            impl Decode for $int {
+                fn decode(input: &mut &[u8]) -> Option<Self> {
+                    let bytes = take(input, std::mem::size_of::<$int>())?;
+
+                    Some(<$int>::from_le_bytes(bytes.try_into().ok()?))
+                }
+            }
+        )*
+    };
+}
+
+int_codec!(u8, u16, u32, u64);
+
+impl Encode for str {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        out.extend_from_slice(self.as_bytes());
+    }
+}
+
+impl Encode for String {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_str().encode(out);
+    }
+}
+
+impl Decode for String {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = u32::decode(input)? as usize;
+
+        String::from_utf8(take(input, len)?.to_vec()).ok()
+    }
+}
+
+impl<T: Encode> Encode for [T] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+
+        for item in self {
+            item.encode(out);
+        }
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.as_slice().encode(out);
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let len = u32::decode(input)?;
+
+        (0..len).map(|_| T::decode(input)).collect()
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Some(value) => {
+                1u8.encode(out);
+                value.encode(out);
+            }
+            None => 0u8.encode(out),
+        }
+    }
+}
+
+impl<T: Decode> Decode for Option<T> {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        match u8::decode(input)? {
+            0 => Some(None),
+            1 => Some(Some(T::decode(input)?)),
+            _ => None,
+        }
+    }
+}
+
+impl Encode for Position {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.line.encode(out);
+        self.column.encode(out);
+        self.len.encode(out);
+    }
+}
+
+impl Decode for Position {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Position {
+            line: u32::decode(input)?,
+            column: u32::decode(input)?,
+            len: u16::decode(input)?,
+        })
+    }
+}
+
+impl Encode for ResponseStatusCode {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (*self as u16).encode(out);
+    }
+}
+
+impl Decode for ResponseStatusCode {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        ResponseStatusCode::try_from(u16::decode(input)?).ok()
+    }
+}
+
+impl Encode for RuleToken {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let (tag, value) = match &self.kind {
+            RuleTokenKind::Ident(value) => (0u8, value),
+            RuleTokenKind::LitStr(value) => (1, value),
+            RuleTokenKind::LitInt(value) => (2, value),
+            // the grammar only puts literals and identifiers in values
+            kind => unreachable!("{kind:?}"),
+        };
+
+        tag.encode(out);
+        value.encode(out);
+        self.position.encode(out);
+    }
+}
+
+impl Decode for RuleToken {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let kind = match (u8::decode(input)?, String::decode(input)?) {
+            (0, value) => RuleTokenKind::Ident(value),
+            (1, value) => RuleTokenKind::LitStr(value),
+            (2, value) if value.parse::<u32>().is_ok() => RuleTokenKind::LitInt(value),
+            _ => return None,
+        };
+
+        Some(RuleToken {
+            kind,
+            position: Position::decode(input)?,
+        })
+    }
+}
+
+const OPERATORS: [Operator; 11] = [
+    Operator::And,
+    Operator::Or,
+    Operator::Eq,
+    Operator::NotEq,
+    Operator::Lt,
+    Operator::LtEq,
+    Operator::Gt,
+    Operator::GtEq,
+    Operator::Mod,
+    Operator::Dot,
+    Operator::Call,
+];
+
+impl Encode for Operator {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let tag = OPERATORS
+            .iter()
+            .position(|operator| operator == self)
+            .unwrap();
+
+        (tag as u8).encode(out);
+    }
+}
+
+impl Decode for Operator {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        OPERATORS.get(u8::decode(input)? as usize).copied()
+    }
+}
+
+impl Encode for ExprOrValue {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ExprOrValue::Expr(expr) => {
+                0u8.encode(out);
+                expr.operator.encode(out);
+                expr.lhs.encode(out);
+                expr.rhs.encode(out);
+            }
+            ExprOrValue::Value(token) => {
+                1u8.encode(out);
+                token.encode(out);
+            }
+            ExprOrValue::List(items) => {
+                2u8.encode(out);
+                items.encode(out);
+            }
+        }
+    }
+}
+
+impl Decode for ExprOrValue {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let expr_or_value = match u8::decode(input)? {
+            0 => ExprOrValue::Expr(Expr {
+                operator: Operator::decode(input)?,
+                lhs: Box::new(ExprOrValue::decode(input)?),
+                rhs: Box::new(ExprOrValue::decode(input)?),
+            }),
+            1 => ExprOrValue::Value(RuleToken::decode(input)?),
+            2 => ExprOrValue::List(Vec::decode(input)?),
+            _ => return None,
+        };
+
+        Some(expr_or_value)
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+impl<A: Decode, B: Decode> Decode for (A, B) {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl Encode for Statement {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match &self.kind {
+            StatementKind::Redirect(status_code, location) => {
+                0u8.encode(out);
+                status_code.encode(out);
+                location.encode(out);
+            }
+            StatementKind::Return(status_code, body) => {
+                1u8.encode(out);
+                status_code.encode(out);
+                body.encode(out);
+            }
+            StatementKind::If(condition, statements) => {
+                2u8.encode(out);
+                condition.encode(out);
+                statements.encode(out);
+            }
+            StatementKind::Matches(pattern, statements) => {
+                3u8.encode(out);
+                pattern.encode(out);
+                statements.encode(out);
+            }
+            StatementKind::AddHeaders(headers) => {
+                4u8.encode(out);
+                headers.encode(out);
+            }
+            StatementKind::Expr(expr) => {
+                5u8.encode(out);
+                expr.encode(out);
+            }
+        }
+
+        self.position.encode(out);
+    }
+}
+
+impl Decode for Statement {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let kind = match u8::decode(input)? {
+            0 => {
+                StatementKind::Redirect(ResponseStatusCode::decode(input)?, String::decode(input)?)
+            }
+            1 => StatementKind::Return(ResponseStatusCode::decode(input)?, Option::decode(input)?),
+            2 => StatementKind::If(ExprOrValue::decode(input)?, Vec::decode(input)?),
+            3 => StatementKind::Matches(String::decode(input)?, Vec::decode(input)?),
+            4 => StatementKind::AddHeaders(Vec::decode(input)?),
+            5 => StatementKind::Expr(ExprOrValue::decode(input)?),
+            _ => return None,
+        };
+
+        Some(Statement {
+            kind,
+            position: Position::decode(input)?,
+        })
+    }
+}
+
+impl Encode for Rule {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.pattern.encode(out);
+        self.statements.encode(out);
+        self.position.encode(out);
+    }
+}
+
+impl Decode for Rule {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        Some(Rule {
+            pattern: String::decode(input)?,
+            statements: Vec::decode(input)?,
+            position: Position::decode(input)?,
+        })
+    }
+}
+
+impl Encode for RuleWarning {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self.kind() {
+            RuleWarningKind::ShadowedConstant(name) => {
+                0u8.encode(out);
+                name.encode(out);
+            }
+            RuleWarningKind::DuplicatePattern(pattern) => {
+                1u8.encode(out);
+                pattern.encode(out);
+            }
+            RuleWarningKind::AlwaysFalse => 2u8.encode(out),
+            RuleWarningKind::IgnoredConcurrencyLimit => 3u8.encode(out),
+        }
+
+        self.position().encode(out);
+    }
+}
+
+impl Decode for RuleWarning {
+    fn decode(input: &mut &[u8]) -> Option<Self> {
+        let kind = match u8::decode(input)? {
+            0 => RuleWarningKind::ShadowedConstant(String::decode(input)?),
+            1 => RuleWarningKind::DuplicatePattern(String::decode(input)?),
+            2 => RuleWarningKind::AlwaysFalse,
+            3 => RuleWarningKind::IgnoredConcurrencyLimit,
+            _ => return None,
+        };
+
+        Some(RuleWarning::new(kind, Position::decode(input)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rules::cache::{cache_path, from_bytes, to_bytes};
+    use crate::rules::parser::{parse_file_cached, parse_str};
+
+    static SOURCE: &str = r#"
+        const LIMIT = 10;
+        const LIMIT = 20;
+        matches /api {
+            if request.header("X-Count") == "1" && hash(request.ip) % 100 < LIMIT {
+                add_headers { "X-Canary": "yes", "X-Limit": LIMIT, }
+            }
+            matches /v2 { redirect 301 "/v3"; }
+            if 1 == 2 { log("never"); }
+            return 404 "Not here";
+        }
+        matches /api { return 204; }
+    "#;
+
+    #[test]
+    fn round_trips_rules_and_warnings() {
+        let (rules, warnings) = parse_str(SOURCE).unwrap();
+        let bytes = to_bytes(SOURCE, &rules, &warnings);
+
+        let (cached_rules, cached_warnings) = from_bytes(SOURCE, &bytes).unwrap();
+
+        assert_eq!(format!("{cached_rules:?}"), format!("{rules:?}"));
+        assert_eq!(format!("{cached_warnings:?}"), format!("{warnings:?}"));
+        assert_eq!(warnings.len(), 3);
+    }
+
+    #[test]
+    fn rejects_cache_of_other_source() {
+        let (rules, warnings) = parse_str(SOURCE).unwrap();
+        let bytes = to_bytes(SOURCE, &rules, &warnings);
+
+        assert!(from_bytes("matches / { }", &bytes).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_cache() {
+        let (rules, warnings) = parse_str(SOURCE).unwrap();
+        let bytes = to_bytes(SOURCE, &rules, &warnings);
+
+        for len in [0, 10, bytes.len() / 2, bytes.len() - 1] {
+            assert!(from_bytes(SOURCE, &bytes[..len]).is_none(), "{len}");
+        }
+    }
+
+    #[test]
+    fn parse_file_cached_writes_cache_for_current_source() {
+        let path = std::env::temp_dir().join(format!("http_rs_cache_{}.rules", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(path, SOURCE).unwrap();
+
+        let rules = parse_file_cached(path).unwrap();
+        let bytes = std::fs::read(cache_path(path)).unwrap();
+        assert!(from_bytes(SOURCE, &bytes).is_some());
+        assert_eq!(
+            format!("{:?}", parse_file_cached(path).unwrap().rules),
+            format!("{:?}", rules.rules)
+        );
+
+        std::fs::write(path, "matches /other { return 404; }").unwrap();
+        let rules = parse_file_cached(path).unwrap();
+        assert_eq!(rules.rules[0].pattern, "/other");
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(cache_path(path)).unwrap();
+    }
+}
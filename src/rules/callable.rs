@@ -30,6 +30,17 @@ where
     }
 }
 
+impl<F, A, B, R> Function<(A, B)> for F
+where
+    F: Fn(A, B) -> R,
+{
+    type Result = R;
+
+    fn invoke(&self, args: (A, B)) -> Self::Result {
+        self(args.0, args.1)
+    }
+}
+
 impl<F, A, B, C, R> Function<(A, B, C)> for F
 where
     F: Fn(A, B, C) -> R,
@@ -44,6 +44,10 @@ pub enum RuntimeErrorKind {
     UnresolvedReference(String),
     MemberNotDefined(String, String),
     TooFewArguments(usize, usize),
+    BudgetExceeded(String),
+    BodyTooLarge(usize),
+    BodyUnavailable,
+    InvalidArgument(String),
 }
 
 impl Display for RuntimeErrorKind {
@@ -62,6 +66,14 @@ impl Display for RuntimeErrorKind {
                     "Function takes {expected} arguments, but {got} arguments were passed"
                 )
             }
+            RuntimeErrorKind::BudgetExceeded(limit) => {
+                write!(f, "Evaluation took more than {limit}")
+            }
+            RuntimeErrorKind::BodyTooLarge(limit) => {
+                write!(f, "Response body would be larger than {limit} bytes")
+            }
+            RuntimeErrorKind::BodyUnavailable => write!(f, "Response body could not be read"),
+            RuntimeErrorKind::InvalidArgument(s) => write!(f, "Invalid argument, {s}"),
         }
     }
 }
@@ -122,6 +134,13 @@ impl RuleError {
     pub fn position(&self) -> &Position {
         &self.position
     }
+
+    pub fn is_budget_exceeded(&self) -> bool {
+        matches!(
+            self.kind,
+            RuleErrorKind::Runtime(RuntimeErrorKind::BudgetExceeded(_))
+        )
+    }
 }
 
 impl Display for RuleError {
@@ -136,16 +155,84 @@ impl Display for RuleError {
 
 impl Error for RuleError {}
 
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuleWarningKind {
+    ShadowedConstant(String),
+    DuplicatePattern(String),
+    AlwaysFalse,
+    /// `concurrency_limit` nested in a block or without three integer literals
+    IgnoredConcurrencyLimit,
+}
+
+impl Display for RuleWarningKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleWarningKind::ShadowedConstant(s) => {
+                write!(f, "Constant \"{s}\" is declared again")
+            }
+            RuleWarningKind::DuplicatePattern(s) => {
+                write!(f, "Pattern \"{s}\" is used by an earlier rule too")
+            }
+            RuleWarningKind::AlwaysFalse => write!(f, "Condition is always false"),
+            RuleWarningKind::IgnoredConcurrencyLimit => write!(
+                f,
+                "concurrency_limit takes three integers and only applies directly in a rule"
+            ),
+        }
+    }
+}
+
+/// Something in a rules file that parses and runs, but is most likely a mistake.
+#[derive(Debug)]
+pub struct RuleWarning {
+    kind: RuleWarningKind,
+    position: Position,
+}
+
+impl RuleWarning {
+    pub fn new(kind: RuleWarningKind, position: Position) -> Self {
+        RuleWarning { kind, position }
+    }
+
+    pub fn kind(&self) -> &RuleWarningKind {
+        &self.kind
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+}
+
+impl Display for RuleWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Warning: {} at {}:{}",
+            self.kind, self.position.line, self.position.column
+        )
+    }
+}
+
 pub fn format_error_in_file(err: RuleError, file_contents: &str) -> String {
-    let base_err = err.to_string();
+    format_in_file(&err.to_string(), err.position(), file_contents)
+}
+
+pub fn format_warning_in_file(warning: &RuleWarning, file_contents: &str) -> String {
+    format_in_file(&warning.to_string(), warning.position(), file_contents)
+}
+
+fn format_in_file(message: &str, pos: &Position, file_contents: &str) -> String {
+    // the end of the file has no position
+    if pos.line == 0 {
+        return message.to_string();
+    }
 
     let lines = file_contents.lines().collect::<Vec<&str>>();
 
-    let pos = err.position();
     let line_indent = format!("{} | ", pos.line);
     let line = lines.get(pos.line as usize - 1).unwrap_or(&"");
-    let caret_indent = " ".repeat(line_indent.len() + pos.column as usize - 1);
+    let caret_indent = " ".repeat(line_indent.len() + (pos.column as usize).saturating_sub(1));
     let caret = "^".repeat(pos.len as usize);
 
-    format!("{base_err}\n{line_indent}{line}\n{caret_indent}{caret}")
+    format!("{message}\n{line_indent}{line}\n{caret_indent}{caret}")
 }
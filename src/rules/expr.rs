@@ -6,12 +6,17 @@ use crate::rules::value::{Type, Value};
 
 type Result<T> = std::result::Result<T, RuleError>;
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Operator {
     And,
     Or,
     Eq,
     NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Mod,
     Dot,
     Call,
 }
@@ -26,7 +31,10 @@ pub enum ExprOrValue {
 impl ExprOrValue {
     pub fn eval(&self, scope: &RuleScope) -> Result<Value> {
         match self {
-            ExprOrValue::Value(token) => eval_value(token),
+            ExprOrValue::Value(token) => {
+                scope.spend_step(token.position)?;
+                eval_value(token)
+            }
             ExprOrValue::Expr(expr) => eval_expr(expr, scope),
             ExprOrValue::List(args) => {
                 let mut val_args: Vec<Value> = vec![];
@@ -72,6 +80,9 @@ fn eval_expr(expr: &Expr, scope: &RuleScope) -> Result<Value> {
         }
         Operator::Eq => Type::Bool(lhs_value.eq(&rhs_value)),
         Operator::NotEq => Type::Bool(lhs_value.ne(&rhs_value)),
+        Operator::Lt | Operator::LtEq | Operator::Gt | Operator::GtEq | Operator::Mod => {
+            return eval_int_expr(&lhs_value, &expr.operator, &rhs_value)
+        }
         Operator::Dot => return eval_path_expr(lhs_value, rhs_value, scope),
         Operator::Call => return eval_call_expr(lhs_value, rhs_value, scope),
     };
@@ -84,12 +95,10 @@ fn eval_bool_expr(lhs_value: &Value, operator: &Operator, rhs_value: &Value) ->
 
     for (index, value) in [lhs_value, rhs_value].iter().enumerate() {
         let Type::Bool(v) = value.t() else {
-            return Err(
-                RuleError::runtime(
-                    RuntimeErrorKind::IncorrectType("bool".to_owned(), value.t().type_string()),
-                    *value.position(),
-                )
-            );
+            return Err(RuleError::runtime(
+                RuntimeErrorKind::IncorrectType("bool".to_owned(), value.t().type_string()),
+                *value.position(),
+            ));
         };
 
         values[index] = *v;
@@ -110,6 +119,40 @@ fn eval_bool_expr(lhs_value: &Value, operator: &Operator, rhs_value: &Value) ->
     ))
 }
 
+fn eval_int_expr(lhs_value: &Value, operator: &Operator, rhs_value: &Value) -> Result<Value> {
+    let mut values = [0; 2];
+
+    for (index, value) in [lhs_value, rhs_value].iter().enumerate() {
+        let Type::Int(v) = value.t() else {
+            return Err(RuleError::runtime(
+                RuntimeErrorKind::IncorrectType("int".to_owned(), value.t().type_string()),
+                *value.position(),
+            ));
+        };
+
+        values[index] = *v;
+    }
+
+    let t = match operator {
+        Operator::Lt => Type::Bool(values[0] < values[1]),
+        Operator::LtEq => Type::Bool(values[0] <= values[1]),
+        Operator::Gt => Type::Bool(values[0] > values[1]),
+        Operator::GtEq => Type::Bool(values[0] >= values[1]),
+        Operator::Mod => Type::Int(values[0].checked_rem(values[1]).ok_or_else(|| {
+            RuleError::runtime(
+                RuntimeErrorKind::InvalidArgument("modulo by zero".to_owned()),
+                *rhs_value.position(),
+            )
+        })?),
+        _ => {
+            // guaranteed by caller
+            unreachable!()
+        }
+    };
+
+    Ok(Value::new(t, lhs_value.position() + rhs_value.position()))
+}
+
 fn eval_path_expr(target_val: Value, member_val: Value, scope: &RuleScope) -> Result<Value> {
     let (Type::Ident(target), Type::Ident(member)) = (target_val.t(), member_val.t()) else {
         // guaranteed by parser
@@ -121,7 +164,10 @@ fn eval_path_expr(target_val: Value, member_val: Value, scope: &RuleScope) -> Re
     let t = match var {
         Some(Type::Object(obj)) => {
             let Some(member) = obj.get_member(member) else {
-                return Err(RuleError::runtime(RuntimeErrorKind::MemberNotDefined(member.to_owned(), target.to_owned()), *member_val.position()));
+                return Err(RuleError::runtime(
+                    RuntimeErrorKind::MemberNotDefined(member.to_owned(), target.to_owned()),
+                    *member_val.position(),
+                ));
             };
 
             match member.kind {
@@ -189,7 +235,7 @@ fn eval_call_expr(target_val: Value, args_val: Value, scope: &RuleScope) -> Resu
         }
     };
 
-    result.map_err(|e| {
+    let t = result.map_err(|e| {
         if e.position() == &Position::zero() {
             RuleError::new(e.kind_owned(), *target_val.position())
         } else {
@@ -197,10 +243,13 @@ fn eval_call_expr(target_val: Value, args_val: Value, scope: &RuleScope) -> Resu
         }
     })?;
 
-    Ok(Value::new(
-        Type::Bool(true),
-        target_val.position() + args_val.position(),
-    ))
+    let position = if args.is_empty() {
+        *target_val.position()
+    } else {
+        target_val.position() + args_val.position()
+    };
+
+    Ok(Value::new(t, position))
 }
 
 #[derive(Debug)]
@@ -0,0 +1,27 @@
+use crate::error::{Error, Result};
+use maxminddb::geoip2;
+use std::net::IpAddr;
+
+/// MaxMind database (GeoLite2 or GeoIP2, Country or City) for the `geoip_country` rule function.
+pub struct GeoIp {
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    pub fn open(path: &str) -> Result<Self> {
+        let reader = maxminddb::Reader::open_readfile(path)
+            .map_err(|err| Error::Config(format!("Cannot open GeoIP database {path}: {err}")))?;
+
+        Ok(GeoIp { reader })
+    }
+
+    /// ISO 3166-1 code of the country `ip` is located in.
+    pub fn country(&self, ip: IpAddr) -> Option<String> {
+        let country = self.reader.lookup::<geoip2::Country>(ip).ok()?;
+
+        country
+            .country
+            .and_then(|country| country.iso_code)
+            .map(|iso_code| iso_code.to_string())
+    }
+}
@@ -1,8 +1,11 @@
 use crate::response_status_code::ResponseStatusCode;
-use crate::rules::error::{RuleError, SemanticErrorKind, SyntaxErrorKind};
+use crate::rules::error::{
+    RuleError, RuleWarning, RuleWarningKind, SemanticErrorKind, SyntaxErrorKind,
+};
 use crate::rules::expr::{Expr, ExprOrValue, Operator};
-use crate::rules::lexer::{RuleToken, RuleTokenKind};
+use crate::rules::lexer::{Position, RuleToken, RuleTokenKind};
 use crate::rules::Rule;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::iter::Peekable;
 use std::vec::IntoIter;
@@ -17,6 +20,9 @@ pub enum StatementKind {
     Redirect(ResponseStatusCode, String),
     Return(ResponseStatusCode, Option<String>),
     If(ExprOrValue, Vec<Statement>),
+    /// Nested `matches` block, its statements run when the url contains the pattern too
+    Matches(String, Vec<Statement>),
+    AddHeaders(Vec<(String, ExprOrValue)>),
     Expr(ExprOrValue),
 }
 
@@ -26,6 +32,8 @@ impl Display for StatementKind {
             StatementKind::Redirect(_, _) => "redirect",
             StatementKind::Return(_, _) => "return",
             StatementKind::If(_, _) => "if",
+            StatementKind::Matches(_, _) => "matches",
+            StatementKind::AddHeaders(_) => "add_headers",
             StatementKind::Expr(_) => "expr",
         };
 
@@ -36,6 +44,7 @@ impl Display for StatementKind {
 #[derive(Debug)]
 pub struct Statement {
     pub kind: StatementKind,
+    pub position: Position,
 }
 
 pub fn file(tokens: Vec<RuleToken>) -> Result<Vec<Rule>> {
@@ -50,10 +59,77 @@ pub fn file(tokens: Vec<RuleToken>) -> Result<Vec<Rule>> {
     Ok(rules)
 }
 
+/// Takes out top-level `const NAME = literal;` declarations and puts the literal in place of
+/// every later use of the name. Member names (after a dot) are left alone.
+/// A constant declared again replaces the earlier value from there on, with a warning.
+pub fn resolve_constants(tokens: Vec<RuleToken>) -> Result<(Vec<RuleToken>, Vec<RuleWarning>)> {
+    let mut constants: HashMap<String, RuleTokenKind> = HashMap::new();
+    let mut resolved: Vec<RuleToken> = vec![];
+    let mut warnings: Vec<RuleWarning> = vec![];
+    let mut depth = 0;
+    let mut iter = tokens.into_iter().peekable();
+
+    while let Some(mut token) = iter.next() {
+        match &token.kind {
+            RuleTokenKind::Const if depth == 0 => {
+                let RuleToken {
+                    kind: RuleTokenKind::Ident(name),
+                    position,
+                } = ident(&mut iter)?
+                else {
+                    unreachable!()
+                };
+                swallow(&mut iter, RuleTokenKind::Assign)?;
+                let value = match iter.next() {
+                    Some(value) if value.kind.is_lit() => value,
+                    other => {
+                        let other = other.unwrap_or(RuleToken::eof());
+                        return Err(RuleError::syntax(
+                            SyntaxErrorKind::ExpectedOther(
+                                "literal".to_string(),
+                                other.kind.to_string(),
+                            ),
+                            other.position,
+                        ));
+                    }
+                };
+                swallow(&mut iter, RuleTokenKind::Semicolon)?;
+
+                if constants.insert(name.clone(), value.kind).is_some() {
+                    warnings.push(RuleWarning::new(
+                        RuleWarningKind::ShadowedConstant(name),
+                        position,
+                    ));
+                }
+                continue;
+            }
+            RuleTokenKind::LBrace => depth += 1,
+            RuleTokenKind::RBrace => depth -= 1,
+            RuleTokenKind::Ident(name) => {
+                let is_member = resolved
+                    .last()
+                    .is_some_and(|previous| previous.kind == RuleTokenKind::Dot);
+
+                if let Some(value) = constants.get(name).filter(|_| !is_member) {
+                    token.kind = value.clone();
+                }
+            }
+            _ => {}
+        }
+
+        resolved.push(token);
+    }
+
+    Ok((resolved, warnings))
+}
+
 pub fn rule(iter: &mut TokenIter) -> Result<Rule> {
+    let position = statement_position(iter);
     swallow(iter, RuleTokenKind::Matches)?;
 
-    let RuleTokenKind::LitStr(pattern) = pattern(iter)?.kind else { unreachable!() };
+    let RuleTokenKind::LitStr(pattern) = pattern(iter)?.kind else {
+        unreachable!()
+    };
 
     swallow(iter, RuleTokenKind::LBrace)?;
 
@@ -64,6 +140,7 @@ pub fn rule(iter: &mut TokenIter) -> Result<Rule> {
     let rule = Rule {
         pattern,
         statements,
+        position,
     };
 
     Ok(rule)
@@ -80,6 +157,8 @@ pub fn rule_statements(iter: &mut TokenIter) -> Result<Vec<Statement>> {
             RuleTokenKind::Redirect => redirect_statement(iter)?,
             RuleTokenKind::Return => return_statement(iter)?,
             RuleTokenKind::If => if_statement(iter)?,
+            RuleTokenKind::Matches => matches_statement(iter)?,
+            RuleTokenKind::AddHeaders => add_headers_statement(iter)?,
             RuleTokenKind::RBrace => break,
             _ => {
                 return Err(RuleError::syntax(
@@ -104,16 +183,23 @@ pub fn rule_statements(iter: &mut TokenIter) -> Result<Vec<Statement>> {
     Ok(statements)
 }
 
+fn statement_position(iter: &mut TokenIter) -> Position {
+    iter.peek().unwrap_or(&EOF_TOKEN).position
+}
+
 pub fn base_statement(iter: &mut TokenIter) -> Result<Statement> {
+    let position = statement_position(iter);
     let expression = expr(iter)?;
     swallow(iter, RuleTokenKind::Semicolon)?;
 
     Ok(Statement {
         kind: StatementKind::Expr(expression),
+        position,
     })
 }
 
 pub fn redirect_statement(iter: &mut TokenIter) -> Result<Statement> {
+    let position = statement_position(iter);
     swallow(iter, RuleTokenKind::Redirect)?;
 
     let response_code = status_code(iter)?;
@@ -125,6 +211,7 @@ pub fn redirect_statement(iter: &mut TokenIter) -> Result<Statement> {
 
     let statement = Statement {
         kind: StatementKind::Redirect(response_code, location),
+        position,
     };
 
     swallow(iter, RuleTokenKind::Semicolon)?;
@@ -133,6 +220,7 @@ pub fn redirect_statement(iter: &mut TokenIter) -> Result<Statement> {
 }
 
 pub fn return_statement(iter: &mut TokenIter) -> Result<Statement> {
+    let position = statement_position(iter);
     swallow(iter, RuleTokenKind::Return)?;
 
     let response_code = status_code(iter)?;
@@ -144,6 +232,7 @@ pub fn return_statement(iter: &mut TokenIter) -> Result<Statement> {
 
     let statement = Statement {
         kind: StatementKind::Return(response_code, location_or_body),
+        position,
     };
 
     swallow(iter, RuleTokenKind::Semicolon)?;
@@ -152,6 +241,7 @@ pub fn return_statement(iter: &mut TokenIter) -> Result<Statement> {
 }
 
 pub fn if_statement(iter: &mut TokenIter) -> Result<Statement> {
+    let position = statement_position(iter);
     swallow(iter, RuleTokenKind::If)?;
 
     let condition = expr(iter)?;
@@ -162,6 +252,49 @@ pub fn if_statement(iter: &mut TokenIter) -> Result<Statement> {
 
     Ok(Statement {
         kind: StatementKind::If(condition, statements),
+        position,
+    })
+}
+
+pub fn matches_statement(iter: &mut TokenIter) -> Result<Statement> {
+    let position = statement_position(iter);
+    let Rule {
+        pattern,
+        statements,
+        ..
+    } = rule(iter)?;
+
+    Ok(Statement {
+        kind: StatementKind::Matches(pattern, statements),
+        position,
+    })
+}
+
+/// `add_headers { "Name": value, ... }`, the trailing comma is optional.
+pub fn add_headers_statement(iter: &mut TokenIter) -> Result<Statement> {
+    let position = statement_position(iter);
+    swallow(iter, RuleTokenKind::AddHeaders)?;
+    swallow(iter, RuleTokenKind::LBrace)?;
+
+    let mut headers = vec![];
+
+    while swallow(iter, RuleTokenKind::RBrace).is_err() {
+        let name = match string(iter)?.kind {
+            RuleTokenKind::LitStr(str_val) => str_val,
+            _ => unreachable!(),
+        };
+        swallow(iter, RuleTokenKind::Colon)?;
+        headers.push((name, expr(iter)?));
+
+        if swallow(iter, RuleTokenKind::Comma).is_err() {
+            swallow(iter, RuleTokenKind::RBrace)?;
+            break;
+        }
+    }
+
+    Ok(Statement {
+        kind: StatementKind::AddHeaders(headers),
+        position,
     })
 }
 
@@ -221,22 +354,19 @@ fn bool_expr(iter: &mut TokenIter) -> Result<ExprOrValue> {
 }
 
 fn cmp_expr(iter: &mut TokenIter) -> Result<ExprOrValue> {
-    let lhs = primary(iter)?;
-
-    match iter.peek() {
-        Some(token) if matches!(token.kind, RuleTokenKind::Eq | RuleTokenKind::NotEq) => {}
+    let lhs = mod_expr(iter)?;
+
+    let operator = match iter.peek().map(|token| &token.kind) {
+        Some(RuleTokenKind::Eq) => Operator::Eq,
+        Some(RuleTokenKind::NotEq) => Operator::NotEq,
+        Some(RuleTokenKind::Lt) => Operator::Lt,
+        Some(RuleTokenKind::LtEq) => Operator::LtEq,
+        Some(RuleTokenKind::Gt) => Operator::Gt,
+        Some(RuleTokenKind::GtEq) => Operator::GtEq,
         _ => return Ok(lhs),
-    }
-
-    let operator = match swallow_any(iter, vec![RuleTokenKind::Eq, RuleTokenKind::NotEq])
-        .unwrap()
-        .kind
-    {
-        RuleTokenKind::Eq => Operator::Eq,
-        RuleTokenKind::NotEq => Operator::NotEq,
-        _ => unreachable!(),
     };
-    let rhs = primary(iter)?;
+    iter.next();
+    let rhs = mod_expr(iter)?;
 
     Ok(ExprOrValue::Expr(Expr {
         lhs: lhs.into(),
@@ -245,6 +375,22 @@ fn cmp_expr(iter: &mut TokenIter) -> Result<ExprOrValue> {
     }))
 }
 
+fn mod_expr(iter: &mut TokenIter) -> Result<ExprOrValue> {
+    let mut lhs = primary(iter)?;
+
+    while swallow(iter, RuleTokenKind::Percent).is_ok() {
+        let rhs = primary(iter)?;
+
+        lhs = ExprOrValue::Expr(Expr {
+            lhs: lhs.into(),
+            operator: Operator::Mod,
+            rhs: rhs.into(),
+        });
+    }
+
+    Ok(lhs)
+}
+
 fn primary(iter: &mut TokenIter) -> Result<ExprOrValue> {
     let next = iter.peek();
     match next {
@@ -0,0 +1,150 @@
+use crate::rules::Rule;
+
+/// Byte trie over rule patterns. A rule matches when its pattern occurs anywhere in the url,
+/// so a lookup walks the trie from every position of the url: the cost depends on the url
+/// and the longest pattern, not on the number of rules.
+#[derive(Debug)]
+pub(crate) struct RuleIndex {
+    nodes: Vec<Node>,
+    rule_count: usize,
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    // sorted by byte
+    children: Vec<(u8, usize)>,
+    // rules whose pattern ends here
+    rules: Vec<usize>,
+}
+
+impl Node {
+    fn child(&self, byte: u8) -> Option<usize> {
+        self.children
+            .binary_search_by_key(&byte, |(child_byte, _)| *child_byte)
+            .ok()
+            .map(|position| self.children[position].1)
+    }
+}
+
+impl Default for RuleIndex {
+    fn default() -> Self {
+        RuleIndex {
+            nodes: vec![Node::default()],
+            rule_count: 0,
+        }
+    }
+}
+
+impl RuleIndex {
+    pub(crate) fn new(rules: &[Rule]) -> Self {
+        let mut index = RuleIndex::default();
+
+        for (rule_index, rule) in rules.iter().enumerate() {
+            index.insert(rule.pattern.as_bytes(), rule_index);
+        }
+
+        index
+    }
+
+    fn insert(&mut self, pattern: &[u8], rule_index: usize) {
+        let mut node = 0;
+
+        for &byte in pattern {
+            node = match self.nodes[node].child(byte) {
+                Some(child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(Node::default());
+                    let children = &mut self.nodes[node].children;
+                    let position = children.partition_point(|(child_byte, _)| *child_byte < byte);
+                    children.insert(position, (byte, child));
+                    child
+                }
+            };
+        }
+
+        self.nodes[node].rules.push(rule_index);
+        self.rule_count = self.rule_count.max(rule_index + 1);
+    }
+
+    /// Indices of the rules matching `url`, in the order they were declared.
+    pub(crate) fn matching(&self, url: &str) -> Vec<usize> {
+        let url = url.as_bytes();
+        let mut matched = vec![false; self.rule_count];
+
+        // empty patterns match every url
+        for &rule_index in &self.nodes[0].rules {
+            matched[rule_index] = true;
+        }
+
+        for start in 0..url.len() {
+            let mut node = 0;
+
+            for &byte in &url[start..] {
+                let Some(child) = self.nodes[node].child(byte) else {
+                    break;
+                };
+                node = child;
+
+                for &rule_index in &self.nodes[node].rules {
+                    matched[rule_index] = true;
+                }
+            }
+        }
+
+        matched
+            .iter()
+            .enumerate()
+            .filter(|(_, matched)| **matched)
+            .map(|(rule_index, _)| rule_index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rules::index::RuleIndex;
+    use crate::rules::lexer::Position;
+    use crate::rules::Rule;
+
+    fn rule(pattern: &str) -> Rule {
+        Rule {
+            pattern: pattern.to_string(),
+            statements: vec![],
+            position: Position::zero(),
+        }
+    }
+
+    #[test]
+    fn agrees_with_substring_matching() {
+        let rules = ["/", "/api", "/api/v2", ".png", "", "/ap", "/images/"].map(rule);
+        let index = RuleIndex::new(&rules);
+
+        for url in [
+            "/",
+            "/api/v2/items",
+            "/static/logo.png",
+            "/images",
+            "",
+            "/apx",
+        ] {
+            let expected: Vec<usize> = rules
+                .iter()
+                .enumerate()
+                .filter(|(_, rule)| rule.matches(url))
+                .map(|(rule_index, _)| rule_index)
+                .collect();
+
+            assert_eq!(index.matching(url), expected, "{url}");
+        }
+    }
+
+    #[test]
+    fn scales_to_many_rules() {
+        let rules: Vec<Rule> = (0..5000).map(|i| rule(&format!("/page/{i}/"))).collect();
+        let index = RuleIndex::new(&rules);
+
+        assert_eq!(index.matching("/page/4321/index.html"), vec![4321]);
+        assert!(index.matching("/other").is_empty());
+    }
+}
@@ -15,12 +15,19 @@ pub enum RuleTokenKind {
     LParen,
     RParen,
     Comma,
+    Colon,
     Semicolon,
     Dot,
+    Assign,
     Eq,
     NotEq,
     And,
     Or,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Percent,
 
     // literals
     LitStr(String),
@@ -31,12 +38,14 @@ pub enum RuleTokenKind {
     Redirect,
     Return,
     If,
+    AddHeaders,
+    Const,
 
     Eof,
 }
 
 impl RuleTokenKind {
-    pub fn len(&self) -> u16 {
+    pub(crate) fn len(&self) -> u16 {
         match self {
             RuleTokenKind::Ident(val) => val.len() as u16,
             RuleTokenKind::LBrace => 1,
@@ -44,18 +53,27 @@ impl RuleTokenKind {
             RuleTokenKind::LParen => 1,
             RuleTokenKind::RParen => 1,
             RuleTokenKind::Comma => 1,
+            RuleTokenKind::Colon => 1,
             RuleTokenKind::Semicolon => 1,
             RuleTokenKind::Dot => 1,
+            RuleTokenKind::Assign => 1,
             RuleTokenKind::Eq => 2,
             RuleTokenKind::NotEq => 2,
             RuleTokenKind::And => 2,
             RuleTokenKind::Or => 2,
+            RuleTokenKind::Lt => 1,
+            RuleTokenKind::LtEq => 2,
+            RuleTokenKind::Gt => 1,
+            RuleTokenKind::GtEq => 2,
+            RuleTokenKind::Percent => 1,
             RuleTokenKind::LitStr(val) => val.len() as u16 + 2,
             RuleTokenKind::LitInt(val) => val.len() as u16,
             RuleTokenKind::Matches => 7,
             RuleTokenKind::Redirect => 8,
             RuleTokenKind::Return => 6,
             RuleTokenKind::If => 2,
+            RuleTokenKind::AddHeaders => 11,
+            RuleTokenKind::Const => 5,
             RuleTokenKind::Eof => 1,
         }
     }
@@ -74,18 +92,27 @@ impl Display for RuleTokenKind {
             RuleTokenKind::LParen => "(",
             RuleTokenKind::RParen => ")",
             RuleTokenKind::Comma => ",",
+            RuleTokenKind::Colon => ":",
             RuleTokenKind::Semicolon => ";",
             RuleTokenKind::Dot => ".",
+            RuleTokenKind::Assign => "=",
             RuleTokenKind::Eq => "==",
             RuleTokenKind::NotEq => "!=",
             RuleTokenKind::And => "&&",
             RuleTokenKind::Or => "||",
+            RuleTokenKind::Lt => "<",
+            RuleTokenKind::LtEq => "<=",
+            RuleTokenKind::Gt => ">",
+            RuleTokenKind::GtEq => ">=",
+            RuleTokenKind::Percent => "%",
             RuleTokenKind::LitStr(s) => s,
             RuleTokenKind::LitInt(s) => s,
             RuleTokenKind::Matches => "matches",
             RuleTokenKind::Redirect => "redirect",
             RuleTokenKind::Return => "return",
             RuleTokenKind::If => "if",
+            RuleTokenKind::AddHeaders => "add_headers",
+            RuleTokenKind::Const => "const",
             RuleTokenKind::Eof => "EOF",
         };
 
@@ -160,7 +187,6 @@ impl RuleToken {
 }
 
 struct LexerIter<'a> {
-    input: &'a str,
     iter: Peekable<Chars<'a>>,
     // Position of the token that will be returned on next Self::next() call
     position: Position,
@@ -169,7 +195,6 @@ struct LexerIter<'a> {
 impl<'a> LexerIter<'a> {
     fn new(input: &'a str) -> Self {
         LexerIter {
-            input,
             iter: input.chars().peekable(),
             position: Position {
                 line: 1,
@@ -214,7 +239,7 @@ impl<'a> LexerIter<'a> {
         let (lit, next) = self.read_until_inner(|next: &char| next != &'"');
 
         match next {
-            Some(c) if c == '"' => {
+            Some('"') => {
                 // swallow ending "
                 self.next();
 
@@ -266,7 +291,7 @@ impl<'a> LexerIter<'a> {
     }
 }
 
-pub(crate) fn tokenize(input: &str) -> Result<Vec<RuleToken>> {
+pub fn tokenize(input: &str) -> Result<Vec<RuleToken>> {
     let mut iter = LexerIter::new(input);
 
     let mut tokens: Vec<RuleToken> = vec![];
@@ -282,6 +307,7 @@ pub(crate) fn tokenize(input: &str) -> Result<Vec<RuleToken>> {
             '(' => RuleTokenKind::LParen,
             ')' => RuleTokenKind::RParen,
             ',' => RuleTokenKind::Comma,
+            ':' => RuleTokenKind::Colon,
             '"' => {
                 let lit = iter.read_string()?;
 
@@ -294,12 +320,7 @@ pub(crate) fn tokenize(input: &str) -> Result<Vec<RuleToken>> {
                     iter.next();
                     RuleTokenKind::Eq
                 }
-                _ => {
-                    return Err(RuleError::syntax(
-                        SyntaxErrorKind::UnexpectedToken(character.into()),
-                        position,
-                    ))
-                }
+                _ => RuleTokenKind::Assign,
             },
             '!' => match iter.peek() {
                 Some(c) if c == &'=' => {
@@ -337,6 +358,21 @@ pub(crate) fn tokenize(input: &str) -> Result<Vec<RuleToken>> {
                     ))
                 }
             },
+            '<' => match iter.peek() {
+                Some(c) if c == &'=' => {
+                    iter.next();
+                    RuleTokenKind::LtEq
+                }
+                _ => RuleTokenKind::Lt,
+            },
+            '>' => match iter.peek() {
+                Some(c) if c == &'=' => {
+                    iter.next();
+                    RuleTokenKind::GtEq
+                }
+                _ => RuleTokenKind::Gt,
+            },
+            '%' => RuleTokenKind::Percent,
             '#' => {
                 // This is a comment
                 iter.read_until_lf();
@@ -352,6 +388,8 @@ pub(crate) fn tokenize(input: &str) -> Result<Vec<RuleToken>> {
                     "redirect" => RuleTokenKind::Redirect,
                     "return" => RuleTokenKind::Return,
                     "if" => RuleTokenKind::If,
+                    "add_headers" => RuleTokenKind::AddHeaders,
+                    "const" => RuleTokenKind::Const,
                     _ => RuleTokenKind::Ident(ident),
                 }
             }
@@ -2,17 +2,35 @@ mod lexer;
 
 mod parser;
 
-pub use parser::{parse_file, Rules};
+pub use lexer::{tokenize, Position, RuleToken, RuleTokenKind};
+pub use parser::{parse_file, parse_file_cached, parse_str, Rules};
 
+mod analyzer;
+mod builtins;
+mod cache;
 mod callable;
 mod error;
 
-pub use error::format_error_in_file;
+pub use callable::{wrap_callable, Call, Function};
+pub use error::{
+    format_error_in_file, format_warning_in_file, RuleError, RuleErrorKind, RuleWarning,
+    RuleWarningKind, RuntimeErrorKind, SemanticErrorKind, SyntaxErrorKind,
+};
 mod expr;
+#[cfg(feature = "geoip")]
+mod geoip;
 mod grammar;
+mod index;
 mod object;
 mod rule;
 mod scope;
 mod value;
 
+pub use expr::{Expr, ExprOrValue, Operator};
+#[cfg(feature = "geoip")]
+pub use geoip::GeoIp;
+pub use grammar::{Statement, StatementKind};
+pub use object::{Member, MemberKind, Object, ObjectBuilder};
 pub use rule::*;
+pub use scope::{RuleBudget, RuleScope};
+pub use value::{FromValue, FromVec, Type, Value};
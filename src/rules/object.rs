@@ -1,8 +1,10 @@
 use crate::request::Request;
 use crate::response::Response;
 use crate::rules::callable::{wrap_callable, Call, Function};
-use crate::rules::error::RuleError;
+use crate::rules::error::{RuleError, RuntimeErrorKind};
+use crate::rules::lexer::Position;
 use crate::rules::value::{FromVec, Type, Value};
+use crate::utils::read_exact_at;
 use std::any::Any;
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::HashMap;
@@ -75,46 +77,145 @@ impl ObjectBuilder {
     }
 }
 
-pub trait IntoObject {
-    fn into_object(self) -> Object;
-}
-
-fn downcast_instance_ref<T: 'static>(instance: &Rc<RefCell<dyn Any>>) -> Ref<T> {
+fn downcast_instance_ref<T: 'static>(instance: &Rc<RefCell<dyn Any>>) -> Ref<'_, T> {
     Ref::map(instance.borrow(), |v| v.downcast_ref::<T>().unwrap())
 }
 
-fn downcast_instance_mut<T: 'static>(instance: &Rc<RefCell<dyn Any>>) -> RefMut<T> {
+fn downcast_instance_mut<T: 'static>(instance: &Rc<RefCell<dyn Any>>) -> RefMut<'_, T> {
     RefMut::map(instance.borrow_mut(), |v| v.downcast_mut::<T>().unwrap())
 }
 
-impl IntoObject for Rc<RefCell<Request>> {
-    fn into_object(self) -> Object {
-        Object::builder()
-            .add_field("method", |instance: Rc<RefCell<dyn Any>>| {
-                let instance = downcast_instance_ref::<Request>(&instance);
-                Ok(Type::String(instance.method.to_string()))
-            })
-            .get(self)
+fn is_text_content_type(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    mime.starts_with("text/")
+        || mime.ends_with("/json")
+        || mime.ends_with("+json")
+        || mime.ends_with("/xml")
+        || mime.ends_with("+xml")
+        || mime == "application/x-www-form-urlencoded"
+}
+
+/// `body_text()` gives the body only when it is text of at most `max_body_size` bytes,
+/// an empty string otherwise.
+pub fn request_object(request: Rc<RefCell<Request>>, max_body_size: usize) -> Object {
+    Object::builder()
+        .add_field("method", |instance: Rc<RefCell<dyn Any>>| {
+            let instance = downcast_instance_ref::<Request>(&instance);
+            Ok(Type::String(instance.method.to_string()))
+        })
+        .add_field("ip", |instance: Rc<RefCell<dyn Any>>| {
+            let instance = downcast_instance_ref::<Request>(&instance);
+            let ip = instance.peer_addr.map(|ip| ip.to_string());
+            Ok(Type::String(ip.unwrap_or_default()))
+        })
+        .add_method("header", |instance: Rc<RefCell<dyn Any>>, name: String| {
+            let instance = downcast_instance_ref::<Request>(&instance);
+            Ok(Type::String(instance.get_header(&name).unwrap_or_default()))
+        })
+        .add_method("body_text", move |instance: Rc<RefCell<dyn Any>>| {
+            let instance = downcast_instance_ref::<Request>(&instance);
+            let is_text = instance
+                .get_header("Content-Type")
+                .is_some_and(|content_type| is_text_content_type(&content_type));
+
+            let text = match std::str::from_utf8(&instance.body) {
+                Ok(text) if is_text && text.len() <= max_body_size => text.to_string(),
+                _ => String::new(),
+            };
+
+            Ok(Type::String(text))
+        })
+        .get(request)
+}
+
+fn body_len(response: &Response) -> usize {
+    match response.body_file() {
+        Some((_, len)) => len as usize,
+        None => response.body().len(),
     }
 }
 
-impl IntoObject for Rc<RefCell<Response>> {
-    fn into_object(self) -> Object {
-        Object::builder()
-            .add_method(
-                "set_header",
-                |instance: Rc<RefCell<dyn Any>>, name: String, value: String| {
-                    let mut instance = downcast_instance_mut::<Response>(&instance);
-                    instance.set_header(&name, &value);
-                    Ok(Type::Bool(true))
-                },
-            )
-            .add_field("status_code", |instance: Rc<RefCell<dyn Any>>| {
-                let instance = downcast_instance_ref::<Response>(&instance);
-                Ok(Type::Int((*instance.status_code()) as u32))
-            })
-            .get(self)
+fn replace_body(
+    response: &mut Response,
+    body: Vec<u8>,
+    max_body_size: usize,
+) -> Result<(), RuleError> {
+    if body.len() > max_body_size {
+        return Err(RuleError::runtime(
+            RuntimeErrorKind::BodyTooLarge(max_body_size),
+            Position::zero(),
+        ));
     }
+
+    response.set_header("Content-Length", &body.len().to_string());
+    response.set_body(body);
+
+    Ok(())
+}
+
+/// Bodies set by `set_body` and `append_body` may not grow past `max_body_size` bytes.
+pub fn response_object(response: Rc<RefCell<Response>>, max_body_size: usize) -> Object {
+    Object::builder()
+        .add_method(
+            "set_header",
+            |instance: Rc<RefCell<dyn Any>>, name: String, value: String| {
+                let mut instance = downcast_instance_mut::<Response>(&instance);
+                instance.set_header(&name, &value);
+                Ok(Type::Bool(true))
+            },
+        )
+        .add_field("status_code", |instance: Rc<RefCell<dyn Any>>| {
+            let instance = downcast_instance_ref::<Response>(&instance);
+            Ok(Type::Int((*instance.status_code()) as u32))
+        })
+        .add_field("body_len", |instance: Rc<RefCell<dyn Any>>| {
+            let instance = downcast_instance_ref::<Response>(&instance);
+            Ok(Type::Int(
+                body_len(&instance).try_into().unwrap_or(u32::MAX),
+            ))
+        })
+        .add_method(
+            "set_body",
+            move |instance: Rc<RefCell<dyn Any>>, body: String| {
+                let mut instance = downcast_instance_mut::<Response>(&instance);
+                replace_body(&mut instance, body.into_bytes(), max_body_size)?;
+                Ok(Type::Bool(true))
+            },
+        )
+        .add_method(
+            "append_body",
+            move |instance: Rc<RefCell<dyn Any>>, text: String| {
+                let mut instance = downcast_instance_mut::<Response>(&instance);
+                let len = body_len(&instance);
+                if len + text.len() > max_body_size {
+                    return Err(RuleError::runtime(
+                        RuntimeErrorKind::BodyTooLarge(max_body_size),
+                        Position::zero(),
+                    ));
+                }
+
+                let mut body = match instance.body_file() {
+                    Some((file, len)) => {
+                        let mut body = vec![0u8; len as usize];
+                        read_exact_at(file, &mut body, 0).map_err(|_| {
+                            RuleError::runtime(RuntimeErrorKind::BodyUnavailable, Position::zero())
+                        })?;
+                        body
+                    }
+                    None => instance.body().clone(),
+                };
+                body.extend_from_slice(text.as_bytes());
+                replace_body(&mut instance, body, max_body_size)?;
+                Ok(Type::Bool(true))
+            },
+        )
+        .get(response)
 }
 
 #[derive(Clone)]
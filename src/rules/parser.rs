@@ -1,33 +1,124 @@
-use crate::rules::error::{format_error_in_file, RuleError};
-use crate::rules::grammar::file;
+use crate::concurrency_limit::ConcurrencyLimit;
+use crate::error::Error;
+use crate::rules::analyzer::analyze;
+use crate::rules::cache;
+use crate::rules::error::{format_error_in_file, RuleError, RuleWarning};
+use crate::rules::grammar::{file, resolve_constants};
+use crate::rules::index::RuleIndex;
 use crate::rules::lexer::tokenize;
 use crate::rules::Rule;
+use crate::stats::{RuleCounters, RuleStats};
+use log::debug;
+use std::fs;
 use std::fs::File;
 use std::io::Read;
+use std::time::Duration;
 
 #[derive(Default)]
 pub struct Rules {
     pub rules: Vec<Rule>,
     pub file: String,
+    warnings: Vec<RuleWarning>,
+    index: RuleIndex,
+    counters: RuleCounters,
 }
 
-pub fn parse_file(path: &str) -> Result<Rules, String> {
-    let mut file = File::open(path).unwrap();
+impl Rules {
+    fn new(rules: Vec<Rule>, warnings: Vec<RuleWarning>, file: String) -> Self {
+        Rules {
+            index: RuleIndex::new(&rules),
+            counters: RuleCounters::new(rules.len()),
+            rules,
+            file,
+            warnings,
+        }
+    }
+
+    /// Rules whose pattern occurs in `url` with their index in `rules`,
+    /// in the order they were declared.
+    pub fn matching<'a>(&'a self, url: &str) -> impl Iterator<Item = (usize, &'a Rule)> {
+        self.index
+            .matching(url)
+            .into_iter()
+            .map(|rule_index| (rule_index, &self.rules[rule_index]))
+    }
+
+    pub(crate) fn record_evaluation(&self, rule_index: usize, evaluation_time: Duration) {
+        self.counters.record(rule_index, evaluation_time);
+    }
+
+    /// Non-fatal findings of parsing, see `format_warning_in_file` to print them.
+    pub fn warnings(&self) -> &[RuleWarning] {
+        &self.warnings
+    }
+
+    /// Limits set by `concurrency_limit(..)` in the rules, see `Rule::concurrency_limit`.
+    pub fn concurrency_limits(&self) -> Vec<ConcurrencyLimit> {
+        self.rules
+            .iter()
+            .filter_map(|rule| rule.concurrency_limit())
+            .collect()
+    }
+
+    pub fn stats(&self) -> Vec<RuleStats> {
+        self.counters
+            .snapshot(self.rules.iter().map(|rule| rule.pattern.as_str()))
+    }
+}
+
+fn read_file(path: &str) -> crate::Result<String> {
+    let mut file = File::open(path)?;
 
     let mut file_contents = String::new();
 
-    file.read_to_string(&mut file_contents).unwrap();
+    file.read_to_string(&mut file_contents)?;
+
+    Ok(file_contents)
+}
 
-    let rules = parse_str(&file_contents).map_err(|err| {
-        format_error_in_file(err, &file_contents)
-    })?;
+pub fn parse_file(path: &str) -> crate::Result<Rules> {
+    let file_contents = read_file(path)?;
 
-    Ok(Rules {
-        rules,
-        file: file_contents,
-    })
+    let (rules, warnings) = parse_str(&file_contents)
+        .map_err(|err| Error::Rules(format_error_in_file(err, &file_contents)))?;
+
+    Ok(Rules::new(rules, warnings, file_contents))
+}
+
+/// Like `parse_file`, but loads the rules from `<path>.cache` when it was compiled from the
+/// current contents of the file, and writes that cache otherwise.
+pub fn parse_file_cached(path: &str) -> crate::Result<Rules> {
+    let file_contents = read_file(path)?;
+    let cache_path = cache::cache_path(path);
+
+    let cached = fs::read(&cache_path)
+        .ok()
+        .and_then(|bytes| cache::from_bytes(&file_contents, &bytes));
+    if let Some((rules, warnings)) = cached {
+        debug!("Loaded rules from {}", cache_path.display());
+        return Ok(Rules::new(rules, warnings, file_contents));
+    }
+
+    let (rules, warnings) = parse_str(&file_contents)
+        .map_err(|err| Error::Rules(format_error_in_file(err, &file_contents)))?;
+
+    // the rules work without the cache, so failing to write it is not an error
+    let bytes = cache::to_bytes(&file_contents, &rules, &warnings);
+    let tmp_path = cache_path.with_extension(format!("tmp{}", std::process::id()));
+    let written = fs::write(&tmp_path, bytes).and_then(|_| fs::rename(&tmp_path, &cache_path));
+    if let Err(err) = written {
+        let _ = fs::remove_file(&tmp_path);
+        debug!("Could not write {}: {err}", cache_path.display());
+    }
+
+    Ok(Rules::new(rules, warnings, file_contents))
 }
 
-fn parse_str(source: &str) -> Result<Vec<Rule>, RuleError> {
-    file(tokenize(source)?)
+/// Parses rules from a string, with the warnings found on the way.
+pub fn parse_str(source: &str) -> Result<(Vec<Rule>, Vec<RuleWarning>), RuleError> {
+    let (tokens, mut warnings) = resolve_constants(tokenize(source)?)?;
+    let rules = file(tokens)?;
+    warnings.extend(analyze(&rules));
+
+    Ok((rules, warnings))
 }
@@ -1,26 +1,84 @@
+use crate::concurrency_limit::ConcurrencyLimit;
 use crate::request::Request;
 use crate::response::Response;
-use crate::rules::callable::wrap_callable;
+use crate::response_status_code::ResponseStatusCode;
+use crate::rules::builtins;
 use crate::rules::error::{RuleError, RuntimeErrorKind};
+use crate::rules::expr::{ExprOrValue, Operator};
+#[cfg(feature = "geoip")]
+use crate::rules::geoip::GeoIp;
 use crate::rules::grammar::{Statement, StatementKind};
-use crate::rules::object::IntoObject;
-use crate::rules::scope::RuleScope;
+use crate::rules::lexer::{Position, RuleTokenKind};
+use crate::rules::object::{request_object, response_object};
+use crate::rules::scope::{RuleBudget, RuleScope};
 use crate::rules::value::Type;
 use log::info;
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
 
 type Result<T> = std::result::Result<T, RuleError>;
 
+/// Arguments of a `concurrency_limit(..)` statement, None for other statements
+/// and for arguments that are not three integer literals.
+pub(crate) fn concurrency_limit_args(kind: &StatementKind) -> Option<[u32; 3]> {
+    let args = concurrency_limit_call(kind)?;
+    let args = args
+        .iter()
+        .map(|arg| match arg {
+            ExprOrValue::Value(token) => match &token.kind {
+                RuleTokenKind::LitInt(value) => value.parse().ok(),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Option<Vec<u32>>>()?;
+
+    args.try_into().ok()
+}
+
+/// Arguments of a statement calling `concurrency_limit`, whatever they are.
+pub(crate) fn concurrency_limit_call(kind: &StatementKind) -> Option<&[ExprOrValue]> {
+    let StatementKind::Expr(ExprOrValue::Expr(expr)) = kind else {
+        return None;
+    };
+    let (ExprOrValue::Value(target), Operator::Call, ExprOrValue::List(args)) =
+        (&*expr.lhs, expr.operator, &*expr.rhs)
+    else {
+        return None;
+    };
+
+    matches!(&target.kind, RuleTokenKind::Ident(name) if name == "concurrency_limit")
+        .then_some(args.as_slice())
+}
+
 pub enum RuleEvaluationResult {
     Continue,
     Finish,
 }
 
+/// How `Rule::evaluate_with` runs a rule.
+#[derive(Clone, Default)]
+pub struct EvaluationOptions {
+    /// Log every change to the response at info level (target "http_rs::rules::audit")
+    /// with the rule's pattern and the statement's position
+    pub audit: bool,
+    /// Exceeding it fails the evaluation with a runtime error
+    pub budget: RuleBudget,
+    /// Values of `secret("NAME")`
+    pub secrets: Arc<HashMap<String, String>>,
+    /// Database for `geoip_country(ip)`, which gives an empty string without one
+    #[cfg(feature = "geoip")]
+    pub geoip: Option<Arc<GeoIp>>,
+}
+
 #[derive(Debug)]
 pub struct Rule {
     pub pattern: String,
     pub statements: Vec<Statement>,
+    pub position: Position,
 }
 
 impl Rule {
@@ -28,23 +86,71 @@ impl Rule {
         !url.matches(&self.pattern).collect::<Vec<&str>>().is_empty()
     }
 
+    /// Limit set by `concurrency_limit(max_concurrent, queue_size, queue_timeout_secs);`
+    /// among the rule's own statements, for paths starting with the pattern.
+    /// It is read when the rules load, rules run too late to hold a request back.
+    pub fn concurrency_limit(&self) -> Option<ConcurrencyLimit> {
+        self.statements.iter().find_map(|statement| {
+            let [max_concurrent, queue_size, queue_timeout] =
+                concurrency_limit_args(&statement.kind)?;
+
+            Some(
+                ConcurrencyLimit::new(&self.pattern, max_concurrent as usize).queue(
+                    queue_size as usize,
+                    Duration::from_secs(queue_timeout as u64),
+                ),
+            )
+        })
+    }
+
     pub fn evaluate(
         &self,
         request: Rc<RefCell<Request>>,
         response: Rc<RefCell<Response>>,
     ) -> Result<RuleEvaluationResult> {
-        let mut scope = RuleScope::new();
-        scope.update_var("request", Type::Object(request.clone().into_object()));
-        scope.update_var(
-            "log",
-            Type::Function(wrap_callable(|text: String| {
-                info!("{}", text);
-                Ok(Type::Bool(true))
-            })),
+        self.evaluate_with(request, response, &EvaluationOptions::default())
+    }
+
+    pub fn evaluate_with(
+        &self,
+        request: Rc<RefCell<Request>>,
+        response: Rc<RefCell<Response>>,
+        options: &EvaluationOptions,
+    ) -> Result<RuleEvaluationResult> {
+        let mut scope = RuleScope::with_budget(options.budget);
+
+        self.evaluate_in(request, response, &mut scope, options)
+    }
+
+    /// Evaluates the rule with the variables, functions and objects of `scope`, which take
+    /// precedence over `request`, `response` and the built-in functions.
+    /// The budget is the scope's, so it is shared by every rule evaluated in it.
+    pub fn evaluate_in(
+        &self,
+        request: Rc<RefCell<Request>>,
+        response: Rc<RefCell<Response>>,
+        scope: &mut RuleScope,
+        options: &EvaluationOptions,
+    ) -> Result<RuleEvaluationResult> {
+        scope.define_var(
+            "request",
+            Type::Object(request_object(
+                request.clone(),
+                options.budget.max_body_size,
+            )),
+        );
+        builtins::register(scope, options);
+        scope.define_var(
+            "response",
+            Type::Object(response_object(
+                response.clone(),
+                options.budget.max_body_size,
+            )),
         );
-        scope.update_var("response", Type::Object(response.clone().into_object()));
 
-        Self::evaluate_statements(&self.statements, request, response, &scope)
+        let audit = options.audit.then_some(self.pattern.as_str());
+
+        Self::evaluate_statements(&self.statements, request, response, scope, audit)
     }
 
     fn evaluate_statements(
@@ -52,9 +158,33 @@ impl Rule {
         request: Rc<RefCell<Request>>,
         response: Rc<RefCell<Response>>,
         scope: &RuleScope,
+        audit: Option<&str>,
     ) -> Result<RuleEvaluationResult> {
         for statement in statements {
+            scope.spend_step(statement.position)?;
+
             let response = response.clone();
+            // nested statements log their own changes
+            let before = audit
+                .filter(|_| {
+                    !matches!(
+                        statement.kind,
+                        StatementKind::If(_, _) | StatementKind::Matches(_, _)
+                    )
+                })
+                .map(|_| ResponseSnapshot::of(&response.borrow()));
+            let log_changes = |mutations: Vec<String>| {
+                if let Some(pattern) = audit {
+                    for mutation in mutations {
+                        info!(
+                            target: "http_rs::rules::audit",
+                            "rule={pattern:?} line={} column={} {mutation}",
+                            statement.position.line,
+                            statement.position.column
+                        );
+                    }
+                }
+            };
 
             match &statement.kind {
                 StatementKind::Redirect(response_code, location) => {
@@ -62,6 +192,11 @@ impl Rule {
                     out_response.set_status_code(*response_code);
                     out_response.set_header("Location", location);
 
+                    log_changes(vec![format!(
+                        "redirect status={} location={location:?}",
+                        *response_code as u16
+                    )]);
+
                     return Ok(RuleEvaluationResult::Finish);
                 }
                 StatementKind::Return(response_code, additional_data) => {
@@ -76,6 +211,10 @@ impl Rule {
                         out_response.set_header("Content-Length", &body_len.to_string());
                     }
 
+                    if let Some(before) = &before {
+                        log_changes(before.changes(&out_response));
+                    }
+
                     return Ok(RuleEvaluationResult::Finish);
                 }
                 StatementKind::If(condition_expr, statements) => {
@@ -88,6 +227,7 @@ impl Rule {
                                     request.clone(),
                                     response,
                                     scope,
+                                    audit,
                                 )? {
                                     RuleEvaluationResult::Continue => {}
                                     RuleEvaluationResult::Finish => {
@@ -107,8 +247,51 @@ impl Rule {
                         }
                     }
                 }
+                StatementKind::Matches(pattern, statements) => {
+                    if request.borrow().url.contains(pattern.as_str()) {
+                        if let RuleEvaluationResult::Finish = Self::evaluate_statements(
+                            statements,
+                            request.clone(),
+                            response,
+                            scope,
+                            audit,
+                        )? {
+                            return Ok(RuleEvaluationResult::Finish);
+                        }
+                    }
+                }
+                StatementKind::AddHeaders(headers) => {
+                    // all values are evaluated first, so an error leaves the response as it was
+                    let mut values = vec![];
+                    for (name, value_expr) in headers {
+                        let value = value_expr.eval(scope)?;
+                        let Type::String(text) = value.t() else {
+                            return Err(RuleError::runtime(
+                                RuntimeErrorKind::IncorrectType(
+                                    "string".to_owned(),
+                                    value.t().type_string(),
+                                ),
+                                *value.position(),
+                            ));
+                        };
+                        values.push((name, text.clone()));
+                    }
+
+                    let mut out_response = response.borrow_mut();
+                    for (name, value) in values {
+                        out_response.set_header(name, &value);
+                    }
+
+                    if let Some(before) = &before {
+                        log_changes(before.changes(&out_response));
+                    }
+                }
                 StatementKind::Expr(expr) => {
                     expr.eval(scope)?;
+
+                    if let Some(before) = &before {
+                        log_changes(before.changes(&response.borrow()));
+                    }
                 }
             }
         }
@@ -116,3 +299,346 @@ impl Rule {
         Ok(RuleEvaluationResult::Continue)
     }
 }
+
+/// The parts of a response that rules can change, kept for the audit log.
+struct ResponseSnapshot {
+    status_code: ResponseStatusCode,
+    headers: HashMap<String, String>,
+    body_len: usize,
+}
+
+impl ResponseSnapshot {
+    fn of(response: &Response) -> Self {
+        ResponseSnapshot {
+            status_code: *response.status_code(),
+            headers: response.headers().clone(),
+            body_len: response.body().len(),
+        }
+    }
+
+    fn changes(&self, response: &Response) -> Vec<String> {
+        let mut changes = vec![];
+
+        if *response.status_code() != self.status_code {
+            changes.push(format!("status={}", *response.status_code() as u16));
+        }
+
+        let mut headers: Vec<(&String, &String)> = response.headers().iter().collect();
+        headers.sort();
+        for (name, value) in headers {
+            if self.headers.get(name) != Some(value) {
+                changes.push(format!("set_header name={name:?} value={value:?}"));
+            }
+        }
+
+        let mut removed: Vec<&String> = self
+            .headers
+            .keys()
+            .filter(|name| !response.headers().contains_key(*name))
+            .collect();
+        removed.sort();
+        for name in removed {
+            changes.push(format!("remove_header name={name:?}"));
+        }
+
+        if response.body().len() != self.body_len {
+            changes.push(format!("body bytes={}", response.body().len()));
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod response_snapshot {
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::rules::rule::ResponseSnapshot;
+
+        #[test]
+        fn lists_changes() {
+            let mut response = Response::builder().header("Server", "http-rs").get();
+            let before = ResponseSnapshot::of(&response);
+
+            response.set_status_code(ResponseStatusCode::Forbidden);
+            response.set_header("X-Rule", "1");
+            response.set_header("Server", "http-rs");
+            response.set_body(b"Naaaah".to_vec());
+
+            assert_eq!(
+                before.changes(&response),
+                vec![
+                    "status=403".to_string(),
+                    "set_header name=\"X-Rule\" value=\"1\"".to_string(),
+                    "body bytes=6".to_string(),
+                ]
+            );
+        }
+    }
+
+    mod evaluate_with {
+        use crate::request::Request;
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::rules::parser::parse_str;
+        use crate::rules::rule::EvaluationOptions;
+        use crate::rules::RuleBudget;
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        fn evaluate_request(
+            source: &str,
+            request: Request,
+            body: &str,
+            max_body_size: usize,
+        ) -> (bool, Response) {
+            let (rules, _) = parse_str(source).unwrap();
+            let request = Rc::new(RefCell::new(request));
+            let response = Rc::new(RefCell::new(Response::builder().text_body(body).get()));
+            let options = EvaluationOptions {
+                budget: RuleBudget {
+                    max_body_size,
+                    ..RuleBudget::default()
+                },
+                ..EvaluationOptions::default()
+            };
+
+            let result = rules[0].evaluate_with(request, response.clone(), &options);
+
+            (
+                result.is_ok(),
+                Rc::try_unwrap(response).ok().unwrap().into_inner(),
+            )
+        }
+
+        fn evaluate(source: &str, body: &str, max_body_size: usize) -> (bool, Response) {
+            evaluate_request(source, Request::builder().get(), body, max_body_size)
+        }
+
+        #[test]
+        fn rewrites_body() {
+            let (ok, response) = evaluate(
+                "matches / { if response.body_len == 5 { response.append_body(\"!\"); } }",
+                "Hello",
+                16,
+            );
+
+            assert!(ok);
+            assert_eq!(response.body(), b"Hello!");
+            assert_eq!(response.get_header("Content-Length"), Some("6"));
+
+            let (ok, response) = evaluate("matches / { response.set_body(\"Bye\"); }", "Hello", 16);
+
+            assert!(ok);
+            assert_eq!(response.body(), b"Bye");
+            assert_eq!(response.get_header("Content-Length"), Some("3"));
+        }
+
+        #[test]
+        fn err_if_body_grows_past_limit() {
+            let (ok, response) = evaluate(
+                "matches / { response.append_body(\", world\"); }",
+                "Hello",
+                8,
+            );
+
+            assert!(!ok);
+            assert_eq!(response.body(), b"Hello");
+        }
+
+        #[test]
+        fn reads_secrets() {
+            let (rules, _) = parse_str(
+                "matches / { response.set_header(\"X-Token\", secret(\"token\")); response.set_header(\"X-Other\", secret(\"other\")); }",
+            )
+            .unwrap();
+            let request = Rc::new(RefCell::new(Request::builder().get()));
+            let response = Rc::new(RefCell::new(Response::builder().get()));
+            let options = EvaluationOptions {
+                secrets: Arc::new(HashMap::from([("token".to_string(), "s3cr3t".to_string())])),
+                ..EvaluationOptions::default()
+            };
+
+            let result = rules[0].evaluate_with(request, response.clone(), &options);
+
+            assert!(result.is_err());
+            assert_eq!(response.borrow().get_header("X-Token"), Some("s3cr3t"));
+        }
+
+        #[test]
+        fn matches_client_address() {
+            let source = "matches / { if cidr_match(request.ip, \"10.0.0.0/8\") { return 403; } }";
+            let request = |peer_addr: Option<&str>| {
+                let mut request = Request::builder().get();
+                request.peer_addr = peer_addr.map(|ip| ip.parse().unwrap());
+                request
+            };
+
+            let (_, response) = evaluate_request(source, request(Some("10.0.0.1")), "", 0);
+            assert_eq!(response.status_code(), &ResponseStatusCode::Forbidden);
+
+            let (_, response) = evaluate_request(source, request(Some("127.0.0.1")), "", 0);
+            assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+
+            let (_, response) = evaluate_request(source, request(None), "", 0);
+            assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+        }
+
+        #[test]
+        fn compares_and_hashes_ints() {
+            let forbidden = |condition: &str| {
+                let source = format!("matches / {{ if {condition} {{ return 403; }} }}");
+                let request = Request::builder().header("X-User", "alice").get();
+                let (ok, response) = evaluate_request(&source, request, "", 0);
+                assert!(ok, "{condition}");
+
+                response.status_code() == &ResponseStatusCode::Forbidden
+            };
+
+            assert!(forbidden(
+                "7 % 3 == 1 && 2 <= 2 && 3 > 2 && 1 < 2 && 2 >= 2"
+            ));
+            assert!(forbidden("rand_percent() < 100"));
+            assert!(forbidden(
+                "hash(request.header(\"X-User\")) == hash(\"alice\") && hash(\"alice\") % 100 < 100"
+            ));
+            assert!(!forbidden("hash(\"alice\") == hash(\"bob\")"));
+
+            let (ok, _) = evaluate("matches / { if 1 % 0 == 0 { return 403; } }", "", 0);
+            assert!(!ok);
+        }
+
+        #[test]
+        fn adds_headers() {
+            let (ok, response) = evaluate(
+                "matches / { add_headers { \"X-Frame-Options\": \"DENY\", \"X-Method\": request.method, } }",
+                "",
+                0,
+            );
+
+            assert!(ok);
+            assert_eq!(response.get_header("X-Frame-Options"), Some("DENY"));
+            assert_eq!(response.get_header("X-Method"), Some("GET"));
+
+            let (ok, response) = evaluate(
+                "matches / { add_headers { \"X-Frame-Options\": \"DENY\", \"X-Status\": 200 } }",
+                "",
+                0,
+            );
+
+            assert!(!ok);
+            assert_eq!(response.get_header("X-Frame-Options"), None);
+        }
+
+        #[test]
+        fn runs_nested_matches_blocks() {
+            let source = "matches /api {
+                response.set_header(\"X-Api\", \"1\");
+                matches /v2 { response.set_header(\"X-Version\", \"2\"); return 403; }
+                matches /v1 { response.set_header(\"X-Version\", \"1\"); }
+                response.set_header(\"X-After\", \"1\");
+            }";
+            let request = |url: &str| Request::builder().url(url).get();
+
+            let (_, response) = evaluate_request(source, request("/api/v2/items"), "", 0);
+            assert_eq!(response.get_header("X-Api"), Some("1"));
+            assert_eq!(response.get_header("X-Version"), Some("2"));
+            assert_eq!(response.get_header("X-After"), None);
+            assert_eq!(response.status_code(), &ResponseStatusCode::Forbidden);
+
+            let (_, response) = evaluate_request(source, request("/api/v1/items"), "", 0);
+            assert_eq!(response.get_header("X-Version"), Some("1"));
+            assert_eq!(response.get_header("X-After"), Some("1"));
+        }
+
+        #[test]
+        fn resolves_constants() {
+            let source = "const method = \"POST\";
+            const BACKEND = \"https://api.internal\";
+            matches / {
+                if request.method != method { redirect 307 BACKEND; }
+            }";
+
+            let (_, response) = evaluate(source, "", 0);
+            assert_eq!(
+                response.get_header("Location"),
+                Some("https://api.internal")
+            );
+            assert!(parse_str("matches / { const A = 1; }").is_err());
+        }
+
+        #[test]
+        fn reads_small_text_request_body() {
+            let source = "matches / { if contains(request.body_text(), \"DROP\") { return 403; } }";
+            let request = |content_type: &str, body: &str| {
+                Request::builder()
+                    .header("Content-Type", content_type)
+                    .text_body(body)
+                    .get()
+            };
+
+            let (_, response) = evaluate_request(
+                source,
+                request("application/json", "{ \"q\": \"DROP TABLE\" }"),
+                "",
+                32,
+            );
+            assert_eq!(response.status_code(), &ResponseStatusCode::Forbidden);
+
+            let (_, response) =
+                evaluate_request(source, request("application/octet-stream", "DROP"), "", 32);
+            assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+
+            let (_, response) = evaluate_request(
+                source,
+                request("text/plain", &format!("{}DROP", " ".repeat(32))),
+                "",
+                32,
+            );
+            assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+        }
+    }
+
+    mod concurrency_limit {
+        use crate::concurrency_limit::ConcurrencyLimit;
+        use crate::request::Request;
+        use crate::response::Response;
+        use crate::rules::parser::parse_str;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        use std::time::Duration;
+
+        #[test]
+        fn reads_limit_from_rule_statements() {
+            let (rules, _) =
+                parse_str("matches /reports { concurrency_limit(2, 5, 10); } matches / { }")
+                    .unwrap();
+
+            assert_eq!(
+                rules[0].concurrency_limit(),
+                Some(ConcurrencyLimit::new("/reports", 2).queue(5, Duration::from_secs(10)))
+            );
+            assert_eq!(rules[1].concurrency_limit(), None);
+        }
+
+        #[test]
+        fn ignores_nested_limits() {
+            let (rules, _) =
+                parse_str("matches / { if 1 == 1 { concurrency_limit(2, 5, 10); } }").unwrap();
+
+            assert_eq!(rules[0].concurrency_limit(), None);
+        }
+
+        #[test]
+        fn does_nothing_when_evaluated() {
+            let (rules, _) = parse_str("matches / { concurrency_limit(2, 5, 10); }").unwrap();
+            let request = Rc::new(RefCell::new(Request::builder().get()));
+            let response = Rc::new(RefCell::new(Response::builder().get()));
+
+            assert!(rules[0].evaluate(request, response).is_ok());
+        }
+    }
+}
@@ -1,15 +1,55 @@
+use crate::rules::error::{RuleError, RuntimeErrorKind};
+use crate::rules::lexer::Position;
 use crate::rules::value::Type;
+use std::cell::Cell;
 use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Limits for evaluating a single rule, so an expensive rule cannot stall the request thread.
+/// A step is a statement or a value in an expression.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RuleBudget {
+    pub max_steps: u32,
+    pub max_duration: Duration,
+    /// Largest body, in bytes, that `request.body_text` reads and `response.set_body` and
+    /// `response.append_body` produce
+    pub max_body_size: usize,
+}
+
+impl Default for RuleBudget {
+    fn default() -> Self {
+        RuleBudget {
+            max_steps: 10_000,
+            max_duration: Duration::from_millis(100),
+            max_body_size: 64 * 1024,
+        }
+    }
+}
 
-#[derive(Default)]
 pub struct RuleScope {
     vars: HashMap<String, Type>,
+    budget: RuleBudget,
+    steps: Cell<u32>,
+    started: Instant,
+}
+
+impl Default for RuleScope {
+    fn default() -> Self {
+        RuleScope::new()
+    }
 }
 
 impl RuleScope {
     pub fn new() -> Self {
+        RuleScope::with_budget(RuleBudget::default())
+    }
+
+    pub fn with_budget(budget: RuleBudget) -> Self {
         RuleScope {
             vars: HashMap::new(),
+            budget,
+            steps: Cell::new(0),
+            started: Instant::now(),
         }
     }
 
@@ -20,4 +60,27 @@ impl RuleScope {
     pub fn update_var(&mut self, ident: &str, value: Type) {
         self.vars.insert(ident.to_owned(), value);
     }
+
+    /// Like `update_var`, but keeps the value the variable already has.
+    pub fn define_var(&mut self, ident: &str, value: Type) {
+        self.vars.entry(ident.to_owned()).or_insert(value);
+    }
+
+    pub fn spend_step(&self, position: Position) -> Result<(), RuleError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+
+        let exceeded = if steps > self.budget.max_steps {
+            format!("{} steps", self.budget.max_steps)
+        } else if self.started.elapsed() > self.budget.max_duration {
+            format!("{:?}", self.budget.max_duration)
+        } else {
+            return Ok(());
+        };
+
+        Err(RuleError::runtime(
+            RuntimeErrorKind::BudgetExceeded(exceeded),
+            position,
+        ))
+    }
 }
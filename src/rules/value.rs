@@ -1,5 +1,5 @@
 use crate::rules::callable::Call;
-use crate::rules::error::{RuleError, RuntimeErrorKind, SemanticErrorKind};
+use crate::rules::error::{RuleError, RuntimeErrorKind};
 use crate::rules::lexer::Position;
 use crate::rules::object::Object;
 use std::any::Any;
@@ -137,6 +137,19 @@ impl<A: FromValue> FromVec for (A,) {
     }
 }
 
+impl<A: FromValue, B: FromValue> FromVec for (A, B) {
+    fn from_vec(values: &[Value]) -> Result<Self, RuleError>
+    where
+        Self: Sized,
+    {
+        let mut iter = values.iter();
+        Ok((
+            A::from_value(next_vec_value(&mut iter, 2, 0)?)?,
+            B::from_value(next_vec_value(&mut iter, 2, 1)?)?,
+        ))
+    }
+}
+
 impl<A: FromValue, B: FromValue, C: FromValue> FromVec for (A, B, C) {
     fn from_vec(values: &[Value]) -> Result<Self, RuleError>
     where
@@ -1,107 +1,742 @@
-use crate::connection::{Connection, ReadStrategy};
-use crate::request::{parse_chunked_body, parse_request, Request, RequestBodyType};
+use crate::access_log::{AccessLog, AccessLogEntry};
+use crate::canonical_paths::CanonicalPaths;
+#[cfg(feature = "https")]
+use crate::certificate::{chain_expiry, CertificateExpiry};
+use crate::clock::{Clock, SystemClock};
+use crate::compression::{
+    accepts_brotli, accepts_gzip, gzip, set_content_encoding, CompressionCache,
+};
+use crate::concurrency_limit::RouteLimiter;
+use crate::conditional::{read_preconditions, write_preconditions_pass, Validators};
+#[cfg(feature = "tokio")]
+use crate::connection::BufferedStream;
+use crate::connection::{Connection, ReadStrategy, TlsConfig};
+use crate::content_source::{get_content, Content, ContentBody, ContentSource, FsContentSource};
+use crate::etag::{sha256_etag, strong_etag, weak_etag, HashCache};
+use crate::file_cache::{ContentCache, OpenFileCache};
+use crate::header::names;
+use crate::http_version::HttpVersion;
+use crate::manifest::{build_manifest_cached, ManifestCache, MANIFEST_URL};
+use crate::negotiation::negotiate;
+use crate::path_lock::PathLocks;
+use crate::proxy::Proxy;
+use crate::rate_limit::Throttle;
+use crate::recorder::Recorder;
+use crate::redirect::{encode_location, is_redirect_allowed};
+use crate::request::{
+    has_path_prefix, normalize_path, parse_chunked_body_with_trailers, parse_request, Request,
+    RequestBodyType,
+};
 use crate::request_method::RequestMethod;
-use crate::response::{Response, ResponseBuilder};
+use crate::response::{Response, ResponseBody, ResponseBuilder};
 use crate::response_status_code::ResponseStatusCode;
-use crate::rules::{parse_file, RuleEvaluationResult, format_error_in_file, Rules};
-use crate::server_config::{KeepAliveConfig, ServerConfig};
+use crate::router::Router;
+#[cfg(feature = "geoip")]
+use crate::rules::GeoIp;
+use crate::rules::{
+    format_error_in_file, format_warning_in_file, parse_file, parse_file_cached, EvaluationOptions,
+    RuleEvaluationResult, Rules,
+};
+use crate::server_config::{
+    ETagConfig, FaviconFallback, KeepAliveConfig, ServerConfig, UpgradePolicy,
+};
+use crate::server_handle::{ConnectionTracker, ServerHandle};
+use crate::static_response::StaticResponse;
+use crate::stats::{ConnectionStats, RuleStats, StatsCounters};
+use crate::timing::{Phase, RequestTiming};
 use crate::types::IoResult;
-use log::{debug, error, info};
+use crate::utils::{escape_json, read_exact_at};
+#[cfg(feature = "https")]
+use crate::vhost::VirtualHostCertResolver;
+use crate::vhost::{match_host, normalize_host, VirtualHost};
+use crate::well_known::WellKnown;
+use log::{debug, error, info, warn};
+use socket2::{Domain, Protocol, Socket, Type};
 use std::cell::RefCell;
 use std::fs;
 use std::io::ErrorKind;
-use std::net::{TcpListener, TcpStream};
+#[cfg(feature = "tokio")]
+use std::net::IpAddr;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 type RequestListener = dyn Fn(&Request) -> Option<Response> + Send + Sync;
+type ContentTypeHandler = dyn Fn(&Request) -> Response + Send + Sync;
+type PassthroughHandler = dyn Fn(&Request) -> Response + Send + Sync;
+type UploadProgressListener = dyn Fn(&Request, UploadProgress) + Send + Sync;
+#[cfg(feature = "https")]
+type CertificateExpiryListener = dyn Fn(&CertificateExpiry) + Send + Sync;
+
+const FAVICON_URL: &str = "/favicon.ico";
+
+// a 1x1 icon with a single fully transparent 32-bit pixel
+#[rustfmt::skip]
+const TRANSPARENT_FAVICON: [u8; 70] = [
+    // ICONDIR: reserved, type 1 (icon), one image
+    0, 0, 1, 0, 1, 0,
+    // ICONDIRENTRY: 1x1, no palette, 1 plane, 32 bits, 48 bytes of image at offset 22
+    1, 1, 0, 0, 1, 0, 32, 0, 48, 0, 0, 0, 22, 0, 0, 0,
+    // BITMAPINFOHEADER: 40 bytes, 1x2 (XOR and AND masks), 1 plane, 32 bits, uncompressed
+    40, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 1, 0, 32, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    // the pixel, BGRA
+    0, 0, 0, 0,
+    // AND mask row, padded to 4 bytes, the pixel is transparent
+    0x80, 0, 0, 0,
+];
+
+// bodies are read in parts of at most this size, so progress can be reported in between
+const BODY_READ_CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UploadProgress {
+    pub received: usize,
+    /// Content-Length of the request, None for chunked bodies
+    pub total: Option<usize>,
+}
+type ErrorRenderer = dyn Fn(ResponseStatusCode, Option<&Request>) -> String + Send + Sync;
 
 #[derive(Clone)]
 pub struct Server {
     config: Arc<ServerConfig>,
-    rules: Arc<Rules>,
-    https_config: Option<Arc<rustls::ServerConfig>>,
-    listener: Option<Arc<RequestListener>>,
+    // swapped as a whole by reload_rules, requests keep the rules they started with
+    rules: Arc<RwLock<Arc<Rules>>>,
+    #[cfg(feature = "geoip")]
+    geoip: Option<Arc<GeoIp>>,
+    route_limiters: Arc<Vec<Arc<RouteLimiter>>>,
+    // limits set by the rules, swapped with them
+    rule_limiters: Arc<RwLock<Arc<Vec<Arc<RouteLimiter>>>>>,
+    etag_cache: Arc<HashCache>,
+    manifest_cache: Arc<ManifestCache>,
+    open_file_cache: Option<Arc<OpenFileCache>>,
+    content_cache: Option<Arc<ContentCache>>,
+    compression_cache: Option<Arc<CompressionCache>>,
+    canonical_paths: Arc<CanonicalPaths>,
+    write_locks: Arc<PathLocks>,
+    proxies: Arc<Vec<Arc<Proxy>>>,
+    content_source: Arc<dyn ContentSource>,
+    stats: Arc<StatsCounters>,
+    https_config: Option<TlsConfig>,
+    // with the path prefix they handle, tried in order
+    listeners: Vec<(String, Arc<RequestListener>)>,
+    router: Option<Arc<Router>>,
+    well_known: Option<Arc<WellKnown>>,
+    upload_progress: Option<Arc<UploadProgressListener>>,
+    #[cfg(feature = "https")]
+    certificate_expiry_listener: Option<Arc<CertificateExpiryListener>>,
+    content_type_handlers: Vec<(String, Arc<ContentTypeHandler>)>,
+    passthrough_routes: Vec<(String, Arc<PassthroughHandler>)>,
+    recorder: Option<Arc<Recorder>>,
+    access_log: Option<Arc<AccessLog>>,
+    clock: Arc<dyn Clock>,
+    error_renderer: Option<Arc<ErrorRenderer>>,
+    // why the rules file did not load at startup, reported by self_check
+    rules_error: Option<String>,
+    // same for the proxy route that could not be set up, which is left out
+    proxy_error: Option<String>,
 }
 
 impl Server {
     pub fn new(config: Option<ServerConfig>) -> Self {
+        let mut rules_error = None;
         let rules = match &config {
-            Some(config) if config.rules_path.is_some() => {
-                match parse_file(config.rules_path.as_ref().unwrap()) {
-                    Ok(rules) => rules,
+            Some(config) => load_rules(config).unwrap_or_else(|e| {
+                error!("\nError parsing rules file: {e}");
+                rules_error = Some(e.to_string());
+                Rules::default()
+            }),
+            None => Rules::default(),
+        };
+
+        let config = config.unwrap_or_default();
+        #[cfg(feature = "geoip")]
+        let geoip = config
+            .geoip_database
+            .as_deref()
+            .and_then(|path| match GeoIp::open(path) {
+                Ok(geoip) => Some(Arc::new(geoip)),
+                Err(e) => {
+                    error!("\n{e}");
+                    None
+                }
+            });
+        let route_limiters = config
+            .concurrency_limits
+            .iter()
+            .map(|limit| Arc::new(RouteLimiter::new(limit.clone())))
+            .collect();
+        let rule_limiters = limiters_for_rules(&rules, &[]);
+        let open_file_cache = config
+            .open_file_cache
+            .clone()
+            .map(|cache_config| Arc::new(OpenFileCache::new(cache_config)));
+        let content_cache = config
+            .content_cache
+            .clone()
+            .map(|cache_config| Arc::new(ContentCache::new(cache_config)));
+        let compression_cache = config.compression.as_ref().and_then(|compression| {
+            compression
+                .cache_dir
+                .as_deref()
+                .map(|dir| Arc::new(CompressionCache::new(dir, compression.level)))
+        });
+        let canonical_paths = Arc::new(CanonicalPaths::with_roots(
+            std::iter::once(config.root.as_str()).chain(
+                config
+                    .virtual_hosts
+                    .iter()
+                    .map(|virtual_host| virtual_host.root.as_str()),
+            ),
+        ));
+
+        let access_log =
+            config
+                .access_log
+                .as_ref()
+                .and_then(|access_log| match AccessLog::open(access_log) {
+                    Ok(access_log) => Some(Arc::new(access_log)),
+                    Err(e) => {
+                        error!("\nError opening access log {}: {e}", access_log.path);
+                        None
+                    }
+                });
+
+        // upstreams are parsed up front, so bad configuration fails at startup
+        let mut proxy_error = None;
+        let proxies = config
+            .proxy_routes
+            .iter()
+            .cloned()
+            .filter_map(|route| {
+                let path_prefix = route.path_prefix.clone();
+
+                match Proxy::new(route) {
+                    Ok(proxy) => Some(Arc::new(proxy)),
                     Err(e) => {
-                        error!("\nError parsing rules file: {e}");
-                        Rules::default()
+                        error!("\nError setting up proxy for {path_prefix}: {e}");
+                        proxy_error.get_or_insert(format!("proxy for {path_prefix}: {e}"));
+                        None
                     }
                 }
-            }
-            _ => Rules::default(),
-        };
+            })
+            .collect();
 
         Server {
-            config: Arc::new(config.unwrap_or(ServerConfig::default())),
-            rules: Arc::new(rules),
+            config: Arc::new(config),
+            rules: Arc::new(RwLock::new(Arc::new(rules))),
+            #[cfg(feature = "geoip")]
+            geoip,
+            route_limiters: Arc::new(route_limiters),
+            rule_limiters: Arc::new(RwLock::new(Arc::new(rule_limiters))),
+            etag_cache: Arc::new(HashCache::default()),
+            manifest_cache: Arc::new(ManifestCache::default()),
+            open_file_cache,
+            content_cache,
+            compression_cache,
+            content_source: Arc::new(FsContentSource::with_paths(canonical_paths.clone())),
+            canonical_paths,
+            write_locks: Arc::new(PathLocks::default()),
+            proxies: Arc::new(proxies),
+            stats: Arc::new(StatsCounters::default()),
             https_config: None,
-            listener: None,
+            listeners: vec![],
+            router: None,
+            well_known: None,
+            upload_progress: None,
+            #[cfg(feature = "https")]
+            certificate_expiry_listener: None,
+            content_type_handlers: vec![],
+            passthrough_routes: vec![],
+            recorder: None,
+            access_log,
+            clock: Arc::new(SystemClock),
+            error_renderer: None,
+            rules_error,
+            proxy_error,
         }
     }
 
+    /// Adds a listener for every path, same as `listener_at("/", listener)`.
     pub fn listener(
+        self,
+        listener: impl Fn(&Request) -> Option<Response> + Send + Sync + 'static,
+    ) -> Self {
+        self.listener_at("/", listener)
+    }
+
+    /// Adds a listener for urls starting with `path_prefix`. Requests without static content
+    /// go through the matching listeners in the order they were added,
+    /// until one of them returns a response.
+    pub fn listener_at(
         mut self,
+        path_prefix: &str,
         listener: impl Fn(&Request) -> Option<Response> + Send + Sync + 'static,
     ) -> Self {
-        self.listener = Some(Arc::new(listener));
+        self.listeners
+            .push((path_prefix.to_string(), Arc::new(listener)));
+
+        self
+    }
+
+    /// Handles requests for paths that have no static content, before the listener.
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = Some(Arc::new(router));
+
+        self
+    }
+
+    /// Answers `/.well-known/*` requests its providers handle, before static content.
+    pub fn well_known(mut self, well_known: WellKnown) -> Self {
+        self.well_known = Some(Arc::new(well_known));
+
+        self
+    }
+
+    /// Routes every request with a matching Content-Type to `handler`, before static content
+    /// is looked up. `content_type` may be a wildcard like `application/*`,
+    /// the most specific match wins, then the one registered first.
+    pub fn content_type_handler(
+        mut self,
+        content_type: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.content_type_handlers
+            .push((content_type.to_ascii_lowercase(), Arc::new(handler)));
+
+        self
+    }
+
+    /// Hands every request under `path_prefix` straight to `handler`, with the decoded body
+    /// and trailers. Its response is sent as is: rules, redirects and error body negotiation
+    /// are skipped, errors detected by the server are sent with an empty body.
+    /// Meant for binary protocols like gRPC-Web.
+    pub fn passthrough(
+        mut self,
+        path_prefix: &str,
+        handler: impl Fn(&Request) -> Response + Send + Sync + 'static,
+    ) -> Self {
+        self.passthrough_routes
+            .push((path_prefix.to_string(), Arc::new(handler)));
+
+        self
+    }
+
+    /// Serves static content from somewhere else than the root directory.
+    pub fn content_source(mut self, content_source: Arc<dyn ContentSource>) -> Self {
+        self.content_source = content_source;
+
+        self
+    }
+
+    /// Time source for upload and read deadlines.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+
+        self
+    }
+
+    /// Records every request/response pair, e.g. for a HAR export.
+    pub fn recorder(mut self, recorder: Arc<Recorder>) -> Self {
+        self.recorder = Some(recorder);
+
+        self
+    }
+
+    /// Called every time another part of a request body arrives.
+    pub fn upload_progress(
+        mut self,
+        listener: impl Fn(&Request, UploadProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.upload_progress = Some(Arc::new(listener));
+
+        self
+    }
+
+    /// Called for every certificate that expires within `CertificateExpiryConfig::warn_before`,
+    /// or has expired, each time certificates are checked.
+    #[cfg(feature = "https")]
+    pub fn certificate_expiry_listener(
+        mut self,
+        listener: impl Fn(&CertificateExpiry) + Send + Sync + 'static,
+    ) -> Self {
+        self.certificate_expiry_listener = Some(Arc::new(listener));
 
         self
     }
 
-    pub fn run(&mut self, stop: Arc<bool>) -> IoResult<()> {
-        self.https_config = init_https(&self.config);
+    /// Replaces the default HTML body of error responses.
+    /// Called only when the client accepts HTML, JSON clients still get problem details.
+    pub fn error_renderer(
+        mut self,
+        renderer: impl Fn(ResponseStatusCode, Option<&Request>) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.error_renderer = Some(Arc::new(renderer));
+
+        self
+    }
+
+    /// Counters summed over every closed connection.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+
+    /// Match counts and evaluation times of the rules, in the order they were declared.
+    /// They start over when the rules are reloaded.
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        self.current_rules().stats()
+    }
+
+    fn current_rules(&self) -> Arc<Rules> {
+        self.rules.read().unwrap().clone()
+    }
+
+    /// Parses `rules_path` again and replaces the rules with it. Requests being handled
+    /// finish with the old rules. If the file does not parse, the old rules stay.
+    pub fn reload_rules(&self) -> crate::Result<()> {
+        let rules = load_rules(&self.config)?;
+        let mut rule_limiters = self.rule_limiters.write().unwrap();
+        *rule_limiters = Arc::new(limiters_for_rules(&rules, &rule_limiters));
+        *self.rules.write().unwrap() = Arc::new(rules);
+        drop(rule_limiters);
+        info!(
+            "Reloaded rules from {}",
+            self.config.rules_path.as_deref().unwrap_or_default()
+        );
+
+        Ok(())
+    }
+
+    /// Reloads the rules whenever the modification time of the file changes, checking every
+    /// `interval` until the server stops.
+    fn watch_rules(&self, interval: Duration, tracker: Arc<ConnectionTracker>) {
+        let Some(rules_path) = self.config.rules_path.clone() else {
+            return;
+        };
+        let modified = move || {
+            fs::metadata(&rules_path)
+                .and_then(|meta| meta.modified())
+                .ok()
+        };
+        let server = self.clone();
+
+        std::thread::spawn(move || {
+            let mut last_modified = modified();
+
+            while !tracker.is_stopping() {
+                std::thread::sleep(interval);
+
+                let current = modified();
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+
+                if let Err(e) = server.reload_rules() {
+                    error!("\nError reloading rules file: {e}");
+                }
+            }
+        });
+    }
+
+    /// Serves until the process exits, see `start` for a server that can be stopped.
+    /// Fails before binding if `self_check` does.
+    pub fn run(&mut self) -> crate::Result<()> {
+        self.self_check()?;
+        self.start()?.wait();
+
+        Ok(())
+    }
+
+    /// First problem that would keep the server from serving what it is configured to:
+    /// a missing root directory, a rules file or proxy route that did not load, or, with HTTPS on,
+    /// a certificate or key that cannot be read.
+    pub fn self_check(&self) -> crate::Result<()> {
+        let roots = std::iter::once(self.config.root.as_str()).chain(
+            self.config
+                .virtual_hosts
+                .iter()
+                .map(|virtual_host| virtual_host.root.as_str()),
+        );
+        for root in roots {
+            if !Path::new(root).is_dir() {
+                return Err(crate::Error::Config(format!(
+                    "Root directory {root} does not exist"
+                )));
+            }
+        }
+
+        if let Some(rules_error) = &self.rules_error {
+            return Err(crate::Error::Config(format!(
+                "Could not load {}: {rules_error}",
+                self.config.rules_path.as_deref().unwrap_or_default()
+            )));
+        }
+
+        if let Some(proxy_error) = &self.proxy_error {
+            return Err(crate::Error::Config(format!(
+                "Could not set up {proxy_error}"
+            )));
+        }
+
+        if self.config.https {
+            for path in self.tls_file_paths() {
+                if let Err(err) = fs::metadata(path) {
+                    return Err(crate::Error::Config(format!("Cannot read {path}: {err}")));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Certificates first, then keys, of the server and of virtual hosts.
+    fn tls_file_paths(&self) -> impl Iterator<Item = &str> {
+        let virtual_hosts = &self.config.virtual_hosts;
+
+        std::iter::once(&self.config.cert_path)
+            .chain(
+                virtual_hosts
+                    .iter()
+                    .map(|virtual_host| &virtual_host.cert_path),
+            )
+            .chain(std::iter::once(&self.config.key_path))
+            .chain(
+                virtual_hosts
+                    .iter()
+                    .map(|virtual_host| &virtual_host.key_path),
+            )
+            .filter_map(|path| path.as_deref())
+    }
+
+    /// What the server serves and how, logged once its listeners are bound.
+    /// `workers` describes what connections are served on.
+    pub(crate) fn log_startup(&self, listeners: &[(SocketAddr, bool)], workers: &str) {
+        info!("{}", self.startup_summary(listeners, workers));
+
+        #[cfg(feature = "https")]
+        if self.config.https && self.config.certificate_expiry.is_none() {
+            for expiry in self.certificate_expiries() {
+                if expiry
+                    .not_after
+                    .is_some_and(|not_after| not_after <= self.clock.system_time())
+                {
+                    warn!("Certificate {} has expired", expiry.cert_path);
+                }
+            }
+        }
+    }
+
+    fn startup_summary(&self, listeners: &[(SocketAddr, bool)], workers: &str) -> String {
+        let listening = listeners
+            .iter()
+            .map(|(address, tls)| match tls {
+                true => format!("https://{address}"),
+                false => format!("http://{address}"),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let root = match Path::new(&self.config.root).is_dir() {
+            true => self.config.root.clone(),
+            false => format!("{} (missing)", self.config.root),
+        };
+        let rules = match &self.config.rules_path {
+            Some(rules_path) => format!("{} from {rules_path}", self.current_rules().rules.len()),
+            None => "none".to_string(),
+        };
+
+        format!(
+            "Server started\n  listening: {listening}\n  root: {root}\n  tls: {}\n  rules: {rules}\n  workers: {workers}",
+            self.tls_summary()
+        )
+    }
+
+    #[cfg(feature = "https")]
+    fn tls_summary(&self) -> String {
+        if !self.config.https {
+            return "off".to_string();
+        }
+
+        let certificates = self
+            .certificate_expiries()
+            .into_iter()
+            .map(|expiry| match expiry.not_after {
+                Some(not_after) if not_after <= self.clock.system_time() => format!(
+                    "{} expired {}",
+                    expiry.cert_path,
+                    httpdate::fmt_http_date(not_after)
+                ),
+                Some(not_after) => format!(
+                    "{} valid until {}",
+                    expiry.cert_path,
+                    httpdate::fmt_http_date(not_after)
+                ),
+                None => format!("{} without a readable expiry date", expiry.cert_path),
+            })
+            .collect::<Vec<_>>();
+
+        format!("on, {}", certificates.join(", "))
+    }
+
+    #[cfg(not(feature = "https"))]
+    fn tls_summary(&self) -> String {
+        "off".to_string()
+    }
+
+    /// Certificates of the server and of virtual hosts, read from disk again on every call.
+    #[cfg(feature = "https")]
+    pub fn certificate_expiries(&self) -> Vec<CertificateExpiry> {
+        let virtual_hosts = &self.config.virtual_hosts;
+
+        std::iter::once(&self.config.cert_path)
+            .chain(
+                virtual_hosts
+                    .iter()
+                    .map(|virtual_host| &virtual_host.cert_path),
+            )
+            .filter_map(|path| path.as_deref())
+            .map(|cert_path| CertificateExpiry {
+                cert_path: cert_path.to_string(),
+                not_after: chain_expiry(cert_path),
+            })
+            .collect()
+    }
+
+    /// Logs certificates that expire within `warn_before` and passes them to the listener.
+    /// Returns them too.
+    #[cfg(feature = "https")]
+    fn check_certificates(&self, warn_before: Duration) -> Vec<CertificateExpiry> {
+        let now = self.clock.system_time();
+        let expiring = self
+            .certificate_expiries()
+            .into_iter()
+            .filter(|expiry| {
+                expiry
+                    .not_after
+                    .is_some_and(|not_after| not_after <= now + warn_before)
+            })
+            .collect::<Vec<_>>();
+
+        for expiry in &expiring {
+            let not_after = expiry.not_after.unwrap_or(now);
+            let date = httpdate::fmt_http_date(not_after);
+            match not_after.duration_since(now) {
+                Ok(remaining) => warn!(
+                    "Certificate {} expires in {} days, on {date}",
+                    expiry.cert_path,
+                    remaining.as_secs() / (24 * 60 * 60)
+                ),
+                Err(_) => error!("Certificate {} expired on {date}", expiry.cert_path),
+            }
+
+            if let Some(listener) = &self.certificate_expiry_listener {
+                listener(expiry);
+            }
+        }
+
+        expiring
+    }
+
+    /// Checks certificates right away and then every `CertificateExpiryConfig::check_interval`,
+    /// until the server stops. Renewed certificates are picked up, since they are read again.
+    #[cfg(feature = "https")]
+    pub(crate) fn watch_certificates(&self, tracker: Arc<ConnectionTracker>) {
+        let Some(expiry_config) = self.config.certificate_expiry.filter(|_| self.config.https)
+        else {
+            return;
+        };
+        let server = self.clone();
+
+        std::thread::spawn(move || {
+            while !tracker.is_stopping() {
+                server.check_certificates(expiry_config.warn_before);
+                std::thread::sleep(expiry_config.check_interval);
+            }
+        });
+    }
+
+    /// Binds the listeners and accepts connections on background threads.
+    pub fn start(&mut self) -> crate::Result<ServerHandle> {
+        // connections are served over HTTP/1 only, so ALPN has nothing to offer
+        self.https_config = init_https(&self.config, &[])?;
 
-        let mut listeners = vec![TcpListener::bind(format!(
-            "127.0.0.1:{}",
-            self.config.port
-        ))?];
+        // the flag tells whether connections on the listener speak TLS
+        let mut listeners = vec![(
+            bind_listener(
+                &format!("{}:{}", self.config.bind_address, self.config.port),
+                &self.config,
+            )?,
+            false,
+        )];
 
         if self.https_config.is_some() {
-            listeners.push(TcpListener::bind("127.0.0.1:443".to_string())?);
+            let bind_address = self
+                .config
+                .https_bind_address
+                .as_ref()
+                .unwrap_or(&self.config.bind_address);
+            listeners.push((
+                bind_listener(
+                    &format!("{bind_address}:{}", self.config.https_port),
+                    &self.config,
+                )?,
+                true,
+            ));
         }
 
-        let (tx, rx) = std::sync::mpsc::channel();
+        let tracker = Arc::new(ConnectionTracker::default());
+        if let Some(interval) = self.config.rules_reload_interval {
+            self.watch_rules(interval, tracker.clone());
+        }
+        #[cfg(feature = "https")]
+        self.watch_certificates(tracker.clone());
+        let mut addresses = vec![];
+        let mut bound = vec![];
+        let mut accept_threads = vec![];
 
-        for (index, listener) in listeners.into_iter().enumerate() {
+        for (listener, tls) in listeners {
+            addresses.push(listener.local_addr()?);
+            bound.push((listener.local_addr()?, tls));
             let cloned_server = self.clone();
-            let tx = tx.clone();
-            let stop = stop.clone();
-            std::thread::spawn(move || {
+            let tracker = tracker.clone();
+            accept_threads.push(std::thread::spawn(move || {
                 for stream in listener.incoming() {
+                    if tracker.is_stopping() {
+                        break;
+                    }
+
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            info!("Accept error: {err:?}");
+                            continue;
+                        }
+                    };
                     debug!("New connection");
+                    let guard = tracker.track(&stream);
                     let cloned_server = cloned_server.clone();
                     std::thread::spawn(move || {
-                        match cloned_server.handle_connection(&mut stream.unwrap()) {
+                        match cloned_server.handle_connection(&mut stream, tls) {
                             Ok(_) => debug!("Connection closed"),
                             Err(err) => info!("Connection error: {err:?}"),
                         }
-                    });
 
-                    if *stop {
-                        debug!("Stopping listening for connections");
-                        break;
-                    }
+                        drop(guard);
+                    });
                 }
-
-                tx.send(index).unwrap();
-            });
+            }));
         }
 
-        rx.recv().unwrap();
+        self.log_startup(&bound, "a thread per connection");
 
-        Ok(())
+        Ok(ServerHandle::new(tracker, addresses, accept_threads))
     }
 
-    fn handle_connection(&self, stream: &mut TcpStream) -> IoResult<()> {
+    fn handle_connection(&self, stream: &mut TcpStream, tls: bool) -> IoResult<()> {
+        stream.set_nodelay(self.config.tcp_nodelay)?;
         let (persistent, max_requests) = match self.config.keep_alive {
             KeepAliveConfig::On {
                 timeout,
@@ -119,682 +754,3406 @@ impl Server {
             }
         };
 
-        let mut connection = Connection::new(stream, self.https_config.clone(), persistent);
+        let https_config = self.https_config.clone().filter(|_| tls);
+        let mut connection = Connection::new(stream, https_config, persistent);
+
+        self.serve_connection(&mut connection, persistent, max_requests)
+    }
+
+    /// Keep-alive settings without the socket timeouts, which the caller sets on its own stream.
+    #[cfg(any(test, feature = "testing", feature = "tokio"))]
+    pub(crate) fn persistence(&self) -> (bool, u8) {
+        match self.config.keep_alive {
+            KeepAliveConfig::On { max_requests, .. } => (true, max_requests),
+            _ => (false, 0),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Counted once per connection, `serve_buffered` only counts what its requests did.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn count_connection(&self) {
+        self.stats.add(&ConnectionStats {
+            connections: 1,
+            ..Default::default()
+        });
+    }
+
+    /// Runs a request that has arrived in full through the connection state machine.
+    /// Returns the bytes to send back and whether the connection closes after them.
+    /// `remaining_requests` is how many more the connection may serve, when persistent.
+    /// `secure` tells whether the request came in over TLS.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn serve_buffered(
+        &self,
+        request: Vec<u8>,
+        peer_addr: Option<IpAddr>,
+        remaining_requests: u8,
+        secure: bool,
+    ) -> (Vec<u8>, bool) {
+        let (persistent, _) = self.persistence();
+        let mut stream = BufferedStream::new(request);
+        let mut connection = Connection::plain(&mut stream, persistent);
+        connection.set_peer_addr(peer_addr);
+        connection.set_tls_terminated(secure);
+        connection.stats.connections = 0;
 
         let mut state = HandleConnectionState::New;
-        let mut state_machine =
-            HandleConnectionStateMachine::new(self, &mut connection, persistent, max_requests);
+        let mut responded = false;
+        let mut state_machine = HandleConnectionStateMachine::new(
+            self,
+            &mut connection,
+            persistent,
+            remaining_requests,
+        );
 
-        loop {
+        let close = loop {
             state = state_machine.next(state);
             match state {
-                HandleConnectionState::Close => return Ok(()),
-                HandleConnectionState::Error(err) => return Err(err.into()),
+                HandleConnectionState::Close | HandleConnectionState::Error(_) => break true,
+                // waiting for the next request, which the caller reads from its socket
+                HandleConnectionState::Read(None) if responded => break false,
+                HandleConnectionState::SendResponse(..)
+                | HandleConnectionState::ClientError(..) => responded = true,
                 _ => {}
             }
-        }
-    }
+        };
 
-    fn prepare_response(&self, request: &Request) -> Response {
-        if request.method == RequestMethod::Options && request.url == "*" {
-            options_response(request)
-        } else {
-            self.serve_content(request)
-        }
+        self.stats.add(&connection.stats);
+
+        (stream.response, close)
     }
 
-    fn serve_content(&self, request: &Request) -> Response {
-        let content = get_content(&self.config.root, &request.url);
+    pub(crate) fn serve_connection(
+        &self,
+        connection: &mut Connection,
+        persistent: bool,
+        max_requests: u8,
+    ) -> IoResult<()> {
+        let mut state = HandleConnectionState::New;
+        let mut state_machine =
+            HandleConnectionStateMachine::new(self, connection, persistent, max_requests);
 
-        if let Ok(content_bytes) = content {
-            if !request.method.is_safe() {
-                let mut response =
-                    error_response(Some(request), ResponseStatusCode::MethodNotAllowed);
-                response.set_header("Allow", &RequestMethod::safe_methods_str());
-                return response;
-            } else if request.method == RequestMethod::Options {
-                return options_response(request);
+        let result = loop {
+            state = state_machine.next(state);
+            match state {
+                HandleConnectionState::Close => break Ok(()),
+                HandleConnectionState::Error(err) => {
+                    if matches!(
+                        err,
+                        ErrorKind::ConnectionReset
+                            | ErrorKind::ConnectionAborted
+                            | ErrorKind::BrokenPipe
+                    ) {
+                        connection.stats.resets += 1;
+                    }
+                    break Err(err.into());
+                }
+                _ => {}
             }
+        };
 
-            return content_response(request, content_bytes, self.config.keep_alive);
-        }
+        // what the last response left, the ones before were added as they were sent
+        self.stats.add(&connection.stats);
 
-        if let Some(listener) = &self.listener {
-            if let Some(response) = listener(request) {
-                return response;
-            }
-        }
+        result
+    }
 
-        error_response(Some(request), ResponseStatusCode::NotFound)
+    fn virtual_host(&self, request: &Request) -> Option<&VirtualHost> {
+        match_host(
+            &self.config.virtual_hosts,
+            request.get_header(names::HOST).as_deref(),
+        )
     }
-}
 
-fn init_https(config: &ServerConfig) -> Option<Arc<rustls::ServerConfig>> {
-    if !config.https {
-        return None;
+    /// Web root of the virtual host the request is addressed to.
+    fn root(&self, request: &Request) -> &str {
+        self.virtual_host(request)
+            .map_or(&self.config.root, |virtual_host| &virtual_host.root)
     }
 
-    let certs = config.load_certs();
-    let key = config.load_key();
+    fn passthrough_handler_for(&self, request: &Request) -> Option<&PassthroughHandler> {
+        self.passthrough_routes
+            .iter()
+            .filter(|(path_prefix, _)| has_path_prefix(request.path(), path_prefix))
+            .max_by_key(|(path_prefix, _)| path_prefix.len())
+            .map(|(_, handler)| handler.as_ref())
+    }
 
-    if certs.is_empty() {
-        panic!("Specified file does not contain a valid certificate");
+    fn proxy_for(&self, request: &Request) -> Option<&Arc<Proxy>> {
+        self.proxies
+            .iter()
+            .filter(|proxy| request.url.starts_with(proxy.path_prefix()))
+            .max_by_key(|proxy| proxy.path_prefix().len())
     }
 
-    if key.is_none() {
-        panic!("Specified file does not contain a valid private key");
+    fn static_response_for(&self, request: &Request) -> Option<&StaticResponse> {
+        self.config
+            .static_responses
+            .iter()
+            .find(|static_response| static_response.matches(request))
     }
 
-    Some(Arc::new(
-        rustls::ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(certs, key.unwrap())
-            .unwrap(),
-    ))
-}
+    fn is_passthrough(&self, request: &Request) -> bool {
+        self.passthrough_handler_for(request).is_some()
+    }
 
-enum HandleConnectionState {
-    New,
-    Read(Option<Request>),
-    SendResponse(Option<Request>, Response),
-    ClientError(Option<Request>, ResponseStatusCode),
-    Close,
-    Error(ErrorKind),
-}
+    fn content_type_handler_for(&self, request: &Request) -> Option<&ContentTypeHandler> {
+        let content_type = request.get_header(names::CONTENT_TYPE)?;
+        let mut best: Option<(u8, &ContentTypeHandler)> = None;
 
-struct HandleConnectionStateMachine<'server, 'connection, 'stream> {
-    server: &'server Server,
-    connection: &'connection mut Connection<'stream>,
-    persistent: bool,
-    max_requests: u8,
-    served_requests_count: u8,
-}
+        for (pattern, handler) in &self.content_type_handlers {
+            let Some(specificity) = content_type_specificity(pattern, &content_type) else {
+                continue;
+            };
 
-impl<'server, 'connection, 'stream> HandleConnectionStateMachine<'server, 'connection, 'stream> {
-    fn new(
-        server: &'server Server,
-        connection: &'connection mut Connection<'stream>,
-        persistent: bool,
-        max_requests: u8,
-    ) -> Self {
-        HandleConnectionStateMachine {
-            server,
-            connection,
-            persistent,
-            max_requests,
-            served_requests_count: 0u8,
+            if best.is_none_or(|(best_specificity, _)| specificity > best_specificity) {
+                best = Some((specificity, handler.as_ref()));
+            }
         }
+
+        best.map(|(_, handler)| handler)
     }
 
-    fn next(&mut self, state: HandleConnectionState) -> HandleConnectionState {
-        let new_state: HandleConnectionState = match state {
-            HandleConnectionState::New => HandleConnectionState::Read(None),
-            HandleConnectionState::Read(current_request) => self.read(current_request),
-            HandleConnectionState::SendResponse(request, response) => {
-                self.send_response(request, response)
-            }
-            HandleConnectionState::ClientError(request, status_code) => {
-                self.client_error(request, status_code)
-            }
-            HandleConnectionState::Close | HandleConnectionState::Error(_) => state,
-        };
+    /// Static content for the request, with the path it was found at. Urls ending in /
+    /// get the first of `ServerConfig::index_files` in that directory.
+    fn content(&self, request: &Request) -> IoResult<(String, Arc<Content>)> {
+        // the query is for handlers and signed urls, not part of the file name
+        let path = request.path();
 
-        new_state
+        if path.ends_with('/') {
+            return self
+                .index_content(request, path)
+                .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound));
+        }
+
+        Ok((path.to_string(), self.content_at(request, path)?))
     }
 
-    fn read(&mut self, current_request: Option<Request>) -> HandleConnectionState {
-        let read_strategy = if let Some(request) = &current_request {
-            match request.body_type() {
-                RequestBodyType::ContentLength => ReadStrategy::UntilNoBytesRead(
-                    request.content_length().unwrap() - request.body.len(),
-                ),
-                RequestBodyType::TransferEncodingChunked => ReadStrategy::UntilDoubleCrlfAtEnd,
-                RequestBodyType::None => unreachable!(),
-            }
+    fn read_content(&self, content: &Content) -> IoResult<Vec<u8>> {
+        match &self.content_cache {
+            Some(cache) => cache.get_or_read(content),
+            None => content.read(),
+        }
+    }
+
+    fn index_content(&self, request: &Request, directory: &str) -> Option<(String, Arc<Content>)> {
+        self.config.index_files.iter().find_map(|index_file| {
+            let path = format!("{directory}{index_file}");
+            let content = self.content_at(request, &path).ok()?;
+
+            Some((path, content))
+        })
+    }
+
+    /// 301 from a directory url without the trailing slash to the one with it, e.g. /docs to
+    /// /docs/, so relative links in its index file resolve. Directories without one are left alone.
+    fn directory_redirect(&self, request: &Request) -> Option<Response> {
+        if !matches!(request.method, RequestMethod::Get | RequestMethod::Head) {
+            return None;
+        }
+
+        let (path, query) = request.url.split_at(request.path().len());
+        if path.ends_with('/') {
+            return None;
+        }
+        // one leading slash, //host/ would send browsers to another host
+        let directory = format!("/{}/", path.trim_start_matches('/'));
+        self.index_content(request, &directory)?;
+
+        Some(
+            Response::builder()
+                .status_code(ResponseStatusCode::MovedPermanently)
+                .header(names::LOCATION, &format!("{directory}{query}"))
+                .get(),
+        )
+    }
+
+    fn content_at(&self, request: &Request, path: &str) -> IoResult<Arc<Content>> {
+        let root = self.root(request);
+
+        match &self.open_file_cache {
+            Some(cache) => cache.get_or_open(root, path, &*self.clock, || {
+                self.content_source.get(root, path)
+            }),
+            None => self.content_source.get(root, path).map(Arc::new),
+        }
+    }
+
+    /// Redirect for plain HTTP requests to hosts that are only served over HTTPS.
+    fn https_redirect(&self, request: &Request, secure: bool) -> Option<Response> {
+        if secure
+            || !self
+                .virtual_host(request)
+                .is_some_and(|virtual_host| virtual_host.redirect_to_https)
+        {
+            return None;
+        }
+
+        let host = normalize_host(&request.get_header(names::HOST)?);
+        let authority = match self.config.https_port {
+            443 => host,
+            port => format!("{host}:{port}"),
+        };
+        // 308 keeps the method and body, which matters for anything but GET and HEAD
+        let status_code = if request.method.is_safe() {
+            ResponseStatusCode::MovedPermanently
         } else {
-            ReadStrategy::UntilDoubleCrlf
+            ResponseStatusCode::PermanentRedirect
         };
 
-        let request_bytes = match self.connection.read(read_strategy) {
-            Ok(bytes) if bytes.is_empty() && current_request.is_some() => {
-                return HandleConnectionState::ClientError(
-                    current_request,
-                    ResponseStatusCode::BadRequest,
-                );
-            }
-            Ok(bytes) if bytes.is_empty() => {
-                debug!("Got empty message (TCP FIN, probably)");
-                return HandleConnectionState::Close;
-            }
-            Ok(bytes) => bytes,
-            Err(err) => {
-                return match err.kind() {
-                    ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {
-                        HandleConnectionState::Close
-                    }
-                    ErrorKind::TimedOut => {
-                        HandleConnectionState::ClientError(None, ResponseStatusCode::RequestTimeout)
-                    }
-                    _ => HandleConnectionState::Error(err.kind()),
-                };
-            }
-        };
+        Some(
+            Response::builder()
+                .status_code(status_code)
+                .header(
+                    names::LOCATION,
+                    &format!("https://{authority}{}", request.url),
+                )
+                .get(),
+        )
+    }
 
-        return match current_request {
-            None => {
-                let request = parse_request(request_bytes.as_slice());
-                match request {
-                    Ok((request, is_request_complete)) => {
-                        let has_body = match request.body_type() {
-                            RequestBodyType::ContentLength => {
-                                matches!(request.content_length(), Some(length) if !(request.body.len() == length || length == 0))
-                            }
-                            RequestBodyType::TransferEncodingChunked => !is_request_complete,
-                            RequestBodyType::None => false,
-                        };
+    /// None if the request may be served, the response to deny it with otherwise.
+    fn check_auth_request(&self, request: &Request) -> Option<Response> {
+        let auth_request = self
+            .config
+            .auth_requests
+            .iter()
+            .filter(|auth_request| auth_request.applies_to(request))
+            .max_by_key(|auth_request| auth_request.path_prefix.len())?;
 
-                        // todo: this probably can be changed to is_request_complete
-                        if !has_body {
-                            let response = self.server.prepare_response(&request);
-                            HandleConnectionState::SendResponse(Some(request), response)
-                        } else {
-                            HandleConnectionState::Read(Some(request))
-                        }
-                    }
-                    Err(err) => {
-                        debug!("Parse request error: {err:?}");
-                        HandleConnectionState::ClientError(None, ResponseStatusCode::BadRequest)
-                    }
+        let auth_response = self.prepare_response(&auth_request.subrequest(request));
+        let status_code = *auth_response.status_code();
+
+        match status_code as u16 {
+            200..=299 => None,
+            401 | 403 => {
+                debug!(
+                    "{} denied access to {}",
+                    auth_request.auth_path, request.url
+                );
+                let mut response = self.error_response(Some(request), status_code);
+                if let Some(challenge) = auth_response.get_header(names::WWW_AUTHENTICATE) {
+                    response.set_header(names::WWW_AUTHENTICATE, challenge);
                 }
+                Some(response)
             }
-            Some(mut request) => {
-                if matches!(request.body_type(), RequestBodyType::ContentLength)
-                    && request_bytes.len() > request.content_length().unwrap()
-                {
-                    return HandleConnectionState::ClientError(
-                        Some(request),
-                        ResponseStatusCode::BadRequest,
-                    );
-                }
+            status => {
+                error!(
+                    "{} answered {status} when checking {}",
+                    auth_request.auth_path, request.url
+                );
+                Some(self.error_response(Some(request), ResponseStatusCode::InternalServerError))
+            }
+        }
+    }
 
-                let mut request_bytes = request_bytes;
+    /// Rewrites the path of the url to the one files are looked up at, so path prefixes of
+    /// auth requests, signed urls and limits see what is served. 400 for paths above the root.
+    fn normalize_url(&self, request: &mut Request) -> Option<Response> {
+        // e.g. * for OPTIONS
+        if !request.url.starts_with('/') {
+            return None;
+        }
 
-                if matches!(
-                    request.body_type(),
-                    RequestBodyType::TransferEncodingChunked
-                ) {
-                    let Ok((body, is_complete)) = parse_chunked_body(request_bytes) else {
-                        return HandleConnectionState::ClientError(Some(request), ResponseStatusCode::BadRequest);
-                    };
+        let (path, rest) = request.url.split_at(request.path().len());
+        match normalize_path(path) {
+            Some(path) => {
+                request.url = path + rest;
+                None
+            }
+            None => {
+                debug!("Refusing {} outside of the root", request.url);
+                Some(self.error_response(Some(request), ResponseStatusCode::BadRequest))
+            }
+        }
+    }
 
-                    // not sure if there will ever be a case when is_complete is false
-                    if !is_complete {
-                        return HandleConnectionState::Read(Some(request));
-                    }
+    /// Drops or refuses a protocol switch the request asks for, depending on `ServerConfig::upgrade`.
+    fn apply_upgrade_policy(&self, request: &mut Request) -> Option<Response> {
+        if !request.has_header(names::UPGRADE, None) {
+            return None;
+        }
 
-                    request_bytes = body;
+        match self.config.upgrade {
+            UpgradePolicy::Honor => None,
+            UpgradePolicy::Strip => {
+                debug!("Ignoring Upgrade on {}", request.url);
+                request.headers.remove(names::UPGRADE);
+                let connection = request.get_header(names::CONNECTION).unwrap_or_default();
+                let options = connection
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|option| !option.is_empty() && !option.eq_ignore_ascii_case("upgrade"))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                request.headers.remove(names::CONNECTION);
+                if !options.is_empty() {
+                    request.headers.add(names::CONNECTION, &options);
                 }
 
-                request.body.extend(request_bytes);
+                None
+            }
+            UpgradePolicy::Reject(status_code) => {
+                debug!("Refusing Upgrade on {}", request.url);
+                Some(self.error_response(Some(request), status_code))
+            }
+        }
+    }
 
-                let response = self.server.serve_content(&request);
-                HandleConnectionState::SendResponse(Some(request), response)
+    /// Runs the handler for a request, after redirects and auth subrequests.
+    fn dispatch(&self, request: &Request, secure: bool) -> Response {
+        // a panicking listener or route answers with 500 instead of taking the thread down
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            if let Some(static_response) = self.static_response_for(request) {
+                return static_response.response().unwrap_or_else(|err| {
+                    error!("Could not read static response for {}: {err}", request.url);
+                    self.error_response(Some(request), ResponseStatusCode::InternalServerError)
+                });
             }
-        };
+            if let Some(handler) = self.passthrough_handler_for(request) {
+                return handler(request);
+            }
+
+            self.https_redirect(request, secure)
+                .or_else(|| self.check_auth_request(request))
+                .unwrap_or_else(|| self.prepare_response(request))
+        }));
+
+        result.unwrap_or_else(|payload| {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown cause");
+            error!("Handler panicked on {}: {message}", request.url);
+
+            self.error_response(Some(request), ResponseStatusCode::InternalServerError)
+        })
     }
 
-    fn send_response(
-        &mut self,
-        request: Option<Request>,
+    /// Rules and the passes after them, which responses go through whatever connection
+    /// they are sent on. Passthrough routes and static responses skip them.
+    fn finalize_response(
+        &self,
+        request: &Rc<RefCell<Request>>,
         response: Response,
-    ) -> HandleConnectionState {
-        let request = request.map(|v| Rc::new(RefCell::new(v)));
-        let mut response = match &request {
-            Some(request) => apply_rules(&self.server.rules, request.clone(), response),
-            None => response,
+        timing: &mut RequestTiming,
+    ) -> Response {
+        if self.is_passthrough(&request.borrow())
+            || self.static_response_for(&request.borrow()).is_some()
+        {
+            return response;
+        }
+
+        let rules = &self.current_rules();
+        let options = EvaluationOptions {
+            audit: self.config.rules_audit_log,
+            budget: self.config.rule_budget,
+            secrets: self.config.rule_secrets.clone(),
+            #[cfg(feature = "geoip")]
+            geoip: self.geoip.clone(),
         };
+        let response = timing
+            .measure(Phase::Rules, || {
+                apply_rules(rules, request.clone(), response, &options)
+            })
+            .unwrap_or_else(|status_code| {
+                self.error_response(Some(&request.borrow()), status_code)
+            });
+        let response = self.finalize_encoding(&request.borrow(), response);
+        let response = self.finalize_conditional(&request.borrow(), response);
 
-        let should_close = !self.persistent
-            || self.served_requests_count == self.max_requests - 1
-            || request
-            .as_ref()
-            .is_some_and(|request| request.borrow().has_header("Connection", Some("close")));
+        self.finalize_location(&request.borrow(), response)
+    }
 
-        if should_close {
-            response.set_header("Connection", "close");
+    /// Date, Alt-Svc and, over TLS, Strict-Transport-Security.
+    fn add_response_headers(
+        &self,
+        request: Option<&Request>,
+        response: &mut Response,
+        secure: bool,
+    ) {
+        if !response.has_header(names::DATE) {
+            let date = httpdate::fmt_http_date(self.clock.system_time());
+            response.set_header(names::DATE, &date);
         }
 
-        match self.connection.write(&response.as_bytes()) {
-            Ok(_) => {}
-            Err(err) => return HandleConnectionState::Error(err.kind()),
+        if let Some(alt_svc) = &self.config.alt_svc {
+            if !response.has_header(names::ALT_SVC) {
+                response.set_header(names::ALT_SVC, &alt_svc.header_value());
+            }
         }
 
-        self.served_requests_count += 1;
+        if secure {
+            let hsts = request
+                .and_then(|request| self.virtual_host(request))
+                .and_then(|virtual_host| virtual_host.hsts.as_ref())
+                .map(|hsts| hsts.header_value());
 
-        if should_close {
-            HandleConnectionState::Close
+            if let Some(hsts) = hsts {
+                response.set_header(names::STRICT_TRANSPORT_SECURITY, &hsts);
+            }
+        }
+    }
+
+    /// Response to a request that came in over HTTP/2, through the same handlers and rules
+    /// as HTTP/1 ones. Framing and connection headers are up to the caller.
+    #[cfg(feature = "http2")]
+    pub(crate) fn respond(&self, mut request: Request) -> Response {
+        let mut timing = RequestTiming::default();
+        self.config.request_headers.normalize(&mut request);
+        let response = self
+            .normalize_url(&mut request)
+            .unwrap_or_else(|| self.dispatch(&request, true));
+        let request = Rc::new(RefCell::new(request));
+        let mut response = self.finalize_response(&request, response, &mut timing);
+        self.add_response_headers(Some(&request.borrow()), &mut response, true);
+
+        response
+    }
+
+    fn prepare_response(&self, request: &Request) -> Response {
+        if request.method == RequestMethod::Options && request.url == "*" {
+            options_response(request)
+        } else if self.config.serve_manifest && request.url == MANIFEST_URL {
+            self.manifest_response(request)
+        } else if self.config.metrics_path.as_deref() == Some(request.path()) {
+            self.metrics_response(request)
+        } else if let Some(proxy) = self.proxy_for(request) {
+            proxy
+                .handle(request, &self.clock)
+                .unwrap_or_else(|status_code| self.error_response(Some(request), status_code))
         } else {
-            HandleConnectionState::Read(None)
+            self.serve_content(request)
         }
     }
 
-    fn client_error(
-        &mut self,
-        request: Option<Request>,
-        status_code: ResponseStatusCode,
-    ) -> HandleConnectionState {
-        let response = error_response(request.as_ref(), status_code);
-        HandleConnectionState::SendResponse(request, response)
+    fn serve_content(&self, request: &Request) -> Response {
+        let signed_url = self
+            .config
+            .signed_urls
+            .iter()
+            .find(|signed_url| signed_url.applies_to(request.path()));
+        if let Some(signed_url) = signed_url {
+            if !signed_url.verify(&request.url, self.clock.system_time()) {
+                debug!("Invalid or expired signature for {}", request.url);
+                return self.error_response(Some(request), ResponseStatusCode::Forbidden);
+            }
+        }
+
+        let hotlink_protection = self
+            .config
+            .hotlink_protections
+            .iter()
+            .find(|hotlink_protection| hotlink_protection.blocks(request));
+        if let Some(hotlink_protection) = hotlink_protection {
+            debug!("Blocked hotlink to {}", request.url);
+            return match &hotlink_protection.redirect {
+                Some(location) => Response::builder()
+                    .status_code(ResponseStatusCode::Found)
+                    .header(names::LOCATION, location)
+                    .get(),
+                None => self.error_response(Some(request), ResponseStatusCode::Forbidden),
+            };
+        }
+
+        let rule_limiters = self.rule_limiters.read().unwrap().clone();
+        let permit = match self
+            .route_limiters
+            .iter()
+            .chain(rule_limiters.iter())
+            .find(|limiter| limiter.matches(request.path()))
+        {
+            Some(limiter) => match limiter.acquire(&*self.clock) {
+                Some(permit) => Some(permit),
+                None => {
+                    debug!("Concurrency limit reached for {}", request.url);
+                    return self
+                        .error_response(Some(request), ResponseStatusCode::ServiceUnavailable);
+                }
+            },
+            None => None,
+        };
+
+        let mut response = self.serve_permitted(request);
+        // the slot is taken until the body is sent, files are streamed after this returns
+        if let Some(permit) = permit {
+            response.hold_until_sent(Arc::new(permit));
+        }
+
+        response
     }
-}
 
-fn apply_rules(rules: &Rules, request: Rc<RefCell<Request>>, response: Response) -> Response {
-    let out_response = Rc::new(RefCell::new(response));
+    /// Handlers, writes and static files, what concurrency limits hold back.
+    fn serve_permitted(&self, request: &Request) -> Response {
+        if let Some(handler) = self.content_type_handler_for(request) {
+            return handler(request);
+        }
 
-    for rule in &rules.rules {
-        if !rule.matches(&request.borrow().url) {
-            continue;
+        if self.config.static_writes
+            && matches!(request.method, RequestMethod::Put | RequestMethod::Delete)
+        {
+            return self.write_content(request);
         }
 
-        match rule.evaluate(request.clone(), out_response.clone()) {
-            Ok(RuleEvaluationResult::Continue) => {}
-            Ok(RuleEvaluationResult::Finish) => {
-                return Rc::try_unwrap(out_response).unwrap().into_inner();
+        if let Some(response) = self
+            .well_known
+            .as_ref()
+            .and_then(|well_known| well_known.handle(request))
+        {
+            return response;
+        }
+
+        if let Some(text) = self.generated_text_file(request) {
+            if let Some(response) = self.read_only_response(request) {
+                return response;
             }
-            Err(e) => {
-                error!("Error during rule evaluation:\n{}", format_error_in_file(e, &rules.file))
-                // todo: 500?
+
+            let mut response =
+                content_response(request, ResponseBody::Bytes(text.as_bytes().to_vec()));
+            response.set_header(names::CONTENT_TYPE, "text/plain; charset=utf-8");
+            return response;
+        }
+
+        if let Ok((content_path, content)) = self.content(request) {
+            if let Some(response) = self.read_only_response(request) {
+                return response;
+            }
+
+            let etag = self.etag(&content);
+            let (variants, coding) = self.precompressed_variant(request, &content_path, &content);
+            let body_content = coding.as_ref().map_or(&content, |(_, variant)| variant);
+            let send_from_disk = self
+                .config
+                .sendfile_threshold
+                .is_some_and(|threshold| body_content.len >= threshold);
+
+            let body = match &body_content.body {
+                ContentBody::File(file) if send_from_disk => {
+                    ResponseBody::File(file.clone(), body_content.len)
+                }
+                _ => match self.read_content(body_content) {
+                    Ok(bytes) => ResponseBody::Bytes(bytes),
+                    Err(_) => {
+                        return self.error_response(Some(request), ResponseStatusCode::NotFound)
+                    }
+                },
+            };
+            let mut response = content_response(request, body);
+            // an index file, the url names its directory
+            if request.path() != content_path {
+                response.set_header(names::CONTENT_TYPE, &content_type_for(&content_path));
+            }
+
+            if let Some(etag) = etag {
+                response.set_header(names::ETAG, &etag);
+            }
+            // a file modified after the Date of its response would never be fresh in caches
+            if let Some(modified) = content.modified {
+                let modified = modified.min(self.clock.system_time());
+                response.set_header(names::LAST_MODIFIED, &httpdate::fmt_http_date(modified));
+            }
+
+            if variants {
+                response.add_vary(names::ACCEPT_ENCODING);
+            }
+            if let Some((coding, _)) = coding {
+                set_content_encoding(&mut response, coding);
+                return response;
             }
+
+            return self.encode_content(request, &content, response);
+        }
+
+        if let Some(response) = self.directory_redirect(request) {
+            return response;
+        }
+
+        if let Some(response) = self
+            .router
+            .as_ref()
+            .and_then(|router| router.handle(request))
+        {
+            return response;
+        }
+
+        for (path_prefix, listener) in &self.listeners {
+            if !has_path_prefix(request.path(), path_prefix) {
+                continue;
+            }
+
+            if let Some(response) = listener(request) {
+                return response;
+            }
+        }
+
+        if let Some(response) = self.favicon_fallback(request) {
+            return response;
         }
+
+        self.error_response(Some(request), ResponseStatusCode::NotFound)
     }
 
-    Rc::try_unwrap(out_response).unwrap().into_inner()
-}
+    /// `.br` and `.gz` siblings of a static file, compressed ahead of time. Tells whether the file
+    /// has any, which makes its responses vary by Accept-Encoding, and picks the one to send.
+    /// Siblings older than the file are ignored, they were left behind by an old build.
+    fn precompressed_variant(
+        &self,
+        request: &Request,
+        path: &str,
+        content: &Content,
+    ) -> (bool, Option<(&'static str, Arc<Content>)>) {
+        if !self.config.serve_precompressed {
+            return (false, None);
+        }
 
-fn get_content(root: &str, content_path: &str) -> IoResult<Vec<u8>> {
-    let root_path = Path::new(root);
-    let path = root_path.join(content_path.trim_start_matches('/'));
-    let canonical_root_path = fs::canonicalize(root_path)?;
-    let canonical_path = fs::canonicalize(path)?;
+        let accept_encoding = request.get_header(names::ACCEPT_ENCODING);
+        let mut has_variants = false;
 
-    // Do this check so no smarty-pants tries to access files
-    // outside web root directory, e.g. with GET /../example_http.rs
-    if !canonical_path.starts_with(canonical_root_path) {
-        return Err(std::io::Error::from(ErrorKind::PermissionDenied));
+        // br first, it compresses better
+        for (coding, extension, accepts) in [
+            ("br", "br", accepts_brotli as fn(Option<&str>) -> bool),
+            ("gzip", "gz", accepts_gzip),
+        ] {
+            let Ok(variant) = self.content_at(request, &format!("{path}.{extension}")) else {
+                continue;
+            };
+            if variant.modified < content.modified {
+                continue;
+            }
+
+            has_variants = true;
+            if accepts(accept_encoding.as_deref()) {
+                return (true, Some((coding, variant)));
+            }
+        }
+
+        (has_variants, None)
     }
 
-    fs::read(canonical_path)
-}
+    /// Swaps the body of a static file for its gzipped variant from the compression cache,
+    /// so each version is compressed once. Without a cache, `finalize_encoding` compresses it.
+    fn encode_content(
+        &self,
+        request: &Request,
+        content: &Content,
+        mut response: Response,
+    ) -> Response {
+        let (Some(cache), Some(compression)) = (&self.compression_cache, &self.config.compression)
+        else {
+            return response;
+        };
+        if !compression.applies_to(&response) {
+            return response;
+        }
 
-fn content_response(
-    request: &Request,
-    content_bytes: Vec<u8>,
-    keep_alive_config: KeepAliveConfig,
-) -> Response {
-    let mime_type = mime_guess::from_path(&request.url).first();
-    let content_type = if let Some(mime) = mime_type {
-        let charset = if mime.type_() == "text" {
-            "; charset=utf-8"
-        } else {
-            ""
+        response.add_vary(names::ACCEPT_ENCODING);
+        if !accepts_gzip(request.get_header(names::ACCEPT_ENCODING).as_deref()) {
+            return response;
+        }
+
+        let (file, len) = match cache.get_or_compress(content) {
+            Ok(Some(variant)) => variant,
+            Ok(None) => return response,
+            Err(err) => {
+                error!("Could not cache compressed {}: {err}", request.url);
+                return response;
+            }
         };
-        mime.essence_str().to_string() + charset
-    } else {
-        "application/octet-stream".to_string()
-    };
 
-    let mut builder = Response::builder()
-        .status_code(ResponseStatusCode::Ok)
-        .header("Content-Type", &content_type)
-        .header("Content-Length", &content_bytes.len().to_string());
+        let send_from_disk = self
+            .config
+            .sendfile_threshold
+            .is_some_and(|threshold| len >= threshold);
+        if send_from_disk {
+            response.set_body_file(file, len);
+        } else {
+            let mut bytes = vec![0u8; len as usize];
+            if let Err(err) = read_exact_at(&file, &mut bytes, 0) {
+                error!("Could not read compressed {}: {err}", request.url);
+                return response;
+            }
+            response.set_header(names::CONTENT_LENGTH, &len.to_string());
+            response.set_body(bytes);
+        }
+        set_content_encoding(&mut response, "gzip");
 
-    if let KeepAliveConfig::On {
-        timeout,
-        max_requests,
-        include_header,
-    } = keep_alive_config
-    {
-        if include_header {
-            builder = builder.header(
-                "Keep-Alive",
-                &format!("timeout={timeout}, max={max_requests}"),
-            );
+        response
+    }
+
+    /// Text from the config served in place of a file, like robots.txt.
+    fn generated_text_file(&self, request: &Request) -> Option<&str> {
+        let path = request.url.split(['?', '#']).next().unwrap_or_default();
+
+        match path {
+            "/robots.txt" => self.config.robots_txt.as_deref(),
+            "/.well-known/security.txt" => self.config.security_txt.as_deref(),
+            _ => None,
         }
     }
 
-    if request.method == RequestMethod::Get {
-        // todo: this is where content should actually be read
-        builder = builder.body(content_bytes);
+    fn favicon_fallback(&self, request: &Request) -> Option<Response> {
+        let path = request.url.split(['?', '#']).next().unwrap_or_default();
+        if path != FAVICON_URL
+            || !matches!(request.method, RequestMethod::Get | RequestMethod::Head)
+        {
+            return None;
+        }
+
+        match self.config.favicon_fallback {
+            FaviconFallback::Off => None,
+            FaviconFallback::Transparent => {
+                let mut response =
+                    content_response(request, ResponseBody::Bytes(TRANSPARENT_FAVICON.to_vec()));
+                response.set_header(names::CONTENT_TYPE, "image/x-icon");
+                Some(response)
+            }
+            FaviconFallback::NoContent => Some(
+                Response::builder()
+                    .status_code(ResponseStatusCode::NoContent)
+                    .get(),
+            ),
+        }
     }
 
-    builder.get()
-}
+    /// Answer to requests for content that can only be read, None for GET and HEAD.
+    fn read_only_response(&self, request: &Request) -> Option<Response> {
+        if !request.method.is_safe() {
+            let mut response =
+                self.error_response(Some(request), ResponseStatusCode::MethodNotAllowed);
+            response.set_header(names::ALLOW, &RequestMethod::safe_methods_str());
+            Some(response)
+        } else if request.method == RequestMethod::Options {
+            Some(options_response(request))
+        } else {
+            None
+        }
+    }
 
-fn error_response(request: Option<&Request>, status_code: ResponseStatusCode) -> Response {
-    let mut response_builder = ResponseBuilder::new().status_code(status_code);
+    fn write_content(&self, request: &Request) -> Response {
+        // the same file GET serves, a query does not name another one
+        let content_path = request.path();
+        let path = Path::new(self.root(request)).join(content_path.trim_start_matches('/'));
+        // two writes with the same If-Match must not both pass the check before either writes
+        let _guard = self.write_locks.lock(&path);
 
-    let accepts_html = if let Some(request) = request {
-        let accept_header = request.headers.get("Accept");
-        matches!(accept_header, Some(v) if v.contains("text/html") || v.contains("text/*") || v.contains("*/*"))
-    } else {
-        false
+        let existing = get_content(&self.canonical_paths, self.root(request), content_path).ok();
+        let etag = existing.as_ref().and_then(|content| self.etag(content));
+        let validators = existing.as_ref().map(|content| Validators {
+            etag: etag.as_deref(),
+            modified: content.modified,
+        });
+
+        if !write_preconditions_pass(request, validators.as_ref()) {
+            return self.error_response(Some(request), ResponseStatusCode::PreconditionFailed);
+        }
+
+        let result = match (&request.method, &existing) {
+            (RequestMethod::Put, _) => put_content(self.root(request), content_path, &request.body)
+                .map(|_| match existing {
+                    Some(_) => ResponseStatusCode::NoContent,
+                    None => ResponseStatusCode::Created,
+                }),
+            (RequestMethod::Delete, Some(content)) => {
+                fs::remove_file(&content.path).map(|_| ResponseStatusCode::NoContent)
+            }
+            (RequestMethod::Delete, None) => {
+                return self.error_response(Some(request), ResponseStatusCode::NotFound)
+            }
+            _ => unreachable!(),
+        };
+
+        if let Some(cache) = &self.open_file_cache {
+            cache.invalidate(self.root(request), content_path);
+        }
+        if let (Some(cache), Some(content)) = (&self.content_cache, &existing) {
+            cache.invalidate(&content.path);
+        }
+        self.canonical_paths.invalidate(&path);
+
+        match result {
+            Ok(status_code) => Response::builder().status_code(status_code).get(),
+            Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+                self.error_response(Some(request), ResponseStatusCode::Forbidden)
+            }
+            Err(err) => {
+                error!("Could not modify {}: {err}", request.url);
+                self.error_response(Some(request), ResponseStatusCode::InternalServerError)
+            }
+        }
+    }
+
+    /// Compresses bodies held in memory once rules are done with them, for clients
+    /// that accept gzip. Static files may already be compressed by `encode_content`.
+    fn finalize_encoding(&self, request: &Request, mut response: Response) -> Response {
+        let Some(compression) = &self.config.compression else {
+            return response;
+        };
+        // files sent from disk are left to `encode_content`
+        if response.body_file().is_some() || !compression.applies_to(&response) {
+            return response;
+        }
+
+        response.add_vary(names::ACCEPT_ENCODING);
+        if !accepts_gzip(request.get_header(names::ACCEPT_ENCODING).as_deref()) {
+            return response;
+        }
+
+        match gzip(response.body(), compression.level) {
+            Ok(bytes) => {
+                response.set_header(names::CONTENT_LENGTH, &bytes.len().to_string());
+                response.set_body(bytes);
+                set_content_encoding(&mut response, "gzip");
+            }
+            Err(err) => error!("Could not compress {}: {err}", request.url),
+        }
+
+        response
+    }
+
+    /// Runs once handlers and rules are done, so validators attached by either of them
+    /// still answer conditional GET and HEAD requests.
+    fn finalize_conditional(&self, request: &Request, mut response: Response) -> Response {
+        if !matches!(request.method, RequestMethod::Get | RequestMethod::Head)
+            || *response.status_code() != ResponseStatusCode::Ok
+        {
+            return response;
+        }
+
+        let modified = response
+            .get_header(names::LAST_MODIFIED)
+            .and_then(|date| httpdate::parse_http_date(date).ok());
+        let validators = Validators {
+            etag: response.get_header(names::ETAG),
+            modified,
+        };
+
+        match read_preconditions(request, &validators) {
+            Some(ResponseStatusCode::NotModified) => {
+                // ETag, Cache-Control, Vary and the like stay, the representation goes
+                response.set_status_code(ResponseStatusCode::NotModified);
+                response.remove_header(names::CONTENT_LENGTH);
+                response.remove_header(names::TRANSFER_ENCODING);
+                response.set_body(vec![]);
+                response
+            }
+            Some(status_code) => self.error_response(Some(request), status_code),
+            None => response,
+        }
+    }
+
+    /// Location headers set by handlers and rules are percent-encoded, and absolute ones
+    /// must point to the request's own host or to one of `redirect_hosts`.
+    fn finalize_location(&self, request: &Request, mut response: Response) -> Response {
+        let Some(location) = response.get_header(names::LOCATION).map(encode_location) else {
+            return response;
+        };
+
+        if let Some(redirect_hosts) = &self.config.redirect_hosts {
+            let host = request.get_header(names::HOST);
+            if !is_redirect_allowed(&location, host.as_deref(), redirect_hosts) {
+                error!("Refusing to redirect {} to {location}", request.url);
+                return self.error_response(Some(request), ResponseStatusCode::InternalServerError);
+            }
+        }
+
+        response.remove_header(names::LOCATION);
+        response.set_header(names::LOCATION, &location);
+
+        response
+    }
+
+    fn error_response(
+        &self,
+        request: Option<&Request>,
+        status_code: ResponseStatusCode,
+    ) -> Response {
+        error_response(request, status_code, self.error_renderer.as_deref())
+    }
+
+    fn manifest_response(&self, request: &Request) -> Response {
+        if !request.method.is_safe() {
+            let mut response =
+                self.error_response(Some(request), ResponseStatusCode::MethodNotAllowed);
+            response.set_header(names::ALLOW, &RequestMethod::safe_methods_str());
+            return response;
+        }
+
+        match build_manifest_cached(self.root(request), &self.manifest_cache) {
+            Ok(manifest) => {
+                let json = manifest.to_json();
+                Response::builder()
+                    .status_code(ResponseStatusCode::Ok)
+                    .header(names::CONTENT_TYPE, "application/json")
+                    .header(names::CONTENT_LENGTH, &json.len().to_string())
+                    .text_body(&json)
+                    .get()
+            }
+            Err(err) => {
+                error!("Could not build manifest: {err}");
+                self.error_response(Some(request), ResponseStatusCode::InternalServerError)
+            }
+        }
+    }
+
+    fn metrics_response(&self, request: &Request) -> Response {
+        if !request.method.is_safe() {
+            let mut response =
+                self.error_response(Some(request), ResponseStatusCode::MethodNotAllowed);
+            response.set_header(names::ALLOW, &RequestMethod::safe_methods_str());
+            return response;
+        }
+
+        let text = self.stats().to_prometheus();
+        Response::builder()
+            .status_code(ResponseStatusCode::Ok)
+            .header(names::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .header(names::CACHE_CONTROL, "no-store")
+            .header(names::CONTENT_LENGTH, &text.len().to_string())
+            .text_body(&text)
+            .get()
+    }
+
+    fn etag(&self, content: &Content) -> Option<String> {
+        match self.config.etag {
+            ETagConfig::Off => None,
+            ETagConfig::Weak => Some(weak_etag(content.len, content.modified)),
+            ETagConfig::Strong => Some(self.content_etag(content, strong_etag)),
+            ETagConfig::Sha256 => Some(self.content_etag(content, sha256_etag)),
+        }
+    }
+
+    /// Weak for a file that can't be read, that one is not cached.
+    fn content_etag(&self, content: &Content, hash: fn(&[u8]) -> String) -> String {
+        self.etag_cache
+            .try_get_or_compute(&content.path, content.len, content.modified, || {
+                content.read().map(|bytes| hash(&bytes))
+            })
+            .unwrap_or_else(|_| weak_etag(content.len, content.modified))
+    }
+}
+
+#[cfg(not(feature = "https"))]
+fn init_https(config: &ServerConfig, _protocols: &[&[u8]]) -> crate::Result<Option<TlsConfig>> {
+    match config.https {
+        true => Err(crate::Error::Config(
+            "HTTPS needs the https feature".to_string(),
+        )),
+        false => Ok(None),
+    }
+}
+
+/// TLS config for the HTTPS listener, None if HTTPS is off. `protocols` are offered
+/// through ALPN in order of preference, e.g. h2 before http/1.1.
+#[cfg(feature = "https")]
+pub(crate) fn init_https(
+    config: &ServerConfig,
+    protocols: &[&[u8]],
+) -> crate::Result<Option<TlsConfig>> {
+    if !config.https {
+        return Ok(None);
+    }
+
+    let has_virtual_host_tls = config.virtual_hosts.iter().any(VirtualHost::has_tls);
+
+    // server-wide certificate is optional only if virtual hosts bring their own
+    let fallback = if config.cert_path.is_some() || !has_virtual_host_tls {
+        Some(config.load_certified_key()?)
+    } else {
+        None
     };
 
-    if accepts_html {
-        let text_body = format!(
-            "<html><body><h1 style='text-align: center'>{} {}</h1></body></html>",
-            status_code as u16, status_code
-        );
-        response_builder = response_builder
-            .header("Content-Type", "text/html; charset=utf-8")
-            .text_body(&text_body)
-    }
+    let resolver = VirtualHostCertResolver::new(&config.virtual_hosts, fallback)?;
+    let mut tls_config = rustls::ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(resolver));
+    tls_config.alpn_protocols = protocols.iter().map(|protocol| protocol.to_vec()).collect();
+
+    Ok(Some(Arc::new(tls_config)))
+}
+
+pub(crate) enum HandleConnectionState {
+    New,
+    Read(Option<Request>),
+    SendResponse(Option<Request>, Response),
+    ClientError(Option<Request>, ResponseStatusCode),
+    Close,
+    Error(ErrorKind),
+}
+
+pub(crate) struct HandleConnectionStateMachine<'server, 'connection, 'stream> {
+    server: &'server Server,
+    connection: &'connection mut Connection<'stream>,
+    persistent: bool,
+    max_requests: u8,
+    served_requests_count: u8,
+    timing: RequestTiming,
+    upload_started: Option<Instant>,
+    request_started: Option<Instant>,
+    // set once the connection layer rejects a request, the bytes after it cannot be trusted
+    // to start the next request
+    rejected: bool,
+}
+
+impl<'server, 'connection, 'stream> HandleConnectionStateMachine<'server, 'connection, 'stream> {
+    pub(crate) fn new(
+        server: &'server Server,
+        connection: &'connection mut Connection<'stream>,
+        persistent: bool,
+        max_requests: u8,
+    ) -> Self {
+        connection.set_clock(server.clock.clone());
+        connection.set_throttle(
+            server
+                .config
+                .connection_rate_limit
+                .map(|rate| Throttle::new(rate, rate, server.clock.now())),
+        );
+
+        HandleConnectionStateMachine {
+            server,
+            connection,
+            persistent,
+            max_requests,
+            served_requests_count: 0u8,
+            timing: RequestTiming::default(),
+            upload_started: None,
+            request_started: None,
+            rejected: false,
+        }
+    }
+
+    pub(crate) fn next(&mut self, state: HandleConnectionState) -> HandleConnectionState {
+        let new_state: HandleConnectionState = match state {
+            HandleConnectionState::New => HandleConnectionState::Read(None),
+            HandleConnectionState::Read(current_request) => self.read(current_request),
+            HandleConnectionState::SendResponse(request, response) => {
+                self.send_response(request, response)
+            }
+            HandleConnectionState::ClientError(request, status_code) => {
+                self.client_error(request, status_code)
+            }
+            HandleConnectionState::Close | HandleConnectionState::Error(_) => state,
+        };
+
+        new_state
+    }
+
+    fn read(&mut self, current_request: Option<Request>) -> HandleConnectionState {
+        let read_strategy = if let Some(request) = &current_request {
+            match request.body_type() {
+                RequestBodyType::ContentLength => ReadStrategy::UntilNoBytesRead(
+                    (request.content_length().unwrap() - request.body.len()).min(BODY_READ_CHUNK),
+                ),
+                RequestBodyType::TransferEncodingChunked => ReadStrategy::UntilDoubleCrlfAtEnd,
+                RequestBodyType::None => unreachable!(),
+            }
+        } else {
+            ReadStrategy::UntilDoubleCrlf {
+                bare_lf: self.server.config.parser.allow_lf_line_endings,
+            }
+        };
+
+        let upload_deadline = [self.upload_deadline(), self.request_deadline()]
+            .into_iter()
+            .flatten()
+            .min();
+        if upload_deadline.is_some_and(|deadline| self.server.clock.now() >= deadline) {
+            return self.upload_timed_out(current_request);
+        }
+        self.connection.set_deadline(upload_deadline);
+
+        let request_bytes = match self.connection.read(read_strategy) {
+            // the deadline passed before the client sent anything
+            Ok(bytes)
+                if bytes.is_empty()
+                    && upload_deadline
+                        .is_some_and(|deadline| self.server.clock.now() >= deadline) =>
+            {
+                return self.upload_timed_out(current_request);
+            }
+            Ok(bytes) if bytes.is_empty() && current_request.is_some() => {
+                return HandleConnectionState::ClientError(
+                    current_request,
+                    ResponseStatusCode::BadRequest,
+                );
+            }
+            Ok(bytes) if bytes.is_empty() => {
+                debug!("Got empty message (TCP FIN, probably)");
+                return HandleConnectionState::Close;
+            }
+            Ok(bytes) => bytes,
+            Err(err) => {
+                return match err.kind() {
+                    ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted => {
+                        self.connection.stats.resets += 1;
+                        HandleConnectionState::Close
+                    }
+                    ErrorKind::TimedOut if current_request.is_some() => {
+                        self.upload_timed_out(current_request)
+                    }
+                    ErrorKind::TimedOut => {
+                        self.connection.stats.timeouts += 1;
+                        HandleConnectionState::ClientError(None, ResponseStatusCode::RequestTimeout)
+                    }
+                    _ => HandleConnectionState::Error(err.kind()),
+                };
+            }
+        };
+
+        match current_request {
+            None => {
+                self.request_started = Some(self.server.clock.now());
+                let parser_config = &self.server.config.parser;
+                let request = self.timing.measure(Phase::Parse, || {
+                    parse_request(request_bytes.as_slice(), parser_config)
+                });
+                match request {
+                    Ok((mut request, is_request_complete)) => {
+                        request.peer_addr = self.connection.peer_addr();
+                        let has_body = match request.body_type() {
+                            RequestBodyType::ContentLength => {
+                                matches!(request.content_length(), Some(length) if !(request.body.len() == length || length == 0))
+                            }
+                            RequestBodyType::TransferEncodingChunked => !is_request_complete,
+                            RequestBodyType::None => false,
+                        };
+
+                        // todo: this probably can be changed to is_request_complete
+                        if !has_body {
+                            let response = self.handle_request(&mut request);
+                            HandleConnectionState::SendResponse(Some(request), response)
+                        } else {
+                            self.upload_started = Some(self.server.clock.now());
+                            HandleConnectionState::Read(Some(request))
+                        }
+                    }
+                    Err(err) => {
+                        debug!("Parse request error: {err:?}");
+                        self.connection.stats.parse_errors += 1;
+                        HandleConnectionState::ClientError(None, ResponseStatusCode::BadRequest)
+                    }
+                }
+            }
+            Some(mut request) => {
+                if matches!(request.body_type(), RequestBodyType::ContentLength)
+                    && request.body.len() + request_bytes.len() > request.content_length().unwrap()
+                {
+                    return HandleConnectionState::ClientError(
+                        Some(request),
+                        ResponseStatusCode::BadRequest,
+                    );
+                }
+
+                let mut request_bytes = request_bytes;
+
+                if matches!(
+                    request.body_type(),
+                    RequestBodyType::TransferEncodingChunked
+                ) {
+                    let chunked_body = self.timing.measure(Phase::Parse, || {
+                        parse_chunked_body_with_trailers(request_bytes)
+                    });
+                    let Ok((body, trailers, is_complete)) = chunked_body else {
+                        self.connection.stats.parse_errors += 1;
+                        return HandleConnectionState::ClientError(
+                            Some(request),
+                            ResponseStatusCode::BadRequest,
+                        );
+                    };
+
+                    // not sure if there will ever be a case when is_complete is false
+                    if !is_complete {
+                        return HandleConnectionState::Read(Some(request));
+                    }
+
+                    request_bytes = body;
+                    request.trailers = trailers;
+                }
+
+                request.body.extend(request_bytes);
+                self.report_upload_progress(&request);
+
+                if matches!(request.body_type(), RequestBodyType::ContentLength)
+                    && request.body.len() < request.content_length().unwrap()
+                {
+                    return HandleConnectionState::Read(Some(request));
+                }
+
+                self.upload_started = None;
+                self.connection.set_deadline(None);
+
+                let response = self.handle_request(&mut request);
+                HandleConnectionState::SendResponse(Some(request), response)
+            }
+        }
+    }
+
+    fn send_response(
+        &mut self,
+        request: Option<Request>,
+        response: Response,
+    ) -> HandleConnectionState {
+        let request = request.map(|v| Rc::new(RefCell::new(v)));
+        let server = self.server;
+        let mut response = match &request {
+            Some(request) => server.finalize_response(request, response, &mut self.timing),
+            None => response,
+        };
+
+        let max_response_size = self.server.config.max_response_size;
+        let known_length = response.response_body().len().or_else(|| {
+            response
+                .get_header(names::CONTENT_LENGTH)
+                .and_then(|len| len.parse().ok())
+        });
+        if let (Some(max), Some(len)) = (max_response_size, known_length) {
+            if len > max {
+                error!(
+                    "Response to {} is {len} bytes, over the maximum of {max}",
+                    request_path(request.as_ref())
+                );
+                self.connection.stats.oversized_responses += 1;
+                response = self.server.error_response(
+                    request.as_ref().map(|request| request.borrow()).as_deref(),
+                    ResponseStatusCode::InternalServerError,
+                );
+            }
+        }
+
+        // a body of unknown length is framed with chunks, HTTP/1.0 clients read it until close
+        let is_http_1_1 = request
+            .as_ref()
+            .is_some_and(|request| request.borrow().version == HttpVersion::Http1_1);
+        let unknown_length =
+            response.body_reader().is_some() && !response.has_header(names::CONTENT_LENGTH);
+        if unknown_length && is_http_1_1 {
+            response.set_header(names::TRANSFER_ENCODING, "chunked");
+        }
+        let chunked = response
+            .get_header(names::TRANSFER_ENCODING)
+            .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+        if !chunked {
+            // there is nowhere to put them
+            response.remove_header(names::TRAILER);
+        }
+
+        let should_close = !self.persistent
+            || (unknown_length && !is_http_1_1)
+            // the rest of a timed out request may still be on its way
+            || *response.status_code() == ResponseStatusCode::RequestTimeout
+            || self.rejected
+            || self.served_requests_count == self.max_requests - 1
+            || request
+                .as_ref()
+                .is_some_and(|request| request.borrow().has_header(names::CONNECTION, Some("close")))
+            // HTTP/1.0 connections are only kept alive when asked for
+            || request.as_ref().is_some_and(|request| {
+                let request = request.borrow();
+                request.version == HttpVersion::Http1_0
+                    && !request.has_header(names::CONNECTION, Some("keep-alive"))
+            });
+
+        self.server.add_response_headers(
+            request.as_ref().map(|request| request.borrow()).as_deref(),
+            &mut response,
+            self.connection.is_tls(),
+        );
+
+        audit_response(&mut response, should_close, self.server.config.keep_alive);
+
+        // handlers answer HEAD like GET, the body is dropped here so the headers stay the same
+        let is_head = request
+            .as_ref()
+            .is_some_and(|request| request.borrow().method == RequestMethod::Head);
+        if is_head {
+            response.set_body(vec![]);
+        }
+
+        let bytes = response.as_bytes_with_format(&self.server.config.header_format);
+        let rate_limit = request.as_ref().and_then(|request| {
+            let request = request.borrow();
+            self.server
+                .config
+                .rate_limits
+                .iter()
+                .find(|rate_limit| has_path_prefix(request.path(), &rate_limit.path_prefix))
+        });
+        self.connection
+            .set_response_throttle(rate_limit.map(|rate_limit| {
+                Throttle::new(
+                    rate_limit.bytes_per_second,
+                    rate_limit.burst,
+                    self.server.clock.now(),
+                )
+            }));
+
+        // whatever is left of the request timeout, the upload deadline is over
+        self.connection.set_deadline(self.request_deadline());
+
+        let connection = &mut self.connection;
+        let bytes_out_before = connection.stats.bytes_out;
+        let mut cut_off = false;
+        let write_result = self.timing.measure(Phase::Write, || {
+            match response.response_body() {
+                ResponseBody::File(file, len) => connection
+                    .write_part(&bytes, false)
+                    .and_then(|_| connection.send_file(file, *len)),
+                ResponseBody::Reader(reader) => connection
+                    .write_part(&bytes, false)
+                    .and_then(|_| {
+                        let mut reader = reader.lock().unwrap();
+                        connection.send_reader(&mut *reader, chunked, max_response_size)
+                    })
+                    .and_then(|read| {
+                        // the client must not take a partial body for a whole one
+                        cut_off = max_response_size.is_some_and(|max| read > max);
+                        match (cut_off, chunked) {
+                            (true, _) => Ok(()),
+                            (false, true) => connection.write_part(&response.last_chunk(), true),
+                            (false, false) => connection.write_part(&[], true),
+                        }
+                    }),
+                ResponseBody::Bytes(_) => connection.write(&bytes),
+            }
+        });
+        let bytes_sent = self.connection.stats.bytes_out - bytes_out_before;
+        // added per response, so stats of long-lived connections show up before they close
+        self.server
+            .stats
+            .add(&std::mem::take(&mut self.connection.stats));
+        self.connection.set_response_throttle(None);
+        self.connection.set_deadline(None);
+        self.request_started = None;
+
+        if cut_off {
+            error!(
+                "Response to {} went over the maximum of {} bytes, closing the connection",
+                request_path(request.as_ref()),
+                max_response_size.unwrap_or_default()
+            );
+            self.connection.stats.oversized_responses += 1;
+        }
+
+        self.log_if_slow(request.as_ref(), &response, bytes_sent);
+
+        if let (Some(recorder), Some(request)) = (&self.server.recorder, &request) {
+            recorder.record(
+                &request.borrow(),
+                &response,
+                self.timing.total(),
+                bytes_sent,
+            );
+        }
+        if let Some(access_log) = &self.server.access_log {
+            access_log.log(&AccessLogEntry {
+                time: self.server.clock.system_time(),
+                peer_addr: self.connection.peer_addr(),
+                request: request.as_ref().map(|request| request.borrow()).as_deref(),
+                status: *response.status_code() as u16,
+                bytes_sent,
+                duration: self.timing.total(),
+            });
+        }
+        self.timing = RequestTiming::default();
+
+        if let Err(err) = write_result {
+            return HandleConnectionState::Error(err.kind());
+        }
+        if cut_off {
+            return HandleConnectionState::Close;
+        }
+
+        self.served_requests_count += 1;
+
+        if should_close {
+            HandleConnectionState::Close
+        } else {
+            HandleConnectionState::Read(None)
+        }
+    }
+
+    fn handle_request(&mut self, request: &mut Request) -> Response {
+        let server = self.server;
+        let secure = self.connection.is_tls();
+        server.config.request_headers.normalize(request);
+        if let Some(response) = server.normalize_url(request) {
+            return response;
+        }
+        if let Some(response) = server.apply_upgrade_policy(request) {
+            return response;
+        }
+
+        let response = self
+            .timing
+            .measure(Phase::Handler, || server.dispatch(request, secure));
+
+        // the handler cannot be interrupted, only its late response replaced
+        if self
+            .request_deadline()
+            .is_some_and(|deadline| server.clock.now() >= deadline)
+        {
+            warn!(
+                "Handling {} took longer than the request timeout",
+                request.url
+            );
+            self.connection.stats.timeouts += 1;
+            // the 503 itself gets sent without a deadline
+            self.request_started = None;
+            return server.error_response(Some(request), ResponseStatusCode::ServiceUnavailable);
+        }
+
+        response
+    }
+
+    fn request_deadline(&self) -> Option<Instant> {
+        let timeout = self.server.config.request_timeout?;
+
+        self.request_started.map(|started| started + timeout)
+    }
+
+    fn upload_deadline(&self) -> Option<Instant> {
+        let max_duration = self.server.config.max_upload_duration?;
+
+        self.upload_started.map(|started| started + max_duration)
+    }
+
+    fn upload_timed_out(&mut self, request: Option<Request>) -> HandleConnectionState {
+        debug!("Upload took too long");
+        self.connection.stats.timeouts += 1;
+        self.upload_started = None;
+        self.request_started = None;
+        self.connection.set_deadline(None);
+
+        HandleConnectionState::ClientError(request, ResponseStatusCode::RequestTimeout)
+    }
+
+    fn report_upload_progress(&self, request: &Request) {
+        if let Some(listener) = &self.server.upload_progress {
+            let total = match request.body_type() {
+                RequestBodyType::ContentLength => request.content_length(),
+                _ => None,
+            };
+
+            listener(
+                request,
+                UploadProgress {
+                    received: request.body.len(),
+                    total,
+                },
+            );
+        }
+    }
+
+    fn log_if_slow(
+        &self,
+        request: Option<&Rc<RefCell<Request>>>,
+        response: &Response,
+        bytes_sent: u64,
+    ) {
+        let Some(threshold) = self.server.config.slow_request_threshold else {
+            return;
+        };
+
+        let total = self.timing.total();
+        if total < threshold {
+            return;
+        }
+
+        let (phase, phase_duration) = self.timing.dominant_phase();
+
+        warn!(
+            "Slow request: {} -> {} took {total:?} (mostly {phase}: {phase_duration:?}), sent {bytes_sent} bytes",
+            request_path(request),
+            *response.status_code() as u16
+        );
+    }
+
+    fn client_error(
+        &mut self,
+        request: Option<Request>,
+        status_code: ResponseStatusCode,
+    ) -> HandleConnectionState {
+        self.rejected = true;
+        let response = match &request {
+            // passthrough clients only care about the status
+            Some(request) if self.server.is_passthrough(request) => {
+                Response::builder().status_code(status_code).get()
+            }
+            _ => self.server.error_response(request.as_ref(), status_code),
+        };
+        HandleConnectionState::SendResponse(request, response)
+    }
+}
+
+/// Err with the status to answer with when a rule ran over its budget.
+/// Limiters for the limits the rules set. Limiters of `previous` whose limit did not change
+/// are kept, so requests holding their slots still count after a reload.
+fn limiters_for_rules(rules: &Rules, previous: &[Arc<RouteLimiter>]) -> Vec<Arc<RouteLimiter>> {
+    rules
+        .concurrency_limits()
+        .into_iter()
+        .map(|limit| {
+            previous
+                .iter()
+                .find(|limiter| *limiter.limit() == limit)
+                .cloned()
+                .unwrap_or_else(|| Arc::new(RouteLimiter::new(limit)))
+        })
+        .collect()
+}
+
+/// Rules of `rules_path`, empty without one. Warnings are logged.
+fn load_rules(config: &ServerConfig) -> crate::Result<Rules> {
+    let Some(rules_path) = &config.rules_path else {
+        return Ok(Rules::default());
+    };
+
+    let rules = match config.rules_cache {
+        true => parse_file_cached(rules_path)?,
+        false => parse_file(rules_path)?,
+    };
+    for warning in rules.warnings() {
+        warn!("\n{}", format_warning_in_file(warning, &rules.file));
+    }
+
+    Ok(rules)
+}
+
+fn request_path(request: Option<&Rc<RefCell<Request>>>) -> String {
+    request.map_or("-".to_string(), |request| request.borrow().url.clone())
+}
+
+fn apply_rules(
+    rules: &Rules,
+    request: Rc<RefCell<Request>>,
+    response: Response,
+    options: &EvaluationOptions,
+) -> Result<Response, ResponseStatusCode> {
+    let out_response = Rc::new(RefCell::new(response));
+
+    let url = request.borrow().url.clone();
+
+    for (rule_index, rule) in rules.matching(&url) {
+        let started = Instant::now();
+        let result = rule.evaluate_with(request.clone(), out_response.clone(), options);
+        rules.record_evaluation(rule_index, started.elapsed());
+
+        match result {
+            Ok(RuleEvaluationResult::Continue) => {}
+            Ok(RuleEvaluationResult::Finish) => {
+                return Ok(Rc::try_unwrap(out_response).unwrap().into_inner());
+            }
+            Err(e) => {
+                let budget_exceeded = e.is_budget_exceeded();
+                error!(
+                    "Error during rule evaluation:\n{}",
+                    format_error_in_file(e, &rules.file)
+                );
+
+                if budget_exceeded {
+                    return Err(ResponseStatusCode::InternalServerError);
+                }
+                // todo: 500?
+            }
+        }
+    }
+
+    Ok(Rc::try_unwrap(out_response).unwrap().into_inner())
+}
+
+fn put_content(root: &str, content_path: &str, bytes: &[u8]) -> IoResult<()> {
+    let root_path = Path::new(root);
+    let path = root_path.join(content_path.trim_start_matches('/'));
+    let canonical_root_path = fs::canonicalize(root_path)?;
+
+    // The file itself may not exist yet, so check where its parent directory points to
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Err(std::io::Error::from(ErrorKind::PermissionDenied));
+    };
+    let canonical_parent = fs::canonicalize(parent)?;
+
+    if !canonical_parent.starts_with(canonical_root_path) {
+        return Err(std::io::Error::from(ErrorKind::PermissionDenied));
+    }
+
+    // writing follows a symlink at the final name, wherever it points to
+    let path = canonical_parent.join(file_name);
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        return Err(std::io::Error::from(ErrorKind::PermissionDenied));
+    }
+
+    fs::write(path, bytes)
+}
+
+/// Listener on the first of `address`'s socket addresses that can be bound,
+/// with the socket options from the config.
+pub(crate) fn bind_listener(address: &str, config: &ServerConfig) -> IoResult<TcpListener> {
+    let mut last_err = None;
+
+    for address in address.to_socket_addrs()? {
+        match bind_socket(address, config) {
+            Ok(listener) => return Ok(listener),
+            Err(err) => last_err = Some(err),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        std::io::Error::new(ErrorKind::InvalidInput, "Address resolved to nothing")
+    }))
+}
+
+fn bind_socket(address: SocketAddr, config: &ServerConfig) -> IoResult<TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    socket.set_reuse_address(config.reuse_addr)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(config.reuse_port)?;
+    #[cfg(not(unix))]
+    if config.reuse_port {
+        warn!("SO_REUSEPORT is not supported on this platform");
+    }
+    socket.bind(&address.into())?;
+    socket.listen(config.backlog.min(i32::MAX as u32) as i32)?;
+
+    Ok(socket.into())
+}
+
+/// Higher is more specific, None if `pattern` does not cover the Content-Type header value.
+pub(crate) fn content_type_specificity(pattern: &str, content_type: &str) -> Option<u8> {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    let (media_type, _) = essence.split_once('/')?;
+
+    match pattern.split_once('/') {
+        Some(("*", "*")) => Some(0),
+        Some((pattern_type, "*")) if pattern_type == media_type => Some(1),
+        _ if pattern == essence => Some(2),
+        _ => None,
+    }
+}
+
+/// Response with the content type guessed from the path of the url. Files are streamed as they are
+/// sent, so large ones are never held in memory.
+fn content_response(request: &Request, body: ResponseBody) -> Response {
+    let mut response = Response::builder()
+        .status_code(ResponseStatusCode::Ok)
+        .header(names::CONTENT_TYPE, &content_type_for(request.path()))
+        .get();
+    if let Some(len) = body.len() {
+        response.set_header(names::CONTENT_LENGTH, &len.to_string());
+    }
+    response.set_response_body(body);
+
+    response
+}
+
+/// Guessed from the extension, text is assumed to be UTF-8.
+fn content_type_for(path: &str) -> String {
+    match mime_guess::from_path(path).first() {
+        Some(mime) if mime.type_() == "text" => mime.essence_str().to_string() + "; charset=utf-8",
+        Some(mime) => mime.essence_str().to_string(),
+        None => "application/octet-stream".to_string(),
+    }
+}
+
+/// Last pass over an outgoing response, drops headers that contradict each other
+/// or the state of the connection.
+fn audit_response(response: &mut Response, should_close: bool, keep_alive_config: KeepAliveConfig) {
+    if should_close {
+        response.set_header(names::CONNECTION, "close");
+        response.remove_header(names::KEEP_ALIVE);
+    } else if let KeepAliveConfig::On {
+        timeout,
+        max_requests,
+        include_header: true,
+    } = keep_alive_config
+    {
+        response.set_header(
+            names::KEEP_ALIVE,
+            &format!("timeout={timeout}, max={max_requests}"),
+        );
+    }
+
+    let status_code = *response.status_code();
+
+    if status_code.is_informational() || status_code == ResponseStatusCode::NoContent {
+        response.remove_header(names::CONTENT_LENGTH);
+        response.remove_header(names::TRANSFER_ENCODING);
+        response.set_body(vec![]);
+    } else if response.has_header(names::TRANSFER_ENCODING) {
+        // RFC 9112, section 6.3: Transfer-Encoding overrides Content-Length
+        response.remove_header(names::CONTENT_LENGTH);
+    }
+}
+
+fn error_response(
+    request: Option<&Request>,
+    status_code: ResponseStatusCode,
+    renderer: Option<&ErrorRenderer>,
+) -> Response {
+    let mut response_builder = ResponseBuilder::new().status_code(status_code);
+
+    // no body at all if the client did not say what it accepts
+    let body_type = request
+        .and_then(|request| request.get_header(names::ACCEPT))
+        .and_then(|accept| {
+            negotiate(
+                Some(&accept),
+                &["text/html", "application/problem+json", "application/json"],
+            )
+        });
+
+    match body_type {
+        Some("text/html") => {
+            let text_body = match renderer {
+                Some(renderer) => renderer(status_code, request),
+                None => format!(
+                    "<html><body><h1 style='text-align: center'>{} {}</h1></body></html>",
+                    status_code as u16, status_code
+                ),
+            };
+            response_builder = response_builder
+                .header(names::CONTENT_TYPE, "text/html; charset=utf-8")
+                .text_body(&text_body)
+        }
+        Some(_) => {
+            // RFC 9457 problem details, "about:blank" type means the status code says it all
+            let mut json_body = format!(
+                "{{\"type\": \"about:blank\", \"title\": \"{}\", \"status\": {}",
+                status_code, status_code as u16
+            );
+            if let Some(request) = request {
+                json_body += &format!(", \"instance\": \"{}\"", escape_json(&request.url));
+            }
+            json_body += "}";
+
+            response_builder = response_builder
+                .header(names::CONTENT_TYPE, "application/problem+json")
+                .text_body(&json_body)
+        }
+        None => {}
+    }
+
+    let mut response = response_builder.get();
+
+    if request.is_some() {
+        // body depends on the Accept header
+        response.add_vary(names::ACCEPT);
+    }
+
+    response
+}
+
+fn options_response(request: &Request) -> Response {
+    let mut response_builder = ResponseBuilder::new().status_code(ResponseStatusCode::NoContent);
+
+    if request.url != "*" {
+        response_builder =
+            response_builder.header(names::ALLOW, &RequestMethod::safe_methods_str());
+    }
+
+    response_builder.get()
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "https")]
+    mod server_init_https {
+        use crate::server::init_https;
+        use crate::server_config::ServerConfig;
+        use crate::Error;
+
+        #[test]
+        fn err_if_could_not_load_certs() {
+            let config = ServerConfig {
+                https: true,
+                ..Default::default()
+            };
+
+            assert!(matches!(init_https(&config, &[]), Err(Error::Config(_))));
+        }
+
+        // Next 3 tests are most certainly not unit tests, but I'm not going to mock fs
+
+        #[test]
+        fn err_if_could_not_load_key() {
+            let config = ServerConfig {
+                https: true,
+                cert_path: Some("./test_files/keys/server.crt".to_string()),
+                key_path: Some("./test_files/keys/server.crt".to_string()),
+                ..Default::default()
+            };
+
+            assert!(matches!(
+                init_https(&config, &[]),
+                Err(Error::Config(message)) if message.contains("private key")
+            ));
+        }
+
+        #[test]
+        fn returns_config() {
+            let config = ServerConfig {
+                https: true,
+                cert_path: Some("./test_files/keys/server.crt".to_string()),
+                key_path: Some("./test_files/keys/server.key".to_string()),
+                ..Default::default()
+            };
+
+            assert!(init_https(&config, &[]).unwrap().is_some());
+        }
+
+        #[test]
+        fn offers_alpn_protocols_in_order() {
+            let config = ServerConfig {
+                https: true,
+                cert_path: Some("./test_files/keys/server.crt".to_string()),
+                key_path: Some("./test_files/keys/server.key".to_string()),
+                ..Default::default()
+            };
+
+            let tls_config = init_https(&config, &[b"h2", b"http/1.1"]).unwrap().unwrap();
+
+            assert_eq!(
+                tls_config.alpn_protocols,
+                vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+            );
+        }
+
+        #[test]
+        fn returns_none_if_https_is_disabled() {
+            let config = ServerConfig {
+                https: false,
+                cert_path: Some("./test_files/keys/server.crt".to_string()),
+                key_path: Some("./test_files/keys/server.key".to_string()),
+                ..Default::default()
+            };
+
+            assert!(init_https(&config, &[]).unwrap().is_none());
+        }
+    }
+
+    #[cfg(not(feature = "https"))]
+    mod server_init_https_without_tls {
+        use crate::server::init_https;
+        use crate::server_config::ServerConfig;
+        use crate::Error;
+
+        #[test]
+        fn config_error_if_https_is_enabled() {
+            let config = ServerConfig {
+                https: true,
+                ..Default::default()
+            };
+
+            assert!(matches!(init_https(&config, &[]), Err(Error::Config(_))));
+        }
+    }
+
+    mod put_content {
+        use crate::server::put_content;
+        use std::io::ErrorKind;
+        use std::path::Path;
+
+        #[test]
+        fn writes_file_inside_root() {
+            put_content("test_files/dir", "/put_content.txt", b"123").unwrap();
+
+            assert_eq!(
+                std::fs::read("test_files/dir/put_content.txt").unwrap(),
+                b"123"
+            );
+            std::fs::remove_file("test_files/dir/put_content.txt").unwrap();
+        }
+
+        #[test]
+        fn err_if_file_is_outside_root() {
+            assert!(
+                matches!(put_content("test_files/dir", "/../put_content.txt", b"123"), Err(e) if e.kind() == ErrorKind::PermissionDenied)
+            );
+            assert!(!Path::new("test_files/put_content.txt").exists());
+        }
+
+        #[test]
+        #[cfg(unix)]
+        fn err_if_file_is_symlink() {
+            let root = std::env::temp_dir().join(format!("http_rs_put_{}", std::process::id()));
+            std::fs::create_dir_all(&root).unwrap();
+            let outside =
+                std::env::temp_dir().join(format!("http_rs_put_{}.txt", std::process::id()));
+            std::fs::write(&outside, "outside").unwrap();
+            std::os::unix::fs::symlink(&outside, root.join("link.txt")).unwrap();
+
+            let result = put_content(root.to_str().unwrap(), "/link.txt", b"123");
+            let written = std::fs::read_to_string(&outside).unwrap();
+            std::fs::remove_dir_all(&root).unwrap();
+            std::fs::remove_file(&outside).unwrap();
+
+            assert!(matches!(result, Err(e) if e.kind() == ErrorKind::PermissionDenied));
+            assert_eq!(written, "outside");
+        }
+    }
+
+    mod write_content {
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use std::path::Path;
+
+        #[test]
+        fn writes_file_at_path_without_query() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files/dir")
+                    .static_writes(true)
+                    .get(),
+            ));
+            let write = |method: RequestMethod, url: &str| {
+                let request = Request::builder()
+                    .method(method)
+                    .url(url)
+                    .text_body("123")
+                    .get();
+                *server.write_content(&request).status_code()
+            };
+
+            assert_eq!(
+                write(RequestMethod::Put, "/write_content.txt?x=1"),
+                ResponseStatusCode::Created
+            );
+            assert!(Path::new("test_files/dir/write_content.txt").exists());
+            assert!(!Path::new("test_files/dir/write_content.txt?x=1").exists());
+
+            assert_eq!(
+                write(RequestMethod::Put, "/write_content.txt?x=2"),
+                ResponseStatusCode::NoContent
+            );
+            assert_eq!(
+                write(RequestMethod::Delete, "/write_content.txt?x=3"),
+                ResponseStatusCode::NoContent
+            );
+            assert!(!Path::new("test_files/dir/write_content.txt").exists());
+        }
+    }
+
+    mod bind_listener {
+        use crate::server::bind_listener;
+        use crate::server_config::ServerConfig;
+
+        #[test]
+        fn binds_with_socket_options() {
+            let config = ServerConfig {
+                reuse_port: true,
+                backlog: 16,
+                ..Default::default()
+            };
+            let listener = bind_listener("127.0.0.1:0", &config).unwrap();
+            let address = listener.local_addr().unwrap().to_string();
+
+            // SO_REUSEPORT lets a second listener share the port
+            #[cfg(unix)]
+            assert!(bind_listener(&address, &config).is_ok());
+            assert!(bind_listener(&address, &ServerConfig::default()).is_err());
+        }
+    }
+
+    mod content_response {
+        use crate::header::Headers;
+        use crate::http_version::HttpVersion;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+        use crate::response::ResponseBody;
+        use crate::server::content_response;
+        use std::fs::File;
+        use std::sync::Arc;
+
+        fn get_request(method: RequestMethod, url: &str) -> Request {
+            Request {
+                method,
+                url: url.to_string(),
+                version: HttpVersion::Http1_1,
+                headers: Headers::new(),
+                body: vec![],
+                trailers: Headers::new(),
+                peer_addr: None,
+            }
+        }
+
+        fn get_default_request(method: RequestMethod) -> Request {
+            get_request(method, "/index.html")
+        }
+
+        #[test]
+        fn adds_content_type_header() {
+            for (url, content_type) in [
+                ("/index.html", "text/html; charset=utf-8"),
+                ("/123", "application/octet-stream"),
+                ("/app.css?v=0123abcd", "text/css; charset=utf-8"),
+            ] {
+                let request = get_request(RequestMethod::Get, url);
+                let response = content_response(&request, ResponseBody::Bytes(vec![]));
+
+                assert_eq!(
+                    response.headers().get("Content-Type"),
+                    Some(&content_type.to_string())
+                );
+            }
+        }
+
+        #[test]
+        fn adds_content_length_header() {
+            let request = get_default_request(RequestMethod::Head);
+            let content_bytes = vec![b'1', b'2', b'3'];
+            let response = content_response(&request, ResponseBody::Bytes(content_bytes.clone()));
+
+            assert_eq!(
+                response.headers().get("Content-Length"),
+                Some(&content_bytes.len().to_string())
+            );
+        }
+
+        #[test]
+        fn streams_files_without_reading_them() {
+            let request = get_default_request(RequestMethod::Get);
+            let file = Arc::new(File::open("test_files/file.txt").unwrap());
+            let response = content_response(&request, ResponseBody::File(file, 1234));
+
+            assert_eq!(
+                response.headers().get("Content-Length"),
+                Some(&"1234".to_string())
+            );
+            assert_eq!(response.body_file().map(|(_, len)| len), Some(1234));
+            assert!(response.body().is_empty());
+        }
+
+        // the body of HEAD responses is dropped when they are sent
+        #[test]
+        fn is_the_same_for_get_and_head() {
+            let get = content_response(
+                &get_default_request(RequestMethod::Get),
+                ResponseBody::Bytes(vec![b'1', b'2', b'3']),
+            );
+            let head = content_response(
+                &get_default_request(RequestMethod::Head),
+                ResponseBody::Bytes(vec![b'1', b'2', b'3']),
+            );
+
+            assert_eq!(get.headers(), head.headers());
+            assert_eq!(get.body(), head.body());
+        }
+    }
+
+    mod audit_response {
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::audit_response;
+        use crate::server_config::KeepAliveConfig;
+
+        static KEEP_ALIVE: KeepAliveConfig = KeepAliveConfig::On {
+            timeout: 123,
+            max_requests: 231,
+            include_header: true,
+        };
+
+        #[test]
+        fn does_not_add_keep_alive_header_with_keep_alive_disabled() {
+            let mut response = Response::builder().get();
+            audit_response(&mut response, false, KeepAliveConfig::Off);
+
+            assert!(response.headers().get("Keep-Alive").is_none());
+        }
+
+        #[test]
+        fn does_not_add_keep_alive_header_with_keep_alive_include_header_false() {
+            let mut response = Response::builder().get();
+            audit_response(
+                &mut response,
+                false,
+                KeepAliveConfig::On {
+                    timeout: 123,
+                    max_requests: 231,
+                    include_header: false,
+                },
+            );
+
+            assert!(response.headers().get("Keep-Alive").is_none());
+        }
+
+        #[test]
+        fn adds_keep_alive_header_with_keep_alive_include_header_true() {
+            let mut response = Response::builder().get();
+            audit_response(&mut response, false, KEEP_ALIVE);
+
+            assert_eq!(
+                response.headers().get("Keep-Alive").unwrap(),
+                "timeout=123, max=231"
+            );
+        }
+
+        #[test]
+        fn removes_keep_alive_header_on_closed_connection() {
+            let mut response = Response::builder().header("keep-alive", "timeout=5").get();
+            audit_response(&mut response, true, KEEP_ALIVE);
+
+            assert!(!response.has_header("Keep-Alive"));
+            assert_eq!(response.headers().get("Connection").unwrap(), "close");
+        }
+
+        #[test]
+        fn transfer_encoding_wins_over_content_length() {
+            let mut response = Response::builder()
+                .header("Content-Length", "3")
+                .header("Transfer-Encoding", "chunked")
+                .get();
+            audit_response(&mut response, false, KeepAliveConfig::Off);
+
+            assert!(!response.has_header("Content-Length"));
+            assert!(response.has_header("Transfer-Encoding"));
+        }
+
+        #[test]
+        fn strips_body_from_no_content_response() {
+            let mut response = Response::builder()
+                .status_code(ResponseStatusCode::NoContent)
+                .text_body("123")
+                .get();
+            audit_response(&mut response, false, KeepAliveConfig::Off);
+
+            assert!(!response.has_header("Content-Length"));
+            assert!(response.body().is_empty());
+        }
+    }
+
+    mod error_response {
+        use crate::header::Headers;
+        use crate::http_version::HttpVersion;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::error_response;
+
+        fn get_request(accept: &str) -> Request {
+            Request {
+                method: RequestMethod::Get,
+                url: "/".to_string(),
+                version: HttpVersion::Http1_1,
+                headers: Headers::from([("Accept".to_string(), accept.to_string())]),
+                body: vec![],
+                trailers: Headers::new(),
+                peer_addr: None,
+            }
+        }
+
+        #[test]
+        fn empty_body_with_no_request() {
+            let response = error_response(None, ResponseStatusCode::NotFound, None);
+
+            assert!(response.body().is_empty());
+            assert_eq!(response.headers().get("Content-Length"), None);
+        }
+
+        #[test]
+        fn empty_body_if_does_not_accept_html() {
+            for accept in ["text/javascript", "image/webp", "text/html;q=0, image/*"] {
+                let response = error_response(
+                    Some(&get_request(accept)),
+                    ResponseStatusCode::NotFound,
+                    None,
+                );
+
+                assert!(response.body().is_empty());
+                assert_eq!(response.headers().get("Content-Length"), None);
+            }
+        }
+
+        #[test]
+        fn problem_json_in_body_if_prefers_json() {
+            for accept in [
+                "application/json, application/xml",
+                "text/html;q=0.5, application/json",
+                "application/problem+json",
+            ] {
+                let response = error_response(
+                    Some(&get_request(accept)),
+                    ResponseStatusCode::NotFound,
+                    None,
+                );
+
+                assert_eq!(
+                    response.headers().get("Content-Type"),
+                    Some(&"application/problem+json".to_string())
+                );
+                assert_eq!(
+                    std::str::from_utf8(response.body()).unwrap(),
+                    "{\"type\": \"about:blank\", \"title\": \"Not Found\", \"status\": 404, \"instance\": \"/\"}"
+                );
+            }
+        }
+
+        #[test]
+        fn renderer_replaces_html_body() {
+            let renderer = |status_code: ResponseStatusCode, request: Option<&Request>| {
+                format!("{} at {}", status_code as u16, request.unwrap().url)
+            };
+
+            let response = error_response(
+                Some(&get_request("text/html")),
+                ResponseStatusCode::NotFound,
+                Some(&renderer),
+            );
+            assert_eq!(std::str::from_utf8(response.body()).unwrap(), "404 at /");
+
+            let response = error_response(
+                Some(&get_request("application/json")),
+                ResponseStatusCode::NotFound,
+                Some(&renderer),
+            );
+            assert_eq!(
+                response.headers().get("Content-Type"),
+                Some(&"application/problem+json".to_string())
+            );
+        }
+
+        #[test]
+        fn default_html_in_body_if_accepts_html() {
+            for accept in ["*/*", "text/html", "application/json, text/*"] {
+                let response = error_response(
+                    Some(&get_request(accept)),
+                    ResponseStatusCode::NotFound,
+                    None,
+                );
+
+                assert!(!response.body().is_empty());
+                assert!(response.headers().get("Content-Length").is_some());
+            }
+        }
+    }
+
+    mod content_type_specificity {
+        use crate::server::content_type_specificity;
+
+        #[test]
+        fn matches_essence_ignoring_parameters_and_case() {
+            assert_eq!(
+                content_type_specificity("application/json", "Application/JSON; charset=utf-8"),
+                Some(2)
+            );
+        }
+
+        #[test]
+        fn wildcards_are_less_specific() {
+            assert_eq!(
+                content_type_specificity("application/*", "application/grpc-web"),
+                Some(1)
+            );
+            assert_eq!(content_type_specificity("*/*", "text/plain"), Some(0));
+        }
+
+        #[test]
+        fn does_not_match_other_types() {
+            assert_eq!(
+                content_type_specificity("application/json", "text/json"),
+                None
+            );
+            assert_eq!(
+                content_type_specificity("application/*", "text/plain"),
+                None
+            );
+            assert_eq!(content_type_specificity("text/plain", "garbage"), None);
+        }
+    }
+
+    mod https_redirect {
+        use crate::header::Headers;
+        use crate::http_version::HttpVersion;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::vhost::VirtualHost;
+
+        fn get_server() -> Server {
+            Server::new(Some(
+                ServerConfigBuilder::new()
+                    .virtual_host(VirtualHost::new("secure.test", "web").redirect_to_https())
+                    .virtual_host(VirtualHost::new("plain.test", "web"))
+                    .get(),
+            ))
+        }
+
+        fn get_request(method: RequestMethod, host: &str) -> Request {
+            Request {
+                method,
+                url: "/a?b=c".to_string(),
+                version: HttpVersion::Http1_1,
+                headers: Headers::from([("Host".to_string(), host.to_string())]),
+                body: vec![],
+                trailers: Headers::new(),
+                peer_addr: None,
+            }
+        }
+
+        #[test]
+        fn redirects_plain_requests() {
+            let request = get_request(RequestMethod::Get, "secure.test:80");
+            let response = get_server().https_redirect(&request, false).unwrap();
+
+            assert_eq!(
+                *response.status_code(),
+                ResponseStatusCode::MovedPermanently
+            );
+            assert_eq!(
+                response.headers().get("Location").unwrap(),
+                "https://secure.test/a?b=c"
+            );
+        }
+
+        #[test]
+        fn includes_non_default_https_port() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .https_port(8443)
+                    .virtual_host(VirtualHost::new("secure.test", "web").redirect_to_https())
+                    .get(),
+            ));
+            let request = get_request(RequestMethod::Get, "secure.test:8080");
+            let response = server.https_redirect(&request, false).unwrap();
+
+            assert_eq!(
+                response.headers().get("Location").unwrap(),
+                "https://secure.test:8443/a?b=c"
+            );
+        }
+
+        #[test]
+        fn keeps_method_for_unsafe_requests() {
+            let request = get_request(RequestMethod::Post, "secure.test");
+            let response = get_server().https_redirect(&request, false).unwrap();
+
+            assert_eq!(
+                *response.status_code(),
+                ResponseStatusCode::PermanentRedirect
+            );
+        }
+
+        #[test]
+        fn does_not_redirect_secure_or_other_hosts() {
+            let server = get_server();
+
+            assert!(server
+                .https_redirect(&get_request(RequestMethod::Get, "secure.test"), true)
+                .is_none());
+            assert!(server
+                .https_redirect(&get_request(RequestMethod::Get, "plain.test"), false)
+                .is_none());
+        }
+    }
+
+    mod passthrough_handler_for {
+        use crate::header::Headers;
+        use crate::http_version::HttpVersion;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+        use crate::response::Response;
+        use crate::server::Server;
+
+        fn get_request(url: &str) -> Request {
+            Request {
+                method: RequestMethod::Post,
+                url: url.to_string(),
+                version: HttpVersion::Http1_1,
+                headers: Headers::new(),
+                body: vec![],
+                trailers: Headers::new(),
+                peer_addr: None,
+            }
+        }
+
+        #[test]
+        fn matches_whole_path_segments() {
+            let server = Server::new(None).passthrough("/grpc", |_| Response::builder().get());
+
+            assert!(server.is_passthrough(&get_request("/grpc")));
+            assert!(server.is_passthrough(&get_request("/grpc/Service/Call?x=1")));
+            assert!(server.is_passthrough(&get_request("/grpc?x=1")));
+            assert!(!server.is_passthrough(&get_request("/grpcweb")));
+            assert!(!server.is_passthrough(&get_request("/other?next=/grpc")));
+        }
+    }
+
+    mod options_response {
+        use crate::header::Headers;
+        use crate::http_version::HttpVersion;
+        use crate::request::Request;
+        use crate::request_method::RequestMethod;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::options_response;
+
+        fn get_request(url: &str) -> Request {
+            Request {
+                method: RequestMethod::Options,
+                url: url.to_string(),
+                version: HttpVersion::Http1_1,
+                headers: Headers::new(),
+                body: vec![],
+                trailers: Headers::new(),
+                peer_addr: None,
+            }
+        }
+
+        #[test]
+        fn has_204_status_code() {
+            let response = options_response(&get_request("/"));
+
+            assert_eq!(response.status_code(), &ResponseStatusCode::NoContent);
+        }
+
+        #[test]
+        fn has_empty_body() {
+            let response = options_response(&get_request("/"));
+
+            assert_eq!(response.body().len(), 0);
+        }
+
+        #[test]
+        fn sets_allow_header_for_non_star_url() {
+            let response = options_response(&get_request("/a/b/index.html"));
+
+            assert_eq!(
+                response.headers().get("Allow"),
+                Some(&RequestMethod::safe_methods_str())
+            );
+        }
+
+        #[test]
+        fn does_not_set_allow_header_for_star_url() {
+            let response = options_response(&get_request("*"));
+
+            assert_eq!(response.headers().get("Allow"), None);
+        }
+    }
+
+    mod finalize_conditional {
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
+
+        fn get_server(rules: &str) -> Server {
+            // tests run in parallel, each needs its own file
+            let rules_path = std::env::temp_dir().join(format!(
+                "http_rs_finalize_conditional_{}.rules",
+                rules.len()
+            ));
+            std::fs::write(&rules_path, rules).unwrap();
+
+            Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .rules_path(rules_path.to_str().unwrap())
+                    .get(),
+            ))
+        }
+
+        fn written(server: &Server, request: &str) -> String {
+            let run = run_script(server, None, vec![ScriptStep::Send(request.into())]);
+
+            String::from_utf8_lossy(&run.written).to_string()
+        }
+
+        // not going to mock fs
+        #[test]
+        fn last_modified_set_by_rules_answers_if_modified_since() {
+            let server = get_server(
+                "matches /file.txt {\n    response.set_header(\"Last-Modified\", \"Sun, 06 Nov 1994 08:49:37 GMT\");\n}\n",
+            );
+
+            let fresh = written(
+                &server,
+                "GET /file.txt HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\nConnection: close\r\n\r\n",
+            );
+            assert!(fresh.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+            assert!(fresh.contains("Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+            assert!(!fresh.contains("Content-Length"));
+            assert!(fresh.ends_with("\r\n\r\n"));
+
+            let stale = written(
+                &server,
+                "GET /file.txt HTTP/1.1\r\nIf-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT\r\nConnection: close\r\n\r\n",
+            );
+            assert!(stale.starts_with("HTTP/1.1 200 OK\r\n"));
+        }
+
+        #[test]
+        fn static_files_answer_if_modified_since() {
+            let server = get_server("");
+            let modified = std::fs::metadata("test_files/file.txt")
+                .unwrap()
+                .modified()
+                .unwrap();
+            let last_modified = httpdate::fmt_http_date(modified);
+
+            let full = written(
+                &server,
+                "GET /file.txt HTTP/1.1\r\nConnection: close\r\n\r\n",
+            );
+            assert!(full.contains(&format!("Last-Modified: {last_modified}\r\n")));
+
+            let fresh = written(
+                &server,
+                &format!("GET /file.txt HTTP/1.1\r\nIf-Modified-Since: {last_modified}\r\nConnection: close\r\n\r\n"),
+            );
+            assert!(fresh.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+            assert!(fresh.contains("ETag: "));
+        }
+
+        #[test]
+        fn counts_rule_matches() {
+            let server = get_server("matches /file.txt {\n}\nmatches /other {\n}\n");
+
+            written(
+                &server,
+                "GET /file.txt HTTP/1.1\r\nConnection: close\r\n\r\n",
+            );
+            written(
+                &server,
+                "GET /dir/file.txt HTTP/1.1\r\nConnection: close\r\n\r\n",
+            );
+            let stats = server.rule_stats();
+
+            assert_eq!(stats[0].pattern, "/file.txt");
+            assert_eq!(stats[0].matches, 2);
+            assert_eq!(stats[1].matches, 0);
+        }
+    }
+
+    mod metrics_response {
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
+
+        #[test]
+        fn counts_earlier_responses_of_open_connection() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .metrics_path("/metrics")
+                    .get(),
+            ));
+
+            let run = run_script(
+                &server,
+                None,
+                vec![
+                    ScriptStep::Send(b"GET /file.txt HTTP/1.1\r\n\r\n".to_vec()),
+                    ScriptStep::Send(
+                        b"GET /metrics?x=1 HTTP/1.1\r\nConnection: close\r\n\r\n".to_vec(),
+                    ),
+                ],
+            );
+            let written = String::from_utf8_lossy(&run.written);
+            let metrics = written.rsplit("\r\n\r\n").next().unwrap();
 
-    response_builder.get()
-}
+            assert!(metrics.contains("\nhttp_rs_connections_total 1\n"));
+            assert!(!metrics.contains("\nhttp_rs_bytes_out_total 0\n"));
+        }
 
-fn options_response(request: &Request) -> Response {
-    let mut response_builder = ResponseBuilder::new().status_code(ResponseStatusCode::NoContent);
+        #[test]
+        fn only_answers_safe_methods() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new().metrics_path("/metrics").get(),
+            ));
+            let run = run_script(
+                &server,
+                None,
+                vec![ScriptStep::Send(
+                    b"POST /metrics HTTP/1.1\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_vec(),
+                )],
+            );
 
-    if request.url != "*" {
-        response_builder = response_builder.header("Allow", &RequestMethod::safe_methods_str());
+            assert!(run
+                .written
+                .starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+        }
     }
 
-    response_builder.get()
-}
+    mod serve_content {
+        use crate::concurrency_limit::ConcurrencyLimit;
+        use crate::request::Request;
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
 
-#[cfg(test)]
-mod test {
-    mod server_init_https {
-        use crate::server::init_https;
-        use crate::server_config::ServerConfig;
+        fn body(server: &Server, url: &str) -> String {
+            let request = format!("GET {url} HTTP/1.1\r\nConnection: close\r\n\r\n");
+            let run = run_script(server, None, vec![ScriptStep::Send(request.into())]);
+            let written = String::from_utf8_lossy(&run.written).to_string();
 
-        #[test]
-        #[should_panic(expected = "certificate")]
-        fn panic_if_could_not_load_certs() {
-            let config = ServerConfig {
-                https: true,
-                ..Default::default()
-            };
-            init_https(&config);
+            written.split("\r\n\r\n").nth(1).unwrap().to_string()
         }
 
-        // Next 3 tests are most certainly not unit tests, but I'm not going to mock fs
+        #[test]
+        fn listeners_are_tried_in_order_by_prefix() {
+            let server = Server::new(None)
+                .listener_at("/api", |request| {
+                    (request.url != "/api/skip").then(|| Response::builder().text_body("api").get())
+                })
+                .listener_at("/api/skip", |_| {
+                    Some(Response::builder().text_body("skip").get())
+                })
+                .listener(|_| Some(Response::builder().text_body("any").get()));
+
+            assert_eq!(body(&server, "/api/users"), "api");
+            assert_eq!(body(&server, "/api/skip"), "skip");
+            assert_eq!(body(&server, "/other"), "any");
+        }
 
         #[test]
-        #[should_panic(expected = "private")]
-        fn panic_if_could_not_load_key() {
-            let config = ServerConfig {
-                https: true,
-                cert_path: Some("./test_files/keys/server.crt".to_string()),
-                ..Default::default()
-            };
-            init_https(&config);
+        fn listener_prefixes_match_whole_path_segments() {
+            let server = Server::new(None)
+                .listener_at("/api", |_| Some(Response::builder().text_body("api").get()))
+                .listener(|_| Some(Response::builder().text_body("any").get()));
+
+            assert_eq!(body(&server, "/api?page=2"), "api");
+            assert_eq!(body(&server, "/apiv2"), "any");
+            assert_eq!(body(&server, "/other?next=/api"), "any");
         }
 
         #[test]
-        fn returns_config() {
-            let config = ServerConfig {
-                https: true,
-                cert_path: Some("./test_files/keys/server.crt".to_string()),
-                key_path: Some("./test_files/keys/server.key".to_string()),
-                ..Default::default()
+        fn holds_concurrency_limit_until_response_is_sent() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .concurrency_limit(ConcurrencyLimit::new("/keys", 1))
+                    .get(),
+            ));
+            let written = |url: &str| {
+                let request = format!("GET {url} HTTP/1.1\r\nConnection: close\r\n\r\n");
+                let run = run_script(&server, None, vec![ScriptStep::Send(request.into())]);
+                String::from_utf8_lossy(&run.written).to_string()
             };
 
-            assert!(init_https(&config).is_some());
+            let unsent = server.serve_content(&Request::builder().url("/keys/server.crt").get());
+            assert_eq!(*unsent.status_code(), ResponseStatusCode::Ok);
+            assert!(
+                written("//keys/server.crt").starts_with("HTTP/1.1 503 Service Unavailable\r\n")
+            );
+
+            drop(unsent);
+            assert!(written("//keys/server.crt").starts_with("HTTP/1.1 200 OK\r\n"));
         }
 
         #[test]
-        fn returns_none_if_https_is_disabled() {
-            let config = ServerConfig {
-                https: false,
-                cert_path: Some("./test_files/keys/server.crt".to_string()),
-                key_path: Some("./test_files/keys/server.key".to_string()),
-                ..Default::default()
+        fn holds_concurrency_limit_from_rules_across_reloads() {
+            let rules_path = std::env::temp_dir().join("http_rs_rule_concurrency_limit.rules");
+            std::fs::write(&rules_path, "matches /keys { concurrency_limit(1, 0, 1); }").unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .rules_path(rules_path.to_str().unwrap())
+                    .get(),
+            ));
+            let status = |url: &str| {
+                *server
+                    .serve_content(&Request::builder().url(url).get())
+                    .status_code()
             };
 
-            assert!(init_https(&config).is_none());
+            let unsent = server.serve_content(&Request::builder().url("/keys/server.crt").get());
+            assert_eq!(
+                status("/keys/server.crt"),
+                ResponseStatusCode::ServiceUnavailable
+            );
+            assert_eq!(status("/file.txt"), ResponseStatusCode::Ok);
+
+            server.reload_rules().unwrap();
+            assert_eq!(
+                status("/keys/server.crt"),
+                ResponseStatusCode::ServiceUnavailable
+            );
+
+            drop(unsent);
+            assert_eq!(status("/keys/server.crt"), ResponseStatusCode::Ok);
         }
     }
 
-    mod get_content {
-        // These tests are dumb but I'm not going to mock fs
-        use crate::server::get_content;
-        use std::io::ErrorKind;
+    mod apply_upgrade_policy {
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::Server;
+        use crate::server_config::{ServerConfigBuilder, UpgradePolicy};
+        use crate::testing::{run_script, ScriptStep};
+
+        // the listener answers with the Upgrade and Connection headers it saw
+        fn written(upgrade: UpgradePolicy) -> String {
+            let server = Server::new(Some(ServerConfigBuilder::new().upgrade(upgrade).get()))
+                .listener(|request| {
+                    let seen = format!(
+                        "{:?} {:?}",
+                        request.get_header("Upgrade"),
+                        request.get_header("Connection")
+                    );
+                    Some(Response::builder().text_body(&seen).get())
+                });
+            let request =
+                "GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade, close\r\n\r\n";
+            let run = run_script(&server, None, vec![ScriptStep::Send(request.into())]);
+
+            String::from_utf8_lossy(&run.written).to_string()
+        }
 
         #[test]
-        fn ok_if_file_exists() {
-            assert!(get_content("test_files", "file.txt").is_ok());
+        fn honor_passes_headers_to_handlers() {
+            let written = written(UpgradePolicy::Honor);
+
+            assert!(written.ends_with("Some(\"websocket\") Some(\"Upgrade, close\")"));
         }
 
         #[test]
-        fn ok_if_file_does_not_exist() {
-            assert!(get_content("test_files", "0qhwe0t9h.txt").is_err());
+        fn strip_drops_upgrade_by_default() {
+            let written = written(UpgradePolicy::default());
+
+            assert!(written.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(written.ends_with("None Some(\"close\")"));
         }
 
         #[test]
-        fn err_if_file_is_outside_root() {
-            assert!(
-                matches!(get_content("test_files/dir", "/../file.txt"), Err(e) if e.kind() == ErrorKind::PermissionDenied)
-            );
+        fn reject_answers_before_handlers() {
+            let written = written(UpgradePolicy::Reject(ResponseStatusCode::UpgradeRequired));
+
+            assert!(written.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
         }
     }
 
-    mod content_response {
-        use crate::header::Headers;
-        use crate::http_version::HttpVersion;
-        use crate::request::Request;
-        use crate::request_method::RequestMethod;
-        use crate::server::content_response;
-        use crate::server_config::KeepAliveConfig;
+    mod compression {
+        use crate::compression::{gzip, CompressionConfig};
+        use crate::response::Response;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+        use std::time::UNIX_EPOCH;
 
-        fn get_request(method: RequestMethod, url: &str) -> Request {
-            Request {
-                method,
-                url: url.to_string(),
-                version: HttpVersion::Http1_1,
-                headers: Headers::new(),
-                body: vec![],
+        // head and decoded body
+        fn get(server: &Server, url: &str, accept_encoding: &str) -> (String, String) {
+            let request = format!(
+                "GET {url} HTTP/1.1\r\nAccept-Encoding: {accept_encoding}\r\nConnection: close\r\n\r\n"
+            );
+            let run = run_script(server, None, vec![ScriptStep::Send(request.into())]);
+            let split = run
+                .written
+                .windows(4)
+                .position(|w| w == b"\r\n\r\n")
+                .unwrap()
+                + 4;
+            let head = String::from_utf8_lossy(&run.written[..split]).to_string();
+            let mut body = String::new();
+
+            if head.contains("Content-Encoding: gzip\r\n") {
+                GzDecoder::new(&run.written[split..])
+                    .read_to_string(&mut body)
+                    .unwrap();
+            } else {
+                body = String::from_utf8_lossy(&run.written[split..]).to_string();
             }
+
+            (head, body)
         }
 
-        fn get_default_request(method: RequestMethod) -> Request {
-            get_request(method, "/index.html")
+        #[test]
+        fn compresses_bodies_for_clients_accepting_gzip() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .compression(CompressionConfig::new())
+                    .get(),
+            ))
+            .listener(|_| Some(Response::builder().text_body(&"a".repeat(2000)).get()));
+
+            let (head, body) = get(&server, "/", "gzip, deflate");
+            assert!(head.contains("Content-Encoding: gzip\r\n"));
+            assert!(head.contains("Vary: Accept-Encoding\r\n"));
+            assert_eq!(body, "a".repeat(2000));
+
+            let (head, body) = get(&server, "/", "identity");
+            assert!(!head.contains("Content-Encoding"));
+            assert!(head.contains("Vary: Accept-Encoding\r\n"));
+            assert_eq!(body, "a".repeat(2000));
         }
 
         #[test]
-        fn adds_content_type_header() {
-            for (url, content_type) in [
-                ("/index.html", "text/html; charset=utf-8"),
-                ("/123", "application/octet-stream"),
-            ] {
-                let request = get_request(RequestMethod::Get, url);
-                let response = content_response(&request, vec![], KeepAliveConfig::Off);
+        fn skips_small_and_compressed_bodies() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .compression(CompressionConfig::new().min_size(100))
+                    .get(),
+            ))
+            .listener(|request| {
+                let response = match request.url.as_str() {
+                    "/small" => Response::builder().text_body(&"a".repeat(99)),
+                    "/image" => Response::builder()
+                        .header("Content-Type", "image/png")
+                        .body(vec![b'a'; 2000]),
+                    _ => Response::builder()
+                        .text_body(&"a".repeat(2000))
+                        .header("Content-Encoding", "identity"),
+                };
 
-                assert_eq!(
-                    response.headers().get("Content-Type"),
-                    Some(&content_type.to_string())
-                );
+                Some(response.get())
+            });
+
+            for url in ["/small", "/image", "/encoded"] {
+                let (head, _) = get(&server, url, "gzip");
+                assert!(!head.contains("Content-Encoding: gzip"), "{url}");
             }
         }
 
         #[test]
-        fn adds_content_length_header() {
-            let request = get_default_request(RequestMethod::Head);
-            let content_bytes = vec![b'1', b'2', b'3'];
-            let response = content_response(&request, content_bytes.clone(), KeepAliveConfig::Off);
+        fn compresses_static_files_once() {
+            let cache_dir = std::env::temp_dir().join(format!(
+                "http_rs_server_compression_cache_{}",
+                std::process::id()
+            ));
+            let root = std::env::temp_dir().join(format!(
+                "http_rs_server_compression_root_{}",
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&cache_dir);
+            std::fs::create_dir_all(&root).unwrap();
+            let file = "a".repeat(2000);
+            std::fs::write(root.join("file.txt"), &file).unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root(root.to_str().unwrap())
+                    .compression(CompressionConfig::new().cache_dir(cache_dir.to_str().unwrap()))
+                    .get(),
+            ));
 
-            assert_eq!(
-                response.headers().get("Content-Length"),
-                Some(&content_bytes.len().to_string())
-            );
+            let (head, body) = get(&server, "/file.txt", "gzip");
+            assert!(head.contains("Content-Encoding: gzip\r\n"));
+            assert!(head.contains("-gzip\"\r\n"));
+            assert_eq!(body, file);
+            assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+            let (_, body) = get(&server, "/file.txt", "gzip");
+            assert_eq!(body, file);
+            assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 1);
+
+            std::fs::remove_dir_all(&cache_dir).unwrap();
+            std::fs::remove_dir_all(&root).unwrap();
         }
 
         #[test]
-        fn does_not_add_keep_alive_header_with_keep_alive_disabled() {
-            let request = get_default_request(RequestMethod::Get);
-            let response = content_response(&request, vec![], KeepAliveConfig::Off);
+        fn serves_precompressed_siblings() {
+            let root = std::env::temp_dir().join(format!(
+                "http_rs_server_precompressed_root_{}",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&root).unwrap();
+            let file = "console.log(1);".repeat(100);
+            std::fs::write(root.join("app.js"), &file).unwrap();
+            std::fs::write(root.join("app.js.gz"), gzip(file.as_bytes(), 6).unwrap()).unwrap();
+            std::fs::write(root.join("app.js.br"), "brotli").unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root(root.to_str().unwrap())
+                    .serve_precompressed(true)
+                    .get(),
+            ));
 
-            assert!(response.headers().get("Keep-Alive").is_none());
+            let (head, body) = get(&server, "/app.js", "gzip, br");
+            assert!(head.contains("Content-Encoding: br\r\n"));
+            assert!(head.contains("Content-Type: text/javascript"));
+            assert!(head.contains("Content-Length: 6\r\n"));
+            assert_eq!(body, "brotli");
+
+            let (head, body) = get(&server, "/app.js", "gzip");
+            assert!(head.contains("Content-Encoding: gzip\r\n"));
+            assert_eq!(body, file);
+
+            let (head, body) = get(&server, "/app.js", "identity");
+            assert!(!head.contains("Content-Encoding"));
+            assert!(head.contains("Vary: Accept-Encoding\r\n"));
+            assert_eq!(body, file);
+
+            // left behind by an older build
+            std::fs::File::options()
+                .write(true)
+                .open(root.join("app.js.br"))
+                .unwrap()
+                .set_modified(UNIX_EPOCH)
+                .unwrap();
+            let (head, _) = get(&server, "/app.js", "gzip, br");
+            assert!(head.contains("Content-Encoding: gzip\r\n"));
+
+            std::fs::remove_dir_all(&root).unwrap();
+        }
+    }
+
+    mod finalize_location {
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
+
+        fn written(config: ServerConfigBuilder, rules: &str) -> String {
+            let rules_path = std::env::temp_dir()
+                .join(format!("http_rs_finalize_location_{}.rules", rules.len()));
+            std::fs::write(&rules_path, rules).unwrap();
+            let server = Server::new(Some(config.rules_path(rules_path.to_str().unwrap()).get()));
+            let run = run_script(
+                &server,
+                None,
+                vec![ScriptStep::Send(
+                    b"GET /old HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_vec(),
+                )],
+            );
+
+            String::from_utf8_lossy(&run.written).to_string()
         }
 
         #[test]
-        fn does_not_add_keep_alive_header_with_keep_alive_include_header_false() {
-            let request = get_default_request(RequestMethod::Get);
-            let timeout = 123;
-            let max_requests = 231;
-            let response = content_response(
-                &request,
-                vec![],
-                KeepAliveConfig::On {
-                    timeout,
-                    max_requests,
-                    include_header: false,
-                },
+        fn encodes_location_from_rules() {
+            let written = written(
+                ServerConfigBuilder::new(),
+                "matches /old {\n    redirect 301 \"/new page\";\n}\n",
             );
 
-            assert!(response.headers().get("Keep-Alive").is_none());
+            assert!(written.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+            assert!(written.contains("Location: /new%20page\r\n"));
         }
 
         #[test]
-        fn adds_keep_alive_header_with_keep_alive_include_header_true() {
-            let request = get_default_request(RequestMethod::Get);
-            let timeout = 123;
-            let max_requests = 231;
-            let response = content_response(
-                &request,
-                vec![],
-                KeepAliveConfig::On {
-                    timeout,
-                    max_requests,
-                    include_header: true,
-                },
+        fn refuses_hosts_outside_of_allowlist() {
+            let config = || ServerConfigBuilder::new().redirect_host("*.example.com");
+
+            let allowed = written(
+                config(),
+                "matches /old {\n    redirect 302 \"https://www.example.com/\";\n}\n",
+            );
+            let refused = written(
+                config(),
+                "matches /old {\n    redirect 302 \"https://evil.com/phish\";\n}\n",
             );
 
-            assert_eq!(
-                response.headers().get("Keep-Alive").unwrap(),
-                &format!("timeout={timeout}, max={max_requests}")
+            assert!(allowed.contains("Location: https://www.example.com/\r\n"));
+            assert!(refused.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+            assert!(!refused.contains("evil.com"));
+        }
+    }
+
+    mod apply_rules {
+        use crate::rules::RuleBudget;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
+        use std::time::Duration;
+
+        #[test]
+        fn rule_over_budget_is_internal_server_error() {
+            let rules_path = std::env::temp_dir().join("http_rs_rule_budget.rules");
+            std::fs::write(
+                &rules_path,
+                "matches / {\n    response.set_header(\"A\", \"1\");\n    response.set_header(\"B\", \"2\");\n}\n",
+            )
+            .unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .rules_path(rules_path.to_str().unwrap())
+                    .rule_budget(RuleBudget {
+                        max_steps: 4,
+                        max_duration: Duration::from_secs(1),
+                        ..RuleBudget::default()
+                    })
+                    .get(),
+            ));
+            let run = run_script(
+                &server,
+                None,
+                vec![ScriptStep::Send(
+                    b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n".to_vec(),
+                )],
             );
+            let written = String::from_utf8_lossy(&run.written);
+
+            assert!(written.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+            assert!(!written.contains("\r\nA: 1\r\n"));
         }
+    }
+
+    mod self_check {
+        #[cfg(feature = "https")]
+        use crate::proxy::ProxyRoute;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::vhost::VirtualHost;
 
         #[test]
-        fn has_body_for_get_request() {
-            let request = get_default_request(RequestMethod::Get);
-            let response = content_response(&request, vec![b'1', b'2', b'3'], KeepAliveConfig::Off);
+        fn fails_on_missing_root() {
+            let server = Server::new(Some(ServerConfigBuilder::new().root("test_files").get()));
+            assert!(server.self_check().is_ok());
 
-            assert!(!response.body().is_empty());
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .virtual_host(VirtualHost::new("example.com", "test_files/missing"))
+                    .get(),
+            ));
+            let err = server.self_check().unwrap_err();
+            assert!(err.to_string().contains("test_files/missing"));
         }
 
         #[test]
-        fn has_no_body_for_non_get_request() {
-            let request = get_default_request(RequestMethod::Post);
-            let response = content_response(&request, vec![b'1', b'2', b'3'], KeepAliveConfig::Off);
+        fn fails_on_rules_that_did_not_load() {
+            let rules_path = std::env::temp_dir().join("http_rs_self_check.rules");
+            std::fs::write(&rules_path, "matches / {").unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .rules_path(rules_path.to_str().unwrap())
+                    .get(),
+            ));
 
-            assert!(response.body().is_empty());
+            let err = server.self_check().unwrap_err();
+            assert!(err.to_string().contains("http_rs_self_check.rules"));
+        }
+
+        #[cfg(feature = "https")]
+        #[test]
+        fn fails_on_proxy_without_trusted_certificates() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .proxy_route(ProxyRoute::new("/api", "https://10.0.0.1"))
+                    .get(),
+            ));
+
+            let err = server.self_check().unwrap_err();
+            assert!(err.to_string().contains("proxy for /api"));
+        }
+
+        #[test]
+        fn fails_on_unreadable_key() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root("test_files")
+                    .https(true)
+                    .cert_path("test_files/keys/server.crt")
+                    .key_path("test_files/keys/missing.key")
+                    .get(),
+            ));
+
+            let err = server.self_check().unwrap_err();
+            assert!(err.to_string().contains("test_files/keys/missing.key"));
         }
     }
 
-    mod error_response {
-        use crate::header::Headers;
-        use crate::http_version::HttpVersion;
-        use crate::request::Request;
-        use crate::request_method::RequestMethod;
-        use crate::response_status_code::ResponseStatusCode;
-        use crate::server::error_response;
+    #[cfg(feature = "https")]
+    mod check_certificates {
+        use crate::clock::ManualClock;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
 
-        fn get_request(accept: &str) -> Request {
-            Request {
-                method: RequestMethod::Get,
-                url: "/".to_string(),
-                version: HttpVersion::Http1_1,
-                headers: Headers::from([("Accept".to_string(), accept.to_string())]),
-                body: vec![],
-            }
+        const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+        fn server_at(date: &str, warned: Arc<Mutex<Vec<String>>>) -> Server {
+            let clock = ManualClock::at(httpdate::parse_http_date(date).unwrap());
+
+            Server::new(Some(
+                ServerConfigBuilder::new()
+                    .https(true)
+                    .cert_path("test_files/keys/server.crt")
+                    .get(),
+            ))
+            .clock(Arc::new(clock))
+            .certificate_expiry_listener(move |expiry| {
+                warned.lock().unwrap().push(expiry.cert_path.clone());
+            })
         }
 
+        // the certificate expires on Thu, 06 Jul 2023 18:36:10 GMT
         #[test]
-        fn empty_body_with_no_request() {
-            let response = error_response(None, ResponseStatusCode::NotFound);
+        fn reports_certificates_within_window() {
+            let warned = Arc::new(Mutex::new(vec![]));
 
-            assert!(response.body().is_empty());
-            assert_eq!(response.headers().get("Content-Length"), None);
+            let server = server_at("Sat, 01 Jul 2023 00:00:00 GMT", warned.clone());
+            assert!(server.check_certificates(DAY * 3).is_empty());
+            assert_eq!(server.check_certificates(DAY * 14).len(), 1);
+
+            let server = server_at("Sat, 01 Jun 2024 00:00:00 GMT", warned.clone());
+            assert_eq!(server.check_certificates(Duration::ZERO).len(), 1);
+
+            assert_eq!(
+                *warned.lock().unwrap(),
+                vec!["test_files/keys/server.crt", "test_files/keys/server.crt"]
+            );
         }
+    }
+
+    mod startup_summary {
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
 
         #[test]
-        fn empty_body_if_does_not_accept_html() {
-            for accept in [
-                "text/javascript",
-                "image/webp",
-                "application/json, application/xml",
-            ] {
-                let response =
-                    error_response(Some(&get_request(accept)), ResponseStatusCode::NotFound);
+        fn lists_listeners_root_and_rules() {
+            let server = Server::new(Some(ServerConfigBuilder::new().root("missing").get()));
+            let summary = server.startup_summary(
+                &[
+                    ("127.0.0.1:80".parse().unwrap(), false),
+                    ("127.0.0.1:443".parse().unwrap(), true),
+                ],
+                "a thread per connection",
+            );
 
-                assert!(response.body().is_empty());
-                assert_eq!(response.headers().get("Content-Length"), None);
-            }
+            assert!(summary.contains("listening: http://127.0.0.1:80, https://127.0.0.1:443\n"));
+            assert!(summary.contains("root: missing (missing)\n"));
+            assert!(summary.contains("tls: off\n"));
+            assert!(summary.contains("rules: none\n"));
+            assert!(summary.ends_with("workers: a thread per connection"));
         }
 
+        #[cfg(feature = "https")]
         #[test]
-        fn default_html_in_body_if_accepts_html() {
-            for accept in ["*/*", "text/html", "application/json, text/*"] {
-                let response =
-                    error_response(Some(&get_request(accept)), ResponseStatusCode::NotFound);
+        fn shows_certificate_expiry() {
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .https(true)
+                    .cert_path("test_files/keys/server.crt")
+                    .get(),
+            ));
+            let summary = server.startup_summary(&[], "a thread per connection");
 
-                assert!(!response.body().is_empty());
-                assert!(response.headers().get("Content-Length").is_some());
-            }
+            assert!(summary.contains(
+                "tls: on, test_files/keys/server.crt expired Thu, 06 Jul 2023 18:36:10 GMT\n"
+            ));
         }
     }
 
-    mod options_response {
-        use crate::header::Headers;
-        use crate::http_version::HttpVersion;
-        use crate::request::Request;
-        use crate::request_method::RequestMethod;
-        use crate::response_status_code::ResponseStatusCode;
-        use crate::server::options_response;
+    mod directory_redirect {
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
 
-        fn get_request(url: &str) -> Request {
-            Request {
-                method: RequestMethod::Options,
-                url: url.to_string(),
-                version: HttpVersion::Http1_1,
-                headers: Headers::new(),
-                body: vec![],
-            }
+        #[test]
+        fn serves_index_files_and_adds_trailing_slash() {
+            let root = std::env::temp_dir()
+                .join(format!("http_rs_server_index_root_{}", std::process::id()));
+            std::fs::create_dir_all(root.join("docs")).unwrap();
+            std::fs::create_dir_all(root.join("empty")).unwrap();
+            std::fs::create_dir_all(root.join("evil.com")).unwrap();
+            std::fs::write(root.join("docs/index.htm"), "<h1>Docs</h1>").unwrap();
+            std::fs::write(root.join("evil.com/index.html"), "").unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root(root.to_str().unwrap())
+                    .get(),
+            ));
+            let written = |url: &str| {
+                let request = format!("GET {url} HTTP/1.1\r\nConnection: close\r\n\r\n");
+                let run = run_script(&server, None, vec![ScriptStep::Send(request.into())]);
+                String::from_utf8_lossy(&run.written).to_string()
+            };
+
+            let index = written("/docs/");
+            assert!(index.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(index.contains("Content-Type: text/html; charset=utf-8\r\n"));
+            assert!(index.ends_with("\r\n\r\n<h1>Docs</h1>"));
+
+            let redirect = written("/docs?page=2");
+            assert!(redirect.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+            assert!(redirect.contains("Location: /docs/?page=2\r\n"));
+            let redirect = written("//evil.com");
+            assert!(redirect.starts_with("HTTP/1.1 301 Moved Permanently\r\n"));
+            assert!(redirect.contains("Location: /evil.com/\r\n"));
+
+            assert!(written("/empty").starts_with("HTTP/1.1 404 Not Found\r\n"));
+            assert!(written("/empty/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+            std::fs::remove_dir_all(&root).unwrap();
         }
+    }
+
+    mod read_content {
+        use crate::server::Server;
+        use crate::server_config::{ContentCacheConfig, ServerConfigBuilder};
+        use crate::testing::{run_script, ScriptStep};
 
         #[test]
-        fn has_204_status_code() {
-            let response = options_response(&get_request("/"));
+        fn serves_changed_files_from_content_cache() {
+            let root = std::env::temp_dir().join(format!(
+                "http_rs_server_content_cache_{}",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&root).unwrap();
+            std::fs::write(root.join("app.js"), "one").unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .root(root.to_str().unwrap())
+                    .content_cache(ContentCacheConfig::default())
+                    .get(),
+            ));
+            let written = || {
+                let request = "GET /app.js HTTP/1.1\r\nConnection: close\r\n\r\n";
+                let run = run_script(&server, None, vec![ScriptStep::Send(request.into())]);
+                String::from_utf8_lossy(&run.written).to_string()
+            };
 
-            assert_eq!(response.status_code(), &ResponseStatusCode::NoContent);
+            assert!(written().ends_with("\r\n\r\none"));
+            assert!(written().ends_with("\r\n\r\none"));
+            std::fs::write(root.join("app.js"), "three").unwrap();
+            assert!(written().ends_with("\r\n\r\nthree"));
+
+            std::fs::remove_dir_all(&root).unwrap();
         }
+    }
+
+    mod content_etag {
+        use crate::content_source::{Content, ContentBody};
+        use crate::etag::{strong_etag, weak_etag};
+        use crate::server::Server;
+        use std::fs::File;
+        use std::path::PathBuf;
+        use std::sync::Arc;
+        use std::time::UNIX_EPOCH;
 
         #[test]
-        fn has_empty_body() {
-            let response = options_response(&get_request("/"));
+        fn falls_back_to_weak_etag_without_caching_it() {
+            let server = Server::new(None);
+            let content = |body: ContentBody| Content {
+                path: PathBuf::from("test_files/file.txt"),
+                body,
+                // longer than the file, so reading it fails
+                len: 1 << 20,
+                modified: Some(UNIX_EPOCH),
+            };
+            let unreadable = content(ContentBody::File(Arc::new(
+                File::open("test_files/file.txt").unwrap(),
+            )));
 
-            assert_eq!(response.body().len(), 0);
+            assert_eq!(
+                server.content_etag(&unreadable, strong_etag),
+                weak_etag(1 << 20, Some(UNIX_EPOCH))
+            );
+            assert_eq!(
+                server.content_etag(
+                    &content(ContentBody::Memory(Arc::from(&b"123"[..]))),
+                    strong_etag
+                ),
+                strong_etag(b"123")
+            );
         }
+    }
+
+    mod static_response_for {
+        use crate::response::Response;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::static_response::StaticResponse;
+        use crate::testing::{run_script, ScriptStep};
 
         #[test]
-        fn sets_allow_header_for_non_star_url() {
-            let response = options_response(&get_request("/a/b/index.html"));
+        fn answers_before_listeners_and_rules() {
+            let rules_path = std::env::temp_dir().join("http_rs_static_response.rules");
+            std::fs::write(
+                &rules_path,
+                "matches / {\n    response.set_header(\"X-Rules\", \"1\");\n}\n",
+            )
+            .unwrap();
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .rules_path(rules_path.to_str().unwrap())
+                    .static_response(
+                        StaticResponse::new("/healthz")
+                            .status_code(ResponseStatusCode::Accepted)
+                            .body("ok"),
+                    )
+                    .get(),
+            ))
+            .listener(|_| Some(Response::builder().text_body("listener").get()));
+            let written = |request: &str| {
+                let run = run_script(&server, None, vec![ScriptStep::Send(request.into())]);
+                String::from_utf8_lossy(&run.written).to_string()
+            };
 
-            assert_eq!(
-                response.headers().get("Allow"),
-                Some(&RequestMethod::safe_methods_str())
+            let written_static =
+                written("POST /healthz?probe=1 HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(written_static.starts_with("HTTP/1.1 202 Accepted\r\n"));
+            assert!(written_static.ends_with("\r\n\r\nok"));
+            assert!(!written_static.contains("X-Rules"));
+
+            let written_other = written("GET /other HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(written_other.contains("\r\nX-Rules: 1\r\n"));
+            assert!(written_other.ends_with("\r\n\r\nlistener"));
+        }
+    }
+
+    mod reload_rules {
+        use crate::server::Server;
+        use crate::server_config::ServerConfigBuilder;
+        use crate::testing::{run_script, ScriptStep};
+
+        fn header_value(server: &Server) -> String {
+            let run = run_script(
+                server,
+                None,
+                vec![ScriptStep::Send(
+                    b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n".to_vec(),
+                )],
             );
+            let written = String::from_utf8_lossy(&run.written).to_string();
+
+            written
+                .split("\r\n")
+                .find_map(|line| line.strip_prefix("X-Version: "))
+                .unwrap_or_default()
+                .to_string()
         }
 
         #[test]
-        fn does_not_set_allow_header_for_star_url() {
-            let response = options_response(&get_request("*"));
+        fn swaps_rules_and_keeps_them_on_error() {
+            let rules_path = std::env::temp_dir().join("http_rs_reload_rules.rules");
+            let write_rules = |version: &str| {
+                std::fs::write(
+                    &rules_path,
+                    format!("matches / {{ response.set_header(\"X-Version\", \"{version}\"); }}"),
+                )
+                .unwrap()
+            };
+            write_rules("1");
+            let server = Server::new(Some(
+                ServerConfigBuilder::new()
+                    .rules_path(rules_path.to_str().unwrap())
+                    .get(),
+            ));
+            assert_eq!(header_value(&server), "1");
 
-            assert_eq!(response.headers().get("Allow"), None);
+            write_rules("2");
+            server.reload_rules().unwrap();
+            assert_eq!(header_value(&server), "2");
+
+            std::fs::write(&rules_path, "matches / {").unwrap();
+            assert!(server.reload_rules().is_err());
+            assert_eq!(header_value(&server), "2");
         }
     }
 }
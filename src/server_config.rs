@@ -1,6 +1,28 @@
+use crate::access_log::AccessLogConfig;
+use crate::auth_request::AuthRequest;
+use crate::compression::CompressionConfig;
+use crate::concurrency_limit::ConcurrencyLimit;
+use crate::hotlink::HotlinkProtection;
+use crate::proxy::ProxyRoute;
+use crate::rate_limit::RateLimit;
+use crate::request_headers::RequestHeadersConfig;
+use crate::response::HeaderFormat;
+use crate::response_status_code::ResponseStatusCode;
+use crate::rules::RuleBudget;
+use crate::signed_url::SignedUrlConfig;
+use crate::static_response::{StaticResponse, StaticResponseBody};
+use crate::vhost::VirtualHost;
+#[cfg(feature = "https")]
+use rustls::sign::CertifiedKey;
+#[cfg(feature = "https")]
 use rustls_pemfile::Item;
+use std::collections::HashMap;
+#[cfg(feature = "https")]
 use std::fs;
+#[cfg(feature = "https")]
 use std::io::BufReader;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum KeepAliveConfig {
@@ -22,64 +44,591 @@ impl Default for KeepAliveConfig {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ETagConfig {
+    Off,
+    /// Built from file size and modification time, no need to read the whole file
+    #[default]
+    Weak,
+    /// Hash of file contents, cached until the file changes
+    Strong,
+    /// Like Strong, with SHA-256 instead of the faster, non-cryptographic xxh3
+    Sha256,
+}
+
+/// What /favicon.ico is answered with when there is no such file.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum FaviconFallback {
+    /// 404, like any other missing file
+    #[default]
+    Off,
+    /// A 1x1 transparent icon
+    Transparent,
+    /// 204 without a body
+    NoContent,
+}
+
+/// What requests asking to switch protocols with Upgrade, e.g. to WebSocket, get.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum UpgradePolicy {
+    /// Handlers see Upgrade and Connection as sent and decide what to answer.
+    /// `AsyncServer` with the `http2` feature switches to h2c itself
+    Honor,
+    /// Upgrade is dropped before handlers see the request, which is answered over HTTP/1.1
+    #[default]
+    Strip,
+    /// Answered with this status, usually 400 or 426, before any handler runs
+    Reject(ResponseStatusCode),
+}
+
+/// Warnings about certificates running out, see `ServerConfig::certificate_expiry`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CertificateExpiryConfig {
+    /// Certificates expiring within this long are warned about
+    pub warn_before: Duration,
+    pub check_interval: Duration,
+}
+
+impl CertificateExpiryConfig {
+    /// Warns two weeks ahead, checking every hour.
+    pub fn new() -> Self {
+        CertificateExpiryConfig {
+            warn_before: Duration::from_secs(14 * 24 * 60 * 60),
+            check_interval: Duration::from_secs(60 * 60),
+        }
+    }
+
+    pub fn warn_before(mut self, warn_before: Duration) -> Self {
+        self.warn_before = warn_before;
+
+        self
+    }
+
+    pub fn check_interval(mut self, check_interval: Duration) -> Self {
+        self.check_interval = check_interval;
+
+        self
+    }
+}
+
+impl Default for CertificateExpiryConfig {
+    fn default() -> Self {
+        CertificateExpiryConfig::new()
+    }
+}
+
+/// Controls how forgiving request parsing is.
+/// Strict mode follows the RFC, lenient mode tolerates what real-world clients tend to send.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParserConfig {
+    /// Accept bare LF as a line terminator
+    pub allow_lf_line_endings: bool,
+    /// Accept repeated or leading whitespace in the request line
+    pub allow_extra_whitespace: bool,
+    /// Accept methods in any casing, e.g. `get`
+    pub allow_lowercase_methods: bool,
+}
+
+impl ParserConfig {
+    pub fn strict() -> Self {
+        ParserConfig {
+            allow_lf_line_endings: false,
+            allow_extra_whitespace: false,
+            allow_lowercase_methods: false,
+        }
+    }
+
+    pub fn lenient() -> Self {
+        ParserConfig {
+            allow_lf_line_endings: true,
+            allow_extra_whitespace: true,
+            allow_lowercase_methods: true,
+        }
+    }
+}
+
+impl Default for ParserConfig {
+    fn default() -> Self {
+        ParserConfig::strict()
+    }
+}
+
 pub struct ServerConfig {
     pub root: String,
+    pub bind_address: String,
     pub port: u32,
     pub https: bool,
+    pub https_port: u32,
+    /// `bind_address` is used when None
+    pub https_bind_address: Option<String>,
+    /// SO_REUSEADDR on the listeners, lets a restarted server bind while old connections linger
+    pub reuse_addr: bool,
+    /// SO_REUSEPORT on the listeners, lets several processes share the port. Unix only
+    pub reuse_port: bool,
+    /// How many connections may wait to be accepted
+    pub backlog: u32,
+    /// TCP_NODELAY on accepted connections, small writes are sent without waiting for more
+    pub tcp_nodelay: bool,
     pub cert_path: Option<String>,
     pub key_path: Option<String>,
+    /// Certificates of the server and of virtual hosts are checked while HTTPS is served,
+    /// and logged and passed to `Server::certificate_expiry_listener` when close to expiring.
+    /// They are not checked when None
+    pub certificate_expiry: Option<CertificateExpiryConfig>,
     pub rules_path: Option<String>,
+    /// Keeps the parsed rules in `<rules_path>.cache`, so unchanged rules files are not parsed
+    /// again at startup
+    pub rules_cache: bool,
+    /// How often the rules file is checked for changes, it is never reloaded when None
+    pub rules_reload_interval: Option<Duration>,
+    /// Logs every change rules make to responses, see `EvaluationOptions::audit`
+    pub rules_audit_log: bool,
+    /// Rules running over it are stopped and the request is answered with 500
+    pub rule_budget: RuleBudget,
+    /// Values rules read with `secret("NAME")`, so they don't have to be kept in the rules file
+    pub rule_secrets: Arc<HashMap<String, String>>,
+    /// MaxMind database that `geoip_country` in rules looks addresses up in
+    #[cfg(feature = "geoip")]
+    pub geoip_database: Option<String>,
     pub keep_alive: KeepAliveConfig,
     pub timeout: u8,
+    /// Checked before the ones rules set with `concurrency_limit(..)`
+    pub concurrency_limits: Vec<ConcurrencyLimit>,
+    /// Bytes per second a connection sends at most, shared by all of its responses
+    pub connection_rate_limit: Option<u64>,
+    /// Per response limits, the first one matching the path applies
+    pub rate_limits: Vec<RateLimit>,
+    pub etag: ETagConfig,
+    pub favicon_fallback: FaviconFallback,
+    pub upgrade: UpgradePolicy,
+    pub serve_manifest: bool,
+    /// Serves the counters of `Server::stats` in the Prometheus text format at this path
+    pub metrics_path: Option<String>,
+    /// Served for urls ending in /, the first one the directory has.
+    /// `/dir` is redirected to `/dir/` when the directory has one
+    pub index_files: Vec<String>,
+    /// Static files are sent as their `.br` or `.gz` sibling to clients accepting that encoding,
+    /// if the sibling is at least as new as the file
+    pub serve_precompressed: bool,
+    /// Allows PUT and DELETE requests to create, replace and remove files in the web root.
+    /// If-Match uses strong comparison, so it only matches with ETagConfig::Strong or Sha256
+    pub static_writes: bool,
+    pub parser: ParserConfig,
+    /// Cleanup of request headers before handlers and rules see them
+    pub request_headers: RequestHeadersConfig,
+    pub header_format: HeaderFormat,
+    /// Requests taking longer than this are logged at warn level
+    pub slow_request_threshold: Option<Duration>,
+    /// Every request is written to it, along with its status, response size and duration
+    pub access_log: Option<AccessLogConfig>,
+    /// Largest response body the server sends, in bytes. Bodies of known length over it are
+    /// replaced with a 500, streamed ones are cut off and their connection closed
+    pub max_response_size: Option<u64>,
+    /// Hosts with their own web root, picked by the Host header. `root` is used when none matches
+    pub virtual_hosts: Vec<VirtualHost>,
+    /// GET responses for files at least this large are sent straight from disk (sendfile on Linux),
+    /// so rules see them with an empty body
+    pub sendfile_threshold: Option<u64>,
+    pub open_file_cache: Option<OpenFileCacheConfig>,
+    /// Contents of small static files kept in memory, they are read from disk on every request
+    /// when None
+    pub content_cache: Option<ContentCacheConfig>,
+    /// Gzip for clients that accept it, responses are sent as they are when None
+    pub compression: Option<CompressionConfig>,
+    /// Requests whose body takes longer than this to arrive are answered with 408,
+    /// regardless of how often the client sends something
+    pub max_upload_duration: Option<Duration>,
+    /// Limit on the time from receiving a request's head to writing the last byte of its response.
+    /// A body still arriving then is answered with 408, a handler still running with 503
+    /// once it returns, and a response still being written is cut off. Unlike `timeout`,
+    /// it does not run while a connection waits for its next request
+    pub request_timeout: Option<Duration>,
+    /// Requests matching one are served only if its subrequest succeeds, the longest prefix wins
+    pub auth_requests: Vec<AuthRequest>,
+    /// Paths only served through signed links, the first matching prefix applies
+    pub signed_urls: Vec<SignedUrlConfig>,
+    /// Media requested from other sites is blocked or redirected, the first matching one applies
+    pub hotlink_protections: Vec<HotlinkProtection>,
+    /// Requests matching a route are forwarded to its upstream, the longest prefix wins
+    pub proxy_routes: Vec<ProxyRoute>,
+    /// Sent with every response that does not set Alt-Svc itself
+    pub alt_svc: Option<AltSvcConfig>,
+    /// Served as /robots.txt, ahead of the file in the root directory
+    pub robots_txt: Option<String>,
+    /// Served as /.well-known/security.txt, ahead of the file in the root directory
+    pub security_txt: Option<String>,
+    /// Hosts that absolute Location headers may point to besides the request's own host,
+    /// "*.example.com" covers subdomains. Redirects anywhere are allowed when None
+    pub redirect_hosts: Option<Vec<String>>,
+    /// Fixed answers for single paths, served before handlers and rules
+    pub static_responses: Vec<StaticResponse>,
+}
+
+/// Alternative services advertised with Alt-Svc (RFC 7838), e.g. HTTP/3 on another port.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AltSvcConfig {
+    /// ALPN protocol ids with their authorities, e.g. ("h3", ":443").
+    /// Without any, clients are told to forget what was advertised before
+    pub services: Vec<(String, String)>,
+    /// Seconds clients may remember the services, 24 hours when None
+    pub max_age: Option<u64>,
+    /// Keep remembering the services when the client's network changes
+    pub persist: bool,
+}
+
+impl AltSvcConfig {
+    pub fn new() -> Self {
+        AltSvcConfig::default()
+    }
+
+    pub fn clear() -> Self {
+        AltSvcConfig::default()
+    }
+
+    pub fn service(mut self, protocol_id: &str, authority: &str) -> Self {
+        self.services
+            .push((protocol_id.to_string(), authority.to_string()));
+
+        self
+    }
+
+    pub fn max_age(mut self, max_age: u64) -> Self {
+        self.max_age = Some(max_age);
+
+        self
+    }
+
+    pub fn persist(mut self) -> Self {
+        self.persist = true;
+
+        self
+    }
+
+    pub fn header_value(&self) -> String {
+        if self.services.is_empty() {
+            return "clear".to_string();
+        }
+
+        let mut parameters = String::new();
+
+        if let Some(max_age) = self.max_age {
+            parameters += &format!("; ma={max_age}");
+        }
+
+        if self.persist {
+            parameters += "; persist=1";
+        }
+
+        self.services
+            .iter()
+            .map(|(protocol_id, authority)| format!("{protocol_id}=\"{authority}\"{parameters}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Keeps files open between requests, like nginx open_file_cache.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpenFileCacheConfig {
+    pub max_entries: usize,
+    /// How long an entry is trusted before the file is looked up again
+    pub valid: Duration,
+}
+
+impl Default for OpenFileCacheConfig {
+    fn default() -> Self {
+        OpenFileCacheConfig {
+            max_entries: 1000,
+            valid: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Sizes of `ServerConfig::content_cache`, in bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentCacheConfig {
+    /// Larger files are not cached
+    pub max_entry_size: u64,
+    /// Total size of the cached files
+    pub capacity: u64,
+}
+
+impl Default for ContentCacheConfig {
+    fn default() -> Self {
+        ContentCacheConfig {
+            max_entry_size: 64 * 1024,
+            capacity: 16 * 1024 * 1024,
+        }
+    }
 }
 
 impl Default for ServerConfig {
     fn default() -> Self {
         ServerConfig {
             root: String::from("web"),
+            bind_address: String::from("127.0.0.1"),
             port: 80,
             https: false,
+            https_port: 443,
+            https_bind_address: None,
+            reuse_addr: true,
+            reuse_port: false,
+            backlog: 128,
+            tcp_nodelay: false,
             cert_path: None,
             key_path: None,
+            certificate_expiry: None,
             rules_path: None,
+            rules_cache: false,
+            rules_reload_interval: None,
+            rules_audit_log: false,
+            rule_budget: RuleBudget::default(),
+            rule_secrets: Arc::new(HashMap::new()),
+            #[cfg(feature = "geoip")]
+            geoip_database: None,
             keep_alive: KeepAliveConfig::default(),
             timeout: 10,
+            concurrency_limits: vec![],
+            connection_rate_limit: None,
+            rate_limits: vec![],
+            etag: ETagConfig::default(),
+            favicon_fallback: FaviconFallback::default(),
+            upgrade: UpgradePolicy::default(),
+            serve_manifest: false,
+            metrics_path: None,
+            index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            serve_precompressed: false,
+            static_writes: false,
+            parser: ParserConfig::default(),
+            request_headers: RequestHeadersConfig::default(),
+            header_format: HeaderFormat::default(),
+            slow_request_threshold: None,
+            access_log: None,
+            max_response_size: None,
+            virtual_hosts: vec![],
+            sendfile_threshold: Some(1024 * 1024),
+            open_file_cache: None,
+            content_cache: None,
+            compression: None,
+            max_upload_duration: None,
+            request_timeout: None,
+            auth_requests: vec![],
+            signed_urls: vec![],
+            hotlink_protections: vec![],
+            proxy_routes: vec![],
+            alt_svc: None,
+            robots_txt: None,
+            security_txt: None,
+            redirect_hosts: None,
+            static_responses: vec![],
+        }
+    }
+}
+
+/// A setting of the config file, with its value in a `ServerConfig`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigSetting {
+    pub key: String,
+    pub description: &'static str,
+    /// As a TOML value, None for optional settings left unset
+    pub value: Option<String>,
+}
+
+impl ConfigSetting {
+    fn new(key: impl Into<String>, description: &'static str, value: impl ToString) -> Self {
+        ConfigSetting {
+            key: key.into(),
+            description,
+            value: Some(value.to_string()),
+        }
+    }
+
+    fn string(key: impl Into<String>, description: &'static str, value: Option<&str>) -> Self {
+        ConfigSetting {
+            key: key.into(),
+            description,
+            value: value.map(|value| format!("\"{value}\"")),
         }
     }
 }
 
 impl ServerConfig {
-    pub(crate) fn load_certs(&self) -> Vec<rustls::Certificate> {
-        if let Some(cert_path) = &self.cert_path {
-            let cert_file = fs::File::open(cert_path).expect("Could not open certificate file");
-            let mut reader = BufReader::new(cert_file);
-            rustls_pemfile::certs(&mut reader)
-                .unwrap()
-                .iter()
-                .map(|v| rustls::Certificate(v.clone()))
-                .collect()
-        } else {
-            vec![]
-        }
-    }
-
-    pub(crate) fn load_key(&self) -> Option<rustls::PrivateKey> {
-        let Some(key_path) = &self.key_path else {
-            return None
-        };
+    /// Every setting the config file can set, with its effective value.
+    pub fn schema(&self) -> Vec<ConfigSetting> {
+        let mut settings = vec![
+            ConfigSetting::string("root", "Directory files are served from", Some(&self.root)),
+            ConfigSetting::string(
+                "bind_address",
+                "Address of the plain HTTP listener",
+                Some(&self.bind_address),
+            ),
+            ConfigSetting::new("port", "Port of the plain HTTP listener", self.port),
+            ConfigSetting::new("https", "Whether the HTTPS listener is started", self.https),
+            ConfigSetting::new("https_port", "Port of the HTTPS listener", self.https_port),
+            ConfigSetting::string(
+                "https_bind_address",
+                "Address of the HTTPS listener, bind_address when unset",
+                self.https_bind_address.as_deref(),
+            ),
+            ConfigSetting::new(
+                "reuse_addr",
+                "SO_REUSEADDR on the listeners",
+                self.reuse_addr,
+            ),
+            ConfigSetting::new(
+                "reuse_port",
+                "SO_REUSEPORT on the listeners",
+                self.reuse_port,
+            ),
+            ConfigSetting::new(
+                "backlog",
+                "How many connections may wait to be accepted",
+                self.backlog,
+            ),
+            ConfigSetting::new(
+                "tcp_nodelay",
+                "TCP_NODELAY on accepted connections",
+                self.tcp_nodelay,
+            ),
+            ConfigSetting::string(
+                "cert_path",
+                "Certificate chain in PEM",
+                self.cert_path.as_deref(),
+            ),
+            ConfigSetting::string("key_path", "Private key in PEM", self.key_path.as_deref()),
+            ConfigSetting::string("rules_path", "Rules file", self.rules_path.as_deref()),
+            ConfigSetting::new(
+                "rules_cache",
+                "Keeps the parsed rules in <rules_path>.cache",
+                self.rules_cache,
+            ),
+            ConfigSetting::new(
+                "serve_manifest",
+                "Serves a manifest of the files in the root directory",
+                self.serve_manifest,
+            ),
+            ConfigSetting::string(
+                "metrics_path",
+                "Path the connection counters are served at",
+                self.metrics_path.as_deref(),
+            ),
+            ConfigSetting::new(
+                "serve_precompressed",
+                "Sends static files as their .br or .gz sibling to clients accepting it",
+                self.serve_precompressed,
+            ),
+            ConfigSetting::new(
+                "static_writes",
+                "Allows PUT and DELETE to change files in the root directory",
+                self.static_writes,
+            ),
+        ];
+
+        for static_response in &self.static_responses {
+            let prefix = format!("static_response.\"{}\"", static_response.path);
+            settings.push(ConfigSetting::new(
+                format!("{prefix}.status"),
+                "Status of a static response",
+                static_response.status_code as u16,
+            ));
+            for (name, value) in &static_response.headers {
+                settings.push(ConfigSetting::string(
+                    format!("{prefix}.headers.{name}"),
+                    "Header sent with a static response",
+                    Some(value),
+                ));
+            }
+            match &static_response.body {
+                Some(StaticResponseBody::Inline(body)) => settings.push(ConfigSetting::string(
+                    format!("{prefix}.body"),
+                    "Body of a static response",
+                    Some(body),
+                )),
+                Some(StaticResponseBody::File(path)) => settings.push(ConfigSetting::string(
+                    format!("{prefix}.file"),
+                    "File sent as the body of a static response",
+                    Some(path),
+                )),
+                None => {}
+            }
+        }
+
+        settings
+    }
 
-        let key_file = fs::File::open(key_path).expect("Could not open key file");
-        let mut reader = BufReader::new(key_file);
-        let Ok(Some(item)) = rustls_pemfile::read_one(&mut reader) else {
-            return None;
+    /// The settings of `schema` in the config file format, which is also valid TOML.
+    /// Unset ones are commented out.
+    pub fn dump(&self) -> String {
+        self.schema()
+            .iter()
+            .map(|setting| match &setting.value {
+                Some(value) => format!("# {}\n{} = {value}\n", setting.description, setting.key),
+                None => format!("# {}\n# {} =\n", setting.description, setting.key),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(feature = "https")]
+impl ServerConfig {
+    /// Certificate chain and private key of `cert_path` and `key_path`.
+    pub(crate) fn load_certified_key(&self) -> crate::Result<CertifiedKey> {
+        let (Some(cert_path), Some(key_path)) = (&self.cert_path, &self.key_path) else {
+            return Err(crate::Error::Config(
+                "HTTPS needs a certificate and a private key".to_string(),
+            ));
         };
 
-        match item {
-            Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key) => {
-                Some(rustls::PrivateKey(key))
-            }
-            _ => None,
+        certified_key(cert_path, key_path)
+    }
+}
+
+/// Certificate chain of `cert_path` with the private key of `key_path`.
+#[cfg(feature = "https")]
+pub(crate) fn certified_key(cert_path: &str, key_path: &str) -> crate::Result<CertifiedKey> {
+    let certs = load_certs(cert_path)?;
+    let key = rustls::sign::any_supported_type(&load_key(key_path)?).map_err(|_| {
+        crate::Error::Config(format!("{key_path} contains an unsupported private key"))
+    })?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// Fails unless the file holds at least one certificate.
+#[cfg(feature = "https")]
+pub(crate) fn load_certs(cert_path: &str) -> crate::Result<Vec<rustls::Certificate>> {
+    let cert_file = fs::File::open(cert_path)
+        .map_err(|err| crate::Error::Config(format!("Could not open {cert_path}: {err}")))?;
+    let mut reader = BufReader::new(cert_file);
+    let certs: Vec<rustls::Certificate> = rustls_pemfile::certs(&mut reader)
+        .map_err(|err| crate::Error::Config(format!("Could not read {cert_path}: {err}")))?
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect();
+
+    if certs.is_empty() {
+        return Err(crate::Error::Config(format!(
+            "{cert_path} does not contain a valid certificate"
+        )));
+    }
+
+    Ok(certs)
+}
+
+#[cfg(feature = "https")]
+pub(crate) fn load_key(key_path: &str) -> crate::Result<rustls::PrivateKey> {
+    let key_file = fs::File::open(key_path)
+        .map_err(|err| crate::Error::Config(format!("Could not open {key_path}: {err}")))?;
+    let mut reader = BufReader::new(key_file);
+
+    match rustls_pemfile::read_one(&mut reader) {
+        Ok(Some(Item::RSAKey(key) | Item::PKCS8Key(key) | Item::ECKey(key))) => {
+            Ok(rustls::PrivateKey(key))
         }
+        _ => Err(crate::Error::Config(format!(
+            "{key_path} does not contain a valid private key"
+        ))),
     }
 }
 
@@ -101,6 +650,12 @@ impl ServerConfigBuilder {
         self
     }
 
+    pub fn bind_address(mut self, bind_address: &str) -> Self {
+        self.server_config.bind_address = bind_address.to_string();
+
+        self
+    }
+
     pub fn port(mut self, port: u32) -> Self {
         self.server_config.port = port;
 
@@ -113,6 +668,42 @@ impl ServerConfigBuilder {
         self
     }
 
+    pub fn https_port(mut self, https_port: u32) -> Self {
+        self.server_config.https_port = https_port;
+
+        self
+    }
+
+    pub fn https_bind_address(mut self, https_bind_address: &str) -> Self {
+        self.server_config.https_bind_address = Some(https_bind_address.to_string());
+
+        self
+    }
+
+    pub fn reuse_addr(mut self, reuse_addr: bool) -> Self {
+        self.server_config.reuse_addr = reuse_addr;
+
+        self
+    }
+
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.server_config.reuse_port = reuse_port;
+
+        self
+    }
+
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.server_config.backlog = backlog;
+
+        self
+    }
+
+    pub fn tcp_nodelay(mut self, tcp_nodelay: bool) -> Self {
+        self.server_config.tcp_nodelay = tcp_nodelay;
+
+        self
+    }
+
     pub fn cert_path(mut self, cert_path: &str) -> Self {
         self.server_config.cert_path = Some(cert_path.to_string());
 
@@ -125,6 +716,12 @@ impl ServerConfigBuilder {
         self
     }
 
+    pub fn certificate_expiry(mut self, certificate_expiry: CertificateExpiryConfig) -> Self {
+        self.server_config.certificate_expiry = Some(certificate_expiry);
+
+        self
+    }
+
     pub fn rules_path(mut self, rules_path: &str) -> Self {
         self.server_config.rules_path = Some(rules_path.to_string());
 
@@ -137,7 +734,277 @@ impl ServerConfigBuilder {
         self
     }
 
+    pub fn concurrency_limit(mut self, concurrency_limit: ConcurrencyLimit) -> Self {
+        self.server_config
+            .concurrency_limits
+            .push(concurrency_limit);
+
+        self
+    }
+
+    pub fn connection_rate_limit(mut self, bytes_per_second: u64) -> Self {
+        self.server_config.connection_rate_limit = Some(bytes_per_second);
+
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.server_config.rate_limits.push(rate_limit);
+
+        self
+    }
+
+    pub fn etag(mut self, etag_config: ETagConfig) -> Self {
+        self.server_config.etag = etag_config;
+
+        self
+    }
+
+    pub fn favicon_fallback(mut self, favicon_fallback: FaviconFallback) -> Self {
+        self.server_config.favicon_fallback = favicon_fallback;
+
+        self
+    }
+
+    pub fn upgrade(mut self, upgrade: UpgradePolicy) -> Self {
+        self.server_config.upgrade = upgrade;
+
+        self
+    }
+
+    pub fn serve_manifest(mut self, serve_manifest: bool) -> Self {
+        self.server_config.serve_manifest = serve_manifest;
+
+        self
+    }
+
+    pub fn metrics_path(mut self, metrics_path: &str) -> Self {
+        self.server_config.metrics_path = Some(metrics_path.to_string());
+
+        self
+    }
+
+    /// Replaces the default index files, index.html and index.htm.
+    pub fn index_files(mut self, index_files: &[&str]) -> Self {
+        self.server_config.index_files = index_files.iter().map(|v| v.to_string()).collect();
+
+        self
+    }
+
+    pub fn serve_precompressed(mut self, serve_precompressed: bool) -> Self {
+        self.server_config.serve_precompressed = serve_precompressed;
+
+        self
+    }
+
+    pub fn static_writes(mut self, static_writes: bool) -> Self {
+        self.server_config.static_writes = static_writes;
+
+        self
+    }
+
+    pub fn parser(mut self, parser_config: ParserConfig) -> Self {
+        self.server_config.parser = parser_config;
+
+        self
+    }
+
+    pub fn header_format(mut self, header_format: HeaderFormat) -> Self {
+        self.server_config.header_format = header_format;
+
+        self
+    }
+
+    pub fn slow_request_threshold(mut self, threshold: Duration) -> Self {
+        self.server_config.slow_request_threshold = Some(threshold);
+
+        self
+    }
+
+    pub fn access_log(mut self, access_log: AccessLogConfig) -> Self {
+        self.server_config.access_log = Some(access_log);
+
+        self
+    }
+
+    pub fn max_response_size(mut self, size: u64) -> Self {
+        self.server_config.max_response_size = Some(size);
+
+        self
+    }
+
+    pub fn virtual_host(mut self, virtual_host: VirtualHost) -> Self {
+        self.server_config.virtual_hosts.push(virtual_host);
+
+        self
+    }
+
+    pub fn sendfile_threshold(mut self, threshold: Option<u64>) -> Self {
+        self.server_config.sendfile_threshold = threshold;
+
+        self
+    }
+
+    pub fn open_file_cache(mut self, open_file_cache: OpenFileCacheConfig) -> Self {
+        self.server_config.open_file_cache = Some(open_file_cache);
+
+        self
+    }
+
+    pub fn content_cache(mut self, content_cache: ContentCacheConfig) -> Self {
+        self.server_config.content_cache = Some(content_cache);
+
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.server_config.compression = Some(compression);
+
+        self
+    }
+
+    pub fn max_upload_duration(mut self, max_upload_duration: Duration) -> Self {
+        self.server_config.max_upload_duration = Some(max_upload_duration);
+
+        self
+    }
+
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.server_config.request_timeout = Some(request_timeout);
+
+        self
+    }
+
+    pub fn auth_request(mut self, auth_request: AuthRequest) -> Self {
+        self.server_config.auth_requests.push(auth_request);
+
+        self
+    }
+
+    pub fn signed_url(mut self, signed_url: SignedUrlConfig) -> Self {
+        self.server_config.signed_urls.push(signed_url);
+
+        self
+    }
+
+    pub fn hotlink_protection(mut self, hotlink_protection: HotlinkProtection) -> Self {
+        self.server_config
+            .hotlink_protections
+            .push(hotlink_protection);
+
+        self
+    }
+
+    pub fn robots_txt(mut self, robots_txt: &str) -> Self {
+        self.server_config.robots_txt = Some(robots_txt.to_string());
+
+        self
+    }
+
+    pub fn security_txt(mut self, security_txt: &str) -> Self {
+        self.server_config.security_txt = Some(security_txt.to_string());
+
+        self
+    }
+
+    pub fn static_response(mut self, static_response: StaticResponse) -> Self {
+        self.server_config.static_responses.push(static_response);
+
+        self
+    }
+
+    pub fn proxy_route(mut self, proxy_route: ProxyRoute) -> Self {
+        self.server_config.proxy_routes.push(proxy_route);
+
+        self
+    }
+
+    pub fn alt_svc(mut self, alt_svc: AltSvcConfig) -> Self {
+        self.server_config.alt_svc = Some(alt_svc);
+
+        self
+    }
+
+    pub fn request_headers(mut self, request_headers: RequestHeadersConfig) -> Self {
+        self.server_config.request_headers = request_headers;
+
+        self
+    }
+
+    pub fn redirect_host(mut self, host: &str) -> Self {
+        self.server_config
+            .redirect_hosts
+            .get_or_insert_with(Vec::new)
+            .push(host.to_string());
+
+        self
+    }
+
+    pub fn rules_cache(mut self) -> Self {
+        self.server_config.rules_cache = true;
+
+        self
+    }
+
+    pub fn rules_reload_interval(mut self, interval: Duration) -> Self {
+        self.server_config.rules_reload_interval = Some(interval);
+
+        self
+    }
+
+    pub fn rules_audit_log(mut self) -> Self {
+        self.server_config.rules_audit_log = true;
+
+        self
+    }
+
+    pub fn rule_budget(mut self, rule_budget: RuleBudget) -> Self {
+        self.server_config.rule_budget = rule_budget;
+
+        self
+    }
+
+    pub fn rule_secret(mut self, name: &str, value: &str) -> Self {
+        Arc::make_mut(&mut self.server_config.rule_secrets)
+            .insert(name.to_string(), value.to_string());
+
+        self
+    }
+
+    #[cfg(feature = "geoip")]
+    pub fn geoip_database(mut self, path: &str) -> Self {
+        self.server_config.geoip_database = Some(path.to_string());
+
+        self
+    }
+
     pub fn get(self) -> ServerConfig {
         self.server_config
     }
 }
+
+#[cfg(test)]
+mod test {
+    mod alt_svc_config {
+        use crate::server_config::AltSvcConfig;
+
+        #[test]
+        fn lists_services_with_parameters() {
+            let alt_svc = AltSvcConfig::new()
+                .service("h3", ":443")
+                .service("h2", "alt.example.com:8443")
+                .max_age(3600)
+                .persist();
+
+            assert_eq!(
+                alt_svc.header_value(),
+                "h3=\":443\"; ma=3600; persist=1, h2=\"alt.example.com:8443\"; ma=3600; persist=1"
+            );
+        }
+
+        #[test]
+        fn clear_without_services() {
+            assert_eq!(AltSvcConfig::clear().header_value(), "clear");
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use log::debug;
+use std::collections::HashMap;
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Open connections of a running server, so it can stop accepting new ones and wait for
+/// the ones it has.
+#[derive(Default)]
+pub(crate) struct ConnectionTracker {
+    stopping: AtomicBool,
+    next_id: AtomicU64,
+    // clones of the streams, their read halves get shut down when the server stops
+    connections: Mutex<HashMap<u64, Option<TcpStream>>>,
+    closed: Condvar,
+}
+
+impl ConnectionTracker {
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    /// The connection counts as open until the guard is dropped.
+    pub(crate) fn track(self: &Arc<Self>, stream: &TcpStream) -> ConnectionGuard {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let stream = stream.try_clone().ok();
+        let mut connections = self.connections.lock().unwrap();
+
+        // stop() may have swept the connections before this one got in
+        if self.is_stopping() {
+            shutdown_read(stream.as_ref());
+        }
+        connections.insert(id, stream);
+
+        ConnectionGuard {
+            tracker: self.clone(),
+            id,
+        }
+    }
+
+    /// Connections waiting for their next request see the end of the stream and close,
+    /// the ones handling a request close once the response is sent.
+    fn stop(&self) {
+        let connections = self.connections.lock().unwrap();
+        self.stopping.store(true, Ordering::SeqCst);
+
+        for stream in connections.values() {
+            shutdown_read(stream.as_ref());
+        }
+    }
+
+    fn wait_until_closed(&self, deadline: Option<Instant>) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+
+        while !connections.is_empty() {
+            connections = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    self.closed
+                        .wait_timeout(connections, deadline - now)
+                        .unwrap()
+                        .0
+                }
+                None => self.closed.wait(connections).unwrap(),
+            };
+        }
+
+        true
+    }
+}
+
+fn shutdown_read(stream: Option<&TcpStream>) {
+    if let Some(stream) = stream {
+        // the connection may be gone already
+        let _ = stream.shutdown(Shutdown::Read);
+    }
+}
+
+pub(crate) struct ConnectionGuard {
+    tracker: Arc<ConnectionTracker>,
+    id: u64,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.tracker.connections.lock().unwrap().remove(&self.id);
+        self.tracker.closed.notify_all();
+    }
+}
+
+/// Running server, returned by `Server::start`.
+pub struct ServerHandle {
+    tracker: Arc<ConnectionTracker>,
+    addresses: Vec<SocketAddr>,
+    accept_threads: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        tracker: Arc<ConnectionTracker>,
+        addresses: Vec<SocketAddr>,
+        accept_threads: Vec<JoinHandle<()>>,
+    ) -> Self {
+        ServerHandle {
+            tracker,
+            addresses,
+            accept_threads,
+        }
+    }
+
+    /// Addresses the server listens on.
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Blocks for as long as the server accepts connections.
+    pub fn wait(self) {
+        for accept_thread in self.accept_threads {
+            let _ = accept_thread.join();
+        }
+    }
+
+    /// Stops accepting connections and waits for every request that is being handled
+    /// to get its response. Requests that are still being received are dropped.
+    pub fn shutdown(self) {
+        self.stop(None);
+    }
+
+    /// Like `shutdown`, but gives up waiting after `timeout`.
+    /// Returns false if some connections were still open by then.
+    pub fn shutdown_timeout(self, timeout: Duration) -> bool {
+        self.stop(Some(Instant::now() + timeout))
+    }
+
+    fn stop(self, deadline: Option<Instant>) -> bool {
+        self.tracker.stop();
+
+        // accept() only returns with a connection, so give it one
+        for address in &self.addresses {
+            let _ = TcpStream::connect(address);
+        }
+        for accept_thread in self.accept_threads {
+            let _ = accept_thread.join();
+        }
+        debug!("Stopped listening for connections");
+
+        self.tracker.wait_until_closed(deadline)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::request::has_path_prefix;
+use crate::utils::{hex, hmac_sha256};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const EXPIRES_PARAM: &str = "expires";
+const SIGNATURE_PARAM: &str = "signature";
+
+/// Paths under `path_prefix` are only served through links made with `sign`, which stop
+/// working once they expire. Requests without a valid signature are answered with 403.
+///
+/// Links carry `expires`, in seconds since the Unix epoch, and `signature`, the hex
+/// HMAC-SHA256 of the path and `expires` joined with a newline, in their query.
+#[derive(Clone, PartialEq)]
+pub struct SignedUrlConfig {
+    pub path_prefix: String,
+    pub key: Vec<u8>,
+}
+
+impl SignedUrlConfig {
+    pub fn new(path_prefix: &str, key: &[u8]) -> Self {
+        SignedUrlConfig {
+            path_prefix: path_prefix.to_string(),
+            key: key.to_vec(),
+        }
+    }
+
+    /// `path` with the query that lets it through until `expires`.
+    pub fn sign(&self, path: &str, expires: SystemTime) -> String {
+        let expires = expires
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        format!(
+            "{path}?{EXPIRES_PARAM}={expires}&{SIGNATURE_PARAM}={}",
+            self.signature(path, expires)
+        )
+    }
+
+    /// Expects a normalized path without the query, prefixes match whole segments.
+    pub(crate) fn applies_to(&self, path: &str) -> bool {
+        has_path_prefix(path, &self.path_prefix)
+    }
+
+    pub(crate) fn verify(&self, url: &str, now: SystemTime) -> bool {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let query = query.split('#').next().unwrap_or_default();
+        let param = |name: &str| {
+            query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(param_name, _)| *param_name == name)
+                .map(|(_, value)| value)
+        };
+
+        let (Some(expires), Some(signature)) = (param(EXPIRES_PARAM), param(SIGNATURE_PARAM))
+        else {
+            return false;
+        };
+        let Ok(expires) = expires.parse::<u64>() else {
+            return false;
+        };
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+
+        now < expires && constant_time_eq(&self.signature(path, expires), signature)
+    }
+
+    fn signature(&self, path: &str, expires: u64) -> String {
+        hex(&hmac_sha256(
+            &self.key,
+            format!("{path}\n{expires}").as_bytes(),
+        ))
+    }
+}
+
+// how much of the signature matches must not show in the response time
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod test {
+    use crate::signed_url::SignedUrlConfig;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn verifies_signed_url_until_it_expires() {
+        let config = SignedUrlConfig::new("/downloads", b"key");
+        let expires = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        let url = config.sign("/downloads/file.zip", expires);
+
+        assert!(url.starts_with("/downloads/file.zip?expires=2000000000&signature="));
+        assert!(config.verify(&url, expires - Duration::from_secs(1)));
+        assert!(!config.verify(&url, expires));
+    }
+
+    #[test]
+    fn rejects_tampered_urls() {
+        let config = SignedUrlConfig::new("/downloads", b"key");
+        let expires = UNIX_EPOCH + Duration::from_secs(2_000_000_000);
+        let now = UNIX_EPOCH;
+        let url = config.sign("/downloads/file.zip", expires);
+
+        assert!(!config.verify("/downloads/file.zip", now));
+        assert!(!config.verify(&url.replace("file", "other"), now));
+        assert!(!config.verify(&url.replace("2000000000", "2000000001"), now));
+        assert!(!SignedUrlConfig::new("/downloads", b"other key").verify(&url, now));
+    }
+}
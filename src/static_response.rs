@@ -0,0 +1,147 @@
+use crate::header::names;
+use crate::request::Request;
+use crate::response::Response;
+use crate::response_status_code::ResponseStatusCode;
+use std::fs;
+
+/// Where the body of a `StaticResponse` comes from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum StaticResponseBody {
+    Inline(String),
+    /// Read on every request, so the file can change while the server runs
+    File(String),
+}
+
+/// A fixed answer for one path, e.g. a health check or a stub endpoint. Requests for it get
+/// the response whatever their method, before handlers and rules run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StaticResponse {
+    /// Matched without the query string
+    pub path: String,
+    pub status_code: ResponseStatusCode,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<StaticResponseBody>,
+}
+
+impl StaticResponse {
+    /// 200 without a body.
+    pub fn new(path: &str) -> Self {
+        StaticResponse {
+            path: path.to_string(),
+            status_code: ResponseStatusCode::Ok,
+            headers: vec![],
+            body: None,
+        }
+    }
+
+    pub fn status_code(mut self, status_code: ResponseStatusCode) -> Self {
+        self.status_code = status_code;
+
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+
+        self
+    }
+
+    pub fn body(mut self, body: &str) -> Self {
+        self.body = Some(StaticResponseBody::Inline(body.to_string()));
+
+        self
+    }
+
+    pub fn file(mut self, path: &str) -> Self {
+        self.body = Some(StaticResponseBody::File(path.to_string()));
+
+        self
+    }
+
+    pub(crate) fn matches(&self, request: &Request) -> bool {
+        request.url.split(['?', '#']).next() == Some(self.path.as_str())
+    }
+
+    /// Content-Type is guessed from the file name, or text/plain for inline bodies,
+    /// unless one of `headers` sets it.
+    pub(crate) fn response(&self) -> std::io::Result<Response> {
+        let (body, content_type) = match &self.body {
+            Some(StaticResponseBody::Inline(body)) => (
+                body.as_bytes().to_vec(),
+                Some("text/plain; charset=utf-8".to_string()),
+            ),
+            Some(StaticResponseBody::File(path)) => (
+                fs::read(path)?,
+                Some(
+                    mime_guess::from_path(path)
+                        .first_or_octet_stream()
+                        .essence_str()
+                        .to_string(),
+                ),
+            ),
+            None => (vec![], None),
+        };
+
+        let mut response = Response::builder().status_code(self.status_code).get();
+        if let Some(content_type) = content_type {
+            response.set_header(names::CONTENT_TYPE, &content_type);
+        }
+        for (name, value) in &self.headers {
+            response.set_header(name, value);
+        }
+        response.set_header(names::CONTENT_LENGTH, &body.len().to_string());
+        response.set_body(body);
+
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    mod response {
+        use crate::header::names;
+        use crate::response_status_code::ResponseStatusCode;
+        use crate::static_response::StaticResponse;
+
+        #[test]
+        fn sets_status_headers_and_body() {
+            let response = StaticResponse::new("/healthz")
+                .status_code(ResponseStatusCode::Accepted)
+                .header("Cache-Control", "no-store")
+                .body("ok")
+                .response()
+                .unwrap();
+
+            assert_eq!(*response.status_code(), ResponseStatusCode::Accepted);
+            assert_eq!(response.get_header("Cache-Control").unwrap(), "no-store");
+            assert_eq!(
+                response.get_header(names::CONTENT_TYPE).unwrap(),
+                "text/plain; charset=utf-8"
+            );
+            assert_eq!(response.get_header(names::CONTENT_LENGTH).unwrap(), "2");
+            assert_eq!(response.body(), b"ok");
+        }
+
+        #[test]
+        fn reads_file_bodies() {
+            let response = StaticResponse::new("/stub")
+                .header("Content-Type", "application/json")
+                .file("test_files/file.txt")
+                .response()
+                .unwrap();
+
+            assert_eq!(
+                response.get_header(names::CONTENT_TYPE).unwrap(),
+                "application/json"
+            );
+            assert_eq!(
+                response.body(),
+                &std::fs::read("test_files/file.txt").unwrap()
+            );
+            assert!(StaticResponse::new("/stub")
+                .file("test_files/missing.txt")
+                .response()
+                .is_err());
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters for a single connection, or summed over all connections when
+/// returned from `Server::stats`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionStats {
+    pub connections: u64,
+    pub tls_handshake_failures: u64,
+    pub parse_errors: u64,
+    pub timeouts: u64,
+    pub resets: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Responses that went over `ServerConfig::max_response_size`
+    pub oversized_responses: u64,
+}
+
+impl ConnectionStats {
+    /// One counter per field in the Prometheus text format, served at
+    /// `ServerConfig::metrics_path`.
+    pub fn to_prometheus(&self) -> String {
+        [
+            ("connections", "Accepted connections", self.connections),
+            (
+                "tls_handshake_failures",
+                "TLS handshakes that failed",
+                self.tls_handshake_failures,
+            ),
+            ("parse_errors", "Requests that could not be parsed", self.parse_errors),
+            ("timeouts", "Requests and responses that timed out", self.timeouts),
+            ("resets", "Connections reset by the client", self.resets),
+            ("bytes_in", "Bytes read from clients", self.bytes_in),
+            ("bytes_out", "Bytes written to clients", self.bytes_out),
+            (
+                "oversized_responses",
+                "Responses cut off at the maximum response size",
+                self.oversized_responses,
+            ),
+        ]
+        .iter()
+        .map(|(name, help, value)| {
+            format!(
+                "# HELP http_rs_{name}_total {help}\n# TYPE http_rs_{name}_total counter\nhttp_rs_{name}_total {value}\n"
+            )
+        })
+        .collect()
+    }
+}
+
+/// Aggregated counters shared by every connection thread.
+#[derive(Default)]
+pub(crate) struct StatsCounters {
+    connections: AtomicU64,
+    tls_handshake_failures: AtomicU64,
+    parse_errors: AtomicU64,
+    timeouts: AtomicU64,
+    resets: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    oversized_responses: AtomicU64,
+}
+
+impl StatsCounters {
+    pub(crate) fn add(&self, stats: &ConnectionStats) {
+        self.connections
+            .fetch_add(stats.connections, Ordering::Relaxed);
+        self.tls_handshake_failures
+            .fetch_add(stats.tls_handshake_failures, Ordering::Relaxed);
+        self.parse_errors
+            .fetch_add(stats.parse_errors, Ordering::Relaxed);
+        self.timeouts.fetch_add(stats.timeouts, Ordering::Relaxed);
+        self.resets.fetch_add(stats.resets, Ordering::Relaxed);
+        self.bytes_in.fetch_add(stats.bytes_in, Ordering::Relaxed);
+        self.bytes_out.fetch_add(stats.bytes_out, Ordering::Relaxed);
+        self.oversized_responses
+            .fetch_add(stats.oversized_responses, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            connections: self.connections.load(Ordering::Relaxed),
+            tls_handshake_failures: self.tls_handshake_failures.load(Ordering::Relaxed),
+            parse_errors: self.parse_errors.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            resets: self.resets.load(Ordering::Relaxed),
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            oversized_responses: self.oversized_responses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// How often a rule matched and how long it took to evaluate, summed over all requests.
+/// Returned from `Server::rule_stats` in the order the rules were declared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuleStats {
+    pub pattern: String,
+    pub matches: u64,
+    pub evaluation_time: Duration,
+}
+
+/// Per-rule counters, indexed like the rules they count.
+#[derive(Debug, Default)]
+pub(crate) struct RuleCounters {
+    matches: Vec<AtomicU64>,
+    // nanoseconds
+    evaluation_time: Vec<AtomicU64>,
+}
+
+impl RuleCounters {
+    pub(crate) fn new(rule_count: usize) -> Self {
+        RuleCounters {
+            matches: (0..rule_count).map(|_| AtomicU64::new(0)).collect(),
+            evaluation_time: (0..rule_count).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub(crate) fn record(&self, rule_index: usize, evaluation_time: Duration) {
+        self.matches[rule_index].fetch_add(1, Ordering::Relaxed);
+        self.evaluation_time[rule_index]
+            .fetch_add(evaluation_time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot<'a>(&self, patterns: impl Iterator<Item = &'a str>) -> Vec<RuleStats> {
+        patterns
+            .zip(self.matches.iter().zip(&self.evaluation_time))
+            .map(|(pattern, (matches, evaluation_time))| RuleStats {
+                pattern: pattern.to_string(),
+                matches: matches.load(Ordering::Relaxed),
+                evaluation_time: Duration::from_nanos(evaluation_time.load(Ordering::Relaxed)),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::stats::{ConnectionStats, RuleCounters, RuleStats, StatsCounters};
+    use std::time::Duration;
+
+    #[test]
+    fn sums_connection_stats() {
+        let counters = StatsCounters::default();
+        let stats = ConnectionStats {
+            connections: 1,
+            parse_errors: 1,
+            bytes_in: 100,
+            bytes_out: 200,
+            ..Default::default()
+        };
+
+        counters.add(&stats);
+        counters.add(&stats);
+
+        assert_eq!(
+            counters.snapshot(),
+            ConnectionStats {
+                connections: 2,
+                parse_errors: 2,
+                bytes_in: 200,
+                bytes_out: 400,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn formats_for_prometheus() {
+        let stats = ConnectionStats {
+            connections: 3,
+            bytes_out: 512,
+            ..Default::default()
+        };
+        let text = stats.to_prometheus();
+
+        assert!(text.starts_with(
+            "# HELP http_rs_connections_total Accepted connections\n# TYPE http_rs_connections_total counter\nhttp_rs_connections_total 3\n"
+        ));
+        assert!(text.contains("\nhttp_rs_bytes_out_total 512\n"));
+        assert!(text.contains("\nhttp_rs_parse_errors_total 0\n"));
+        assert_eq!(text.lines().count(), 8 * 3);
+    }
+
+    #[test]
+    fn sums_rule_evaluations() {
+        let counters = RuleCounters::new(2);
+
+        counters.record(1, Duration::from_millis(2));
+        counters.record(1, Duration::from_millis(3));
+
+        assert_eq!(
+            counters.snapshot(["/", "/api"].into_iter()),
+            vec![
+                RuleStats {
+                    pattern: "/".to_string(),
+                    ..Default::default()
+                },
+                RuleStats {
+                    pattern: "/api".to_string(),
+                    matches: 2,
+                    evaluation_time: Duration::from_millis(5),
+                },
+            ]
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::connection::ReadWrite;
 use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread::JoinHandle;
 
 pub struct MockReadWrite {
     pub(crate) read_buf: Vec<u8>,
@@ -18,7 +20,9 @@ impl Read for MockReadWrite {
 
 impl Write for MockReadWrite {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        todo!()
+        self.write_buf.extend_from_slice(buf);
+
+        Ok(buf.len())
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -35,3 +39,26 @@ impl ReadWrite for MockReadWrite {
         self
     }
 }
+
+/// Answers one connection per response, in order, and returns the requests it got.
+pub fn fake_upstream(responses: Vec<&'static str>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = std::thread::spawn(move || {
+        let mut requests = vec![];
+
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0u8; 4096];
+            let len = stream.read(&mut request).unwrap();
+            stream.write_all(response.as_bytes()).unwrap();
+
+            requests.push(String::from_utf8_lossy(&request[..len]).to_string());
+        }
+
+        requests
+    });
+
+    (endpoint, handle)
+}
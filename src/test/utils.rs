@@ -1,7 +1,8 @@
 use std::{sync::mpsc, thread, time::Duration};
 
 // https://github.com/rust-lang/rfcs/issues/2798
-fn panic_after<T, F>(d: Duration, f: F) -> T
+#[allow(dead_code)]
+pub fn panic_after<T, F>(d: Duration, f: F) -> T
 where
     T: Send + 'static,
     F: FnOnce() -> T,
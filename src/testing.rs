@@ -0,0 +1,688 @@
+//! Helpers for testing the parser and the connection state machine without sockets:
+//! replaying captured traffic and scripting clients, available with the `testing` feature.
+
+use crate::clock::ManualClock;
+use crate::connection::{Connection, ReadWrite};
+use crate::recorder::RecordedExchange;
+use crate::server::{HandleConnectionState, HandleConnectionStateMachine, Server};
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Response headers that differ between runs and are left out of the comparison.
+const VOLATILE_HEADERS: [&str; 1] = ["Date"];
+
+/// Traffic of a single connection: request bytes in the segments they arrived in,
+/// and every response byte the server sent back.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capture {
+    pub name: String,
+    pub request_segments: Vec<Vec<u8>>,
+    pub response: Vec<u8>,
+}
+
+impl Capture {
+    pub fn raw(name: &str, request: &[u8], response: &[u8]) -> Self {
+        Capture {
+            name: name.to_string(),
+            request_segments: vec![request.to_vec()],
+            response: response.to_vec(),
+        }
+    }
+
+    /// Each segment is handed to the server in a separate read.
+    pub fn segmented(name: &str, request_segments: &[&[u8]], response: &[u8]) -> Self {
+        Capture {
+            name: name.to_string(),
+            request_segments: request_segments
+                .iter()
+                .map(|segment| segment.to_vec())
+                .collect(),
+            response: response.to_vec(),
+        }
+    }
+}
+
+/// Rebuilds the wire format of a recorded exchange. Bodies truncated by the recorder
+/// stay truncated, so such exchanges are expected to differ.
+impl From<&RecordedExchange> for Capture {
+    fn from(exchange: &RecordedExchange) -> Self {
+        let mut request = format!(
+            "{} {} {}\r\n",
+            exchange.method, exchange.url, exchange.version
+        )
+        .into_bytes();
+        for (name, value) in &exchange.request_headers {
+            request.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+        }
+        request.extend_from_slice(b"\r\n");
+        request.extend_from_slice(&exchange.request_body.bytes);
+
+        let mut response = format!(
+            "{} {} {}\r\n",
+            exchange.version, exchange.status, exchange.status_text
+        )
+        .into_bytes();
+        for (name, value) in &exchange.response_headers {
+            response.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+        }
+        response.extend_from_slice(b"\r\n");
+        response.extend_from_slice(&exchange.response_body.bytes);
+
+        Capture {
+            name: format!("{} {}", exchange.method, exchange.url),
+            request_segments: vec![request],
+            response,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplayDiff {
+    pub name: String,
+    pub differences: Vec<String>,
+}
+
+/// Feeds every capture through the connection state machine of `server`, as if it came
+/// from a client, and returns the captures whose responses changed.
+pub fn replay(captures: &[Capture], server: &Server) -> Vec<ReplayDiff> {
+    let (persistent, max_requests) = server.persistence();
+
+    captures
+        .iter()
+        .filter_map(|capture| {
+            let steps = capture
+                .request_segments
+                .iter()
+                .map(|segment| ScriptStep::Send(segment.clone()))
+                .collect();
+            let mut stream = ScriptedStream::new(steps, None);
+            let mut connection = Connection::plain(&mut stream, persistent);
+            // errors surface as a missing or different response below
+            let _ = server.serve_connection(&mut connection, persistent, max_requests);
+
+            let differences = diff_responses(&capture.response, &stream.written);
+
+            (!differences.is_empty()).then(|| ReplayDiff {
+                name: capture.name.clone(),
+                differences,
+            })
+        })
+        .collect()
+}
+
+/// Sends `get_request`, which must be a GET, and the same request as HEAD, each on its own
+/// connection. Panics unless the HEAD response has the status line and headers of the GET
+/// one and no body.
+pub fn assert_head_matches_get(server: &Server, get_request: &[u8]) {
+    let head_request = [
+        b"HEAD",
+        get_request.strip_prefix(b"GET").expect("GET request"),
+    ]
+    .concat();
+    let respond = |request: &[u8]| {
+        let mut stream = ScriptedStream::new(vec![ScriptStep::Send(request.to_vec())], None);
+        let mut connection = Connection::plain(&mut stream, false);
+        let _ = server.serve_connection(&mut connection, false, 0);
+
+        split_responses(&stream.written)
+    };
+
+    let get = respond(get_request);
+    let head = respond(&head_request);
+    assert_eq!(get.len(), 1, "expected a single response to GET");
+    assert_eq!(head.len(), 1, "expected a single response to HEAD");
+
+    assert_eq!(head[0].status_line, get[0].status_line, "status line");
+    assert_eq!(head[0].headers, get[0].headers, "headers");
+    assert!(
+        head[0].body.is_empty(),
+        "HEAD response has a body: {:?}",
+        String::from_utf8_lossy(&head[0].body)
+    );
+}
+
+/// What a scripted client does when the server reads from the connection next.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptStep {
+    /// Bytes arriving together, handed out in a single read where they fit
+    Send(Vec<u8>),
+    /// Read failing, e.g. WouldBlock for a socket read timeout
+    Fail(ErrorKind),
+    /// Time passing after the previous step, needs the server to use the same clock
+    Advance(Duration),
+}
+
+/// Stream replaying a script, running out of steps looks like the client closing the connection.
+struct ScriptedStream {
+    steps: VecDeque<ScriptStep>,
+    clock: Option<Arc<ManualClock>>,
+    // a step ending exactly at the end of the read buffer needs an empty read,
+    // otherwise the connection would keep reading into the next step
+    step_ended: bool,
+    written: Vec<u8>,
+}
+
+impl ScriptedStream {
+    fn new(steps: Vec<ScriptStep>, clock: Option<Arc<ManualClock>>) -> Self {
+        ScriptedStream {
+            steps: steps.into(),
+            clock,
+            step_ended: false,
+            written: vec![],
+        }
+    }
+
+    fn advance_clock(&mut self) {
+        while let Some(ScriptStep::Advance(duration)) = self.steps.front() {
+            if let Some(clock) = &self.clock {
+                clock.advance(*duration);
+            }
+            self.steps.pop_front();
+        }
+    }
+}
+
+impl Read for ScriptedStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if std::mem::take(&mut self.step_ended) {
+            return Ok(0);
+        }
+
+        self.advance_clock();
+
+        let len = match self.steps.front_mut() {
+            None => return Ok(0),
+            Some(ScriptStep::Fail(kind)) => {
+                let kind = *kind;
+                self.steps.pop_front();
+                return Err(kind.into());
+            }
+            Some(ScriptStep::Send(bytes)) => {
+                let len = bytes.len().min(buf.len());
+                buf[..len].copy_from_slice(&bytes[..len]);
+                bytes.drain(..len);
+
+                if !bytes.is_empty() {
+                    return Ok(len);
+                }
+
+                len
+            }
+            Some(ScriptStep::Advance(_)) => unreachable!(),
+        };
+
+        self.steps.pop_front();
+        self.step_ended = len == buf.len();
+        // time spent after the bytes arrived, before the server reads again
+        self.advance_clock();
+
+        Ok(len)
+    }
+}
+
+impl Write for ScriptedStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ReadWrite for ScriptedStream {
+    fn as_read_mut(&mut self) -> &mut dyn Read {
+        self
+    }
+
+    fn as_write_mut(&mut self) -> &mut dyn Write {
+        self
+    }
+}
+
+/// Connection state with the request and response left out, status codes stand for responses.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    New,
+    Read { partial_request: bool },
+    SendResponse(u16),
+    ClientError(u16),
+    Close,
+    Error(ErrorKind),
+}
+
+impl From<&HandleConnectionState> for ConnectionState {
+    fn from(state: &HandleConnectionState) -> Self {
+        match state {
+            HandleConnectionState::New => ConnectionState::New,
+            HandleConnectionState::Read(request) => ConnectionState::Read {
+                partial_request: request.is_some(),
+            },
+            HandleConnectionState::SendResponse(_, response) => {
+                ConnectionState::SendResponse(*response.status_code() as u16)
+            }
+            HandleConnectionState::ClientError(_, status_code) => {
+                ConnectionState::ClientError(*status_code as u16)
+            }
+            HandleConnectionState::Close => ConnectionState::Close,
+            HandleConnectionState::Error(kind) => ConnectionState::Error(*kind),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScriptedRun {
+    /// Every state the connection went through, starting with New
+    pub states: Vec<ConnectionState>,
+    pub written: Vec<u8>,
+}
+
+/// Runs the connection state machine of `server` against a scripted client, without sockets.
+/// Pass the clock given to `Server::clock` for `ScriptStep::Advance` to have any effect.
+/// Stops after `MAX_TRANSITIONS` in case a change makes the state machine loop.
+pub fn run_script(
+    server: &Server,
+    clock: Option<Arc<ManualClock>>,
+    steps: Vec<ScriptStep>,
+) -> ScriptedRun {
+    const MAX_TRANSITIONS: usize = 1000;
+
+    let (persistent, max_requests) = server.persistence();
+    let mut stream = ScriptedStream::new(steps, clock);
+    let mut connection = Connection::plain(&mut stream, persistent);
+
+    let mut state = HandleConnectionState::New;
+    let mut states = vec![ConnectionState::from(&state)];
+    let mut state_machine =
+        HandleConnectionStateMachine::new(server, &mut connection, persistent, max_requests);
+
+    while states.len() < MAX_TRANSITIONS {
+        state = state_machine.next(state);
+        states.push(ConnectionState::from(&state));
+
+        if matches!(
+            state,
+            HandleConnectionState::Close | HandleConnectionState::Error(_)
+        ) {
+            break;
+        }
+    }
+
+    ScriptedRun {
+        states,
+        written: stream.written,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct WireResponse {
+    status_line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+fn header_value<'a>(headers: &'a [(String, String)], header_name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(header_name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Splits bytes written to a connection into responses. Bodies are delimited by
+/// Content-Length or the last chunk, otherwise they run until the end of the bytes.
+fn split_responses(mut bytes: &[u8]) -> Vec<WireResponse> {
+    let mut responses = vec![];
+
+    while !bytes.is_empty() {
+        let Some(head_len) = bytes.windows(4).position(|window| window == b"\r\n\r\n") else {
+            responses.push(WireResponse {
+                status_line: String::from_utf8_lossy(bytes).to_string(),
+                headers: vec![],
+                body: vec![],
+            });
+            break;
+        };
+
+        let head = String::from_utf8_lossy(&bytes[..head_len]).to_string();
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().unwrap_or_default().to_string();
+        let mut headers: Vec<(String, String)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.to_string(), value.trim().to_string()))
+            .filter(|(name, _)| {
+                !VOLATILE_HEADERS
+                    .iter()
+                    .any(|volatile| name.eq_ignore_ascii_case(volatile))
+            })
+            .collect();
+        headers.sort_by_key(|(name, _)| name.to_ascii_lowercase());
+
+        bytes = &bytes[head_len + 4..];
+
+        let body_len = if header_value(&headers, "Transfer-Encoding")
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"))
+        {
+            bytes
+                .windows(5)
+                .position(|window| window == b"0\r\n\r\n")
+                .map_or(bytes.len(), |position| position + 5)
+        } else {
+            header_value(&headers, "Content-Length")
+                .and_then(|value| value.parse::<usize>().ok())
+                .unwrap_or(bytes.len())
+        };
+        let body_len = body_len.min(bytes.len());
+
+        responses.push(WireResponse {
+            status_line,
+            headers,
+            body: bytes[..body_len].to_vec(),
+        });
+        bytes = &bytes[body_len..];
+    }
+
+    responses
+}
+
+fn diff_responses(expected: &[u8], actual: &[u8]) -> Vec<String> {
+    let expected = split_responses(expected);
+    let actual = split_responses(actual);
+    let mut differences = vec![];
+
+    if expected.len() != actual.len() {
+        differences.push(format!(
+            "expected {} responses, got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+
+    for (index, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+        if expected.status_line != actual.status_line {
+            differences.push(format!(
+                "response {index}: status line {:?} != {:?}",
+                expected.status_line, actual.status_line
+            ));
+        }
+
+        if expected.headers != actual.headers {
+            differences.push(format!(
+                "response {index}: headers {:?} != {:?}",
+                expected.headers, actual.headers
+            ));
+        }
+
+        if expected.body != actual.body {
+            differences.push(format!(
+                "response {index}: body {:?} != {:?}",
+                String::from_utf8_lossy(&expected.body),
+                String::from_utf8_lossy(&actual.body)
+            ));
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod test {
+    use crate::connection::Connection;
+    use crate::server::Server;
+    use crate::testing::{ScriptStep, ScriptedStream};
+
+    /// What the server currently answers, used as the captured response.
+    fn response_for(server: &Server, request: &[u8]) -> Vec<u8> {
+        let mut stream = ScriptedStream::new(vec![ScriptStep::Send(request.to_vec())], None);
+        let mut connection = Connection::plain(&mut stream, false);
+        let _ = server.serve_connection(&mut connection, false, 0);
+
+        stream.written
+    }
+
+    mod replay {
+        use crate::server::Server;
+        use crate::server_config::{KeepAliveConfig, ServerConfig};
+        use crate::testing::{replay, Capture};
+
+        fn get_server() -> Server {
+            Server::new(Some(ServerConfig {
+                root: "test_files".to_string(),
+                keep_alive: KeepAliveConfig::Off,
+                ..Default::default()
+            }))
+        }
+
+        // not going to mock fs
+        #[test]
+        fn matching_capture_has_no_diff() {
+            let server = get_server();
+            let request = b"GET /file.txt HTTP/1.1\r\n\r\n";
+            let captured = super::response_for(&server, request);
+
+            assert_eq!(
+                replay(&[Capture::raw("file", request, &captured)], &server),
+                vec![]
+            );
+        }
+
+        #[test]
+        fn reports_changed_status() {
+            let server = get_server();
+            let capture = Capture::raw(
+                "missing",
+                b"GET /0qhwe0t9h HTTP/1.1\r\n\r\n",
+                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOk",
+            );
+
+            let diffs = replay(&[capture], &server);
+
+            assert_eq!(diffs.len(), 1);
+            assert_eq!(diffs[0].name, "missing");
+            assert!(diffs[0].differences[0].contains("404"));
+        }
+
+        #[test]
+        fn segments_are_read_separately() {
+            let server = get_server();
+            let request: &[&[u8]] = &[
+                b"POST /0qhwe0t9h HTTP/1.1\r\nContent-Length: 2\r\n\r\n",
+                b"12",
+            ];
+            let captured = super::response_for(&server, &request.concat());
+
+            assert_eq!(
+                replay(&[Capture::segmented("post", request, &captured)], &server),
+                vec![]
+            );
+        }
+    }
+
+    mod run_script {
+        use crate::clock::ManualClock;
+        use crate::server::Server;
+        use crate::server_config::{KeepAliveConfig, ServerConfig};
+        use crate::testing::{run_script, ConnectionState, ScriptStep};
+        use std::io::ErrorKind;
+        use std::sync::Arc;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        fn send(bytes: &[u8]) -> ScriptStep {
+            ScriptStep::Send(bytes.to_vec())
+        }
+
+        #[test]
+        fn client_error_after_read_timeout() {
+            let server = Server::new(None);
+            let run = run_script(
+                &server,
+                None,
+                vec![
+                    send(b"GET / HTTP/1.1\r\n"),
+                    ScriptStep::Fail(ErrorKind::WouldBlock),
+                ],
+            );
+
+            assert_eq!(
+                run.states,
+                vec![
+                    ConnectionState::New,
+                    ConnectionState::Read {
+                        partial_request: false
+                    },
+                    ConnectionState::ClientError(408),
+                    ConnectionState::SendResponse(408),
+                    ConnectionState::Close,
+                ]
+            );
+        }
+
+        #[test]
+        fn idle_timeout_closes_without_response() {
+            let server = Server::new(None);
+            let run = run_script(&server, None, vec![ScriptStep::Fail(ErrorKind::WouldBlock)]);
+
+            assert_eq!(run.states.last(), Some(&ConnectionState::Close));
+            assert!(run.written.is_empty());
+        }
+
+        #[test]
+        fn upload_timeout_uses_server_clock() {
+            let clock = Arc::new(ManualClock::new());
+            let server = Server::new(Some(ServerConfig {
+                max_upload_duration: Some(Duration::from_secs(10)),
+                ..Default::default()
+            }))
+            .clock(clock.clone());
+            let run = run_script(
+                &server,
+                Some(clock),
+                vec![
+                    send(b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\n"),
+                    send(b"12"),
+                    ScriptStep::Advance(Duration::from_secs(11)),
+                    send(b"34"),
+                ],
+            );
+
+            assert!(run.states.contains(&ConnectionState::ClientError(408)));
+        }
+
+        #[test]
+        fn date_header_comes_from_server_clock() {
+            let clock = Arc::new(ManualClock::at(
+                UNIX_EPOCH + Duration::from_secs(784_111_777),
+            ));
+            let server = Server::new(None).clock(clock.clone());
+            let run = run_script(&server, Some(clock), vec![send(b"GET / HTTP/1.1\r\n\r\n")]);
+
+            assert!(String::from_utf8_lossy(&run.written)
+                .contains("Date: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+        }
+
+        // not going to mock fs
+        #[test]
+        fn closes_after_max_requests() {
+            let server = Server::new(Some(ServerConfig {
+                root: "test_files".to_string(),
+                keep_alive: KeepAliveConfig::On {
+                    max_requests: 2,
+                    timeout: 5,
+                    include_header: true,
+                },
+                ..Default::default()
+            }));
+            let request = b"GET /file.txt HTTP/1.1\r\n\r\n";
+            let run = run_script(
+                &server,
+                None,
+                vec![send(request), send(request), send(request)],
+            );
+
+            let responses = run
+                .states
+                .iter()
+                .filter(|state| matches!(state, ConnectionState::SendResponse(200)))
+                .count();
+            assert_eq!(responses, 2);
+            assert_eq!(run.states.last(), Some(&ConnectionState::Close));
+            assert!(String::from_utf8_lossy(&run.written).contains("Connection: close"));
+        }
+    }
+
+    mod assert_head_matches_get {
+        use crate::request_method::RequestMethod;
+        use crate::response::Response;
+        use crate::server::Server;
+        use crate::server_config::ServerConfig;
+        use crate::testing::assert_head_matches_get;
+
+        fn get_server() -> Server {
+            Server::new(Some(ServerConfig {
+                root: "test_files".to_string(),
+                ..Default::default()
+            }))
+        }
+
+        #[test]
+        fn passes_for_static_files_listeners_and_errors() {
+            let server = get_server().listener_at("/streamed", |_| {
+                Some(Response::builder().body_reader(&b"streamed"[..]).get())
+            });
+
+            assert_head_matches_get(&server, b"GET /file.txt HTTP/1.1\r\n\r\n");
+            assert_head_matches_get(&server, b"GET /streamed HTTP/1.1\r\n\r\n");
+            assert_head_matches_get(
+                &server,
+                b"GET /0qhwe0t9h HTTP/1.1\r\nAccept: text/html\r\n\r\n",
+            );
+        }
+
+        #[test]
+        #[should_panic(expected = "headers")]
+        fn panics_for_headers_only_sent_to_get() {
+            let server = get_server().listener(|request| {
+                let builder = Response::builder().text_body("Ok");
+                Some(match request.method {
+                    RequestMethod::Get => builder.header("X-Get", "1").get(),
+                    _ => builder.get(),
+                })
+            });
+
+            assert_head_matches_get(&server, b"GET / HTTP/1.1\r\n\r\n");
+        }
+    }
+
+    mod split_responses {
+        use crate::testing::split_responses;
+
+        #[test]
+        fn splits_by_content_length_and_ignores_date() {
+            let responses = split_responses(
+                b"HTTP/1.1 200 OK\r\nDate: now\r\nContent-Length: 2\r\n\r\nOkHTTP/1.1 204 No Content\r\n\r\n",
+            );
+
+            assert_eq!(responses.len(), 2);
+            assert_eq!(
+                responses[0].headers,
+                vec![("Content-Length".to_string(), "2".to_string())]
+            );
+            assert_eq!(responses[0].body, b"Ok");
+            assert_eq!(responses[1].status_line, "HTTP/1.1 204 No Content");
+        }
+
+        #[test]
+        fn chunked_body_ends_at_last_chunk() {
+            let responses = split_responses(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nOk\r\n0\r\n\r\nrest",
+            );
+
+            assert_eq!(responses.len(), 2);
+            assert_eq!(responses[0].body, b"2\r\nOk\r\n0\r\n\r\n");
+            assert_eq!(responses[1].status_line, "rest");
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Phase {
+    Parse,
+    Rules,
+    Handler,
+    Write,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Phase::Parse => "parse",
+            Phase::Rules => "rules",
+            Phase::Handler => "handler",
+            Phase::Write => "write",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Time spent in each phase of handling a single request.
+#[derive(Debug, Default)]
+pub(crate) struct RequestTiming {
+    parse: Duration,
+    rules: Duration,
+    handler: Duration,
+    write: Duration,
+}
+
+impl RequestTiming {
+    fn phase_mut(&mut self, phase: Phase) -> &mut Duration {
+        match phase {
+            Phase::Parse => &mut self.parse,
+            Phase::Rules => &mut self.rules,
+            Phase::Handler => &mut self.handler,
+            Phase::Write => &mut self.write,
+        }
+    }
+
+    pub(crate) fn record(&mut self, phase: Phase, elapsed: Duration) {
+        *self.phase_mut(phase) += elapsed;
+    }
+
+    /// Runs `f` and adds the time it took to `phase`.
+    pub(crate) fn measure<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.record(phase, start.elapsed());
+
+        result
+    }
+
+    pub(crate) fn total(&self) -> Duration {
+        self.parse + self.rules + self.handler + self.write
+    }
+
+    /// Phase that took the most time, earlier phases win ties.
+    pub(crate) fn dominant_phase(&self) -> (Phase, Duration) {
+        [
+            (Phase::Parse, self.parse),
+            (Phase::Rules, self.rules),
+            (Phase::Handler, self.handler),
+            (Phase::Write, self.write),
+        ]
+        .into_iter()
+        .reduce(|dominant, phase| {
+            if phase.1 > dominant.1 {
+                phase
+            } else {
+                dominant
+            }
+        })
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::timing::{Phase, RequestTiming};
+    use std::time::Duration;
+
+    #[test]
+    fn sums_phases() {
+        let mut timing = RequestTiming::default();
+        timing.record(Phase::Parse, Duration::from_millis(1));
+        timing.record(Phase::Handler, Duration::from_millis(5));
+        timing.record(Phase::Parse, Duration::from_millis(2));
+
+        assert_eq!(timing.total(), Duration::from_millis(8));
+    }
+
+    #[test]
+    fn dominant_phase_is_the_longest_one() {
+        let mut timing = RequestTiming::default();
+        timing.record(Phase::Rules, Duration::from_millis(3));
+        timing.record(Phase::Write, Duration::from_millis(7));
+
+        assert_eq!(
+            timing.dominant_phase(),
+            (Phase::Write, Duration::from_millis(7))
+        );
+    }
+
+    #[test]
+    fn measure_returns_result() {
+        let mut timing = RequestTiming::default();
+
+        assert_eq!(timing.measure(Phase::Handler, || 42), 42);
+    }
+}
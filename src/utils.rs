@@ -1,5 +1,8 @@
+use sha2::{Digest, Sha256};
+use std::fs::File;
 use std::iter::Peekable;
 use std::str::Utf8Error;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 pub trait StringUtils {
     fn as_bytes_vec(&self) -> Vec<u8>;
@@ -50,3 +53,135 @@ pub fn skip_whitespace<'a>(iterator: &mut Peekable<impl Iterator<Item = &'a u8>>
         iterator.next();
     }
 }
+
+pub fn skip_spaces<'a>(iterator: &mut Peekable<impl Iterator<Item = &'a u8>>) {
+    while matches!(iterator.peek(), Some(b' ' | b'\t')) {
+        iterator.next();
+    }
+}
+
+/// Year, month and day for a number of days since the Unix epoch,
+/// http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_date(days: u64) -> (i64, i64, i64) {
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+pub fn iso8601(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_date(seconds / 86400);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+pub fn escape_json(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+// Longest body prefix shown by body_summary
+const BODY_SUMMARY_LIMIT: usize = 512;
+
+/// Body as it would read on the wire, for logs: text is cut at BODY_SUMMARY_LIMIT bytes
+/// and binary content is replaced with its length.
+pub fn body_summary(body: &[u8]) -> String {
+    let shown = &body[..body.len().min(BODY_SUMMARY_LIMIT)];
+
+    // a multi-byte character cut by the limit does not make the body binary
+    if let Err(err) = std::str::from_utf8(shown) {
+        if err.error_len().is_some() {
+            return format!("[{} bytes of binary data]", body.len());
+        }
+    }
+
+    let mut summary = String::from_utf8_lossy(shown).to_string();
+    if shown.len() < body.len() {
+        summary += &format!("[... {} more bytes]", body.len() - shown.len());
+    }
+
+    summary
+}
+
+/// Positional read, so one handle can be shared by concurrent readers.
+pub fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+    }
+
+    #[cfg(windows)]
+    {
+        let mut read = 0;
+        while read < buf.len() {
+            match std::os::windows::fs::FileExt::seek_read(
+                file,
+                &mut buf[read..],
+                offset + read as u64,
+            )? {
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                n => read += n,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut key_block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        key_block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(key_block.map(|byte| byte ^ 0x36));
+    inner.update(data);
+
+    let mut outer = Sha256::new();
+    outer.update(key_block.map(|byte| byte ^ 0x5c));
+    outer.update(inner.finalize());
+
+    outer.finalize().to_vec()
+}
@@ -0,0 +1,353 @@
+#[cfg(feature = "https")]
+use crate::server_config::certified_key;
+#[cfg(feature = "https")]
+use rustls::server::{ClientHello, ResolvesServerCert};
+#[cfg(feature = "https")]
+use rustls::sign::CertifiedKey;
+#[cfg(feature = "https")]
+use std::sync::Arc;
+
+/// Strict-Transport-Security policy, only ever sent over TLS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HstsConfig {
+    pub max_age: u64,
+    pub include_subdomains: bool,
+    pub preload: bool,
+}
+
+impl HstsConfig {
+    pub fn new(max_age: u64) -> Self {
+        HstsConfig {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+
+        self
+    }
+
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+
+        self
+    }
+
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age);
+
+        if self.include_subdomains {
+            value += "; includeSubDomains";
+        }
+
+        if self.preload {
+            value += "; preload";
+        }
+
+        value
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VirtualHost {
+    /// Exact names (example.com), leading wildcards (*.example.com),
+    /// trailing wildcards (www.example.*) or suffix names (.example.com),
+    /// the last one matching both example.com and all of its subdomains.
+    pub server_names: Vec<String>,
+    pub root: String,
+    /// Used when no server name matches the Host header
+    pub default: bool,
+    /// Certificate presented when the client asks for this host through SNI
+    pub cert_path: Option<String>,
+    pub key_path: Option<String>,
+    pub hsts: Option<HstsConfig>,
+    /// Plain HTTP requests get redirected to the same url over HTTPS
+    pub redirect_to_https: bool,
+}
+
+impl VirtualHost {
+    pub fn new(server_name: &str, root: &str) -> Self {
+        VirtualHost {
+            server_names: vec![server_name.to_ascii_lowercase()],
+            root: root.to_string(),
+            default: false,
+            cert_path: None,
+            key_path: None,
+            hsts: None,
+            redirect_to_https: false,
+        }
+    }
+
+    pub fn alias(mut self, server_name: &str) -> Self {
+        self.server_names.push(server_name.to_ascii_lowercase());
+
+        self
+    }
+
+    pub fn default_host(mut self) -> Self {
+        self.default = true;
+
+        self
+    }
+
+    pub fn tls(mut self, cert_path: &str, key_path: &str) -> Self {
+        self.cert_path = Some(cert_path.to_string());
+        self.key_path = Some(key_path.to_string());
+
+        self
+    }
+
+    pub fn hsts(mut self, hsts: HstsConfig) -> Self {
+        self.hsts = Some(hsts);
+
+        self
+    }
+
+    pub fn redirect_to_https(mut self) -> Self {
+        self.redirect_to_https = true;
+
+        self
+    }
+
+    pub fn has_tls(&self) -> bool {
+        self.cert_path.is_some() && self.key_path.is_some()
+    }
+}
+
+#[derive(Debug, PartialEq, PartialOrd)]
+enum NameMatch {
+    // ordered from the weakest, wildcard lengths break ties within the same kind
+    TrailingWildcard(usize),
+    LeadingWildcard(usize),
+    Exact,
+}
+
+fn match_name(server_name: &str, host: &str) -> Option<NameMatch> {
+    if server_name == host {
+        return Some(NameMatch::Exact);
+    }
+
+    if let Some(suffix) = server_name.strip_prefix('*') {
+        // *.example.com, must not match example.com itself
+        return (suffix.starts_with('.') && host.ends_with(suffix))
+            .then_some(NameMatch::LeadingWildcard(suffix.len()));
+    }
+
+    if let Some(suffix) = server_name.strip_prefix('.') {
+        return (host == suffix || host.ends_with(server_name))
+            .then_some(NameMatch::LeadingWildcard(server_name.len()));
+    }
+
+    if let Some(prefix) = server_name.strip_suffix('*') {
+        return (prefix.ends_with('.') && host.starts_with(prefix) && host.len() > prefix.len())
+            .then_some(NameMatch::TrailingWildcard(prefix.len()));
+    }
+
+    None
+}
+
+/// Strips the port and the trailing dot, hosts are matched case-insensitively.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.rsplit_once(':') {
+        // ipv6 literals keep their colons inside brackets
+        Some((name, port)) if !name.is_empty() && !port.contains(']') => name,
+        _ => host,
+    };
+
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Picks the virtual host for a Host header value, following nginx precedence:
+/// exact name, longest leading wildcard, longest trailing wildcard, then the default host.
+pub(crate) fn match_host<'a>(
+    virtual_hosts: &'a [VirtualHost],
+    host: Option<&str>,
+) -> Option<&'a VirtualHost> {
+    let default_host = || {
+        virtual_hosts
+            .iter()
+            .find(|virtual_host| virtual_host.default)
+    };
+
+    let Some(host) = host else {
+        return default_host();
+    };
+    let host = normalize_host(host);
+
+    let mut best: Option<(NameMatch, &VirtualHost)> = None;
+
+    for virtual_host in virtual_hosts {
+        for server_name in &virtual_host.server_names {
+            let Some(name_match) = match_name(server_name, &host) else {
+                continue;
+            };
+
+            if best
+                .as_ref()
+                .is_none_or(|(best_match, _)| name_match > *best_match)
+            {
+                best = Some((name_match, virtual_host));
+            }
+        }
+    }
+
+    best.map(|(_, virtual_host)| virtual_host)
+        .or_else(default_host)
+}
+
+/// Picks the certificate by SNI server name, the same way requests are matched to hosts.
+#[cfg(feature = "https")]
+pub(crate) struct VirtualHostCertResolver {
+    virtual_hosts: Vec<VirtualHost>,
+    keys: Vec<Arc<CertifiedKey>>,
+    fallback: Option<Arc<CertifiedKey>>,
+}
+
+#[cfg(feature = "https")]
+impl VirtualHostCertResolver {
+    pub(crate) fn new(
+        virtual_hosts: &[VirtualHost],
+        fallback: Option<CertifiedKey>,
+    ) -> crate::Result<Self> {
+        let virtual_hosts: Vec<VirtualHost> = virtual_hosts
+            .iter()
+            .filter(|virtual_host| virtual_host.has_tls())
+            .cloned()
+            .collect();
+        let keys = virtual_hosts
+            .iter()
+            .map(|virtual_host| {
+                certified_key(
+                    virtual_host.cert_path.as_ref().unwrap(),
+                    virtual_host.key_path.as_ref().unwrap(),
+                )
+                .map(Arc::new)
+            })
+            .collect::<crate::Result<_>>()?;
+
+        Ok(VirtualHostCertResolver {
+            virtual_hosts,
+            keys,
+            fallback: fallback.map(Arc::new),
+        })
+    }
+
+    fn resolve_name(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        match_host(&self.virtual_hosts, server_name)
+            .and_then(|matched| {
+                self.virtual_hosts
+                    .iter()
+                    .position(|virtual_host| std::ptr::eq(virtual_host, matched))
+            })
+            .map(|index| self.keys[index].clone())
+            .or_else(|| self.fallback.clone())
+    }
+}
+
+#[cfg(feature = "https")]
+impl ResolvesServerCert for VirtualHostCertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        self.resolve_name(client_hello.server_name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "https")]
+    use crate::vhost::VirtualHostCertResolver;
+    use crate::vhost::{match_host, HstsConfig, VirtualHost};
+
+    fn hosts() -> Vec<VirtualHost> {
+        vec![
+            VirtualHost::new("*.example.com", "wildcard"),
+            VirtualHost::new("*.api.example.com", "api"),
+            VirtualHost::new("www.example.com", "www"),
+            VirtualHost::new(".example.org", "org"),
+            VirtualHost::new("mail.*", "mail"),
+            VirtualHost::new("fallback", "fallback").default_host(),
+        ]
+    }
+
+    fn root(host: Option<&str>) -> Option<String> {
+        match_host(&hosts(), host).map(|virtual_host| virtual_host.root.clone())
+    }
+
+    #[test]
+    fn exact_name_wins_over_wildcard() {
+        assert_eq!(root(Some("www.example.com")), Some("www".to_string()));
+    }
+
+    #[test]
+    fn longest_wildcard_suffix_wins() {
+        assert_eq!(root(Some("v1.api.example.com")), Some("api".to_string()));
+        assert_eq!(root(Some("blog.example.com")), Some("wildcard".to_string()));
+    }
+
+    #[test]
+    fn leading_wildcard_does_not_match_bare_domain() {
+        assert_eq!(root(Some("example.com")), Some("fallback".to_string()));
+    }
+
+    #[test]
+    fn suffix_name_matches_bare_domain_and_subdomains() {
+        assert_eq!(root(Some("example.org")), Some("org".to_string()));
+        assert_eq!(root(Some("a.b.example.org")), Some("org".to_string()));
+    }
+
+    #[test]
+    fn trailing_wildcard() {
+        assert_eq!(root(Some("mail.example.net")), Some("mail".to_string()));
+    }
+
+    #[test]
+    fn ignores_port_case_and_trailing_dot() {
+        assert_eq!(root(Some("WWW.Example.com.:8080")), Some("www".to_string()));
+    }
+
+    #[test]
+    fn falls_back_to_default_host() {
+        assert_eq!(root(Some("unknown.net")), Some("fallback".to_string()));
+        assert_eq!(root(None), Some("fallback".to_string()));
+    }
+
+    #[test]
+    fn no_match_without_default_host() {
+        let hosts = vec![VirtualHost::new("example.com", "web")];
+
+        assert_eq!(match_host(&hosts, Some("example.net")), None);
+    }
+
+    #[test]
+    fn hsts_header_value() {
+        assert_eq!(HstsConfig::new(300).header_value(), "max-age=300");
+        assert_eq!(
+            HstsConfig::new(300)
+                .include_subdomains()
+                .preload()
+                .header_value(),
+            "max-age=300; includeSubDomains; preload"
+        );
+    }
+
+    // not going to mock fs, the same pair is used for both hosts
+    #[cfg(feature = "https")]
+    #[test]
+    fn resolves_certificate_by_server_name() {
+        let hosts = vec![
+            VirtualHost::new("a.example.com", "a").tls(
+                "./test_files/keys/server.crt",
+                "./test_files/keys/server.key",
+            ),
+            VirtualHost::new("b.example.com", "b"),
+        ];
+        let resolver = VirtualHostCertResolver::new(&hosts, None).unwrap();
+
+        assert!(resolver.resolve_name(Some("a.example.com")).is_some());
+        assert!(resolver.resolve_name(Some("b.example.com")).is_none());
+        assert!(resolver.resolve_name(None).is_none());
+    }
+}
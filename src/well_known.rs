@@ -0,0 +1,183 @@
+use crate::header::names;
+use crate::request::Request;
+use crate::request_method::RequestMethod;
+use crate::response::Response;
+use crate::response_status_code::ResponseStatusCode;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const WELL_KNOWN_PREFIX: &str = "/.well-known/";
+
+type Provider = dyn Fn(&Request, &str) -> Option<Response> + Send + Sync;
+
+/// Dispatches `/.well-known/*` requests to providers by name, see `Server::well_known`.
+///
+/// A provider registered as `acme-challenge` gets `/.well-known/acme-challenge` and every
+/// path below it, along with the rest of the path after the name. Providers are asked before
+/// static content is looked up; when none of them answers, the request goes on as usual.
+#[derive(Clone, Default)]
+pub struct WellKnown {
+    providers: Vec<(String, Arc<Provider>)>,
+}
+
+impl WellKnown {
+    pub fn new() -> Self {
+        WellKnown::default()
+    }
+
+    /// Adds a provider for `name`, the first one registered for a name that answers wins.
+    pub fn provider(
+        mut self,
+        name: &str,
+        provider: impl Fn(&Request, &str) -> Option<Response> + Send + Sync + 'static,
+    ) -> Self {
+        self.providers
+            .push((name.trim_matches('/').to_string(), Arc::new(provider)));
+
+        self
+    }
+
+    /// Serves `text` as `name`, e.g. security.txt.
+    pub fn text(self, name: &str, text: &str) -> Self {
+        let text = text.to_string();
+
+        self.provider(name, move |request, rest| {
+            (rest.is_empty() && is_read(request))
+                .then(|| content_response("text/plain; charset=utf-8", text.as_bytes().to_vec()))
+        })
+    }
+
+    /// Redirects `name` to `location`, e.g. change-password to the page that does it.
+    pub fn redirect(self, name: &str, location: &str) -> Self {
+        let location = location.to_string();
+
+        self.provider(name, move |request, rest| {
+            (rest.is_empty() && is_read(request)).then(|| {
+                Response::builder()
+                    .status_code(ResponseStatusCode::Found)
+                    .header(names::LOCATION, &location)
+                    .get()
+            })
+        })
+    }
+
+    /// Serves ACME HTTP-01 challenge tokens from files in `dir`, which an ACME client
+    /// like certbot writes to. `dir` does not need to be inside the root directory.
+    pub fn acme_challenge(self, dir: &str) -> Self {
+        let dir = PathBuf::from(dir);
+
+        self.provider("acme-challenge", move |request, token| {
+            if !is_read(request) || !is_acme_token(token) {
+                return None;
+            }
+
+            let key_authorization = fs::read(dir.join(token)).ok()?;
+            Some(content_response(
+                "application/octet-stream",
+                key_authorization,
+            ))
+        })
+    }
+
+    /// None when the path is outside `/.well-known/` or no provider answers for it.
+    pub fn handle(&self, request: &Request) -> Option<Response> {
+        let path = request.url.split(['?', '#']).next().unwrap_or_default();
+        let path = path.strip_prefix(WELL_KNOWN_PREFIX)?;
+
+        self.providers.iter().find_map(|(name, provider)| {
+            let rest = path.strip_prefix(name.as_str())?;
+            let rest = match rest {
+                "" => "",
+                _ => rest.strip_prefix('/')?,
+            };
+
+            provider(request, rest)
+        })
+    }
+}
+
+fn is_read(request: &Request) -> bool {
+    matches!(request.method, RequestMethod::Get | RequestMethod::Head)
+}
+
+// tokens are base64url, which also keeps them from escaping the directory
+fn is_acme_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+fn content_response(content_type: &str, body: Vec<u8>) -> Response {
+    Response::builder()
+        .status_code(ResponseStatusCode::Ok)
+        .header(names::CONTENT_TYPE, content_type)
+        .header(names::CONTENT_LENGTH, &body.len().to_string())
+        .body(body)
+        .get()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::request::Request;
+    use crate::request_method::RequestMethod;
+    use crate::response_status_code::ResponseStatusCode;
+    use crate::well_known::WellKnown;
+
+    fn get(url: &str) -> Request {
+        Request::builder().url(url).get()
+    }
+
+    #[test]
+    fn dispatches_by_name() {
+        let well_known = WellKnown::new()
+            .text("security.txt", "Contact: mailto:security@example.com")
+            .redirect("change-password", "/account/password");
+
+        let security = well_known
+            .handle(&get("/.well-known/security.txt"))
+            .unwrap();
+        assert_eq!(security.body(), b"Contact: mailto:security@example.com");
+
+        let change_password = well_known
+            .handle(&get("/.well-known/change-password?from=app"))
+            .unwrap();
+        assert_eq!(change_password.status_code(), &ResponseStatusCode::Found);
+        assert_eq!(
+            change_password.get_header("Location"),
+            Some("/account/password")
+        );
+
+        assert!(well_known
+            .handle(&get("/.well-known/security.txt.bak"))
+            .is_none());
+        assert!(well_known.handle(&get("/.well-known/other")).is_none());
+        assert!(well_known.handle(&get("/security.txt")).is_none());
+        assert!(well_known
+            .handle(
+                &Request::builder()
+                    .method(RequestMethod::Post)
+                    .url("/.well-known/security.txt")
+                    .get()
+            )
+            .is_none());
+    }
+
+    #[test]
+    fn serves_acme_challenge_tokens() {
+        let dir = std::env::temp_dir().join(format!("http_rs_acme_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("token_1-a"), "token_1-a.thumbprint").unwrap();
+        let well_known = WellKnown::new().acme_challenge(dir.to_str().unwrap());
+
+        let challenge = well_known.handle(&get("/.well-known/acme-challenge/token_1-a"));
+        let missing = well_known.handle(&get("/.well-known/acme-challenge/token_2"));
+        let escaping = well_known.handle(&get("/.well-known/acme-challenge/..%2Ftoken_1-a"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(challenge.unwrap().body(), b"token_1-a.thumbprint");
+        assert!(missing.is_none());
+        assert!(escaping.is_none());
+    }
+}
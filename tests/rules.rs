@@ -0,0 +1,89 @@
+use http_rs::request::Request;
+use http_rs::response::Response;
+use http_rs::response_status_code::ResponseStatusCode;
+use http_rs::rules::{
+    parse_str, tokenize, wrap_callable, EvaluationOptions, Object, RuleEvaluationResult, RuleScope,
+    RuleTokenKind, Type,
+};
+use std::any::Any;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct User {
+    name: String,
+}
+
+fn user_object(name: &str) -> Object {
+    Object::builder()
+        .add_field("name", |instance: Rc<RefCell<dyn Any>>| {
+            let instance = instance.borrow();
+            let user = instance.downcast_ref::<User>().unwrap();
+            Ok(Type::String(user.name.clone()))
+        })
+        .get(Rc::new(RefCell::new(User {
+            name: name.to_string(),
+        })))
+}
+
+fn evaluate(source: &str, scope: &mut RuleScope) -> (RuleEvaluationResult, ResponseStatusCode) {
+    let (rules, warnings) = parse_str(source).unwrap();
+    assert!(warnings.is_empty());
+
+    let request = Rc::new(RefCell::new(Request::builder().url("/admin").get()));
+    let response = Rc::new(RefCell::new(Response::builder().get()));
+
+    let result = rules[0]
+        .evaluate_in(
+            request,
+            response.clone(),
+            scope,
+            &EvaluationOptions::default(),
+        )
+        .unwrap();
+
+    let status_code = *response.borrow().status_code();
+
+    (result, status_code)
+}
+
+#[test]
+fn tokenizes_source() {
+    let tokens = tokenize("matches /a { }").unwrap();
+
+    assert_eq!(tokens[0].kind, RuleTokenKind::Matches);
+    assert_eq!(tokens[1].kind, RuleTokenKind::LitStr("/a".to_string()));
+}
+
+#[test]
+fn evaluates_against_caller_objects() {
+    let source = r#"matches /admin { if user.name != "root" { return 403; } }"#;
+
+    let mut scope = RuleScope::new();
+    scope.update_var("user", Type::Object(user_object("guest")));
+    let (result, status_code) = evaluate(source, &mut scope);
+    assert!(matches!(result, RuleEvaluationResult::Finish));
+    assert_eq!(status_code, ResponseStatusCode::Forbidden);
+
+    let mut scope = RuleScope::new();
+    scope.update_var("user", Type::Object(user_object("root")));
+    let (result, _) = evaluate(source, &mut scope);
+    assert!(matches!(result, RuleEvaluationResult::Continue));
+}
+
+#[test]
+fn caller_functions_replace_builtins() {
+    let logged = Rc::new(RefCell::new(vec![]));
+    let mut scope = RuleScope::new();
+    let log = logged.clone();
+    scope.update_var(
+        "log",
+        Type::Function(wrap_callable(move |text: String| {
+            log.borrow_mut().push(text);
+            Ok(Type::Bool(true))
+        })),
+    );
+
+    evaluate(r#"matches / { log("hello"); }"#, &mut scope);
+
+    assert_eq!(*logged.borrow(), vec!["hello".to_string()]);
+}
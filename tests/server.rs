@@ -1,20 +1,25 @@
 use crate::utils::panic_after;
-use http_rs::http_version::HttpVersion;
+use http_rs::access_log::{AccessLogConfig, AccessLogFormat};
+use http_rs::auth_request::AuthRequest;
+use http_rs::event_stream::Event;
+use http_rs::hotlink::HotlinkProtection;
+use http_rs::rate_limit::RateLimit;
 use http_rs::request::Request;
 use http_rs::request_method::RequestMethod;
 use http_rs::response::Response;
 use http_rs::response_status_code::ResponseStatusCode;
 use http_rs::server::*;
 use http_rs::server_config::*;
+use http_rs::signed_url::SignedUrlConfig;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Result, Write};
 use std::net::TcpStream;
-use std::sync::Arc;
+use std::sync::Mutex;
 
 mod utils;
 
-/**
+/*
   - tests:
     - 400 on incomplete request
     - 408 on timeout
@@ -56,52 +61,33 @@ fn setup(config: Option<ServerConfig>) -> Server {
     })
 }
 
-fn run_test(test: impl Fn()) {
-    let handle = std::thread::spawn(|| {
-        let mut server = setup(None);
-
-        server.run(Arc::new(true)).expect("Server runs");
-    });
+// Every test binds the same port, so they have to take turns
+static SERVER_LOCK: Mutex<()> = Mutex::new(());
 
-    test();
-
-    handle.join().unwrap();
+fn run_test(test: impl Fn()) {
+    run_test_with_config(default_server_config(), test);
 }
 
 fn run_test_with_config(config: ServerConfig, test: impl Fn()) {
-    let handle = std::thread::spawn(|| {
-        let mut server = setup(Some(config));
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
 
-        server.run(Arc::new(true)).expect("Server runs");
-    });
+    let handle = setup(Some(config)).start().expect("Server starts");
 
     test();
 
-    handle.join().unwrap();
+    handle.shutdown();
 }
 
 fn default_get(url: &str) -> Request {
-    Request {
-        method: RequestMethod::Get,
-        url: url.to_string(),
-        version: HttpVersion::Http1_1,
-        headers: Default::default(),
-        body: vec![],
-    }
+    Request::builder().url(url).get()
 }
 
 fn default_post(url: &str, body: &[u8]) -> Request {
-    let mut headers: HashMap<String, String> = HashMap::new();
-
-    headers.insert("Content-Length".to_string(), body.len().to_string());
-
-    Request {
-        method: RequestMethod::Post,
-        url: url.to_string(),
-        version: HttpVersion::Http1_1,
-        headers,
-        body: Vec::from(body),
-    }
+    Request::builder()
+        .method(RequestMethod::Post)
+        .url(url)
+        .body(Vec::from(body))
+        .get()
 }
 
 fn issue_request(
@@ -135,7 +121,7 @@ fn issue_request(
         if index == 0 {
             let parts = line.split(' ').collect::<Vec<&str>>();
             let code_int = parts[1].parse::<u16>().unwrap();
-            status_code = Some(unsafe { std::mem::transmute(code_int) });
+            status_code = Some(ResponseStatusCode::try_from(code_int).unwrap());
         } else if empty_line_found {
             body.extend_from_slice(line.as_bytes());
         } else {
@@ -205,6 +191,28 @@ fn get_request_for_content() {
     });
 }
 
+#[test]
+fn get_request_for_content_sent_from_disk() {
+    let config = ServerConfig {
+        sendfile_threshold: Some(0),
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        let request = default_get("/file.txt");
+
+        let response = issue_req_request(&request).unwrap();
+
+        let file_contents = std::fs::read("test_files/file.txt").unwrap();
+
+        assert_eq!(response.body(), &file_contents);
+        assert_eq!(
+            response.headers().get("Content-Length"),
+            Some(&file_contents.len().to_string())
+        );
+    });
+}
+
 #[test]
 fn post_request() {
     run_test(|| {
@@ -233,6 +241,47 @@ fn malformed_request_400() {
     });
 }
 
+#[test]
+fn rejects_ambiguous_requests_and_closes_connection() {
+    let config = ServerConfig {
+        keep_alive: KeepAliveConfig::On {
+            timeout: 5,
+            max_requests: 10,
+            include_header: true,
+        },
+        ..default_server_config()
+    };
+    let rejected = [
+        "GET / HTTP/1.1\r\nHost: localhost\r\nHost: example.com\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost : localhost\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1\r\nContent-Length: 5\r\n\r\n1",
+    ];
+
+    run_test_with_config(config, || {
+        for request in rejected {
+            let written = panic_after(std::time::Duration::from_secs(2), move || {
+                let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+                // would be answered too if the connection stayed open
+                let pipelined = "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+                tcp.write_all(format!("{request}{pipelined}").as_bytes())
+                    .unwrap();
+                let mut written = String::new();
+                tcp.read_to_string(&mut written).unwrap();
+
+                written
+            });
+
+            assert!(
+                written.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{request}"
+            );
+            assert!(written.contains("Connection: close\r\n"), "{request}");
+            assert_eq!(written.matches("HTTP/1.1 ").count(), 1, "{request}");
+        }
+    });
+}
+
 #[test]
 fn incomplete_request_timeout_408() {
     let closure = || {
@@ -282,3 +331,655 @@ fn handles_segmented_transfer_encoding_chunked() {
         assert_eq!(std::str::from_utf8(response.body()).unwrap(), "123456789");
     });
 }
+
+#[test]
+fn slow_upload_timeout_408() {
+    let config = ServerConfig {
+        max_upload_duration: Some(std::time::Duration::from_millis(60)),
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        let segments = ["POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n", "12", "34"];
+
+        let response = issue_segmented_str_request(&segments).unwrap();
+
+        assert_eq!(response.status_code(), &ResponseStatusCode::RequestTimeout);
+    });
+}
+
+#[test]
+fn request_timeout_covers_upload() {
+    let config = ServerConfig {
+        request_timeout: Some(std::time::Duration::from_millis(60)),
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        let segments = ["POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n", "12", "34"];
+
+        let response = issue_segmented_str_request(&segments).unwrap();
+
+        assert_eq!(response.status_code(), &ResponseStatusCode::RequestTimeout);
+    });
+}
+
+#[test]
+fn request_timeout_replaces_late_response_with_503() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        request_timeout: Some(std::time::Duration::from_millis(100)),
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|request| {
+            if request.url == "/slow" {
+                std::thread::sleep(std::time::Duration::from_millis(200));
+            }
+
+            Some(Response::builder().text_body("done").get())
+        })
+        .start()
+        .expect("Server starts");
+
+    let fast = issue_req_request(&default_get("/fast")).unwrap();
+    assert_eq!(fast.status_code(), &ResponseStatusCode::Ok);
+    let slow = issue_req_request(&default_get("/slow")).unwrap();
+    assert_eq!(slow.status_code(), &ResponseStatusCode::ServiceUnavailable);
+
+    handle.shutdown();
+}
+
+#[test]
+fn serves_signed_urls_only() {
+    let signed_url = SignedUrlConfig::new("/keys", b"secret");
+    let expires = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+    let url = signed_url.sign("/keys/server.crt", expires);
+    let config = ServerConfig {
+        signed_urls: vec![signed_url],
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        let signed = issue_req_request(&default_get(&url)).unwrap();
+        assert_eq!(signed.status_code(), &ResponseStatusCode::Ok);
+        assert_eq!(
+            signed.get_header("Content-Type").unwrap(),
+            "application/x-x509-ca-cert"
+        );
+        assert_eq!(
+            *signed.body(),
+            std::fs::read("test_files/keys/server.crt").unwrap()
+        );
+
+        for unsigned_url in [
+            "/keys/server.crt",
+            "//keys/server.crt",
+            "/keys/./server.crt",
+        ] {
+            let unsigned = issue_req_request(&default_get(unsigned_url)).unwrap();
+            assert_eq!(unsigned.status_code(), &ResponseStatusCode::Forbidden);
+        }
+    });
+}
+
+#[test]
+fn redirects_hotlinked_media() {
+    let config = ServerConfig {
+        hotlink_protections: vec![HotlinkProtection::new(&["text/*"])
+            .allow_host("partner.org")
+            .redirect("https://example.com/hotlink.txt")],
+        ..default_server_config()
+    };
+    let get_with_referer = |referer: &str| {
+        Request::builder()
+            .url("/file.txt")
+            .header("Referer", referer)
+            .get()
+    };
+
+    run_test_with_config(config, || {
+        let allowed = issue_req_request(&get_with_referer("https://partner.org/page")).unwrap();
+        assert_eq!(allowed.status_code(), &ResponseStatusCode::Ok);
+
+        let without_referer = issue_req_request(&default_get("/file.txt")).unwrap();
+        assert_eq!(without_referer.status_code(), &ResponseStatusCode::Ok);
+
+        let hotlinked = issue_req_request(&get_with_referer("https://other.org/page")).unwrap();
+        assert_eq!(hotlinked.status_code(), &ResponseStatusCode::Found);
+        assert_eq!(
+            hotlinked.get_header("Location"),
+            Some("https://example.com/hotlink.txt")
+        );
+    });
+}
+
+#[test]
+fn serves_robots_txt_from_config() {
+    let config = ServerConfig {
+        robots_txt: Some("User-agent: *\nDisallow: /private/\n".to_string()),
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        let robots = issue_req_request(&default_get("/robots.txt")).unwrap();
+        assert_eq!(robots.status_code(), &ResponseStatusCode::Ok);
+        assert_eq!(
+            robots.get_header("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(*robots.body(), b"User-agent: *\nDisallow: /private/\n");
+
+        let post = issue_req_request(&default_post("/robots.txt", b"")).unwrap();
+        assert_eq!(post.status_code(), &ResponseStatusCode::MethodNotAllowed);
+
+        let security = issue_req_request(&default_get("/.well-known/security.txt")).unwrap();
+        assert_eq!(security.status_code(), &ResponseStatusCode::NotFound);
+    });
+}
+
+#[test]
+fn writes_access_log() {
+    let path = std::env::temp_dir().join(format!("http_rs_access_{}.log", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let config = ServerConfig {
+        access_log: Some(AccessLogConfig::new(&path).format(AccessLogFormat::Json)),
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        issue_req_request(&default_get("/file.txt")).unwrap();
+        issue_req_request(&default_get("/missing.txt")).unwrap();
+    });
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(
+        lines[0].contains("\"client\": \"127.0.0.1\", \"method\": \"GET\", \"url\": \"/file.txt\"")
+    );
+    // bytes count the head too
+    let bytes: usize = lines[0]
+        .split("\"status\": 200, \"bytes\": ")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(bytes > std::fs::read("test_files/file.txt").unwrap().len());
+    assert!(lines[1].contains("\"status\": 404"));
+}
+
+#[test]
+fn serves_transparent_favicon_fallback() {
+    let config = ServerConfig {
+        favicon_fallback: FaviconFallback::Transparent,
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        // the icon is binary, which the test client can only take without a body
+        let favicon = issue_req_request(
+            &Request::builder()
+                .method(RequestMethod::Head)
+                .url("/favicon.ico")
+                .get(),
+        )
+        .unwrap();
+        assert_eq!(favicon.status_code(), &ResponseStatusCode::Ok);
+        assert_eq!(favicon.get_header("Content-Type"), Some("image/x-icon"));
+        assert_eq!(favicon.get_header("Content-Length"), Some("70"));
+
+        let other = issue_req_request(&default_get("/missing.ico")).unwrap();
+        assert_eq!(other.status_code(), &ResponseStatusCode::NotFound);
+    });
+}
+
+#[test]
+fn shutdown_finishes_in_flight_requests() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let handle = Server::new(Some(default_server_config()))
+        .listener(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+
+            Some(
+                Response::builder()
+                    .status_code(ResponseStatusCode::Ok)
+                    .text_body("Slow")
+                    .get(),
+            )
+        })
+        .start()
+        .expect("Server starts");
+
+    let client = std::thread::spawn(|| issue_req_request(&default_get("/")));
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    handle.shutdown();
+
+    let response = client.join().unwrap().unwrap();
+    assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+    assert_eq!(response.body(), b"Slow");
+    assert!(TcpStream::connect("127.0.0.1:80").is_err());
+}
+
+#[test]
+#[cfg(feature = "https")]
+fn https_listens_on_configured_port() {
+    let config = ServerConfig {
+        https: true,
+        https_port: 8443,
+        cert_path: Some("examples/keys/server.crt".to_string()),
+        key_path: Some("examples/keys/server.key".to_string()),
+        ..default_server_config()
+    };
+
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = setup(Some(config)).start().expect("Server starts");
+
+    let ports: Vec<u16> = handle
+        .addresses()
+        .iter()
+        .map(|address| address.port())
+        .collect();
+    assert_eq!(ports, vec![80, 8443]);
+
+    // the plain listener does not expect a TLS handshake
+    let response = issue_req_request(&default_get("/")).unwrap();
+    assert_eq!(response.status_code(), &ResponseStatusCode::Ok);
+
+    handle.shutdown();
+}
+
+#[test]
+fn streams_unknown_length_bodies() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let handle = Server::new(Some(default_server_config()))
+        .listener(|_| {
+            Some(
+                Response::builder()
+                    .body_reader(std::io::Cursor::new(b"streamed".to_vec()))
+                    .get(),
+            )
+        })
+        .start()
+        .expect("Server starts");
+
+    let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(!response.contains("Content-Length"));
+    assert!(response.ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn streams_unknown_length_bodies_to_http_1_0_until_close() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    // keep-alive would otherwise hold the connection open for more requests
+    let config = ServerConfig {
+        keep_alive: KeepAliveConfig::On {
+            timeout: 5,
+            max_requests: 10,
+            include_header: true,
+        },
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|_| {
+            Some(
+                Response::builder()
+                    .body_reader(std::io::Cursor::new(b"streamed".to_vec()))
+                    .get(),
+            )
+        })
+        .start()
+        .expect("Server starts");
+
+    let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+    tcp.write_all(b"GET / HTTP/1.0\r\nHost: localhost\r\nConnection: keep-alive\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(!response.contains("Transfer-Encoding"));
+    assert!(!response.contains("Content-Length"));
+    assert!(response.ends_with("\r\n\r\nstreamed"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn closes_http_1_0_connections_unless_kept_alive() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        keep_alive: KeepAliveConfig::On {
+            timeout: 5,
+            max_requests: 10,
+            include_header: true,
+        },
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config)).start().expect("Server starts");
+
+    let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+    tcp.write_all(b"GET /file.txt HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn streams_events_as_they_come() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let (sender, receiver) = std::sync::mpsc::channel::<Event>();
+    let receiver = Mutex::new(Some(receiver));
+    let handle = Server::new(Some(default_server_config()))
+        .listener(move |_| {
+            let receiver = receiver.lock().unwrap().take()?;
+            Some(Response::event_stream(receiver.into_iter()))
+        })
+        .start()
+        .expect("Server starts");
+
+    let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+    tcp.write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    sender.send(Event::new("first").id("1")).unwrap();
+
+    // the first event arrives while the stream is still open
+    let mut response = vec![];
+    let mut buf = [0u8; 1024];
+    while !String::from_utf8_lossy(&response).contains("data: first\n\n") {
+        let read = tcp.read(&mut buf).unwrap();
+        assert_ne!(read, 0);
+        response.extend_from_slice(&buf[..read]);
+    }
+
+    sender.send(Event::new("second")).unwrap();
+    drop(sender);
+    tcp.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8(response).unwrap();
+
+    assert!(response.contains("Content-Type: text/event-stream\r\n"));
+    assert!(response.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(response
+        .ends_with("\r\n\r\n13\r\nid: 1\ndata: first\n\n\r\nE\r\ndata: second\n\n\r\n0\r\n\r\n"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn guards_max_response_size() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        max_response_size: Some(4),
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|request| {
+            let response = match request.url.as_str() {
+                "/streamed" => Response::builder()
+                    .body_reader(std::io::Cursor::new(b"streamed".to_vec()))
+                    .get(),
+                "/fixed" => Response::builder().text_body("fixed").get(),
+                _ => Response::builder().text_body("ok").get(),
+            };
+            Some(response)
+        })
+        .start()
+        .expect("Server starts");
+
+    let get = |path: &str| {
+        let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+        write!(tcp, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    assert!(get("/ok").ends_with("\r\n\r\nok"));
+    assert!(get("/fixed").starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    let streamed = get("/streamed");
+    assert!(streamed.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(streamed.ends_with("\r\n\r\n"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn throttles_responses_matching_rate_limit() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        rate_limits: vec![RateLimit::new("/slow", 2000).burst(0)],
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|_| Some(Response::builder().text_body(&"x".repeat(1000)).get()))
+        .start()
+        .expect("Server starts");
+
+    let get = |path: &str| {
+        let started = std::time::Instant::now();
+        let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+        write!(tcp, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).unwrap();
+        assert!(response.ends_with(&"x".repeat(1000)));
+        started.elapsed()
+    };
+
+    assert!(get("/fast") < std::time::Duration::from_millis(250));
+    assert!(get("/slow") >= std::time::Duration::from_millis(500));
+    assert!(get("//slow") >= std::time::Duration::from_millis(500));
+
+    handle.shutdown();
+}
+
+#[test]
+fn answers_panicking_listener_with_500() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        keep_alive: KeepAliveConfig::On {
+            timeout: 1,
+            max_requests: 2,
+            include_header: true,
+        },
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|request| match request.url.as_str() {
+            "/panic" => panic!("listener failed"),
+            _ => Some(Response::builder().text_body("ok").get()),
+        })
+        .start()
+        .expect("Server starts");
+
+    let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+    tcp.write_all(b"GET /panic HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut error_response = vec![];
+    while !error_response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0u8];
+        tcp.read_exact(&mut byte).unwrap();
+        error_response.push(byte[0]);
+    }
+    assert!(error_response.starts_with(b"HTTP/1.1 500 Internal Server Error\r\n"));
+
+    // the same connection goes on to serve the next request
+    tcp.write_all(b"GET /ok HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with("\r\n\r\nok"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn delegates_authentication_to_auth_request() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        auth_requests: vec![AuthRequest::new("/private", "/internal/auth")],
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|request| {
+            let response = match request.url.as_str() {
+                "/internal/auth" if request.has_header("Authorization", Some("Bearer secret")) => {
+                    Response::builder().get()
+                }
+                "/internal/auth" => Response::builder()
+                    .status_code(ResponseStatusCode::Unauthorized)
+                    .header("WWW-Authenticate", "Bearer")
+                    .get(),
+                _ => Response::builder().text_body("private").get(),
+            };
+            Some(response)
+        })
+        .start()
+        .expect("Server starts");
+
+    let get = |authorization: &str| {
+        let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+        write!(
+            tcp,
+            "GET /private/data HTTP/1.1\r\nHost: localhost\r\n{authorization}\r\n"
+        )
+        .unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    let denied = get("");
+    assert!(denied.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    assert!(denied.contains("WWW-Authenticate: Bearer\r\n"));
+    let allowed = get("Authorization: Bearer secret\r\n");
+    assert!(allowed.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(allowed.ends_with("\r\n\r\nprivate"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn auth_request_covers_equivalent_paths() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let config = ServerConfig {
+        auth_requests: vec![AuthRequest::new("/file.txt", "/auth")],
+        ..default_server_config()
+    };
+    let handle = Server::new(Some(config))
+        .listener(|request| {
+            (request.url == "/auth").then(|| {
+                Response::builder()
+                    .status_code(ResponseStatusCode::Forbidden)
+                    .get()
+            })
+        })
+        .start()
+        .expect("Server starts");
+
+    let get = |path: &str| {
+        let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+        write!(tcp, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        tcp.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    for path in ["/file.txt", "//file.txt", "/./file.txt", "/x/../file.txt"] {
+        assert!(
+            get(path).starts_with("HTTP/1.1 403 Forbidden\r\n"),
+            "{path} skipped the auth request"
+        );
+    }
+    assert!(get("/../file.txt").starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn sends_trailers_after_last_chunk() {
+    let _guard = SERVER_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+    let handle = Server::new(Some(default_server_config()))
+        .listener(|_| {
+            let body = b"streamed".to_vec();
+            let checksum = body.iter().map(|&byte| byte as u32).sum::<u32>();
+
+            Some(
+                Response::builder()
+                    .body_reader(std::io::Cursor::new(body))
+                    .trailers(&["X-Checksum"], move || {
+                        vec![("X-Checksum".to_string(), checksum.to_string())]
+                    })
+                    .get(),
+            )
+        })
+        .start()
+        .expect("Server starts");
+
+    let mut tcp = TcpStream::connect("127.0.0.1:80").unwrap();
+    tcp.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    tcp.read_to_string(&mut response).unwrap();
+
+    assert!(response.contains("Trailer: X-Checksum\r\n"));
+    assert!(response.ends_with("8\r\nstreamed\r\n0\r\nX-Checksum: 853\r\n\r\n"));
+
+    handle.shutdown();
+}
+
+#[test]
+fn reloads_changed_rules_file() {
+    let rules_path = std::env::temp_dir().join("http_rs_watched.rules");
+    let write_rules = |status: u16| {
+        std::fs::write(&rules_path, format!("matches /gone {{ return {status}; }}")).unwrap();
+    };
+    write_rules(404);
+
+    let config = ServerConfig {
+        rules_path: Some(rules_path.to_str().unwrap().to_string()),
+        rules_reload_interval: Some(std::time::Duration::from_millis(20)),
+        ..default_server_config()
+    };
+
+    run_test_with_config(config, || {
+        let response = issue_req_request(&default_get("/gone")).unwrap();
+        assert_eq!(response.status_code(), &ResponseStatusCode::NotFound);
+
+        // modification times may be as coarse as a second
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        write_rules(418);
+        std::thread::sleep(std::time::Duration::from_millis(200));
+
+        let response = issue_req_request(&default_get("/gone")).unwrap();
+        assert_eq!(response.status_code(), &ResponseStatusCode::ImATeapot);
+    });
+}